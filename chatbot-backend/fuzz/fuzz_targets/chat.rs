@@ -0,0 +1,101 @@
+//! Arbitrary bytes as a `/chat` request body and as a message on its own,
+//! answered in every state of the flow with every built-in pipeline step.
+//! Run it with `cargo fuzz run chat` from `chatbot-backend`.
+
+#![no_main]
+
+use std::sync::LazyLock;
+
+use chatbot_backend::{
+    catalog::ServicesCatalog,
+    chatbot::{self, AnswerRules, Vocabulary},
+    config::{LiveConfig, RuntimeConfig},
+    flow,
+    i18n::Language,
+    pipeline::PipelineConfig,
+    render,
+    routes::chat::ChatRequest,
+    session::{ConversationState, FieldValue, Session},
+    synonyms::SynonymTable,
+    tenant::ReplyOverrides,
+    training::LearnedIntents,
+};
+use libfuzzer_sys::fuzz_target;
+
+static CONFIG: LazyLock<LiveConfig> = LazyLock::new(|| {
+    let config = LiveConfig::new(ServicesCatalog::default());
+    let pipeline = PipelineConfig {
+        pre: ["collapse_whitespace", "redact_card_numbers"]
+            .map(String::from)
+            .to_vec(),
+        post: vec!["signature".into()],
+    };
+    let runtime = RuntimeConfig {
+        pipeline,
+        ..RuntimeConfig::default()
+    };
+    config.replace(runtime, "fuzz").expect("a valid config");
+    config
+});
+
+/// A session in `state` with the answers the state needs.
+fn session_in(state: ConversationState, language: Language) -> Session {
+    let mut session = Session::new("fuzz".into(), language);
+    session.state = state;
+    if state != ConversationState::Idle && state != ConversationState::AskingName {
+        session.data.name = Some("Ana".into());
+        session.data.email = Some("ana@example.com".into());
+        session.data.project_details = Some("An online shop for ceramics".into());
+        session.data.budget = Some(FieldValue::Given("5000".into()));
+    }
+    session
+}
+
+fn answer_everywhere(message: &str, language: Option<Language>) {
+    let message = message.trim();
+    if message.is_empty() {
+        return;
+    }
+    let language = language
+        .or_else(|| Language::detect(message))
+        .unwrap_or_default();
+    let (qualification, validation) = (CONFIG.qualification(), CONFIG.validation());
+    for state in flow::STATES {
+        let mut session = session_in(state, language);
+        let message = CONFIG.preprocess(&session, message);
+        session.push_visitor_message(message.as_str(), None);
+        let mut reply = chatbot::generate_reply(
+            &mut session,
+            &message,
+            &CONFIG.catalog(),
+            &ReplyOverrides::default(),
+            AnswerRules {
+                validation: &validation,
+                qualification: qualification.as_ref(),
+            },
+            None,
+            Vocabulary {
+                synonyms: &SynonymTable::default(),
+                learned: &LearnedIntents::default(),
+            },
+        );
+        CONFIG.postprocess(&session, &mut reply);
+        render::parts(&reply.text);
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(request) = serde_json::from_slice::<ChatRequest>(data) {
+        let metadata_ok = request
+            .metadata
+            .as_ref()
+            .is_none_or(|m| m.validate().is_ok());
+        if metadata_ok {
+            let language = request.language.as_deref().and_then(Language::from_code);
+            answer_everywhere(&request.message, language);
+        }
+    }
+    if let Ok(message) = std::str::from_utf8(data) {
+        answer_everywhere(message, None);
+    }
+});
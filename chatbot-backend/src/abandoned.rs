@@ -0,0 +1,147 @@
+//! Inquiries left unfinished when their session expires.
+//!
+//! A visitor who gave their email address but stopped answering before
+//! confirming is still a lead. When such a session is purged, the data
+//! captured so far goes to the inquiry webhook as `inquiry.abandoned`, next
+//! to `inquiry.confirmed`, so sales can follow up before it is gone.
+//!
+//! Marketing automation hears of every session that expires mid-inquiry,
+//! email address or not: with `MARKETING_WEBHOOK_URL` set, each goes there
+//! as `session.abandoned` with what was captured and the page the visitor
+//! was last on, for retargeting.
+
+use serde::Serialize;
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::{
+    i18n::Language,
+    mail::transcript_text,
+    outbox::OutboxJob,
+    session::{ConversationState, Field, FieldValue, Session, SessionData},
+    state::AppState,
+};
+
+/// What sales gets for an unfinished inquiry.
+#[derive(Debug, Clone, Serialize)]
+pub struct PartialLead<'a> {
+    pub session_id: &'a str,
+    pub tenant: &'a str,
+    pub language: Language,
+    pub inquiry: &'a SessionData,
+    /// The question the visitor left unanswered.
+    pub stopped_at: ConversationState,
+    /// Answers the inquiry still lacks.
+    pub missing: Vec<Field>,
+    pub transcript: String,
+}
+
+impl<'a> PartialLead<'a> {
+    /// The lead in an expired session, if it was abandoned mid-inquiry with
+    /// an email address captured.
+    pub fn from_session(session: &'a Session) -> Option<Self> {
+        if !session.state.in_inquiry() || session.data.email.is_none() {
+            return None;
+        }
+        let data = &session.data;
+        let missing = [
+            (Field::Name, data.name.is_none()),
+            (Field::ProjectDetails, data.project_details.is_none()),
+            (
+                Field::Budget,
+                !matches!(data.budget, Some(FieldValue::Given(_))),
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(field, missing)| missing.then_some(field))
+        .collect();
+        Some(Self {
+            session_id: &session.id,
+            tenant: &session.tenant,
+            language: session.language,
+            inquiry: data,
+            stopped_at: session.state,
+            missing,
+            transcript: transcript_text(session),
+        })
+    }
+}
+
+/// What marketing automation gets for a session left mid-inquiry.
+#[derive(Debug, Clone, Serialize)]
+pub struct AbandonedSession<'a> {
+    pub session_id: &'a str,
+    pub tenant: &'a str,
+    pub language: Language,
+    pub inquiry: &'a SessionData,
+    /// The question the visitor left unanswered.
+    pub stopped_at: ConversationState,
+    /// The page of the visitor's last message, when the widget sent it.
+    pub page_url: Option<&'a str>,
+    pub experiment: Option<&'a str>,
+}
+
+impl<'a> AbandonedSession<'a> {
+    /// The expired `session`, if it ended partway through an inquiry.
+    pub fn from_session(session: &'a Session) -> Option<Self> {
+        if !session.state.in_inquiry() {
+            return None;
+        }
+        let metadata = session
+            .messages
+            .iter()
+            .rev()
+            .find_map(|m| m.metadata.as_ref());
+        Some(Self {
+            session_id: &session.id,
+            tenant: &session.tenant,
+            language: session.language,
+            inquiry: &session.data,
+            stopped_at: session.state,
+            page_url: metadata.and_then(|m| m.page_url.as_deref()),
+            experiment: metadata.and_then(|m| m.experiment.as_deref()),
+        })
+    }
+}
+
+/// Hands the abandoned inquiries among `expired` sessions over to sales.
+pub fn notify_sales(state: &AppState, expired: &[Session]) {
+    let leads: Vec<PartialLead> = expired
+        .iter()
+        .filter_map(PartialLead::from_session)
+        .collect();
+    if leads.is_empty() {
+        return;
+    }
+    for lead in &leads {
+        state.metrics.record_inquiry_abandoned(lead.tenant);
+        let Some(url) = state.config_for(lead.tenant).webhook_url() else {
+            warn!(
+                session_id = %lead.session_id,
+                "no webhook_url is configured; abandoned inquiry dropped"
+            );
+            continue;
+        };
+        state.outbox.enqueue(OutboxJob::new(
+            "inquiry.abandoned",
+            &url,
+            json!({ "lead": lead }),
+        ));
+        info!(session_id = %lead.session_id, "abandoned inquiry handed to sales");
+    }
+}
+
+/// Tells marketing automation about the sessions among `expired` that
+/// ended mid-inquiry.
+pub fn notify_marketing(state: &AppState, expired: &[Session]) {
+    let Some(url) = &state.marketing_webhook_url else {
+        return;
+    };
+    for session in expired.iter().filter_map(AbandonedSession::from_session) {
+        state.outbox.enqueue(OutboxJob::new(
+            "session.abandoned",
+            url,
+            json!({ "session": session }),
+        ));
+    }
+}
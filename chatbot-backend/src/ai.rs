@@ -0,0 +1,568 @@
+//! Answers from a language model for what the built-in rules don't
+//! understand, with each tenant's own credentials and monthly token
+//! budget.
+//!
+//! A [`FallbackHandler`] calls the model. The server builds one at startup
+//! when `AI_API_URL` is set, as `AI_PROVIDER` says:
+//!
+//! - `chat_completions` (the default): [`ChatCompletions`], for any API
+//!   that speaks OpenAI's chat completions, at that endpoint;
+//! - `llama_server`: [`LlamaServer`], for a model run locally by
+//!   llama.cpp's `llama-server` listening there.
+//!
+//! Code using the crate can set its own before the server starts:
+//!
+//! ```ignore
+//! state.ai.set_handler(Arc::new(Assistant::new(client)));
+//! ```
+//!
+//! The handler is only asked about messages sent outside the inquiry flow
+//! that the rules would answer with "I didn't understand", and only for
+//! tenants set up through `/admin/tenants/{tenant}/ai`, with an API key
+//! unless the handler needs none.
+//! The tokens of every answer count against the tenant's
+//! `monthly_token_budget` for the calendar month (UTC). A tenant that
+//! reaches its budget gets rule-based replies only until the month ends,
+//! and `ai.budget_exceeded` is posted to the alert targets
+//! (`ALERT_WEBHOOK_URL`, `ALERT_SLACK_WEBHOOK_URL`).
+//!
+//! What the model is told to do is the [`prompts::FALLBACK`] prompt,
+//! which operators can change; see [`crate::prompts`].
+//!
+//! With `TENANT_AI_PATH` set the settings and the month's usage are kept in
+//! that file and survive restarts; without it they last until the server
+//! stops.
+
+use std::{
+    collections::BTreeMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use reqwest::{Client, RequestBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tracing::{info, warn};
+
+use crate::{
+    alerts::AlertTargets,
+    durable,
+    error::ApiError,
+    events::now_ms,
+    i18n::format_month,
+    plugins::{IntentReply, IntentRequest},
+    prompts::{self, Prompt, PromptStore},
+    session::MessageMetadata,
+    state::{AppState, SharedState},
+    tenant,
+};
+
+/// The intent of the fallback's answers in metrics, events and traces.
+pub const FALLBACK_INTENT: &str = "ai_fallback";
+
+/// What a tenant's calls to the model are made with.
+#[derive(Clone)]
+pub struct AiCredentials {
+    pub api_key: String,
+    /// The handler's own default when unset.
+    pub model: Option<String>,
+}
+
+impl fmt::Debug for AiCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AiCredentials")
+            .field("api_key", &"[redacted]")
+            .field("model", &self.model)
+            .finish()
+    }
+}
+
+/// A fallback's answer and the tokens it used.
+#[derive(Debug, Clone)]
+pub struct FallbackReply {
+    pub reply: IntentReply,
+    pub tokens: u64,
+}
+
+#[async_trait]
+pub trait FallbackHandler: Send + Sync {
+    /// Whether tenants need an API key of their own to be answered.
+    fn needs_key(&self) -> bool {
+        true
+    }
+
+    /// Answers `request` as `prompt` instructs, on the tenant's account;
+    /// `None` leaves the message to the rules.
+    async fn answer(
+        &self,
+        request: &IntentRequest,
+        prompt: &Prompt,
+        credentials: &AiCredentials,
+    ) -> Option<FallbackReply>;
+}
+
+/// A tenant's settings, as given to `PUT /admin/tenants/{tenant}/ai`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AiSettings {
+    /// Needed the first time; left out, the key already set is kept.
+    pub api_key: Option<String>,
+    pub model: Option<String>,
+    /// Tokens per calendar month; no limit when unset.
+    pub monthly_token_budget: Option<u64>,
+}
+
+/// A tenant's settings and usage as `/admin` shows them, without the key.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AiStatus {
+    pub model: Option<String>,
+    pub monthly_token_budget: Option<u64>,
+    /// The month `tokens_used` counts, `YYYY-MM`.
+    pub month: String,
+    pub tokens_used: u64,
+    pub over_budget: bool,
+}
+
+/// A tenant reaching its budget, as posted with `ai.budget_exceeded`.
+#[derive(Debug, Clone, Serialize)]
+pub struct BudgetExceeded {
+    pub tenant: String,
+    pub month: String,
+    pub tokens_used: u64,
+    pub monthly_token_budget: u64,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct TenantAi {
+    /// Empty for tenants set up for a handler that needs no key.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    api_key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    monthly_token_budget: Option<u64>,
+    /// The month `tokens_used` counts.
+    #[serde(default)]
+    month: String,
+    #[serde(default)]
+    tokens_used: u64,
+}
+
+impl TenantAi {
+    fn used_in(&self, month: &str) -> u64 {
+        if self.month == month {
+            self.tokens_used
+        } else {
+            0
+        }
+    }
+
+    fn over_budget(&self, month: &str) -> bool {
+        self.monthly_token_budget
+            .is_some_and(|budget| self.used_in(month) >= budget)
+    }
+
+    fn status(&self, month: String) -> AiStatus {
+        AiStatus {
+            model: self.model.clone(),
+            monthly_token_budget: self.monthly_token_budget,
+            tokens_used: self.used_in(&month),
+            over_budget: self.over_budget(&month),
+            month,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct AiFallback {
+    handler: Option<Arc<dyn FallbackHandler>>,
+    tenants: RwLock<BTreeMap<String, TenantAi>>,
+    /// Where the settings and usage are kept, when `TENANT_AI_PATH` is set.
+    path: Option<PathBuf>,
+    /// Held while the file is written, before `tenants`, so the last write
+    /// has the latest usage.
+    writing: Mutex<()>,
+    /// Where `ai.budget_exceeded` goes.
+    pub alerts: AlertTargets,
+    /// What the model is told; see [`crate::prompts`].
+    pub prompts: PromptStore,
+}
+
+impl AiFallback {
+    /// Loads the tenants kept at `path`; a missing file is none.
+    pub fn load(path: PathBuf) -> io::Result<Self> {
+        let tenants = match fs::read(&path) {
+            Ok(raw) => serde_json::from_slice(&raw).map_err(io::Error::other)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            tenants: RwLock::new(tenants),
+            path: Some(path),
+            ..Self::default()
+        })
+    }
+
+    /// Sets what calls the model; without one, every tenant gets
+    /// rule-based replies only.
+    pub fn set_handler(&mut self, handler: Arc<dyn FallbackHandler>) {
+        self.handler = Some(handler);
+    }
+
+    /// The handler and the tenant's credentials, unless the tenant is not
+    /// set up, has no key for a handler that needs one or has used up this
+    /// month's budget.
+    pub fn allowance(&self, tenant: &str) -> Option<(Arc<dyn FallbackHandler>, AiCredentials)> {
+        let handler = self.handler.clone()?;
+        let tenants = self.tenants.read().unwrap();
+        let settings = tenants.get(tenant)?;
+        if (handler.needs_key() && settings.api_key.is_empty())
+            || settings.over_budget(&format_month(now_ms()))
+        {
+            return None;
+        }
+        let credentials = AiCredentials {
+            api_key: settings.api_key.clone(),
+            model: settings.model.clone(),
+        };
+        Some((handler, credentials))
+    }
+
+    /// The fallback prompt in use, filled in for `request`.
+    pub fn prompt(&self, request: &IntentRequest) -> Prompt {
+        let metadata = request.metadata.as_ref();
+        let page = |field: fn(&MessageMetadata) -> &Option<String>| {
+            metadata
+                .and_then(|m| field(m).as_deref())
+                .unwrap_or_default()
+        };
+        self.prompts.render(
+            prompts::FALLBACK,
+            &[
+                ("language", request.language.name()),
+                ("tenant", &request.tenant),
+                ("page_title", page(|m| &m.page_title)),
+                ("page_url", page(|m| &m.page_url)),
+            ],
+        )
+    }
+
+    pub fn status(&self, tenant: &str) -> Option<AiStatus> {
+        let tenants = self.tenants.read().unwrap();
+        Some(tenants.get(tenant)?.status(format_month(now_ms())))
+    }
+
+    /// Sets up `tenant`, or changes its settings; this month's usage
+    /// stays. A key is needed the first time unless the handler needs
+    /// none.
+    pub fn configure(&self, tenant: &str, settings: AiSettings) -> Result<AiStatus, ApiError> {
+        let tenant = tenant::resolve(Some(tenant))?;
+        let api_key = settings.api_key.map(|key| key.trim().to_string());
+        if api_key.as_ref().is_some_and(String::is_empty) {
+            return Err(ApiError::BadRequest("api_key is empty".into()));
+        }
+        if settings.model.as_ref().is_some_and(|m| m.trim().is_empty()) {
+            return Err(ApiError::BadRequest("model is empty".into()));
+        }
+        self.update(|tenants| {
+            let entry = match (tenants.get_mut(tenant), api_key) {
+                (Some(entry), api_key) => {
+                    if let Some(api_key) = api_key {
+                        entry.api_key = api_key;
+                    }
+                    entry
+                }
+                (None, Some(api_key)) => tenants.entry(tenant.to_string()).or_insert(TenantAi {
+                    api_key,
+                    ..TenantAi::default()
+                }),
+                (None, None) if self.handler.as_ref().is_some_and(|h| !h.needs_key()) => {
+                    tenants.entry(tenant.to_string()).or_default()
+                }
+                (None, None) => {
+                    return Err(ApiError::BadRequest(format!(
+                        "tenant {tenant} has no api_key yet"
+                    )));
+                }
+            };
+            entry.model = settings.model;
+            entry.monthly_token_budget = settings.monthly_token_budget;
+            Ok(entry.status(format_month(now_ms())))
+        })
+    }
+
+    /// Forgets `tenant`'s key and usage; `None` if it had no key.
+    pub fn remove(&self, tenant: &str) -> Result<Option<AiStatus>, ApiError> {
+        self.update(|tenants| {
+            Ok(tenants
+                .remove(tenant)
+                .map(|settings| settings.status(format_month(now_ms()))))
+        })
+    }
+
+    /// Applies `change` and writes the tenants through. On a write error
+    /// nothing changes.
+    fn update<T>(
+        &self,
+        change: impl FnOnce(&mut BTreeMap<String, TenantAi>) -> Result<T, ApiError>,
+    ) -> Result<T, ApiError> {
+        let _writing = self.writing.lock().unwrap();
+        let mut tenants = self.tenants.write().unwrap();
+        let mut updated = tenants.clone();
+        let result = change(&mut updated)?;
+        if let Some(path) = &self.path
+            && let Err(e) = save(path, &updated)
+        {
+            warn!("cannot write tenant AI settings {}: {e}", path.display());
+            return Err(ApiError::Busy(
+                "the tenant AI settings could not be saved".into(),
+            ));
+        }
+        *tenants = updated;
+        Ok(result)
+    }
+
+    /// Counts `tokens` against `tenant`'s budget for the month of `at_ms`.
+    /// Returns the crossing when these tokens used the budget up.
+    pub fn record(&self, tenant: &str, tokens: u64, at_ms: u64) -> Option<BudgetExceeded> {
+        let month = format_month(at_ms);
+        let mut tenants = self.tenants.write().unwrap();
+        let settings = tenants.get_mut(tenant)?;
+        let before = settings.used_in(&month);
+        settings.month = month.clone();
+        settings.tokens_used = before.saturating_add(tokens);
+        let budget = settings.monthly_token_budget?;
+        (before < budget && settings.tokens_used >= budget).then(|| BudgetExceeded {
+            tenant: tenant.to_string(),
+            month,
+            tokens_used: settings.tokens_used,
+            monthly_token_budget: budget,
+        })
+    }
+
+    /// Writes the usage through, when the tenants are kept in a file.
+    pub fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let _writing = self.writing.lock().unwrap();
+        let tenants = self.tenants.read().unwrap().clone();
+        if let Err(e) = save(path, &tenants) {
+            warn!("cannot write tenant AI usage {}: {e}", path.display());
+        }
+    }
+}
+
+fn save(path: &Path, tenants: &BTreeMap<String, TenantAi>) -> io::Result<()> {
+    let json = serde_json::to_vec_pretty(tenants).map_err(io::Error::other)?;
+    durable::replace(path, &json)
+}
+
+/// The fallback's answer to `request` as `prompt` instructs, when its
+/// tenant has a key and budget left. The tokens are counted, and the file
+/// is written on a blocking thread so the reply doesn't wait for the disk.
+pub async fn answer(
+    state: &SharedState,
+    request: &IntentRequest,
+    prompt: &Prompt,
+) -> Option<IntentReply> {
+    let (handler, credentials) = state.ai.allowance(&request.tenant)?;
+    info!(
+        tenant = %request.tenant,
+        prompt = %prompt.name,
+        version = prompt.version,
+        "asking the AI fallback"
+    );
+    let answered = handler.answer(request, prompt, &credentials).await?;
+    let exceeded = state.ai.record(&request.tenant, answered.tokens, now_ms());
+    let saving = state.clone();
+    tokio::task::spawn_blocking(move || saving.ai.save());
+    if let Some(exceeded) = exceeded {
+        budget_exceeded(state, &exceeded);
+    }
+    Some(answered.reply)
+}
+
+fn budget_exceeded(state: &AppState, exceeded: &BudgetExceeded) {
+    let summary = format!(
+        "tenant {} used its AI budget for {}: {} of {} tokens; replies are rule-based until the month ends",
+        exceeded.tenant, exceeded.month, exceeded.tokens_used, exceeded.monthly_token_budget
+    );
+    warn!(tenant = %exceeded.tenant, "{summary}");
+    let data = serde_json::to_value(exceeded).expect("budget alerts serialize");
+    state
+        .ai
+        .alerts
+        .send(state, "ai.budget_exceeded", data, &summary);
+}
+
+/// The model [`ChatCompletions`] asks for tenants that name none, unless
+/// `AI_MODEL` says otherwise.
+pub const DEFAULT_MODEL: &str = "gpt-4o-mini";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+/// The longest answer asked of the model.
+const MAX_ANSWER_TOKENS: u32 = 300;
+
+/// A model behind an API that speaks OpenAI's chat completions, called
+/// with each tenant's key.
+pub struct ChatCompletions {
+    http: Client,
+    /// The endpoint, e.g. `https://api.openai.com/v1/chat/completions`.
+    url: String,
+    /// For tenants that don't name one.
+    model: String,
+}
+
+impl ChatCompletions {
+    pub fn new(http: Client, url: String, model: String) -> Self {
+        Self { http, url, model }
+    }
+}
+
+#[derive(Deserialize)]
+struct Completion {
+    choices: Vec<Choice>,
+    #[serde(default)]
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    message: ChoiceMessage,
+}
+
+#[derive(Deserialize)]
+struct ChoiceMessage {
+    #[serde(default)]
+    content: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Usage {
+    total_tokens: u64,
+}
+
+/// The chat completions body asking `model` to answer `request` as
+/// `prompt` instructs; the server's own model when `None`.
+fn completion_body(model: Option<&str>, request: &IntentRequest, prompt: &Prompt) -> Value {
+    let mut body = json!({
+        "max_tokens": MAX_ANSWER_TOKENS,
+        "messages": [
+            { "role": "system", "content": prompt.text },
+            { "role": "user", "content": request.message },
+        ],
+    });
+    if let Some(model) = model {
+        body["model"] = json!(model);
+    }
+    body
+}
+
+/// Sends `call` and reads the first choice of the completion it answers
+/// with; `None`, logged, when there is none.
+async fn complete(call: RequestBuilder, tenant: &str) -> Option<FallbackReply> {
+    let response = call.send().await.and_then(|r| r.error_for_status());
+    let completion: Completion = match response {
+        Ok(response) => match response.json().await {
+            Ok(completion) => completion,
+            Err(e) => {
+                warn!(%tenant, "unreadable AI answer: {e}");
+                return None;
+            }
+        },
+        Err(e) => {
+            warn!(%tenant, "AI fallback failed: {e}");
+            return None;
+        }
+    };
+    let tokens = completion.usage.map_or(0, |u| u.total_tokens);
+    let text = completion
+        .choices
+        .into_iter()
+        .next()
+        .and_then(|c| c.message.content)
+        .map(|text| text.trim().to_string())
+        .filter(|text| !text.is_empty());
+    let Some(text) = text else {
+        info!(%tenant, "AI fallback gave no answer");
+        return None;
+    };
+    Some(FallbackReply {
+        reply: IntentReply {
+            text,
+            quick_replies: Vec::new(),
+        },
+        tokens,
+    })
+}
+
+#[async_trait]
+impl FallbackHandler for ChatCompletions {
+    async fn answer(
+        &self,
+        request: &IntentRequest,
+        prompt: &Prompt,
+        credentials: &AiCredentials,
+    ) -> Option<FallbackReply> {
+        let model = credentials.model.as_deref().unwrap_or(&self.model);
+        let call = self
+            .http
+            .post(&self.url)
+            .bearer_auth(&credentials.api_key)
+            .timeout(REQUEST_TIMEOUT)
+            .json(&completion_body(Some(model), request, prompt));
+        complete(call, &request.tenant).await
+    }
+}
+
+/// Local models answer slower than hosted ones.
+const LOCAL_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A GGUF model served by llama.cpp's `llama-server` on the same network,
+/// for deployments that can't reach a hosted API. The server runs the
+/// model, so no native bindings are built into this one, and tenants need
+/// no key; a tenant's `model` is passed on for servers that host several.
+pub struct LlamaServer {
+    http: Client,
+    /// The server's chat completions endpoint.
+    url: String,
+}
+
+impl LlamaServer {
+    /// `base_url` is where `llama-server` listens, e.g.
+    /// `http://127.0.0.1:8080`.
+    pub fn new(http: Client, base_url: &str) -> Self {
+        Self {
+            http,
+            url: format!("{}/v1/chat/completions", base_url.trim_end_matches('/')),
+        }
+    }
+}
+
+#[async_trait]
+impl FallbackHandler for LlamaServer {
+    fn needs_key(&self) -> bool {
+        false
+    }
+
+    async fn answer(
+        &self,
+        request: &IntentRequest,
+        prompt: &Prompt,
+        credentials: &AiCredentials,
+    ) -> Option<FallbackReply> {
+        let call = self
+            .http
+            .post(&self.url)
+            .timeout(LOCAL_REQUEST_TIMEOUT)
+            .json(&completion_body(
+                credentials.model.as_deref(),
+                request,
+                prompt,
+            ));
+        complete(call, &request.tenant).await
+    }
+}
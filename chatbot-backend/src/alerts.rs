@@ -0,0 +1,230 @@
+//! Threshold alerts, posted to a webhook or Slack.
+//!
+//! Every [`CHECK_INTERVAL`] (`ALERT_CHECK_SECS`) the configured rules are evaluated over what
+//! happened since the last check. A rule that crosses its threshold posts
+//! `alert.firing`; once it is back under, `alert.resolved`. Rates are only
+//! judged on enough samples, so a single failure on a quiet night does not
+//! page anyone.
+//!
+//! The same targets are told when a tenant runs out of its AI token
+//! budget; see [`crate::ai`].
+
+use std::time::Duration;
+
+use serde::Serialize;
+use serde_json::{Value, json};
+use tracing::{info, warn};
+
+use crate::{
+    handoff,
+    outbox::OutboxJob,
+    state::{AppState, SharedState},
+};
+
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// Fewest webhook deliveries in a check to judge their failure rate.
+const MIN_DELIVERIES: u64 = 5;
+/// Fewest visitor messages in a check to judge the unknown-intent rate.
+const MIN_MESSAGES: u64 = 20;
+
+/// Thresholds; rules without one are not checked.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AlertRules {
+    /// Share of webhook delivery attempts that failed, 0 to 1.
+    pub delivery_failure_rate: Option<f64>,
+    /// Share of visitor messages the bot did not understand, 0 to 1.
+    pub unknown_rate: Option<f64>,
+    /// Visitors waiting for a person.
+    pub handoff_queue: Option<usize>,
+}
+
+impl AlertRules {
+    pub fn is_empty(&self) -> bool {
+        self.delivery_failure_rate.is_none()
+            && self.unknown_rate.is_none()
+            && self.handoff_queue.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rule {
+    DeliveryFailureRate,
+    UnknownRate,
+    HandoffQueue,
+}
+
+impl Rule {
+    fn describe(self) -> &'static str {
+        match self {
+            Rule::DeliveryFailureRate => "webhook delivery failure rate",
+            Rule::UnknownRate => "share of messages the bot did not understand",
+            Rule::HandoffQueue => "visitors waiting for a person",
+        }
+    }
+
+    fn format(self, value: f64) -> String {
+        match self {
+            Rule::HandoffQueue => format!("{value:.0}"),
+            Rule::DeliveryFailureRate | Rule::UnknownRate => format!("{:.0}%", value * 100.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub rule: Rule,
+    pub firing: bool,
+    pub value: f64,
+    pub threshold: f64,
+}
+
+impl Alert {
+    fn summary(&self) -> String {
+        let status = if self.firing { "FIRING" } else { "resolved" };
+        format!(
+            "[{status}] {}: {} (threshold {})",
+            self.rule.describe(),
+            self.rule.format(self.value),
+            self.rule.format(self.threshold)
+        )
+    }
+}
+
+/// Where alerts go. A Slack incoming webhook gets a message; any other
+/// URL gets the alert in the standard webhook envelope.
+#[derive(Debug, Clone, Default)]
+pub struct AlertTargets {
+    pub webhook_url: Option<String>,
+    pub slack_url: Option<String>,
+}
+
+impl AlertTargets {
+    pub fn is_empty(&self) -> bool {
+        self.webhook_url.is_none() && self.slack_url.is_none()
+    }
+
+    /// Queues `event` for every target: `data` for the webhook, `summary`
+    /// as the Slack message.
+    pub fn send(&self, state: &AppState, event: &str, data: Value, summary: &str) {
+        if let Some(url) = &self.webhook_url {
+            state.outbox.enqueue(OutboxJob::new(event, url, data));
+        }
+        if let Some(url) = &self.slack_url {
+            let payload = json!({ "text": summary });
+            state.outbox.enqueue(OutboxJob::raw(event, url, payload));
+        }
+    }
+}
+
+/// Counter readings at the last check.
+#[derive(Debug, Default)]
+struct Readings {
+    delivered: u64,
+    failed: u64,
+    messages: u64,
+    unknown: u64,
+}
+
+impl Readings {
+    fn take(state: &AppState) -> Self {
+        let (delivered, failed) = state.outbox.attempts();
+        let metrics = state.metrics.snapshot();
+        Self {
+            delivered,
+            failed,
+            messages: metrics.messages_total,
+            unknown: metrics.intents.get("unknown").copied().unwrap_or(0),
+        }
+    }
+}
+
+pub struct Alerter {
+    rules: AlertRules,
+    targets: AlertTargets,
+    last: Readings,
+    firing: Vec<Rule>,
+}
+
+impl Alerter {
+    pub fn new(rules: AlertRules, targets: AlertTargets) -> Self {
+        Self {
+            rules,
+            targets,
+            last: Readings::default(),
+            firing: Vec::new(),
+        }
+    }
+
+    /// Evaluates every rule and returns the alerts whose state changed.
+    pub fn check(&mut self, state: &AppState) -> Vec<Alert> {
+        let now = Readings::take(state);
+        let mut changed = Vec::new();
+        let attempts = (now.delivered + now.failed) - (self.last.delivered + self.last.failed);
+        if let Some(threshold) = self.rules.delivery_failure_rate
+            && attempts >= MIN_DELIVERIES
+        {
+            let rate = (now.failed - self.last.failed) as f64 / attempts as f64;
+            changed.extend(self.judge(Rule::DeliveryFailureRate, rate, threshold));
+        }
+        let messages = now.messages - self.last.messages;
+        if let Some(threshold) = self.rules.unknown_rate
+            && messages >= MIN_MESSAGES
+        {
+            let rate = (now.unknown - self.last.unknown) as f64 / messages as f64;
+            changed.extend(self.judge(Rule::UnknownRate, rate, threshold));
+        }
+        if let Some(threshold) = self.rules.handoff_queue {
+            let waiting = handoff::waiting(state);
+            changed.extend(self.judge(Rule::HandoffQueue, waiting as f64, threshold as f64));
+        }
+        self.last = now;
+        changed
+    }
+
+    fn judge(&mut self, rule: Rule, value: f64, threshold: f64) -> Option<Alert> {
+        let firing = value > threshold;
+        let was_firing = self.firing.contains(&rule);
+        if firing == was_firing {
+            return None;
+        }
+        if firing {
+            self.firing.push(rule);
+        } else {
+            self.firing.retain(|r| *r != rule);
+        }
+        Some(Alert {
+            rule,
+            firing,
+            value,
+            threshold,
+        })
+    }
+
+    /// Queues `alert` for every target.
+    pub fn notify(&self, state: &AppState, alert: &Alert) {
+        let event = if alert.firing {
+            "alert.firing"
+        } else {
+            "alert.resolved"
+        };
+        if alert.firing {
+            warn!(rule = ?alert.rule, value = alert.value, "{}", alert.summary());
+        } else {
+            info!(rule = ?alert.rule, value = alert.value, "{}", alert.summary());
+        }
+        let data = serde_json::to_value(alert).expect("alerts serialize");
+        self.targets.send(state, event, data, &alert.summary());
+    }
+}
+
+/// Checks the rules every `interval` and sends what changed.
+pub async fn run(state: SharedState, mut alerter: Alerter, interval: Duration) {
+    let mut ticks = tokio::time::interval(interval);
+    loop {
+        ticks.tick().await;
+        for alert in alerter.check(&state) {
+            alerter.notify(&state, &alert);
+        }
+    }
+}
@@ -0,0 +1,129 @@
+//! Questions the bot could not answer, and the words visitors use.
+//!
+//! Every message that ends up with the `unknown` intent is counted under
+//! its normalized text, so the most frequent gaps in intents and answers
+//! float to the top of `/admin/analytics/unanswered`. That is after the AI
+//! fallback (see [`crate::ai`]): what the model answered is not logged,
+//! but what it failed to answer, or wasn't asked for lack of a key or
+//! budget, is. Words of messages outside the inquiry flow are counted for
+//! the weekly report.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use serde::Serialize;
+
+use crate::{events::now_ms, i18n::Language, text::normalize};
+
+/// Distinct questions kept; when full, the least asked one is dropped.
+const MAX_ENTRIES: usize = 5_000;
+/// Distinct words kept; when full, the rarest one is dropped.
+const MAX_KEYWORDS: usize = 10_000;
+/// Shorter words are mostly articles, pronouns and the like.
+const MIN_KEYWORD_CHARS: usize = 4;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UnansweredQuestion {
+    /// The wording of the first occurrence.
+    pub example: String,
+    pub language: Language,
+    pub count: u64,
+    pub first_seen_ms: u64,
+    pub last_seen_ms: u64,
+}
+
+#[derive(Default)]
+pub struct UnansweredLog {
+    entries: Mutex<HashMap<String, UnansweredQuestion>>,
+}
+
+impl UnansweredLog {
+    pub fn record(&self, message: &str, language: Language) {
+        let key = normalize(message);
+        if key.is_empty() {
+            return;
+        }
+        let now = now_ms();
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.get_mut(&key) {
+            entry.count += 1;
+            entry.last_seen_ms = now;
+            return;
+        }
+        if entries.len() >= MAX_ENTRIES
+            && let Some(rarest) = entries
+                .iter()
+                .min_by_key(|(_, e)| (e.count, e.last_seen_ms))
+                .map(|(k, _)| k.clone())
+        {
+            entries.remove(&rarest);
+        }
+        entries.insert(
+            key,
+            UnansweredQuestion {
+                example: message.to_string(),
+                language,
+                count: 1,
+                first_seen_ms: now,
+                last_seen_ms: now,
+            },
+        );
+    }
+
+    /// The most asked questions first, optionally in one language only.
+    pub fn top(&self, language: Option<Language>, limit: usize) -> Vec<UnansweredQuestion> {
+        let entries = self.entries.lock().unwrap();
+        let mut top: Vec<UnansweredQuestion> = entries
+            .values()
+            .filter(|e| language.is_none_or(|l| e.language == l))
+            .cloned()
+            .collect();
+        top.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then(b.last_seen_ms.cmp(&a.last_seen_ms))
+        });
+        top.truncate(limit);
+        top
+    }
+}
+
+/// How often words come up in visitor messages, since the counts were
+/// last taken.
+#[derive(Default)]
+pub struct KeywordCounts {
+    counts: Mutex<HashMap<String, u64>>,
+}
+
+impl KeywordCounts {
+    pub fn record(&self, message: &str) {
+        let mut counts = self.counts.lock().unwrap();
+        for word in normalize(message).split(' ') {
+            if word.chars().count() < MIN_KEYWORD_CHARS || word.chars().all(|c| c.is_numeric()) {
+                continue;
+            }
+            if let Some(count) = counts.get_mut(word) {
+                *count += 1;
+                continue;
+            }
+            if counts.len() >= MAX_KEYWORDS
+                && let Some(rarest) = counts
+                    .iter()
+                    .min_by_key(|(_, n)| **n)
+                    .map(|(k, _)| k.clone())
+            {
+                counts.remove(&rarest);
+            }
+            counts.insert(word.to_string(), 1);
+        }
+    }
+
+    /// The `limit` most used words, most used first, and starts counting
+    /// afresh.
+    pub fn take_top(&self, limit: usize) -> Vec<(String, u64)> {
+        let counts = std::mem::take(&mut *self.counts.lock().unwrap());
+        let mut top: Vec<(String, u64)> = counts.into_iter().collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top.truncate(limit);
+        top
+    }
+}
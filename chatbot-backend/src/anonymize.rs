@@ -0,0 +1,199 @@
+//! Taking personal data out of conversation events before they are kept
+//! for good: in the event log (`EVENT_LOG_ANONYMIZATION`) or a warehouse
+//! sink (`EXPORT_DIR_ANONYMIZATION`, `EXPORT_URL_ANONYMIZATION`).
+//!
+//! Each takes one of these modes:
+//!
+//! - `none`: events as they are.
+//! - `strip`: session ids hashed; what visitors and operators wrote
+//!   reduced to its length, captured answers to whether they were given,
+//!   email addresses, report paths and page metadata dropped.
+//! - `hash`: as `strip`, but names and email addresses stay as salted
+//!   hashes, so returning visitors can be counted without being known.
+//! - `keywords`: as `strip`, but free text keeps the words the bot's
+//!   intents are made of, so what visitors asked about can be analysed.
+//!
+//! Hashes are salted with `EXPORT_SALT`; with the same salt, the same
+//! session or address hashes alike in every sink and every export.
+
+use std::{fmt, str::FromStr};
+
+use serde_json::{Map, Value, json};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    chatbot,
+    events::{ConversationEvent, StoredEvent},
+    session::Field,
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Anonymization {
+    None,
+    #[default]
+    Strip,
+    Hash,
+    Keywords,
+}
+
+impl FromStr for Anonymization {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "strip" => Ok(Self::Strip),
+            "hash" => Ok(Self::Hash),
+            "keywords" => Ok(Self::Keywords),
+            other => Err(format!(
+                "unknown anonymization {other:?}; use none, strip, hash or keywords"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Anonymization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::None => "none",
+            Self::Strip => "strip",
+            Self::Hash => "hash",
+            Self::Keywords => "keywords",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Anonymizer {
+    pub mode: Anonymization,
+    pub salt: String,
+}
+
+impl Anonymizer {
+    /// A stable stand-in for `value`, the same wherever the salt is.
+    pub fn hash(&self, value: &str) -> String {
+        let digest = Sha256::new()
+            .chain_update(&self.salt)
+            .chain_update(value)
+            .finalize();
+        hex::encode(&digest[..12])
+    }
+
+    /// The session id as it may be kept.
+    pub fn session(&self, session_id: &str) -> String {
+        match self.mode {
+            Anonymization::None => session_id.to_string(),
+            _ => self.hash(session_id),
+        }
+    }
+
+    /// `stored` as it may be kept, in the shape of a [`StoredEvent`].
+    pub fn record(&self, stored: &StoredEvent) -> Value {
+        let mut record = Map::new();
+        record.insert("seq".into(), json!(stored.seq));
+        record.insert("session_id".into(), json!(self.session(&stored.session_id)));
+        record.insert("at_ms".into(), json!(stored.at_ms));
+        if let Value::Object(event) = self.event(&stored.event) {
+            record.extend(event);
+        }
+        Value::Object(record)
+    }
+
+    /// The fields of `event` that may be kept, with its `type`.
+    pub fn event(&self, event: &ConversationEvent) -> Value {
+        use ConversationEvent as E;
+        let value = serde_json::to_value(event).expect("events serialize");
+        let Value::Object(mut fields) = value else {
+            return value;
+        };
+        if self.mode == Anonymization::None {
+            return Value::Object(fields);
+        }
+        let kind = fields.remove("type").unwrap_or_default();
+        match event {
+            E::SessionStarted { .. }
+            | E::StateChanged { .. }
+            | E::FieldCleared { .. }
+            | E::LeadTagged { .. }
+            | E::LeadLabelsChanged { .. }
+            | E::SatisfactionRated { .. } => {}
+            E::MessageReceived { text, .. }
+            | E::AgentMessage { text, .. }
+            | E::MessageEdited { text, .. } => fields = self.free_text(text),
+            E::ReplySent { text, intent, .. } => {
+                fields = self.free_text(text);
+                fields.insert("intent".into(), json!(intent));
+            }
+            E::FieldCaptured { field, value } => {
+                fields = Map::new();
+                fields.insert("field".into(), json!(field));
+                fields.insert("given".into(), json!(value.is_some()));
+                if let Some(value) = value {
+                    fields.extend(self.answer(*field, value));
+                }
+            }
+            E::TranscriptEmailQueued { to } => {
+                fields = Map::new();
+                if self.mode == Anonymization::Hash {
+                    fields.insert("to".into(), json!(self.hash(&to.to_lowercase())));
+                }
+            }
+            E::Transferred { provider, .. } => {
+                fields = Map::new();
+                fields.insert("provider".into(), json!(provider));
+            }
+            E::SessionMerged { from } => {
+                fields = Map::new();
+                fields.insert("from".into(), json!(self.hash(from)));
+            }
+            E::ReceiptRecorded { status, .. } => {
+                fields = Map::new();
+                fields.insert("status".into(), json!(status));
+            }
+            E::InquiryConfirmed
+            | E::ReportGenerated { .. }
+            | E::EscalationRequested
+            | E::CallbackRequested
+            | E::AgentJoined
+            | E::AgentLeft
+            | E::InactivityWarning { .. }
+            | E::SurveyAsked { .. }
+            | E::MessageDeleted { .. }
+            | E::SessionDeleted
+            | E::SessionRestored
+            | E::SessionClosed => fields = Map::new(),
+            E::ReplySuggested { intent, source, .. } => {
+                fields = Map::new();
+                fields.insert("intent".into(), json!(intent));
+                fields.insert("source".into(), json!(source));
+            }
+        }
+        fields.insert("type".into(), kind);
+        Value::Object(fields)
+    }
+
+    /// A message's length and, in `keywords` mode, its keywords.
+    fn free_text(&self, text: &str) -> Map<String, Value> {
+        let mut fields = Map::new();
+        fields.insert("length".into(), json!(text.chars().count()));
+        if self.mode == Anonymization::Keywords {
+            fields.insert("keywords".into(), json!(chatbot::keywords_in(text)));
+        }
+        fields
+    }
+
+    /// What may be kept of an answer to the inquiry's `field`.
+    fn answer(&self, field: Field, value: &str) -> Map<String, Value> {
+        let mut fields = Map::new();
+        match (self.mode, field) {
+            (Anonymization::Hash, Field::Name | Field::Email) => {
+                fields.insert("value".into(), json!(self.hash(&value.to_lowercase())));
+            }
+            (Anonymization::Keywords, Field::ProjectDetails) => {
+                fields.insert("keywords".into(), json!(chatbot::keywords_in(value)));
+            }
+            _ => {}
+        }
+        fields
+    }
+}
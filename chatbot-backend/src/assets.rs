@@ -0,0 +1,156 @@
+//! The chat widget and anything else under `static/`, served for paths no
+//! API route matches.
+//!
+//! Files are read from the `static/` directory or, with
+//! `STATIC_ASSETS=embedded`, from copies built into the binary, so a
+//! deployment is a single file. Responses are compressed (brotli or gzip)
+//! when the client accepts it and carry an `ETag`; a request whose
+//! `If-None-Match` still matches gets an empty `304`. HTML pages are
+//! revalidated on every load, so a new widget reaches visitors at once;
+//! other files are cached for an hour.
+//!
+//! With `SPA_FALLBACK=1`, paths that name no file get `index.html`, for
+//! a single-page app doing its own routing.
+
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    path::PathBuf,
+};
+
+use axum::{
+    Router,
+    body::Body,
+    extract::Request,
+    http::{
+        HeaderMap, HeaderValue, Method, StatusCode, Uri,
+        header::{CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_NONE_MATCH, LAST_MODIFIED},
+    },
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+};
+use rust_embed::RustEmbed;
+use tower_http::{
+    compression::CompressionLayer,
+    services::{ServeDir, ServeFile},
+};
+
+const CACHE_HTML: &str = "no-cache";
+const CACHE_OTHER: &str = "public, max-age=3600";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssetSource {
+    Directory(PathBuf),
+    /// The files `static/` held when the binary was built.
+    Embedded,
+}
+
+impl Default for AssetSource {
+    fn default() -> Self {
+        Self::Directory("static".into())
+    }
+}
+
+#[derive(RustEmbed)]
+#[folder = "static/"]
+struct EmbeddedAssets;
+
+/// The service behind the router's fallback.
+pub fn router(source: &AssetSource, spa_fallback: bool) -> Router {
+    let router = match source {
+        AssetSource::Directory(dir) if spa_fallback => Router::new()
+            .fallback_service(ServeDir::new(dir).fallback(ServeFile::new(dir.join("index.html")))),
+        AssetSource::Directory(dir) => Router::new().fallback_service(ServeDir::new(dir)),
+        AssetSource::Embedded => {
+            Router::new().fallback(move |method, uri| embedded(method, uri, spa_fallback))
+        }
+    };
+    router
+        .layer(middleware::from_fn(cache_headers))
+        .layer(CompressionLayer::new())
+}
+
+async fn embedded(method: Method, uri: Uri, spa_fallback: bool) -> Response {
+    if method != Method::GET && method != Method::HEAD {
+        return StatusCode::METHOD_NOT_ALLOWED.into_response();
+    }
+    let mut path = uri.path().trim_start_matches('/').to_string();
+    if path.is_empty() || path.ends_with('/') {
+        path.push_str("index.html");
+    }
+    let found = EmbeddedAssets::get(&path);
+    let found = match found {
+        None if spa_fallback => EmbeddedAssets::get("index.html"),
+        found => found,
+    };
+    let Some(file) = found else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let etag = format!("\"{}\"", hex::encode(&file.metadata.sha256_hash()[..16]));
+    (
+        [
+            (CONTENT_TYPE, file.metadata.mimetype().to_string()),
+            (ETAG, etag),
+        ],
+        file.data,
+    )
+        .into_response()
+}
+
+/// A weak tag for files served from disk, which come without one: their
+/// size and modification time.
+fn file_etag(headers: &HeaderMap) -> Option<HeaderValue> {
+    let modified = headers.get(LAST_MODIFIED)?;
+    let mut hasher = DefaultHasher::new();
+    modified.as_bytes().hash(&mut hasher);
+    headers.get(CONTENT_LENGTH)?.as_bytes().hash(&mut hasher);
+    HeaderValue::from_str(&format!("W/\"{:016x}\"", hasher.finish())).ok()
+}
+
+/// Whether an `If-None-Match` list names `etag`, comparing weakly.
+fn matches(if_none_match: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+async fn cache_headers(req: Request, next: Next) -> Response {
+    let if_none_match = req
+        .headers()
+        .get(IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let mut response = next.run(req).await;
+    if !response.status().is_success() {
+        return response;
+    }
+
+    let headers = response.headers_mut();
+    let etag = headers.get(ETAG).cloned().or_else(|| file_etag(headers));
+    let html = headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|t| t.starts_with("text/html"));
+    let cache_control = HeaderValue::from_static(if html { CACHE_HTML } else { CACHE_OTHER });
+    headers.insert(CACHE_CONTROL, cache_control.clone());
+    let Some(etag) = etag else {
+        return response;
+    };
+    headers.insert(ETAG, etag.clone());
+
+    if let Some(if_none_match) = if_none_match
+        && etag
+            .to_str()
+            .is_ok_and(|etag| matches(&if_none_match, etag))
+    {
+        let mut not_modified = Response::new(Body::empty());
+        *not_modified.status_mut() = StatusCode::NOT_MODIFIED;
+        not_modified.headers_mut().insert(ETAG, etag);
+        not_modified
+            .headers_mut()
+            .insert(CACHE_CONTROL, cache_control);
+        return not_modified;
+    }
+    response
+}
@@ -0,0 +1,60 @@
+//! Scores intent detection on a labeled dataset; see `chatbot_backend::eval`.
+//!
+//! Usage: `eval-intents <dataset.jsonl> [--json] [--min-accuracy 0.9]`.
+//! Exits with status 1 when accuracy falls below `--min-accuracy`, so it can
+//! gate CI.
+
+use std::process::ExitCode;
+
+use chatbot_backend::eval::{evaluate, load_dataset};
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let mut dataset = None;
+    let mut json = false;
+    let mut min_accuracy = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--min-accuracy" => match args.next().and_then(|v| v.parse::<f64>().ok()) {
+                Some(v) => min_accuracy = Some(v),
+                None => return usage(),
+            },
+            _ if dataset.is_none() && !arg.starts_with("--") => dataset = Some(arg),
+            _ => return usage(),
+        }
+    }
+    let Some(dataset) = dataset else {
+        return usage();
+    };
+
+    let samples = match load_dataset(&dataset) {
+        Ok(samples) => samples,
+        Err(e) => {
+            eprintln!("{dataset}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let report = evaluate(&samples);
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).expect("report serializes")
+        );
+    } else {
+        print!("{report}");
+    }
+
+    match min_accuracy {
+        Some(min) if report.accuracy() < min => {
+            eprintln!("accuracy {:.3} is below {min}", report.accuracy());
+            ExitCode::FAILURE
+        }
+        _ => ExitCode::SUCCESS,
+    }
+}
+
+fn usage() -> ExitCode {
+    eprintln!("usage: eval-intents <dataset.jsonl> [--json] [--min-accuracy <0..1>]");
+    ExitCode::from(2)
+}
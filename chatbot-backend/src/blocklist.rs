@@ -0,0 +1,270 @@
+//! Visitors kept out of the chat, managed through `/admin/blocklist`.
+//!
+//! Entries are client addresses, visitor (session) ids and email domains;
+//! a domain also covers its subdomains. Blocked requests are either
+//! dropped without a word or refused politely, in the visitor's language.
+//!
+//! The widget routes are guarded by `routes::blocklist`; messages from the
+//! partner API and the chat channels are checked in
+//! [`crate::channels::Conversations::converse`]. Address entries only apply
+//! to the widget, since channel messages reach the server from the
+//! platform rather than the visitor.
+//!
+//! With `BLOCKLIST_PATH` set the entries are kept in that file and survive
+//! restarts; without it they last until the server stops.
+
+use std::{
+    collections::BTreeSet,
+    fs, io,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    durable,
+    error::ApiError,
+    i18n::{Language, Text, text},
+    state::AppState,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockKind {
+    Ip,
+    Visitor,
+    EmailDomain,
+}
+
+impl BlockKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BlockKind::Ip => "ip",
+            BlockKind::Visitor => "visitor",
+            BlockKind::EmailDomain => "email_domain",
+        }
+    }
+}
+
+/// What a blocked visitor gets.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockResponse {
+    /// An empty `204`, so nothing tells them they are blocked.
+    Drop,
+    /// A `403` saying the conversation can't continue.
+    #[default]
+    Refuse,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BlocklistEntries {
+    #[serde(default)]
+    pub ips: BTreeSet<IpAddr>,
+    #[serde(default)]
+    pub visitors: BTreeSet<String>,
+    #[serde(default)]
+    pub email_domains: BTreeSet<String>,
+    /// Set by `BLOCKLIST_RESPONSE`, not kept in the file.
+    #[serde(default, skip_deserializing)]
+    pub response: BlockResponse,
+}
+
+#[derive(Default)]
+pub struct Blocklist {
+    entries: RwLock<BlocklistEntries>,
+    /// Where the entries are kept, when `BLOCKLIST_PATH` is set.
+    path: Option<PathBuf>,
+}
+
+/// The domain part of `email`, lowercased.
+fn email_domain(email: &str) -> Option<String> {
+    let (_, domain) = email.rsplit_once('@')?;
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    (!domain.is_empty()).then_some(domain)
+}
+
+impl Blocklist {
+    /// Loads the entries kept at `path`; a missing file is an empty list.
+    pub fn load(path: PathBuf) -> io::Result<Self> {
+        let entries = match fs::read(&path) {
+            Ok(raw) => serde_json::from_slice(&raw).map_err(io::Error::other)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BlocklistEntries::default(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            entries: RwLock::new(entries),
+            path: Some(path),
+        })
+    }
+
+    pub fn entries(&self) -> BlocklistEntries {
+        self.entries.read().unwrap().clone()
+    }
+
+    pub fn response(&self) -> BlockResponse {
+        self.entries.read().unwrap().response
+    }
+
+    pub fn set_response(&self, response: BlockResponse) {
+        self.entries.write().unwrap().response = response;
+    }
+
+    /// Adds an entry; `false` if it was already there.
+    pub fn add(&self, kind: BlockKind, value: &str) -> Result<bool, ApiError> {
+        self.update(|entries| {
+            Ok(match kind {
+                BlockKind::Ip => entries.ips.insert(parse_ip(value)?),
+                BlockKind::Visitor => entries.visitors.insert(non_empty(value)?.to_string()),
+                BlockKind::EmailDomain => entries.email_domains.insert(parse_domain(value)?),
+            })
+        })
+    }
+
+    /// Removes an entry; `false` if there was none.
+    pub fn remove(&self, kind: BlockKind, value: &str) -> Result<bool, ApiError> {
+        self.update(|entries| {
+            Ok(match kind {
+                BlockKind::Ip => entries.ips.remove(&parse_ip(value)?),
+                BlockKind::Visitor => entries.visitors.remove(non_empty(value)?),
+                BlockKind::EmailDomain => entries.email_domains.remove(&parse_domain(value)?),
+            })
+        })
+    }
+
+    /// Applies `change` and writes the entries through when it changed
+    /// anything. On a write error nothing changes.
+    fn update(
+        &self,
+        change: impl FnOnce(&mut BlocklistEntries) -> Result<bool, ApiError>,
+    ) -> Result<bool, ApiError> {
+        let mut entries = self.entries.write().unwrap();
+        let mut updated = entries.clone();
+        if !change(&mut updated)? {
+            return Ok(false);
+        }
+        if let Some(path) = &self.path
+            && let Err(e) = save(path, &updated)
+        {
+            warn!("cannot write blocklist {}: {e}", path.display());
+            return Err(ApiError::Busy("the blocklist could not be saved".into()));
+        }
+        *entries = updated;
+        Ok(true)
+    }
+
+    pub fn blocks_ip(&self, ip: IpAddr) -> bool {
+        self.entries.read().unwrap().ips.contains(&ip)
+    }
+
+    pub fn blocks_visitor(&self, id: &str) -> bool {
+        self.entries.read().unwrap().visitors.contains(id)
+    }
+
+    pub fn blocks_email(&self, email: &str) -> bool {
+        let Some(domain) = email_domain(email) else {
+            return false;
+        };
+        let entries = self.entries.read().unwrap();
+        let mut suffix = domain.as_str();
+        loop {
+            if entries.email_domains.contains(suffix) {
+                return true;
+            }
+            match suffix.split_once('.') {
+                Some((_, parent)) => suffix = parent,
+                None => return false,
+            }
+        }
+    }
+
+    /// Whether checking a request needs more than its address.
+    pub fn has_visitor_entries(&self) -> bool {
+        let entries = self.entries.read().unwrap();
+        !entries.visitors.is_empty() || !entries.email_domains.is_empty()
+    }
+
+    /// The entry that keeps out a message in session `session_id` from a
+    /// sender known by `emails`, if any.
+    pub fn blocked_by<'a>(
+        &self,
+        session_id: Option<&str>,
+        mut emails: impl Iterator<Item = &'a str>,
+    ) -> Option<BlockKind> {
+        if session_id.is_some_and(|id| self.blocks_visitor(id)) {
+            return Some(BlockKind::Visitor);
+        }
+        emails
+            .any(|email| self.blocks_email(email))
+            .then_some(BlockKind::EmailDomain)
+    }
+}
+
+/// Counts and logs a blocked message and answers it as
+/// `BLOCKLIST_RESPONSE` says, in `language`.
+pub fn refuse(
+    state: &AppState,
+    kind: BlockKind,
+    tenant: &str,
+    language: Language,
+    client_ip: Option<IpAddr>,
+    session_id: Option<&str>,
+) -> ApiError {
+    state.metrics.record_blocked(tenant, kind.as_str());
+    info!(
+        client_ip = ?client_ip,
+        session_id = ?session_id,
+        reason = kind.as_str(),
+        "request blocked"
+    );
+    match state.blocklist.response() {
+        BlockResponse::Drop => ApiError::Blocked(None),
+        BlockResponse::Refuse => ApiError::Blocked(Some(text(language, Text::Blocked).to_string())),
+    }
+}
+
+/// Saved without the response, which comes from the environment.
+fn save(path: &Path, entries: &BlocklistEntries) -> io::Result<()> {
+    let json = serde_json::to_vec_pretty(&serde_json::json!({
+        "ips": entries.ips,
+        "visitors": entries.visitors,
+        "email_domains": entries.email_domains,
+    }))
+    .map_err(io::Error::other)?;
+    durable::replace(path, &json)
+}
+
+fn non_empty(value: &str) -> Result<&str, ApiError> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Err(ApiError::BadRequest(
+            "blocklist entry must not be empty".into(),
+        ));
+    }
+    Ok(value)
+}
+
+fn parse_ip(value: &str) -> Result<IpAddr, ApiError> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| ApiError::BadRequest(format!("{value:?} is not an IP address")))
+}
+
+fn parse_domain(value: &str) -> Result<String, ApiError> {
+    let domain = non_empty(value)?
+        .trim_start_matches('@')
+        .trim_end_matches('.')
+        .to_ascii_lowercase();
+    let valid = !domain.is_empty()
+        && domain.split('.').all(|label| {
+            !label.is_empty() && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+        });
+    if !valid {
+        return Err(ApiError::BadRequest(format!("{value:?} is not a domain")));
+    }
+    Ok(domain)
+}
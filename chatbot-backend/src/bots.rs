@@ -0,0 +1,131 @@
+//! Distinct bots on one instance, each with a flow of its own.
+//!
+//! With `BOTS_DIR` set, every subdirectory is a bot named after it and
+//! served under `/bots/{bot_id}/chat`. Its `catalog.json` is its services
+//! catalog, and its `config.json`, if there is one, its runtime config
+//! (see [`crate::config`]): prices, the qualification threshold, page
+//! hints, the pipeline and the webhook URL, reloaded whenever the file
+//! changes. Sessions, the event log and the outbox are shared.
+//!
+//! The bot id is the tenant of its sessions (see [`crate::tenant`]), so
+//! metrics break down by bot, and its replies and widget theme are set
+//! through `/admin/tenants/{bot_id}`. Reply texts in a bot's config are
+//! refused, as they would change every bot's.
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use tracing::{info, warn};
+
+use crate::{
+    catalog::ServicesCatalog,
+    config::{ConfigError, LiveConfig},
+    state::SharedState,
+    tenant::{self, DEFAULT_TENANT},
+};
+
+pub struct Bot {
+    pub config: LiveConfig,
+    /// Its `config.json`, which may not exist yet.
+    config_path: PathBuf,
+}
+
+#[derive(Default)]
+pub struct Bots {
+    bots: BTreeMap<String, Bot>,
+}
+
+impl Bots {
+    /// Loads every bot under `dir`, each starting from `base`'s steps and
+    /// default webhook URL.
+    pub fn load(dir: &Path, base: &LiveConfig) -> Result<Self, String> {
+        let entries = fs::read_dir(dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+        let mut bots = BTreeMap::new();
+        for entry in entries {
+            let entry = entry.map_err(|e| format!("{}: {e}", dir.display()))?;
+            if !entry.file_type().is_ok_and(|t| t.is_dir()) {
+                continue;
+            }
+            let path = entry.path();
+            let id = entry.file_name().to_string_lossy().into_owned();
+            if tenant::resolve(Some(&id)).is_err() || id == DEFAULT_TENANT {
+                return Err(format!(
+                    "{}: bot ids are letters, digits, '-' or '_', and not {DEFAULT_TENANT:?}",
+                    path.display()
+                ));
+            }
+            let catalog_path = path.join("catalog.json");
+            let catalog = ServicesCatalog::from_file(&catalog_path)
+                .map_err(|e| format!("{}: {e}", catalog_path.display()))?;
+            catalog.prepare();
+            let config_path = path.join("config.json");
+            let config = base.for_bot(catalog);
+            if config_path.exists() {
+                config
+                    .reload(&config_path)
+                    .map_err(|e| format!("{}: {e}", config_path.display()))?;
+            }
+            bots.insert(
+                id,
+                Bot {
+                    config,
+                    config_path,
+                },
+            );
+        }
+        Ok(Self { bots })
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Bot> {
+        self.bots.get(id)
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = &str> {
+        self.bots.keys().map(String::as_str)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bots.is_empty()
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Polls every bot's `config.json` and reloads the ones that change.
+pub async fn watch(state: SharedState, interval: Duration) {
+    let mut last_modified: BTreeMap<&str, Option<SystemTime>> = state
+        .bots
+        .bots
+        .iter()
+        .map(|(id, bot)| (id.as_str(), modified(&bot.config_path)))
+        .collect();
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        for (id, bot) in &state.bots.bots {
+            let now_modified = modified(&bot.config_path);
+            let last = last_modified.entry(id).or_default();
+            if now_modified == *last {
+                continue;
+            }
+            *last = now_modified;
+            match bot.config.reload(&bot.config_path) {
+                Ok(changes) => info!(bot = %id, ?changes, "bot configuration reloaded"),
+                Err(ConfigError::Io(e)) if e.kind() == io::ErrorKind::NotFound => {
+                    warn!(bot = %id, "config.json is gone; keeping the previous configuration")
+                }
+                Err(e) => warn!(
+                    bot = %id,
+                    "{}: {e}; keeping the previous configuration",
+                    bot.config_path.display()
+                ),
+            }
+        }
+    }
+}
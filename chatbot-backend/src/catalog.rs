@@ -0,0 +1,171 @@
+//! The services catalog visitors can browse from the chat.
+//!
+//! The built-in catalog can be replaced by a JSON file (see
+//! [`ServicesCatalog::from_file`]) holding `{"items": [...]}` and,
+//! optionally, the `currency` its prices are in.
+
+use std::{fmt, fs, io, path::Path, sync::OnceLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::text::{self, PhraseMatcher};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceItem {
+    /// Stable identifier, also used as the metrics sub-intent.
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    /// Lowest price we quote for this service, in whole units of the
+    /// catalog's currency.
+    pub starting_price: u32,
+    /// Extra phrases that refer to this item besides its name.
+    #[serde(default)]
+    pub keywords: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServicesCatalog {
+    pub items: Vec<ServiceItem>,
+    /// ISO 4217 code of the prices; US dollars unless set.
+    #[serde(default = "default_currency")]
+    pub currency: String,
+    /// Item names and keywords, tagged with the item's index; built on the
+    /// first lookup and kept by clones, which only ever change prices.
+    #[serde(skip)]
+    matcher: OnceLock<PhraseMatcher<usize>>,
+}
+
+#[derive(Debug)]
+pub enum CatalogError {
+    Io(io::Error),
+    Parse(serde_json::Error),
+    Empty,
+}
+
+impl fmt::Display for CatalogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CatalogError::Io(e) => write!(f, "cannot read catalog: {e}"),
+            CatalogError::Parse(e) => write!(f, "invalid catalog: {e}"),
+            CatalogError::Empty => write!(f, "catalog has no items"),
+        }
+    }
+}
+
+impl std::error::Error for CatalogError {}
+
+impl ServicesCatalog {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, CatalogError> {
+        let raw = fs::read_to_string(path).map_err(CatalogError::Io)?;
+        let catalog: ServicesCatalog = serde_json::from_str(&raw).map_err(CatalogError::Parse)?;
+        if catalog.items.is_empty() {
+            return Err(CatalogError::Empty);
+        }
+        Ok(catalog)
+    }
+
+    pub fn get(&self, id: &str) -> Option<&ServiceItem> {
+        self.items.iter().find(|item| item.id == id)
+    }
+
+    /// Finds the item a message refers to by name or keyword; the first
+    /// one listed when several match.
+    pub fn find(&self, message: &str) -> Option<&ServiceItem> {
+        let index = self.matcher().first(&text::normalize(message))?;
+        self.items.get(index)
+    }
+
+    /// Builds the lookup [`ServicesCatalog::find`] uses now rather than on
+    /// the first message.
+    pub fn prepare(&self) {
+        self.matcher();
+    }
+
+    fn matcher(&self) -> &PhraseMatcher<usize> {
+        self.matcher.get_or_init(|| {
+            PhraseMatcher::new(
+                self.items
+                    .iter()
+                    .enumerate()
+                    .map(|(i, item)| (i, std::iter::once(&item.name).chain(&item.keywords))),
+            )
+        })
+    }
+}
+
+fn default_currency() -> String {
+    "USD".to_string()
+}
+
+impl Default for ServicesCatalog {
+    fn default() -> Self {
+        let item =
+            |id: &str, name: &str, description: &str, price: u32, keywords: &[&str]| ServiceItem {
+                id: id.to_string(),
+                name: name.to_string(),
+                description: description.to_string(),
+                starting_price: price,
+                keywords: keywords.iter().map(|k| k.to_string()).collect(),
+            };
+        Self {
+            currency: default_currency(),
+            matcher: OnceLock::new(),
+            items: vec![
+                item(
+                    "web-development",
+                    "Web development",
+                    "Custom websites and web applications built with modern, fast and accessible technology.",
+                    1500,
+                    &[
+                        "website",
+                        "web app",
+                        "strona internetowa",
+                        "sitio web",
+                        "página web",
+                    ],
+                ),
+                item(
+                    "e-commerce",
+                    "E-commerce",
+                    "Online stores with payments, product management and shipping integrations.",
+                    3000,
+                    &[
+                        "ecommerce",
+                        "online shop",
+                        "online store",
+                        "sklep internetowy",
+                        "tienda online",
+                    ],
+                ),
+                item(
+                    "mobile-apps",
+                    "Mobile apps",
+                    "Native and cross-platform apps for iOS and Android.",
+                    5000,
+                    &[
+                        "mobile app",
+                        "ios",
+                        "android",
+                        "aplikacja mobilna",
+                        "aplicación móvil",
+                    ],
+                ),
+                item(
+                    "ui-ux-design",
+                    "UI/UX design",
+                    "User research, wireframes and polished interface design.",
+                    800,
+                    &["design", "ui", "ux", "projektowanie", "diseño"],
+                ),
+                item(
+                    "seo",
+                    "SEO",
+                    "Technical audits and optimization to help customers find you.",
+                    400,
+                    &["search engine", "pozycjonowanie", "posicionamiento"],
+                ),
+            ],
+        }
+    }
+}
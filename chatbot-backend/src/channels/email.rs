@@ -0,0 +1,194 @@
+//! Chatting by email, for visitors who leave the widget but answer mail.
+//!
+//! The mail provider posts every message that reaches the inbound mailbox
+//! (`INBOUND_EMAIL_ADDRESS`) to `/integrations/email/inbound`. A message
+//! sent to the session's own address (see [`mail::reply_address`]), e.g. an
+//! answer to the transcript email, continues that session while it is
+//! live; other threads get a session of their own. The bot's replies go
+//! back through the mail relay, threaded under the visitor's message.
+
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{info, warn};
+
+use super::{Conversations, RecentIds, forward_pushes, plain_text};
+use crate::{
+    error::ApiError,
+    mail::{self, Email},
+    outbox::OutboxJob,
+    state::{AppState, SharedState},
+};
+
+const RECENT_MESSAGES: usize = 1_000;
+
+/// An inbound email as the provider's webhook delivers it.
+#[derive(Debug, Clone, Deserialize)]
+pub struct InboundEmail {
+    /// Bare address of the sender.
+    pub from: String,
+    /// Recipients, comma separated, bare or as `Name <address>`.
+    #[serde(default)]
+    pub to: String,
+    #[serde(default)]
+    pub subject: String,
+    /// The plain-text body.
+    #[serde(default)]
+    pub text: String,
+    pub message_id: String,
+    #[serde(default)]
+    pub in_reply_to: Option<String>,
+    #[serde(default)]
+    pub references: Vec<String>,
+}
+
+impl InboundEmail {
+    /// The first message of the thread, which all replies share.
+    fn thread_root(&self) -> &str {
+        self.references
+            .first()
+            .or(self.in_reply_to.as_ref())
+            .unwrap_or(&self.message_id)
+    }
+
+    fn reply_subject(&self) -> String {
+        let subject = self.subject.trim();
+        if subject.len() >= 3 && subject[..3].eq_ignore_ascii_case("re:") {
+            subject.to_string()
+        } else {
+            format!("Re: {subject}")
+        }
+    }
+}
+
+/// The new part of a reply: everything above the quoted original.
+pub fn strip_quoted(body: &str) -> String {
+    let mut lines = Vec::new();
+    for line in body.lines() {
+        let trimmed = line.trim();
+        let attribution = trimmed.ends_with("wrote:")
+            || trimmed.ends_with("napisał:")
+            || trimmed.ends_with("napisała:")
+            || trimmed.ends_with("escribió:");
+        if trimmed.starts_with('>')
+            || attribution
+            || trimmed == "-- "
+            || trimmed.starts_with("-----Original Message-----")
+        {
+            break;
+        }
+        lines.push(line);
+    }
+    lines.join("\n").trim().to_string()
+}
+
+pub struct EmailChannel {
+    address: String,
+    token: String,
+    conversations: Conversations,
+    recent: RecentIds,
+}
+
+impl EmailChannel {
+    /// `address` is the inbound mailbox; the provider authenticates with
+    /// `token`.
+    pub fn new(address: String, token: String) -> Self {
+        Self {
+            address,
+            token,
+            conversations: Conversations::default(),
+            recent: RecentIds::new(RECENT_MESSAGES),
+        }
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// Runs the new part of `email` through the bot and mails the replies
+    /// back. Repeated deliveries of the same message are ignored.
+    pub async fn handle(&self, state: &SharedState, email: InboundEmail) -> Result<(), ApiError> {
+        if !self.recent.insert(&email.message_id) {
+            return Ok(());
+        }
+        let text = strip_quoted(&email.text);
+        if text.is_empty() {
+            return Ok(());
+        }
+        let key = email.thread_root().to_string();
+        if let Some(session_id) = email
+            .to
+            .split(',')
+            .find_map(|to| mail::session_from_address(&self.address, to))
+        {
+            self.conversations.adopt(&key, session_id);
+        }
+
+        let (session_id, is_new, replies) = match self
+            .conversations
+            .converse(state, &key, None, Some(&email.from), &text)
+            .await
+        {
+            Ok(turn) => turn,
+            Err(ApiError::Busy(message) | ApiError::Blocked(Some(message))) => {
+                self.send(state, &email, None, message);
+                return Ok(());
+            }
+            Err(ApiError::Blocked(None)) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        info!(%session_id, from = %email.from, "email answered");
+        if is_new {
+            let (state_for_push, email) = (state.clone(), email.clone());
+            let session = session_id.clone();
+            forward_pushes(state, &session_id, move |text| {
+                if let Some(channel) = &state_for_push.email {
+                    channel.send(&state_for_push, &email, Some(&session), text);
+                }
+                async {}
+            });
+        }
+        let body = replies
+            .iter()
+            .filter(|r| !r.text.is_empty())
+            .map(plain_text)
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        if !body.is_empty() {
+            self.send(state, &email, Some(&session_id), body);
+        }
+        Ok(())
+    }
+
+    /// Queues `text` as an answer to `original`.
+    fn send(
+        &self,
+        state: &AppState,
+        original: &InboundEmail,
+        session_id: Option<&str>,
+        text: String,
+    ) {
+        let Some(url) = &state.mail_relay_url else {
+            warn!("MAIL_RELAY_URL is not set; email reply dropped");
+            return;
+        };
+        let mut references = original.references.clone();
+        references.push(original.message_id.clone());
+        let email = Email {
+            to: original.from.clone(),
+            subject: original.reply_subject(),
+            text,
+            attachments: Vec::new(),
+            reply_to: session_id.map(|id| mail::reply_address(&self.address, id)),
+            in_reply_to: Some(original.message_id.clone()),
+            references,
+        };
+        let data = json!({ "session_id": session_id, "email": email });
+        state
+            .outbox
+            .enqueue(OutboxJob::new("email.reply", url, data));
+    }
+}
@@ -0,0 +1,224 @@
+//! The bot as a Matrix user, so people can chat from Element or any other
+//! Matrix client.
+//!
+//! Talks to the homeserver over the client-server API: a long-polling
+//! `/sync` loop picks up text messages and invites, and replies go back to
+//! the room. Every member of every room gets their own session. Messages
+//! sent before the server started are not answered.
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use super::{Conversations, forward_pushes, plain_text};
+use crate::{error::ApiError, state::SharedState};
+
+/// How long the homeserver may hold a `/sync` request open.
+const SYNC_TIMEOUT: Duration = Duration::from_secs(30);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(40);
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct MatrixConfig {
+    /// E.g. `https://matrix.example.org`.
+    pub homeserver: Url,
+    pub access_token: String,
+    /// The bot's own user id, e.g. `@studio-bot:example.org`.
+    pub user_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SyncResponse {
+    next_batch: String,
+    #[serde(default)]
+    rooms: Rooms,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Rooms {
+    #[serde(default)]
+    join: HashMap<String, JoinedRoom>,
+    #[serde(default)]
+    invite: HashMap<String, Value>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct JoinedRoom {
+    #[serde(default)]
+    timeline: Timeline,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Timeline {
+    #[serde(default)]
+    events: Vec<RoomEvent>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RoomEvent {
+    #[serde(rename = "type")]
+    kind: String,
+    sender: String,
+    #[serde(default)]
+    content: Value,
+}
+
+impl RoomEvent {
+    /// The body of a plain text message.
+    fn text(&self) -> Option<&str> {
+        if self.kind != "m.room.message" || self.content["msgtype"] != "m.text" {
+            return None;
+        }
+        self.content["body"].as_str()
+    }
+}
+
+#[derive(Clone)]
+struct MatrixClient {
+    http: Client,
+    config: Arc<MatrixConfig>,
+}
+
+impl MatrixClient {
+    fn url(&self, segments: &[&str]) -> Url {
+        let mut url = self.config.homeserver.clone();
+        url.path_segments_mut()
+            .expect("homeserver is an http(s) URL")
+            .pop_if_empty()
+            .extend(["_matrix", "client", "v3"])
+            .extend(segments);
+        url
+    }
+
+    async fn sync(&self, since: Option<&str>) -> Result<SyncResponse, reqwest::Error> {
+        let timeout = if since.is_some() {
+            SYNC_TIMEOUT
+        } else {
+            Duration::ZERO
+        };
+        let mut request = self
+            .http
+            .get(self.url(&["sync"]))
+            .bearer_auth(&self.config.access_token)
+            .query(&[("timeout", timeout.as_millis().to_string())])
+            .timeout(REQUEST_TIMEOUT);
+        if let Some(since) = since {
+            request = request.query(&[("since", since)]);
+        }
+        request.send().await?.error_for_status()?.json().await
+    }
+
+    async fn join(&self, room_id: &str) -> Result<(), reqwest::Error> {
+        self.http
+            .post(self.url(&["rooms", room_id, "join"]))
+            .bearer_auth(&self.config.access_token)
+            .json(&json!({}))
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn send(&self, room_id: &str, body: &str) {
+        let txn_id = Uuid::new_v4().to_string();
+        let result = self
+            .http
+            .put(self.url(&["rooms", room_id, "send", "m.room.message", &txn_id]))
+            .bearer_auth(&self.config.access_token)
+            .json(&json!({ "msgtype": "m.text", "body": body }))
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status());
+        if let Err(e) = result {
+            warn!(%room_id, "cannot send Matrix message: {e}");
+        }
+    }
+}
+
+/// Syncs with the homeserver and answers messages until the process
+/// exits.
+pub async fn run(state: SharedState, config: MatrixConfig) {
+    let client = MatrixClient {
+        http: state.http.clone(),
+        config: Arc::new(config),
+    };
+    let conversations = Conversations::default();
+    let mut since: Option<String> = None;
+    info!(user_id = %client.config.user_id, "Matrix channel started");
+    loop {
+        let sync = match client.sync(since.as_deref()).await {
+            Ok(sync) => sync,
+            Err(e) => {
+                warn!("Matrix sync failed: {e}");
+                tokio::time::sleep(RETRY_DELAY).await;
+                continue;
+            }
+        };
+        let caught_up = since.is_some();
+        since = Some(sync.next_batch);
+
+        for room_id in sync.rooms.invite.keys() {
+            match client.join(room_id).await {
+                Ok(()) => info!(%room_id, "joined Matrix room"),
+                Err(e) => warn!(%room_id, "cannot join Matrix room: {e}"),
+            }
+        }
+        if !caught_up {
+            continue;
+        }
+        for (room_id, room) in &sync.rooms.join {
+            for event in &room.timeline.events {
+                if event.sender == client.config.user_id {
+                    continue;
+                }
+                if let Some(text) = event.text().map(str::trim).filter(|t| !t.is_empty()) {
+                    answer(
+                        &state,
+                        &client,
+                        &conversations,
+                        room_id,
+                        &event.sender,
+                        text,
+                    )
+                    .await;
+                }
+            }
+        }
+    }
+}
+
+async fn answer(
+    state: &SharedState,
+    client: &MatrixClient,
+    conversations: &Conversations,
+    room_id: &str,
+    sender: &str,
+    text: &str,
+) {
+    let key = format!("{room_id}|{sender}");
+    match conversations.converse(state, &key, None, None, text).await {
+        Ok((session_id, is_new, replies)) => {
+            if is_new {
+                let (client, room_id) = (client.clone(), room_id.to_string());
+                forward_pushes(state, &session_id, move |text| {
+                    let (client, room_id) = (client.clone(), room_id.clone());
+                    async move { client.send(&room_id, &text).await }
+                });
+            }
+            for reply in replies.iter().filter(|r| !r.text.is_empty()) {
+                client.send(room_id, &plain_text(reply)).await;
+            }
+        }
+        Err(ApiError::Busy(message) | ApiError::Blocked(Some(message))) => {
+            client.send(room_id, &message).await
+        }
+        Err(ApiError::Blocked(None)) => {}
+        Err(e) => warn!(%room_id, "Matrix message dropped: {e:?}"),
+    }
+}
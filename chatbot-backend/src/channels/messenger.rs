@@ -0,0 +1,230 @@
+//! Facebook Messenger through the Send API.
+//!
+//! Meta posts page messages to `/integrations/messenger/webhook`; replies
+//! go out through the Graph API. Sessions are keyed by the page and the
+//! sender's page-scoped id (PSID). Quick replies become Messenger quick
+//! reply chips whose payload is the option text, so tapping one reads
+//! like typing it.
+
+use std::sync::Arc;
+
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tracing::warn;
+
+use super::{Conversations, RecentIds, forward_pushes, plain_text};
+use crate::{chatbot::Reply, error::ApiError, state::SharedState};
+
+/// Messenger's limits on quick reply chips.
+const MAX_QUICK_REPLIES: usize = 13;
+const MAX_QUICK_REPLY_TITLE: usize = 20;
+const RECENT_MESSAGES: usize = 1_000;
+
+#[derive(Debug, Clone)]
+pub struct MessengerConfig {
+    /// Graph API base URL, [`super::meta::GRAPH_API_URL`] unless testing.
+    pub api_url: String,
+    /// Page access token used to send messages.
+    pub page_token: String,
+    pub verify_token: String,
+    /// Used to check that deliveries come from Meta; unchecked when unset.
+    pub app_secret: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WebhookPayload {
+    #[serde(default)]
+    entry: Vec<Entry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Entry {
+    /// The page id.
+    id: String,
+    #[serde(default)]
+    messaging: Vec<MessagingEvent>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessagingEvent {
+    sender: Participant,
+    #[serde(default)]
+    message: Option<InboundMessage>,
+    #[serde(default)]
+    postback: Option<Postback>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Participant {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct InboundMessage {
+    mid: String,
+    #[serde(default)]
+    text: Option<String>,
+    #[serde(default)]
+    quick_reply: Option<QuickReplyPayload>,
+    /// Set on copies of the page's own messages.
+    #[serde(default)]
+    is_echo: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct QuickReplyPayload {
+    payload: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Postback {
+    #[serde(default)]
+    mid: Option<String>,
+    title: String,
+}
+
+impl MessagingEvent {
+    /// Message id and text of something the visitor said or tapped.
+    fn inbound(&self) -> Option<(Option<&str>, &str)> {
+        if let Some(message) = &self.message {
+            if message.is_echo {
+                return None;
+            }
+            let text = match &message.quick_reply {
+                Some(quick_reply) => quick_reply.payload.as_str(),
+                None => message.text.as_deref()?,
+            };
+            return Some((Some(&message.mid), text));
+        }
+        let postback = self.postback.as_ref()?;
+        Some((postback.mid.as_deref(), &postback.title))
+    }
+}
+
+#[derive(Clone)]
+struct Sender {
+    http: Client,
+    config: Arc<MessengerConfig>,
+}
+
+impl Sender {
+    async fn post(&self, psid: &str, message: Value) {
+        let url = format!("{}/me/messages", self.config.api_url.trim_end_matches('/'));
+        let body = json!({
+            "recipient": { "id": psid },
+            "messaging_type": "RESPONSE",
+            "message": message,
+        });
+        let result = self
+            .http
+            .post(url)
+            .bearer_auth(&self.config.page_token)
+            .json(&body)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status());
+        if let Err(e) = result {
+            warn!("cannot send Messenger message: {e}");
+        }
+    }
+
+    async fn send_text(&self, psid: &str, text: &str) {
+        self.post(psid, json!({ "text": text })).await;
+    }
+
+    async fn send_reply(&self, psid: &str, reply: &Reply) {
+        let chips_fit = !reply.quick_replies.is_empty()
+            && reply.quick_replies.len() <= MAX_QUICK_REPLIES
+            && reply
+                .quick_replies
+                .iter()
+                .all(|q| q.chars().count() <= MAX_QUICK_REPLY_TITLE);
+        if !chips_fit {
+            return self.send_text(psid, &plain_text(reply)).await;
+        }
+        let quick_replies: Vec<Value> = reply
+            .quick_replies
+            .iter()
+            .map(|option| {
+                json!({
+                    "content_type": "text",
+                    "title": option,
+                    "payload": option,
+                })
+            })
+            .collect();
+        let message = json!({ "text": reply.text, "quick_replies": quick_replies });
+        self.post(psid, message).await;
+    }
+}
+
+pub struct Messenger {
+    sender: Sender,
+    conversations: Conversations,
+    recent: RecentIds,
+}
+
+impl Messenger {
+    /// Sends through `http`, the shared client.
+    pub fn new(config: MessengerConfig, http: Client) -> Self {
+        Self {
+            sender: Sender {
+                http,
+                config: Arc::new(config),
+            },
+            conversations: Conversations::default(),
+            recent: RecentIds::new(RECENT_MESSAGES),
+        }
+    }
+
+    pub fn config(&self) -> &MessengerConfig {
+        &self.sender.config
+    }
+
+    /// Answers every message and postback in one webhook delivery.
+    pub async fn handle(&self, state: &SharedState, payload: WebhookPayload) {
+        for entry in payload.entry {
+            for event in &entry.messaging {
+                let Some((mid, text)) = event.inbound() else {
+                    continue;
+                };
+                if mid.is_some_and(|mid| !self.recent.insert(mid)) {
+                    continue;
+                }
+                let text = text.trim();
+                if text.is_empty() {
+                    continue;
+                }
+                self.answer(state, &entry.id, &event.sender.id, text).await;
+            }
+        }
+    }
+
+    async fn answer(&self, state: &SharedState, page_id: &str, psid: &str, text: &str) {
+        let key = format!("{page_id}|{psid}");
+        match self
+            .conversations
+            .converse(state, &key, None, None, text)
+            .await
+        {
+            Ok((session_id, is_new, replies)) => {
+                if is_new {
+                    let (sender, psid) = (self.sender.clone(), psid.to_string());
+                    forward_pushes(state, &session_id, move |text| {
+                        let (sender, psid) = (sender.clone(), psid.clone());
+                        async move { sender.send_text(&psid, &text).await }
+                    });
+                }
+                for reply in replies.iter().filter(|r| !r.text.is_empty()) {
+                    self.sender.send_reply(psid, reply).await;
+                }
+            }
+            Err(ApiError::Busy(message) | ApiError::Blocked(Some(message))) => {
+                self.sender.send_text(psid, &message).await
+            }
+            Err(ApiError::Blocked(None)) => {}
+            Err(e) => warn!("Messenger message dropped: {e:?}"),
+        }
+    }
+}
@@ -0,0 +1,45 @@
+//! Webhook plumbing shared by Meta's platforms (WhatsApp, Messenger).
+
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+
+/// Default Graph API base URL.
+pub const GRAPH_API_URL: &str = "https://graph.facebook.com/v21.0";
+
+/// The subscription handshake Meta sends before delivering events:
+/// `GET ?hub.mode=subscribe&hub.verify_token=...&hub.challenge=...`.
+#[derive(Debug, Deserialize)]
+pub struct VerifyQuery {
+    #[serde(rename = "hub.mode")]
+    pub mode: Option<String>,
+    #[serde(rename = "hub.verify_token")]
+    pub verify_token: Option<String>,
+    #[serde(rename = "hub.challenge")]
+    pub challenge: Option<String>,
+}
+
+impl VerifyQuery {
+    /// The challenge to echo back when the token matches ours.
+    pub fn accept(self, verify_token: &str) -> Option<String> {
+        (self.mode.as_deref() == Some("subscribe")
+            && self.verify_token.as_deref() == Some(verify_token))
+        .then_some(self.challenge)
+        .flatten()
+    }
+}
+
+/// Checks `X-Hub-Signature-256` (`sha256=<hex hmac of the body>`) against
+/// the app secret.
+pub fn signature_valid(app_secret: &str, body: &[u8], header: Option<&str>) -> bool {
+    let Some(signature) = header
+        .and_then(|h| h.strip_prefix("sha256="))
+        .and_then(|hex_sig| hex::decode(hex_sig).ok())
+    else {
+        return false;
+    };
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(app_secret.as_bytes()).expect("HMAC takes any key size");
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
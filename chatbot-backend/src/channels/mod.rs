@@ -0,0 +1,206 @@
+//! Chat channels besides the web widget.
+//!
+//! Each adapter maps its own notion of a conversation (a Matrix room
+//! member, a phone number, ...) to a bot session through [`Conversations`]
+//! and runs messages through the same turn as `/chat`.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    future::Future,
+    sync::Mutex,
+};
+
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    blocklist::refuse,
+    chatbot::{Reply, extract_email},
+    error::ApiError,
+    i18n::Language,
+    push::PushMessage,
+    service,
+    state::SharedState,
+    tenant::DEFAULT_TENANT,
+};
+
+pub mod email;
+pub mod matrix;
+pub mod messenger;
+pub mod meta;
+pub mod partner;
+pub mod whatsapp;
+
+/// Conversation key to session id, for one channel.
+#[derive(Default)]
+pub struct Conversations {
+    sessions: Mutex<HashMap<String, String>>,
+}
+
+impl Conversations {
+    /// Continues an existing session in the conversation `key`, unless the
+    /// conversation already has one.
+    pub fn adopt(&self, key: &str, session_id: &str) {
+        self.sessions
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_insert_with(|| session_id.to_string());
+    }
+
+    /// The conversation's current session id, which may have expired.
+    pub fn session(&self, key: &str) -> Option<String> {
+        self.sessions.lock().unwrap().get(key).cloned()
+    }
+
+    /// Runs `message` in the conversation's session, starting a new one
+    /// when there is none yet or the last one expired. Returns the session
+    /// id, whether the session is new, and the replies. `language` is an
+    /// ISO 639-1 code, guessed from the message when absent. `sender` is
+    /// the visitor's email address, on channels that know it.
+    ///
+    /// Messages from blocked visitors are answered with
+    /// [`ApiError::Blocked`] and don't reach the session.
+    pub async fn converse(
+        &self,
+        state: &SharedState,
+        key: &str,
+        language: Option<&str>,
+        sender: Option<&str>,
+        message: &str,
+    ) -> Result<(String, bool, Vec<Reply>), ApiError> {
+        let known = self.session(key);
+        check_blocklist(state, known.as_deref(), language, sender, message).await?;
+        let (session_id, replies) = service::converse(
+            state,
+            known.as_deref(),
+            language,
+            None,
+            &[message],
+            None,
+            None,
+        )
+        .await?;
+        let is_new = known.as_deref() != Some(session_id.as_str());
+        if is_new {
+            self.sessions
+                .lock()
+                .unwrap()
+                .insert(key.to_string(), session_id.clone());
+        }
+        Ok((session_id, is_new, replies))
+    }
+}
+
+/// Refuses a message the blocklist keeps out: by the session it continues,
+/// the address that session gave, the sender's or one in the message.
+async fn check_blocklist(
+    state: &SharedState,
+    session_id: Option<&str>,
+    language: Option<&str>,
+    sender: Option<&str>,
+    message: &str,
+) -> Result<(), ApiError> {
+    if !state.blocklist.has_visitor_entries() {
+        return Ok(());
+    }
+    let session = match session_id {
+        Some(id) => {
+            state
+                .sessions
+                .with_session(id, |s| (s.tenant.clone(), s.language, s.data.email.clone()))
+                .await
+        }
+        None => None,
+    };
+    let (tenant, session_language, session_email) = match session {
+        Some((tenant, language, email)) => (tenant, Some(language), email),
+        None => (DEFAULT_TENANT.to_string(), None, None),
+    };
+    let from_message = extract_email(message);
+    let emails = [sender, session_email.as_deref(), from_message.as_deref()];
+    let Some(kind) = state
+        .blocklist
+        .blocked_by(session_id, emails.into_iter().flatten())
+    else {
+        return Ok(());
+    };
+    let language = session_language
+        .or_else(|| language.and_then(Language::from_code))
+        .or_else(|| Language::detect(message))
+        .unwrap_or_default();
+    Err(refuse(state, kind, &tenant, language, None, session_id))
+}
+
+/// Ids of recently handled inbound messages, so a webhook delivered twice
+/// is answered once.
+pub struct RecentIds {
+    capacity: usize,
+    inner: Mutex<(VecDeque<String>, HashSet<String>)>,
+}
+
+impl RecentIds {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::default(),
+        }
+    }
+
+    /// `false` if `id` was seen before.
+    pub fn insert(&self, id: &str) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let (order, seen) = &mut *inner;
+        if !seen.insert(id.to_string()) {
+            return false;
+        }
+        order.push_back(id.to_string());
+        if order.len() > self.capacity
+            && let Some(oldest) = order.pop_front()
+        {
+            seen.remove(&oldest);
+        }
+        true
+    }
+}
+
+/// A reply for channels without buttons: the text followed by the quick
+/// replies as a list the visitor can type back.
+pub fn plain_text(reply: &Reply) -> String {
+    let mut out = reply.text.clone();
+    if !reply.quick_replies.is_empty() {
+        out.push('\n');
+        for option in &reply.quick_replies {
+            out.push_str("\n• ");
+            out.push_str(option);
+        }
+    }
+    out
+}
+
+/// Passes every message pushed to the session (operator messages,
+/// inactivity warnings, the satisfaction survey, the place in line for an
+/// operator) to `deliver` until the
+/// session ends.
+pub fn forward_pushes<F, Fut>(state: &SharedState, session_id: &str, deliver: F)
+where
+    F: Fn(String) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    let mut pushes = state.push.subscribe(session_id);
+    tokio::spawn(async move {
+        loop {
+            match pushes.recv().await {
+                Ok(
+                    PushMessage::InactivityWarning { text }
+                    | PushMessage::AgentMessage { text, .. }
+                    | PushMessage::Survey { text, .. }
+                    | PushMessage::QueuePosition { text, .. },
+                ) => deliver(text).await,
+                // Channels don't stream replies.
+                Ok(PushMessage::ReplyPart { .. }) => continue,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+}
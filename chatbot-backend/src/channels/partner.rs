@@ -0,0 +1,233 @@
+//! The partner API under `/v1`, for third-party chat frontends that keep
+//! their own UI and conversation ids.
+//!
+//! A partner posts each visitor message to
+//! `/v1/conversations/{id}/messages` and gets back a receipt for it, the
+//! bot's replies and the conversation events the message caused. Messages
+//! the server sends later (operator messages, inactivity warnings) are
+//! delivered to the partner's webhook as `message.created`. The API is
+//! described in `openapi/partner-v1.json`, served at `/v1/openapi.json`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+use super::{Conversations, RecentIds};
+use crate::{
+    error::ApiError,
+    events::{ConversationEvent, StoredEvent, TranscriptEntry, now_ms},
+    outbox::OutboxJob,
+    push::PushMessage,
+    service,
+    session::Role,
+    state::SharedState,
+};
+
+const RECENT_MESSAGES: usize = 10_000;
+
+#[derive(Debug, Deserialize)]
+pub struct PostMessage {
+    pub text: String,
+    /// The partner's id for the message. A message posted again with the
+    /// same id is acknowledged but not answered twice.
+    #[serde(default)]
+    pub message_id: Option<String>,
+    /// ISO 639-1 code; guessed from the first message when absent.
+    #[serde(default)]
+    pub language: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceiptStatus {
+    Processed,
+    /// Seen before; the earlier response carried the replies.
+    Duplicate,
+}
+
+/// Acknowledges a partner's message.
+#[derive(Debug, Serialize)]
+pub struct Receipt {
+    pub message_id: Option<String>,
+    pub status: ReceiptStatus,
+    pub received_at_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OutboundMessage {
+    pub id: String,
+    pub role: Role,
+    pub text: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub quick_replies: Vec<String>,
+    pub created_at_ms: u64,
+}
+
+impl OutboundMessage {
+    /// `id` is the session message's; a fresh one is made for messages
+    /// that are not stored, such as inactivity warnings.
+    fn new(id: Option<String>, role: Role, text: String, quick_replies: Vec<String>) -> Self {
+        Self {
+            id: id.unwrap_or_else(|| Uuid::new_v4().to_string()),
+            role,
+            text,
+            quick_replies,
+            created_at_ms: now_ms(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PostMessageResponse {
+    pub conversation_id: String,
+    pub receipt: Receipt,
+    pub messages: Vec<OutboundMessage>,
+    /// State changes, captured fields, confirmations, ... caused by the
+    /// message; the messages themselves are not repeated here.
+    pub events: Vec<StoredEvent>,
+}
+
+pub struct PartnerApi {
+    token: String,
+    webhook_url: Option<String>,
+    conversations: Conversations,
+    recent: RecentIds,
+}
+
+impl PartnerApi {
+    /// Partners authenticate with `token`; `webhook_url` receives the
+    /// messages the server sends on its own.
+    pub fn new(token: String, webhook_url: Option<String>) -> Self {
+        Self {
+            token,
+            webhook_url,
+            conversations: Conversations::default(),
+            recent: RecentIds::new(RECENT_MESSAGES),
+        }
+    }
+
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// Runs a visitor's message through the bot in the partner's
+    /// conversation, starting a session when needed.
+    pub async fn post_message(
+        &self,
+        state: &SharedState,
+        conversation_id: &str,
+        message: PostMessage,
+    ) -> Result<PostMessageResponse, ApiError> {
+        let text = message.text.trim();
+        if text.is_empty() {
+            return Err(ApiError::BadRequest("text must not be empty".into()));
+        }
+        let received_at_ms = now_ms();
+        let receipt = |status| Receipt {
+            message_id: message.message_id.clone(),
+            status,
+            received_at_ms,
+        };
+        if let Some(id) = &message.message_id
+            && !self.recent.insert(&format!("{conversation_id}|{id}"))
+        {
+            return Ok(PostMessageResponse {
+                conversation_id: conversation_id.to_string(),
+                receipt: receipt(ReceiptStatus::Duplicate),
+                messages: Vec::new(),
+                events: Vec::new(),
+            });
+        }
+
+        // Events are numbered globally, so everything after the session's
+        // last event so far came from this message.
+        let seen = self
+            .conversations
+            .session(conversation_id)
+            .and_then(|id| state.events.session_events(&id).last().map(|e| e.seq))
+            .unwrap_or(0);
+        let (session_id, is_new, replies) = self
+            .conversations
+            .converse(
+                state,
+                conversation_id,
+                message.language.as_deref(),
+                None,
+                text,
+            )
+            .await?;
+        if is_new && let Some(url) = &self.webhook_url {
+            forward_pushes(state, &session_id, conversation_id, url);
+        }
+
+        let messages = replies
+            .into_iter()
+            .filter(|r| !r.text.is_empty())
+            .map(|r| OutboundMessage::new(r.message_id, Role::Bot, r.text, r.quick_replies))
+            .collect();
+        let events = state
+            .events
+            .session_events(&session_id)
+            .into_iter()
+            .filter(|e| e.seq > seen)
+            .filter(|e| {
+                !matches!(
+                    e.event,
+                    ConversationEvent::MessageReceived { .. } | ConversationEvent::ReplySent { .. }
+                )
+            })
+            .collect();
+        Ok(PostMessageResponse {
+            conversation_id: conversation_id.to_string(),
+            receipt: receipt(ReceiptStatus::Processed),
+            messages,
+            events,
+        })
+    }
+
+    /// The transcript of the conversation's live session.
+    pub async fn messages(
+        &self,
+        state: &SharedState,
+        conversation_id: &str,
+    ) -> Result<Vec<TranscriptEntry>, ApiError> {
+        let Some(session_id) = self.conversations.session(conversation_id) else {
+            return Err(ApiError::NotFound(format!(
+                "no conversation {conversation_id}"
+            )));
+        };
+        service::history(state, &session_id).await
+    }
+}
+
+/// Delivers the session's pushed messages to the partner's webhook until
+/// the session ends.
+fn forward_pushes(state: &SharedState, session_id: &str, conversation_id: &str, url: &str) {
+    let mut pushes = state.push.subscribe(session_id);
+    let (state, conversation_id, url) =
+        (state.clone(), conversation_id.to_string(), url.to_string());
+    tokio::spawn(async move {
+        loop {
+            let message = match pushes.recv().await {
+                Ok(
+                    PushMessage::InactivityWarning { text }
+                    | PushMessage::QueuePosition { text, .. },
+                ) => OutboundMessage::new(None, Role::Bot, text, Vec::new()),
+                Ok(PushMessage::Survey {
+                    text,
+                    quick_replies,
+                }) => OutboundMessage::new(None, Role::Bot, text, quick_replies),
+                Ok(PushMessage::AgentMessage { text, message_id }) => {
+                    OutboundMessage::new(Some(message_id), Role::Agent, text, Vec::new())
+                }
+                Ok(PushMessage::ReplyPart { .. }) | Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            };
+            let data = json!({ "conversation_id": conversation_id, "message": message });
+            state
+                .outbox
+                .enqueue(OutboxJob::new("message.created", &url, data));
+        }
+    });
+}
@@ -0,0 +1,234 @@
+//! WhatsApp through Meta's Cloud API.
+//!
+//! Meta posts incoming messages to `/integrations/whatsapp/webhook`; the
+//! replies go out through the Graph API. Each business number and customer
+//! phone number pair gets its own session. Quick replies become reply
+//! buttons when WhatsApp allows it (at most three, short titles), and a
+//! list under the text otherwise.
+
+use std::sync::Arc;
+
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tracing::warn;
+
+use super::{Conversations, RecentIds, forward_pushes, plain_text};
+use crate::{chatbot::Reply, error::ApiError, state::SharedState};
+
+const MAX_BUTTONS: usize = 3;
+const MAX_BUTTON_TITLE: usize = 20;
+const RECENT_MESSAGES: usize = 1_000;
+
+#[derive(Debug, Clone)]
+pub struct WhatsAppConfig {
+    /// Graph API base URL, [`super::meta::GRAPH_API_URL`] unless testing.
+    pub api_url: String,
+    /// System user token allowed to send messages.
+    pub access_token: String,
+    /// What we answer Meta's subscription handshake with.
+    pub verify_token: String,
+    /// Used to check that deliveries come from Meta; unchecked when unset.
+    pub app_secret: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WebhookPayload {
+    #[serde(default)]
+    entry: Vec<Entry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Entry {
+    #[serde(default)]
+    changes: Vec<Change>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Change {
+    value: ChangeValue,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChangeValue {
+    metadata: Option<Metadata>,
+    /// Absent for delivery and read receipts.
+    #[serde(default)]
+    messages: Vec<InboundMessage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Metadata {
+    phone_number_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct InboundMessage {
+    id: String,
+    from: String,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    text: Value,
+    #[serde(default)]
+    interactive: Value,
+    #[serde(default)]
+    button: Value,
+}
+
+impl InboundMessage {
+    /// What the customer typed or the title of the button they tapped.
+    fn text(&self) -> Option<&str> {
+        match self.kind.as_str() {
+            "text" => self.text["body"].as_str(),
+            "interactive" => self.interactive["button_reply"]["title"].as_str(),
+            "button" => self.button["text"].as_str(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Sender {
+    http: Client,
+    config: Arc<WhatsAppConfig>,
+}
+
+impl Sender {
+    async fn post(&self, phone_number_id: &str, body: Value) {
+        let url = format!(
+            "{}/{phone_number_id}/messages",
+            self.config.api_url.trim_end_matches('/')
+        );
+        let result = self
+            .http
+            .post(url)
+            .bearer_auth(&self.config.access_token)
+            .json(&body)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status());
+        if let Err(e) = result {
+            warn!("cannot send WhatsApp message: {e}");
+        }
+    }
+
+    async fn send_text(&self, phone_number_id: &str, to: &str, text: &str) {
+        let body = json!({
+            "messaging_product": "whatsapp",
+            "to": to,
+            "type": "text",
+            "text": { "body": text },
+        });
+        self.post(phone_number_id, body).await;
+    }
+
+    async fn send_reply(&self, phone_number_id: &str, to: &str, reply: &Reply) {
+        let buttons_fit = !reply.quick_replies.is_empty()
+            && reply.quick_replies.len() <= MAX_BUTTONS
+            && reply
+                .quick_replies
+                .iter()
+                .all(|q| q.chars().count() <= MAX_BUTTON_TITLE);
+        if !buttons_fit {
+            return self
+                .send_text(phone_number_id, to, &plain_text(reply))
+                .await;
+        }
+        let buttons: Vec<Value> = reply
+            .quick_replies
+            .iter()
+            .enumerate()
+            .map(|(i, title)| {
+                json!({
+                    "type": "reply",
+                    "reply": { "id": format!("qr{i}"), "title": title },
+                })
+            })
+            .collect();
+        let body = json!({
+            "messaging_product": "whatsapp",
+            "to": to,
+            "type": "interactive",
+            "interactive": {
+                "type": "button",
+                "body": { "text": reply.text },
+                "action": { "buttons": buttons },
+            },
+        });
+        self.post(phone_number_id, body).await;
+    }
+}
+
+pub struct WhatsApp {
+    sender: Sender,
+    conversations: Conversations,
+    recent: RecentIds,
+}
+
+impl WhatsApp {
+    /// Sends through `http`, the shared client.
+    pub fn new(config: WhatsAppConfig, http: Client) -> Self {
+        Self {
+            sender: Sender {
+                http,
+                config: Arc::new(config),
+            },
+            conversations: Conversations::default(),
+            recent: RecentIds::new(RECENT_MESSAGES),
+        }
+    }
+
+    pub fn config(&self) -> &WhatsAppConfig {
+        &self.sender.config
+    }
+
+    /// Answers every message in one webhook delivery.
+    pub async fn handle(&self, state: &SharedState, payload: WebhookPayload) {
+        let changes = payload.entry.into_iter().flat_map(|e| e.changes);
+        for change in changes {
+            let Some(metadata) = change.value.metadata else {
+                continue;
+            };
+            for message in change.value.messages {
+                if !self.recent.insert(&message.id) {
+                    continue;
+                }
+                let Some(text) = message.text().map(str::trim).filter(|t| !t.is_empty()) else {
+                    continue;
+                };
+                self.answer(state, &metadata.phone_number_id, &message.from, text)
+                    .await;
+            }
+        }
+    }
+
+    async fn answer(&self, state: &SharedState, phone_number_id: &str, from: &str, text: &str) {
+        let key = format!("{phone_number_id}|{from}");
+        match self
+            .conversations
+            .converse(state, &key, None, None, text)
+            .await
+        {
+            Ok((session_id, is_new, replies)) => {
+                if is_new {
+                    let sender = self.sender.clone();
+                    let (phone_number_id, from) = (phone_number_id.to_string(), from.to_string());
+                    forward_pushes(state, &session_id, move |text| {
+                        let sender = sender.clone();
+                        let (phone_number_id, from) = (phone_number_id.clone(), from.clone());
+                        async move { sender.send_text(&phone_number_id, &from, &text).await }
+                    });
+                }
+                for reply in replies.iter().filter(|r| !r.text.is_empty()) {
+                    self.sender.send_reply(phone_number_id, from, reply).await;
+                }
+            }
+            Err(ApiError::Busy(message) | ApiError::Blocked(Some(message))) => {
+                self.sender.send_text(phone_number_id, from, &message).await
+            }
+            Err(ApiError::Blocked(None)) => {}
+            Err(e) => warn!("WhatsApp message dropped: {e:?}"),
+        }
+    }
+}
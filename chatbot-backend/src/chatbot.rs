@@ -0,0 +1,1441 @@
+//! Intent detection and the rule-based inquiry flow.
+
+use std::{collections::HashSet, sync::LazyLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    catalog::{ServiceItem, ServicesCatalog},
+    correction::detect_correction,
+    flow::{self, Event, TransitionResult},
+    i18n::{self, Language, Text, format_money, text},
+    plugins::IntentReply,
+    report::InquiryReport,
+    session::{ConversationState, Field, FieldValue, LeadTag, Session},
+    survey,
+    synonyms::SynonymTable,
+    template::{self, fill},
+    tenant::ReplyOverrides,
+    text::{PhraseMatcher, contains_any, normalize},
+    training::LearnedIntents,
+    validation::{Invalid, ValidationRules},
+    yesno::{self, YesNo},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Intent {
+    Greeting,
+    Services,
+    Pricing,
+    Contact,
+    Help,
+    StartInquiry,
+    Reset,
+    /// Declining to answer the pending question.
+    Skip,
+    /// Changing an answer given earlier.
+    Correction,
+    Thanks,
+    Goodbye,
+    /// Asking for a copy of the conversation by email.
+    EmailTranscript,
+    /// Asking to talk to a person instead of the bot.
+    Escalate,
+    /// An operator is handling the conversation; the bot did not answer.
+    HandedOff,
+    /// The message was taken as the answer to the pending flow question.
+    Answer,
+    /// A score for the conversation, answering the satisfaction survey.
+    Rating,
+    Unknown,
+    /// Answered by a registered handler; see [`crate::plugins`].
+    Custom(&'static str),
+}
+
+impl Intent {
+    /// The built-in intents.
+    pub const ALL: [Intent; 17] = [
+        Intent::Greeting,
+        Intent::Services,
+        Intent::Pricing,
+        Intent::Contact,
+        Intent::Help,
+        Intent::StartInquiry,
+        Intent::Reset,
+        Intent::Skip,
+        Intent::Correction,
+        Intent::Thanks,
+        Intent::Goodbye,
+        Intent::EmailTranscript,
+        Intent::Escalate,
+        Intent::HandedOff,
+        Intent::Answer,
+        Intent::Rating,
+        Intent::Unknown,
+    ];
+
+    /// The inverse of [`Intent::as_str`] for the built-in intents.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Intent::ALL.into_iter().find(|i| i.as_str() == name)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Intent::Greeting => "greeting",
+            Intent::Services => "services",
+            Intent::Pricing => "pricing",
+            Intent::Contact => "contact",
+            Intent::Help => "help",
+            Intent::StartInquiry => "start_inquiry",
+            Intent::Reset => "reset",
+            Intent::Skip => "skip",
+            Intent::Correction => "correction",
+            Intent::Thanks => "thanks",
+            Intent::Goodbye => "goodbye",
+            Intent::EmailTranscript => "email_transcript",
+            Intent::Escalate => "escalate",
+            Intent::HandedOff => "handed_off",
+            Intent::Answer => "answer",
+            Intent::Rating => "rating",
+            Intent::Unknown => "unknown",
+            Intent::Custom(name) => name,
+        }
+    }
+}
+
+const RESET_KEYWORDS: &[&str] = &[
+    "reset",
+    "start over",
+    "restart",
+    "cancel",
+    "od nowa",
+    "anuluj",
+    "reiniciar",
+    "cancelar",
+    "empezar de nuevo",
+];
+
+const SKIP_KEYWORDS: &[&str] = &[
+    "skip",
+    "not sure",
+    "prefer not to say",
+    "rather not say",
+    "don't know",
+    "dont know",
+    "no idea",
+    "pomiń",
+    "pomin",
+    "nie wiem",
+    "wolę nie mówić",
+    "nie jestem pewien",
+    "nie jestem pewna",
+    "saltar",
+    "omitir",
+    "no sé",
+    "no lo sé",
+    "no estoy seguro",
+    "prefiero no decirlo",
+];
+
+const INQUIRY_KEYWORDS: &[&str] = &[
+    "start a project",
+    "start project",
+    "new project",
+    "get a quote",
+    "quote",
+    "hire you",
+    "i need a website",
+    "i want a website",
+    "rozpocznij projekt",
+    "nowy projekt",
+    "wycena",
+    "wycenę",
+    "empezar un proyecto",
+    "nuevo proyecto",
+    "quiero un presupuesto",
+];
+
+const PRICING_KEYWORDS: &[&str] = &[
+    "price",
+    "prices",
+    "pricing",
+    "cost",
+    "costs",
+    "how much",
+    "rates",
+    "cena",
+    "ceny",
+    "cennik",
+    "koszt",
+    "ile kosztuje",
+    "precio",
+    "precios",
+    "cuánto cuesta",
+    "cuanto cuesta",
+    "tarifas",
+];
+
+const SERVICES_KEYWORDS: &[&str] = &[
+    "services",
+    "service",
+    "what do you do",
+    "what do you offer",
+    "usługi",
+    "usługa",
+    "oferta",
+    "czym się zajmujecie",
+    "servicios",
+    "servicio",
+    "qué ofrecen",
+    "que ofrecen",
+];
+
+const CONTACT_KEYWORDS: &[&str] = &[
+    "contact",
+    "phone",
+    "call you",
+    "address",
+    "reach you",
+    "kontakt",
+    "telefon",
+    "adres",
+    "contacto",
+    "teléfono",
+    "dirección",
+];
+
+const HELP_KEYWORDS: &[&str] = &[
+    "help",
+    "what can you do",
+    "pomoc",
+    "pomocy",
+    "co potrafisz",
+    "ayuda",
+    "qué puedes hacer",
+];
+
+const GREETING_KEYWORDS: &[&str] = &[
+    "hi",
+    "hello",
+    "hey",
+    "good morning",
+    "good afternoon",
+    "cześć",
+    "czesc",
+    "hej",
+    "dzień dobry",
+    "witam",
+    "hola",
+    "buenos días",
+    "buenas tardes",
+    "buenas",
+];
+
+const THANKS_KEYWORDS: &[&str] = &[
+    "thanks",
+    "thank you",
+    "thx",
+    "dzięki",
+    "dziękuję",
+    "dziekuje",
+    "gracias",
+];
+
+const GOODBYE_KEYWORDS: &[&str] = &[
+    "bye",
+    "goodbye",
+    "see you",
+    "do widzenia",
+    "na razie",
+    "adiós",
+    "adios",
+    "hasta luego",
+];
+
+const TRANSCRIPT_KEYWORDS: &[&str] = &[
+    "transcript",
+    "email me a copy",
+    "email me the conversation",
+    "copy of the conversation",
+    "copy of this conversation",
+    "transkrypcję",
+    "transkrypcja",
+    "kopię rozmowy",
+    "transcripción",
+    "copia de la conversación",
+];
+
+const ESCALATE_KEYWORDS: &[&str] = &[
+    "talk to a human",
+    "speak to a human",
+    "talk to a person",
+    "speak to a person",
+    "real person",
+    "live agent",
+    "human agent",
+    "talk to someone",
+    "z człowiekiem",
+    "z konsultantem",
+    "prawdziwa osoba",
+    "con una persona",
+    "email me back",
+    "kontakt mailowy",
+    "que me escriban",
+    "con un humano",
+    "agente humano",
+    "persona real",
+];
+
+/// Intent keywords in priority order, compiled on first use.
+static INTENT_MATCHER: LazyLock<PhraseMatcher<Intent>> = LazyLock::new(|| {
+    PhraseMatcher::new([
+        (Intent::Reset, RESET_KEYWORDS),
+        (Intent::Escalate, ESCALATE_KEYWORDS),
+        (Intent::Skip, SKIP_KEYWORDS),
+        (Intent::StartInquiry, INQUIRY_KEYWORDS),
+        (Intent::EmailTranscript, TRANSCRIPT_KEYWORDS),
+        (Intent::Pricing, PRICING_KEYWORDS),
+        (Intent::Services, SERVICES_KEYWORDS),
+        (Intent::Contact, CONTACT_KEYWORDS),
+        (Intent::Help, HELP_KEYWORDS),
+        (Intent::Greeting, GREETING_KEYWORDS),
+        (Intent::Thanks, THANKS_KEYWORDS),
+        (Intent::Goodbye, GOODBYE_KEYWORDS),
+    ])
+});
+
+/// Shortest word of an intent keyword that counts as a keyword on its own.
+const MIN_KEYWORD_CHARS: usize = 3;
+
+/// Every word the intent keywords are made of, in normalized form.
+static KEYWORD_WORDS: LazyLock<HashSet<String>> = LazyLock::new(|| {
+    [
+        RESET_KEYWORDS,
+        ESCALATE_KEYWORDS,
+        SKIP_KEYWORDS,
+        INQUIRY_KEYWORDS,
+        TRANSCRIPT_KEYWORDS,
+        PRICING_KEYWORDS,
+        SERVICES_KEYWORDS,
+        CONTACT_KEYWORDS,
+        HELP_KEYWORDS,
+        GREETING_KEYWORDS,
+        THANKS_KEYWORDS,
+        GOODBYE_KEYWORDS,
+    ]
+    .into_iter()
+    .flatten()
+    .flat_map(|phrase| {
+        normalize(phrase)
+            .split(' ')
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    })
+    .filter(|word| word.chars().count() >= MIN_KEYWORD_CHARS)
+    .collect()
+});
+
+/// The words of `message` the bot's intents know, once each and in order:
+/// what a visitor asked about, without what they said about themselves.
+pub fn keywords_in(message: &str) -> Vec<String> {
+    let mut found: Vec<String> = Vec::new();
+    for word in normalize(message).split(' ') {
+        if KEYWORD_WORDS.contains(word) && !found.iter().any(|w| w == word) {
+            found.push(word.to_string());
+        }
+    }
+    found
+}
+
+/// Builds the intent matcher now rather than on the first message.
+pub fn prepare() {
+    LazyLock::force(&INTENT_MATCHER);
+}
+
+/// Maps a message to an intent by keyword. Earlier rules win, so "reset"
+/// beats everything and starting an inquiry beats a bare price question.
+/// `Skip` only matters when the message is not a valid answer on its own.
+pub fn detect_intent(message: &str) -> Intent {
+    INTENT_MATCHER
+        .first(&normalize(message))
+        .unwrap_or(Intent::Unknown)
+}
+
+/// A reply ready to send, plus what it was about for metrics.
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub text: String,
+    pub quick_replies: Vec<String>,
+    pub intent: Intent,
+    pub sub_intent: Option<String>,
+    /// The visitor just confirmed the inquiry summary.
+    pub inquiry_confirmed: bool,
+    /// The visitor consented to receiving the transcript by email.
+    pub transcript_requested: bool,
+    /// The visitor asked for a person.
+    pub escalation_requested: bool,
+    /// The visitor left an address for the team to email them back at.
+    pub callback_requested: bool,
+    /// The conversation reached its limit and ends with this reply.
+    pub session_closed: bool,
+    /// The visitor's answer to the satisfaction survey, from 1 to 5.
+    pub satisfaction: Option<u8>,
+    /// Set once the reply is stored in the session.
+    pub message_id: Option<String>,
+    /// The inquiry report as it would be written, in Markdown, while the
+    /// visitor is asked to confirm it.
+    pub preview: Option<String>,
+    /// The text before its variables were filled in, when it had any.
+    pub template: Option<String>,
+}
+
+impl Reply {
+    fn new(intent: Intent, text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            quick_replies: Vec::new(),
+            intent,
+            sub_intent: None,
+            inquiry_confirmed: false,
+            transcript_requested: false,
+            escalation_requested: false,
+            callback_requested: false,
+            session_closed: false,
+            satisfaction: None,
+            message_id: None,
+            preview: None,
+            template: None,
+        }
+    }
+
+    fn with_quick_replies(mut self, quick_replies: Vec<String>) -> Self {
+        self.quick_replies = quick_replies;
+        self
+    }
+
+    /// A registered handler's answer to its intent `name`.
+    pub fn custom(name: &'static str, reply: IntentReply) -> Self {
+        Reply::new(Intent::Custom(name), reply.text).with_quick_replies(reply.quick_replies)
+    }
+}
+
+const NAME_PREFIXES: &[&str] = &[
+    "my name is",
+    "i'm",
+    "i am",
+    "it's",
+    "name:",
+    "nazywam się",
+    "mam na imię",
+    "jestem",
+    "me llamo",
+    "mi nombre es",
+    "soy",
+];
+
+/// Strips lead-ins such as "my name is" from a name answer.
+pub fn extract_name(message: &str) -> String {
+    let trimmed = message.trim();
+    let lower = trimmed.to_lowercase();
+    for prefix in NAME_PREFIXES {
+        if lower.starts_with(prefix) {
+            let rest: String = trimmed.chars().skip(prefix.chars().count()).collect();
+            return rest.trim().trim_end_matches(['.', '!']).to_string();
+        }
+    }
+    trimmed.trim_end_matches(['.', '!']).to_string()
+}
+
+/// Picks the first whitespace-separated token containing `@`.
+pub fn extract_email(message: &str) -> Option<String> {
+    message
+        .split_whitespace()
+        .find(|token| token.contains('@'))
+        .map(|token| {
+            token
+                .trim_matches(|c: char| !c.is_alphanumeric())
+                .to_string()
+        })
+}
+
+/// Whether a message mid-budget-question could be the answer; whether it
+/// is one is up to the [`ValidationRules`].
+pub fn is_valid_budget(budget: &str) -> bool {
+    budget.chars().any(|c| c.is_ascii_digit())
+}
+
+/// How messages sent from some pages are read, `page_hints` in the runtime
+/// config.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PageHint {
+    /// Pages whose path is this or below it, e.g. `/pricing`.
+    pub path: String,
+    /// What a message that matches no intent is taken to ask.
+    #[serde(default)]
+    pub intent: Option<HintIntent>,
+    /// Catalog id of the service the page is about; service and price
+    /// questions get its details.
+    #[serde(default)]
+    pub service: Option<String>,
+}
+
+impl PageHint {
+    /// Whether the hint covers `page_url`, a full URL or just a path.
+    pub fn covers(&self, page_url: &str) -> bool {
+        let parsed = reqwest::Url::parse(page_url);
+        let path = match &parsed {
+            Ok(url) => url.path(),
+            Err(_) => page_url.split(['?', '#']).next().unwrap_or_default(),
+        };
+        let prefix = self.path.trim_end_matches('/');
+        path.strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+}
+
+/// The intents a page can stand in for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HintIntent {
+    Services,
+    Pricing,
+    Contact,
+    StartInquiry,
+}
+
+impl From<HintIntent> for Intent {
+    fn from(hint: HintIntent) -> Self {
+        match hint {
+            HintIntent::Services => Intent::Services,
+            HintIntent::Pricing => Intent::Pricing,
+            HintIntent::Contact => Intent::Contact,
+            HintIntent::StartInquiry => Intent::StartInquiry,
+        }
+    }
+}
+
+/// The budget an inquiry needs, `qualification` in the runtime config.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Qualification {
+    /// In the catalog's currency; budgets are compared by amount alone.
+    pub min_budget: u32,
+    /// Where smaller projects are sent instead of sending an inquiry.
+    /// Without one the visitor is told what to expect and the inquiry
+    /// goes on.
+    #[serde(default)]
+    pub self_service_url: Option<String>,
+}
+
+/// Multipliers written after an amount, longest first.
+const AMOUNT_SUFFIXES: &[(&str, u64)] = &[
+    ("million", 1_000_000),
+    ("millón", 1_000_000),
+    ("millon", 1_000_000),
+    ("mln", 1_000_000),
+    ("tys", 1_000),
+    ("mil", 1_000),
+    ("k", 1_000),
+];
+
+/// The first amount in a budget answer, ignoring the currency: "5,000
+/// EUR", "5 000 zł" and "5k" are all 5000. A range gives its lower end.
+pub fn budget_amount(budget: &str) -> Option<u64> {
+    let lower = budget.to_lowercase();
+    let start = lower.find(|c: char| c.is_ascii_digit())?;
+    let mut groups: Vec<&str> = Vec::new();
+    let mut separators: Vec<char> = Vec::new();
+    let mut rest = &lower[start..];
+    loop {
+        let end = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        groups.push(&rest[..end]);
+        rest = &rest[end..];
+        let mut chars = rest.chars();
+        match (chars.next(), chars.next()) {
+            (Some(sep @ (',' | '.' | ' ' | '\u{a0}' | '\'')), Some(next))
+                if next.is_ascii_digit() =>
+            {
+                separators.push(sep);
+                rest = &rest[sep.len_utf8()..];
+            }
+            _ => break,
+        }
+    }
+    // "2.5k" or "1500,50": a last group of other than three digits after a
+    // point or comma is a fraction, not thousands.
+    let fraction = match (separators.last(), groups.last()) {
+        (Some('.' | ','), Some(last)) if last.len() != 3 => {
+            separators.pop();
+            groups.pop()
+        }
+        _ => None,
+    };
+    let whole: u64 = groups.concat().parse().ok()?;
+    let fraction: f64 = fraction.map_or(0.0, |f| format!("0.{f}").parse().unwrap_or(0.0));
+    let suffix = |text: &str| {
+        AMOUNT_SUFFIXES
+            .iter()
+            .find(|(suffix, _)| text.trim_start().starts_with(suffix))
+            .map(|(_, m)| *m)
+    };
+    // In "3-5k" the suffix belongs to both ends.
+    let multiplier = suffix(rest)
+        .or_else(|| {
+            let upper = rest.trim_start().strip_prefix(['-', '–'])?;
+            suffix(
+                upper
+                    .trim_start()
+                    .trim_start_matches(|c: char| c.is_ascii_digit() || matches!(c, '.' | ',')),
+            )
+        })
+        .unwrap_or(1);
+    Some(((whole as f64 + fraction) * multiplier as f64).round() as u64)
+}
+
+/// Rejected answers in a row before the visitor is offered a person.
+const MAX_FAILED_ANSWERS: u8 = 3;
+
+fn services_overview(lang: Language, catalog: &ServicesCatalog) -> Reply {
+    let mut body = text(lang, Text::ServicesIntro).to_string();
+    for item in &catalog.items {
+        let price = format_money(lang, item.starting_price.into(), &catalog.currency);
+        body.push_str(&format!(
+            "\n• {}: {}",
+            item.name,
+            fill(text(lang, Text::FromPrice), &[("price", &price)])
+        ));
+    }
+    if let Some(first) = catalog.items.first() {
+        body.push_str("\n\n");
+        body.push_str(&fill(
+            text(lang, Text::ServicesOutro),
+            &[("example", &first.name.to_lowercase())],
+        ));
+    }
+    let quick_replies = catalog
+        .items
+        .iter()
+        .map(|item| i18n::fill(text(lang, Text::MoreAbout), &[("service", &item.name)]))
+        .collect();
+    Reply::new(Intent::Services, body).with_quick_replies(quick_replies)
+}
+
+/// Describes one catalog item. `offer_project` adds the "start a project"
+/// follow-up, which only makes sense outside the inquiry flow.
+fn service_detail(
+    lang: Language,
+    item: &ServiceItem,
+    currency: &str,
+    offer_project: bool,
+) -> Reply {
+    let detail = fill(
+        text(lang, Text::ServiceDetail),
+        &[
+            ("service", &item.name),
+            ("description", &item.description),
+            (
+                "price",
+                &format_money(lang, item.starting_price.into(), currency),
+            ),
+        ],
+    );
+    let mut reply = if offer_project {
+        Reply::new(
+            Intent::Services,
+            format!("{detail} {}", text(lang, Text::ServiceFollowUp)),
+        )
+        .with_quick_replies(vec![
+            text(lang, Text::StartProject).to_string(),
+            text(lang, Text::OtherServices).to_string(),
+        ])
+    } else {
+        Reply::new(Intent::Services, detail)
+    };
+    reply.sub_intent = Some(item.id.clone());
+    reply
+}
+
+/// The tenant's reply for `intent` when it has one, otherwise the global
+/// text `key`.
+fn canned(lang: Language, intent: Intent, key: Text, overrides: &ReplyOverrides) -> Reply {
+    let text = overrides
+        .get(intent, lang)
+        .unwrap_or_else(|| text(lang, key));
+    Reply::new(intent, text)
+}
+
+/// The goodbye for a conversation past its limit (see
+/// [`crate::session::SessionLimits`]), pointing the visitor to the team's
+/// contact details for anything left.
+pub fn wrap_up(lang: Language, overrides: &ReplyOverrides) -> Reply {
+    let contact = canned(lang, Intent::Contact, Text::Contact, overrides).text;
+    let mut reply = Reply::new(
+        Intent::Goodbye,
+        format!("{}\n\n{contact}", text(lang, Text::ConversationLimit)),
+    );
+    reply.session_closed = true;
+    reply
+}
+
+/// Answers a question about the business. Returns `None` for intents that
+/// are not purely informational. A service named in the message wins over
+/// the one the visitor's page is about.
+fn informational_reply(
+    session: &Session,
+    intent: Intent,
+    message: &str,
+    catalog: &ServicesCatalog,
+    overrides: &ReplyOverrides,
+    page: Option<&PageHint>,
+) -> Option<Reply> {
+    let lang = session.language;
+    let offer_project = session.state == ConversationState::Idle;
+    match intent {
+        Intent::Services | Intent::Pricing | Intent::Unknown
+            if let Some(item) = catalog.find(message) =>
+        {
+            Some(service_detail(lang, item, &catalog.currency, offer_project))
+        }
+        Intent::Services | Intent::Pricing
+            if let Some(item) = page
+                .and_then(|p| p.service.as_deref())
+                .and_then(|id| catalog.get(id)) =>
+        {
+            Some(service_detail(lang, item, &catalog.currency, offer_project))
+        }
+        // On a pricing page the visitor wants figures, not the way to them.
+        Intent::Pricing if page.is_some_and(|p| p.intent == Some(HintIntent::Pricing)) => {
+            let mut reply = services_overview(lang, catalog);
+            reply.intent = intent;
+            Some(reply)
+        }
+        Intent::Services => {
+            let mut reply = services_overview(lang, catalog);
+            if let Some(list) = overrides.get(intent, lang) {
+                reply.text = list.to_string();
+            }
+            Some(reply)
+        }
+        Intent::Pricing => Some(canned(lang, intent, Text::Pricing, overrides)),
+        Intent::Contact => Some(canned(lang, intent, Text::Contact, overrides)),
+        Intent::Help => Some(canned(lang, intent, Text::Help, overrides)),
+        _ => None,
+    }
+}
+
+/// True for "tell me more about …" in any language, the phrasing of the
+/// catalog quick replies.
+fn asks_for_details(message: &str) -> bool {
+    let normalized = normalize(message);
+    Language::ALL.into_iter().any(|lang| {
+        let template = text(lang, Text::MoreAbout);
+        let prefix = normalize(&template[..template.find("{service}").unwrap_or(template.len())]);
+        normalized.starts_with(&prefix)
+    })
+}
+
+/// Whether a mid-flow message is a side question rather than the answer to
+/// the pending question. Answers win whenever they look plausible, so a
+/// project description that mentions "services" still counts as details.
+fn is_interruption(state: ConversationState, language: Language, message: &str) -> bool {
+    if asks_for_details(message) {
+        return state != ConversationState::Idle;
+    }
+    match state {
+        ConversationState::Idle => false,
+        ConversationState::AskingName => true,
+        ConversationState::AskingEmail | ConversationState::AskingCallbackEmail => {
+            !message.contains('@')
+        }
+        ConversationState::AskingProjectDetails => message.trim_end().ends_with('?'),
+        ConversationState::AskingBudget => !is_valid_budget(message),
+        ConversationState::ConfirmingSummary => {
+            yesno::interpret(message, language).is_none()
+                && mentioned_field(&normalize(message)).is_none()
+        }
+        ConversationState::ConfirmingTranscriptEmail => {
+            yesno::interpret(message, language).is_none()
+        }
+    }
+}
+
+fn field_label(field: Field) -> Text {
+    match field {
+        Field::Name => Text::FieldName,
+        Field::Email => Text::FieldEmail,
+        Field::ProjectDetails => Text::FieldProjectDetails,
+        Field::Budget => Text::FieldBudget,
+    }
+}
+
+/// Repeats the pending question, addressing the visitor by name once known.
+fn pending_reminder(session: &Session) -> Option<String> {
+    let key = match session.state {
+        ConversationState::Idle => return None,
+        ConversationState::AskingName => Text::RemindName,
+        ConversationState::AskingEmail => Text::RemindEmail,
+        ConversationState::AskingProjectDetails => Text::RemindProjectDetails,
+        ConversationState::AskingBudget => Text::RemindBudget,
+        ConversationState::ConfirmingSummary => Text::RemindConfirm,
+        ConversationState::ConfirmingTranscriptEmail => Text::AskTranscriptConsent,
+        ConversationState::AskingCallbackEmail => Text::AskCallbackEmail,
+    };
+    let name = session.data.name.as_deref().unwrap_or_default();
+    let email = session.data.email.as_deref().unwrap_or_default();
+    Some(fill(
+        text(session.language, key),
+        &[("name", name), ("email", email)],
+    ))
+}
+
+/// Moves the session along the flow after `event`. When the flow has no
+/// move for it, the reply written for the move would be wrong, so this
+/// returns the one to send instead: the question still pending.
+fn move_on(session: &mut Session, event: Event) -> Option<Reply> {
+    match flow::fire(session, event) {
+        TransitionResult::Moved(_) => None,
+        TransitionResult::Refused { .. } => {
+            let reminder = pending_reminder(session)
+                .unwrap_or_else(|| text(session.language, Text::Unknown).to_string());
+            Some(Reply::new(Intent::Unknown, reminder))
+        }
+    }
+}
+
+/// How answers to the inquiry questions are judged, from the runtime
+/// config.
+#[derive(Clone, Copy)]
+pub struct AnswerRules<'a> {
+    pub validation: &'a ValidationRules,
+    pub qualification: Option<&'a Qualification>,
+}
+
+/// What the bot has been taught on top of its keywords.
+#[derive(Clone, Copy)]
+pub struct Vocabulary<'a> {
+    pub synonyms: &'a SynonymTable,
+    /// Utterances whose intent people corrected; see [`crate::training`].
+    pub learned: &'a LearnedIntents,
+}
+
+/// Produces the bot's answer to `message` and advances the session's flow.
+/// `overrides` are the session tenant's own replies. Variables in the
+/// reply (see [`crate::template`]) are filled in from the session as it is
+/// after the turn.
+pub fn generate_reply(
+    session: &mut Session,
+    message: &str,
+    catalog: &ServicesCatalog,
+    overrides: &ReplyOverrides,
+    rules: AnswerRules,
+    page: Option<&PageHint>,
+    vocabulary: Vocabulary,
+) -> Reply {
+    let mut reply = respond(
+        session, message, catalog, overrides, rules, page, vocabulary,
+    );
+    let context = template::Context {
+        data: &session.data,
+        pending_question: pending_reminder(session),
+    };
+    let rendered = template::render(&reply.text, &context);
+    let filled = template::unseal(&reply.text);
+    if rendered != filled {
+        reply.template = Some(filled);
+    }
+    reply.text = rendered;
+    reply
+}
+
+fn respond(
+    session: &mut Session,
+    message: &str,
+    catalog: &ServicesCatalog,
+    overrides: &ReplyOverrides,
+    rules: AnswerRules,
+    page: Option<&PageHint>,
+    vocabulary: Vocabulary,
+) -> Reply {
+    if session.handed_off {
+        return Reply::new(Intent::HandedOff, String::new());
+    }
+
+    let lang = session.language;
+    // The survey question only stands for one message.
+    if std::mem::take(&mut session.survey_pending)
+        && let Some(score) = survey::score(message)
+    {
+        let mut reply = Reply::new(Intent::Rating, text(lang, Text::SurveyThanks));
+        reply.satisfaction = Some(score);
+        return reply;
+    }
+    // What the message asks for is read with aliases resolved; answers
+    // keep the visitor's own words.
+    let expanded = vocabulary.synonyms.expand(message);
+    let learned = vocabulary.learned.intent(message);
+    let intent = match learned.unwrap_or_else(|| detect_intent(&expanded)) {
+        // Mid-flow, a message that matches nothing is usually the answer.
+        Intent::Unknown if session.state == ConversationState::Idle => page
+            .and_then(|p| p.intent)
+            .map_or(Intent::Unknown, Intent::from),
+        intent => intent,
+    };
+
+    if intent == Intent::Reset {
+        session.reset();
+        return Reply::new(intent, text(lang, Text::Reset));
+    }
+
+    // Works mid-flow too; whatever was captured goes along to the team.
+    if intent == Intent::Escalate {
+        if !session.operators_available {
+            return offer_callback(session);
+        }
+        let mut reply = Reply::new(intent, text(lang, Text::Escalated));
+        reply.escalation_requested = true;
+        return reply;
+    }
+
+    // Corrections only apply to fields that already have a value; anything
+    // else is the normal answer to the pending question.
+    if let Some(correction) = detect_correction(message, lang, rules.validation)
+        && session.data.has(correction.field)
+    {
+        let label = text(lang, field_label(correction.field));
+        let mut reply = Reply::new(
+            Intent::Correction,
+            fill(
+                text(lang, Text::FieldUpdated),
+                &[("field", label), ("value", &correction.value)],
+            ),
+        );
+        session.data.set(correction.field, correction.value);
+        retag(session, rules.qualification);
+        if let Some(reminder) = pending_reminder(session) {
+            reply.text.push(' ');
+            reply.text.push_str(&reminder);
+        }
+        return reply;
+    }
+
+    if session.state != ConversationState::Idle
+        && is_interruption(session.state, session.language, message)
+        && let Some(mut reply) =
+            informational_reply(session, intent, &expanded, catalog, overrides, page)
+    {
+        if let Some(reminder) = pending_reminder(session) {
+            reply.text.push_str("\n\n");
+            reply.text.push_str(&reminder);
+        }
+        return reply;
+    }
+
+    match session.state {
+        ConversationState::Idle => idle_reply(session, intent, &expanded, catalog, overrides, page),
+        ConversationState::AskingName => answer_name(session, intent, message, rules),
+        ConversationState::AskingEmail => answer_email(session, intent, message, rules),
+        ConversationState::AskingProjectDetails => {
+            answer_project_details(session, intent, message, rules)
+        }
+        ConversationState::AskingBudget => {
+            answer_budget(session, intent, message, rules, &catalog.currency)
+        }
+        ConversationState::ConfirmingSummary => handle_confirmation(session, message),
+        ConversationState::ConfirmingTranscriptEmail => handle_transcript_consent(session, message),
+        ConversationState::AskingCallbackEmail => {
+            answer_callback_email(session, intent, message, rules)
+        }
+    }
+}
+
+/// Outside the inquiry flow: information, small talk, or the way into the
+/// flow.
+fn idle_reply(
+    session: &mut Session,
+    intent: Intent,
+    message: &str,
+    catalog: &ServicesCatalog,
+    overrides: &ReplyOverrides,
+    page: Option<&PageHint>,
+) -> Reply {
+    let lang = session.language;
+    if let Some(reply) = informational_reply(session, intent, message, catalog, overrides, page) {
+        return reply;
+    }
+    match intent {
+        Intent::StartInquiry => {
+            session.data = Default::default();
+            let mut reply = ask_next(session, Event::StartInquiry);
+            reply.intent = intent;
+            reply
+        }
+        Intent::EmailTranscript => offer_transcript(session),
+        Intent::Greeting => canned(lang, intent, Text::Greeting, overrides),
+        Intent::Thanks => canned(lang, intent, Text::Thanks, overrides),
+        Intent::Goodbye => canned(lang, intent, Text::Goodbye, overrides),
+        _ => Reply::new(Intent::Unknown, text(lang, Text::Unknown)),
+    }
+}
+
+fn answer_name(session: &mut Session, intent: Intent, message: &str, rules: AnswerRules) -> Reply {
+    let name = extract_name(message);
+    let checked = match intent {
+        Intent::Skip => Err(Invalid::Name),
+        _ => rules.validation.check_name(&name, session.language),
+    };
+    if let Err(invalid) = checked {
+        let prompt = rules.validation.prompt(invalid, session.language, "");
+        return reject_answer(session, intent, prompt);
+    }
+    session.data.name = Some(name);
+    ask_next(session, Event::Answered)
+}
+
+fn answer_email(session: &mut Session, intent: Intent, message: &str, rules: AnswerRules) -> Reply {
+    match extract_email(message) {
+        Some(email) if rules.validation.check_email(&email).is_ok() => {
+            session.data.email = Some(email);
+            ask_next(session, Event::Answered)
+        }
+        _ => {
+            let prompt = text(session.language, Text::InvalidEmail).to_string();
+            reject_answer(session, intent, prompt)
+        }
+    }
+}
+
+fn answer_project_details(
+    session: &mut Session,
+    intent: Intent,
+    message: &str,
+    rules: AnswerRules,
+) -> Reply {
+    let details = message.trim();
+    if let Err(invalid) = rules.validation.check_project_details(details) {
+        let prompt = rules.validation.prompt(invalid, session.language, "");
+        return reject_answer(session, intent, prompt);
+    }
+    session.data.project_details = Some(details.to_string());
+    ask_next(session, Event::Answered)
+}
+
+fn answer_budget(
+    session: &mut Session,
+    intent: Intent,
+    message: &str,
+    rules: AnswerRules,
+    currency: &str,
+) -> Reply {
+    let budget = message.trim();
+    match rules.validation.check_budget(budget) {
+        Ok(()) => {
+            session.data.budget = Some(FieldValue::Given(budget.to_string()));
+            qualify(session, rules.qualification, currency)
+        }
+        Err(_) if intent == Intent::Skip => {
+            session.data.budget = Some(FieldValue::Unspecified);
+            session.data.tag = None;
+            let mut reply = ask_next(session, Event::Answered);
+            reply.intent = Intent::Skip;
+            reply
+        }
+        Err(invalid) => {
+            let prompt = rules.validation.prompt(invalid, session.language, currency);
+            reject_answer(session, intent, prompt)
+        }
+    }
+}
+
+/// Moves on after `event` and asks the question of the state the flow
+/// picks: the first unanswered question, or the summary once every field
+/// is captured. Editing a field from the summary therefore returns
+/// straight to the summary.
+fn ask_next(session: &mut Session, event: Event) -> Reply {
+    if let Some(reply) = move_on(session, event) {
+        return reply;
+    }
+    question(session)
+}
+
+/// The question for the state the session is in, asked for the first time.
+fn question(session: &Session) -> Reply {
+    let lang = session.language;
+    match session.state {
+        ConversationState::AskingName => Reply::new(Intent::Answer, text(lang, Text::AskName)),
+        ConversationState::AskingEmail => {
+            let name = session.data.name.as_deref().unwrap_or_default();
+            Reply::new(
+                Intent::Answer,
+                fill(text(lang, Text::AskEmail), &[("name", name)]),
+            )
+        }
+        ConversationState::AskingProjectDetails => {
+            Reply::new(Intent::Answer, text(lang, Text::AskProjectDetails))
+        }
+        ConversationState::AskingBudget => ask_budget(lang),
+        ConversationState::ConfirmingSummary => summary(session),
+        ConversationState::Idle
+        | ConversationState::ConfirmingTranscriptEmail
+        | ConversationState::AskingCallbackEmail => Reply::new(
+            Intent::Answer,
+            pending_reminder(session).unwrap_or_default(),
+        ),
+    }
+}
+
+/// Moves on from a budget answer. A budget below the minimum is tagged and
+/// either sent to the self-service offering, ending the inquiry, or told
+/// what to expect before the inquiry goes on.
+fn qualify(session: &mut Session, qualification: Option<&Qualification>, currency: &str) -> Reply {
+    let lang = session.language;
+    let Some(qualification) = below_minimum(session, qualification) else {
+        session.data.tag = None;
+        return ask_next(session, Event::Answered);
+    };
+    let min_budget = format_money(lang, qualification.min_budget.into(), currency);
+    if let Some(url) = &qualification.self_service_url {
+        session.data.tag = Some(LeadTag::SelfService);
+        if let Some(reply) = move_on(session, Event::Answered) {
+            return reply;
+        }
+        let name = session.data.name.as_deref().unwrap_or_default();
+        return Reply::new(
+            Intent::Answer,
+            fill(
+                text(lang, Text::SelfServiceOffer),
+                &[("name", name), ("min_budget", &min_budget), ("url", url)],
+            ),
+        );
+    }
+    session.data.tag = Some(LeadTag::BelowMinimumBudget);
+    let mut reply = ask_next(session, Event::Answered);
+    reply.text = format!(
+        "{}\n\n{}",
+        fill(
+            text(lang, Text::BudgetBelowMinimum),
+            &[("min_budget", &min_budget)]
+        ),
+        reply.text
+    );
+    reply
+}
+
+/// The qualification the session's budget falls short of, if any.
+fn below_minimum<'a>(
+    session: &Session,
+    qualification: Option<&'a Qualification>,
+) -> Option<&'a Qualification> {
+    let amount = match &session.data.budget {
+        Some(FieldValue::Given(budget)) => budget_amount(budget),
+        _ => None,
+    };
+    qualification.filter(|q| amount.is_some_and(|a| a < u64::from(q.min_budget)))
+}
+
+/// Tags the session as [`qualify`] would after its data was corrected
+/// without the budget question being asked again. A lead already sent to
+/// the self-service offering stays there while still below the minimum.
+fn retag(session: &mut Session, qualification: Option<&Qualification>) {
+    session.data.tag = match below_minimum(session, qualification) {
+        None => None,
+        Some(_) if session.data.tag == Some(LeadTag::SelfService) => Some(LeadTag::SelfService),
+        Some(_) => Some(LeadTag::BelowMinimumBudget),
+    };
+}
+
+fn ask_budget(lang: Language) -> Reply {
+    Reply::new(Intent::Answer, text(lang, Text::AskBudget))
+        .with_quick_replies(vec![text(lang, Text::NotSureYet).to_string()])
+}
+
+/// Lists everything captured and asks the visitor to confirm it.
+fn summary(session: &Session) -> Reply {
+    let lang = session.language;
+    let data = &session.data;
+    let budget = match &data.budget {
+        Some(FieldValue::Given(budget)) => budget.as_str(),
+        _ => text(lang, Text::NotSpecified),
+    };
+    let rows = [
+        (Field::Name, data.name.as_deref().unwrap_or_default()),
+        (Field::Email, data.email.as_deref().unwrap_or_default()),
+        (
+            Field::ProjectDetails,
+            data.project_details.as_deref().unwrap_or_default(),
+        ),
+        (Field::Budget, budget),
+    ];
+    let mut body = text(lang, Text::SummaryIntro).to_string();
+    for (field, value) in rows {
+        body.push_str(&format!(
+            "\n• {}: {}",
+            capitalize(text(lang, field_label(field))),
+            template::seal(value)
+        ));
+    }
+    body.push_str("\n\n");
+    body.push_str(text(lang, Text::RemindConfirm));
+    let mut reply = Reply::new(Intent::Answer, body).with_quick_replies(vec![
+        text(lang, Text::ConfirmSend).to_string(),
+        text(lang, Text::ChangeSomething).to_string(),
+    ]);
+    reply.preview = Some(InquiryReport::new(session).markdown());
+    reply
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .map(|first| first.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+const EDIT_KEYWORDS: &[&str] = &[
+    "change", "edit", "fix", "update", "zmień", "zmienić", "popraw", "edytuj", "cambiar", "cambia",
+    "editar", "corregir",
+];
+
+const FIELD_KEYWORDS: &[(Field, &[&str])] = &[
+    (Field::Name, &["name", "imię", "imie", "nombre"]),
+    (Field::Email, &["email", "e mail", "mail", "correo"]),
+    (
+        Field::ProjectDetails,
+        &[
+            "project",
+            "description",
+            "details",
+            "projekt",
+            "opis",
+            "proyecto",
+            "descripción",
+        ],
+    ),
+    (Field::Budget, &["budget", "budżet", "presupuesto"]),
+];
+
+fn mentioned_field(normalized: &str) -> Option<Field> {
+    FIELD_KEYWORDS
+        .iter()
+        .find(|(_, keywords)| contains_any(normalized, keywords))
+        .map(|(field, _)| *field)
+}
+
+/// The summary step: "yes" confirms the inquiry, naming a field reopens
+/// that question, and anything negative asks what to change. A hedge gets
+/// the reminder to confirm.
+fn handle_confirmation(session: &mut Session, message: &str) -> Reply {
+    let lang = session.language;
+    let normalized = normalize(message);
+    let answer = yesno::interpret(message, lang);
+    let negated = answer == Some(YesNo::No);
+    let editing = contains_any(&normalized, EDIT_KEYWORDS) && !yesno::is_double_negative(message);
+
+    if answer == Some(YesNo::Yes) && !editing {
+        return confirm_inquiry(session);
+    }
+
+    if let Some(field) = mentioned_field(&normalized) {
+        if let Some(reply) = move_on(session, Event::Edit(field)) {
+            return reply;
+        }
+        let prompt = fill(
+            text(lang, Text::AskNewValue),
+            &[("field", text(lang, field_label(field)))],
+        );
+        let mut reply = Reply::new(Intent::Answer, prompt);
+        if field == Field::Budget {
+            reply.quick_replies = vec![text(lang, Text::NotSureYet).to_string()];
+        }
+        return reply;
+    }
+
+    if negated || editing {
+        let fields = FIELD_KEYWORDS
+            .iter()
+            .map(|(field, _)| capitalize(text(lang, field_label(*field))))
+            .collect();
+        return Reply::new(Intent::Answer, text(lang, Text::WhatToChange))
+            .with_quick_replies(fields);
+    }
+
+    let mut reply = summary(session);
+    reply.text = text(lang, Text::RemindConfirm).to_string();
+    reply
+}
+
+/// Replies to an answer that failed validation. A "skip" on a required
+/// question gets an explanation instead of the validation hint. After
+/// [`MAX_FAILED_ANSWERS`] in a row the visitor is offered a person.
+fn reject_answer(session: &mut Session, intent: Intent, prompt: String) -> Reply {
+    let lang = session.language;
+    session.failed_answers = session.failed_answers.saturating_add(1);
+    let mut reply = if intent == Intent::Skip {
+        let mut reply = Reply::new(Intent::Skip, text(lang, Text::FieldRequired));
+        if let Some(reminder) = pending_reminder(session) {
+            reply.text.push(' ');
+            reply.text.push_str(&reminder);
+        }
+        reply
+    } else {
+        Reply::new(Intent::Answer, prompt)
+    };
+    if session.failed_answers >= MAX_FAILED_ANSWERS {
+        let (offer, option) = if session.operators_available {
+            (Text::OfferPerson, Text::TalkToPerson)
+        } else {
+            (Text::OfferCallback, Text::RequestCallback)
+        };
+        if session.state != ConversationState::AskingCallbackEmail {
+            reply.text.push_str("\n\n");
+            reply.text.push_str(text(lang, offer));
+            reply.quick_replies = vec![text(lang, option).to_string()];
+        }
+    }
+    reply
+}
+
+/// Offers to have the team email the visitor back when nobody is online
+/// to talk to: at the address they gave, or at one they are asked for.
+/// Mid-inquiry the inquiry asks for the address itself, and goes to the
+/// team once confirmed.
+fn offer_callback(session: &mut Session) -> Reply {
+    let lang = session.language;
+    if let Some(email) = session.data.email.clone() {
+        let mut reply = Reply::new(
+            Intent::Escalate,
+            fill(text(lang, Text::CallbackQueued), &[("email", &email)]),
+        );
+        reply.callback_requested = true;
+        if let Some(reminder) = pending_reminder(session) {
+            reply.text.push_str("\n\n");
+            reply.text.push_str(&reminder);
+        }
+        return reply;
+    }
+    if session.state.in_inquiry() {
+        let mut reply = Reply::new(Intent::Escalate, text(lang, Text::CallbackAfterInquiry));
+        if let Some(reminder) = pending_reminder(session) {
+            reply.text.push(' ');
+            reply.text.push_str(&reminder);
+        }
+        return reply;
+    }
+    if session.state != ConversationState::AskingCallbackEmail
+        && let Some(reply) = move_on(session, Event::CallbackRequested)
+    {
+        return reply;
+    }
+    Reply::new(Intent::Escalate, text(lang, Text::AskCallbackEmail))
+}
+
+/// The address to email the visitor back at. "Skip" or "no" drops the
+/// callback.
+fn answer_callback_email(
+    session: &mut Session,
+    intent: Intent,
+    message: &str,
+    rules: AnswerRules,
+) -> Reply {
+    let lang = session.language;
+    if intent == Intent::Skip || yesno::interpret(message, lang) == Some(YesNo::No) {
+        if let Some(reply) = move_on(session, Event::Answered) {
+            return reply;
+        }
+        return Reply::new(Intent::Answer, text(lang, Text::CallbackDeclined));
+    }
+    match extract_email(message) {
+        Some(email) if rules.validation.check_email(&email).is_ok() => {
+            if let Some(reply) = move_on(session, Event::Answered) {
+                return reply;
+            }
+            let mut reply = Reply::new(
+                Intent::Answer,
+                fill(text(lang, Text::CallbackQueued), &[("email", &email)]),
+            );
+            session.data.email = Some(email);
+            reply.callback_requested = true;
+            reply
+        }
+        _ => {
+            let prompt = text(lang, Text::InvalidEmail).to_string();
+            reject_answer(session, intent, prompt)
+        }
+    }
+}
+
+/// Ends the inquiry flow once the visitor confirmed the summary. The caller
+/// generates the report when it sees `inquiry_confirmed`.
+fn confirm_inquiry(session: &mut Session) -> Reply {
+    if let Some(reply) = move_on(session, Event::Confirmed) {
+        return reply;
+    }
+    let name = session.data.name.clone().unwrap_or_default();
+    let email = session.data.email.clone().unwrap_or_default();
+    let mut reply = Reply::new(
+        Intent::Answer,
+        fill(
+            text(session.language, Text::InquiryComplete),
+            &[("name", &name), ("email", &email)],
+        ),
+    );
+    reply.inquiry_confirmed = true;
+    reply.with_quick_replies(vec![
+        text(session.language, Text::EmailTranscriptOption).to_string(),
+    ])
+}
+
+/// Asks for consent before emailing the transcript to the address captured
+/// during the inquiry.
+fn offer_transcript(session: &mut Session) -> Reply {
+    let lang = session.language;
+    let Some(email) = session.data.email.as_deref() else {
+        return Reply::new(
+            Intent::EmailTranscript,
+            text(lang, Text::TranscriptNeedsEmail),
+        );
+    };
+    let prompt = fill(text(lang, Text::AskTranscriptConsent), &[("email", email)]);
+    // Asked again while the consent is pending.
+    if session.state != ConversationState::ConfirmingTranscriptEmail
+        && let Some(reply) = move_on(session, Event::TranscriptRequested)
+    {
+        return reply;
+    }
+    Reply::new(Intent::EmailTranscript, prompt).with_quick_replies(vec![
+        text(lang, Text::ConfirmSend).to_string(),
+        text(lang, Text::NoThanks).to_string(),
+    ])
+}
+
+/// Only an explicit "yes" counts as consent; a hedge is asked again. The
+/// caller sends the email when it sees `transcript_requested`.
+fn handle_transcript_consent(session: &mut Session, message: &str) -> Reply {
+    let lang = session.language;
+    let email = session.data.email.clone().unwrap_or_default();
+    let answer = yesno::interpret(message, lang);
+    if answer == Some(YesNo::No) {
+        if let Some(reply) = move_on(session, Event::Answered) {
+            return reply;
+        }
+        return Reply::new(Intent::Answer, text(lang, Text::TranscriptEmailDeclined));
+    }
+    if answer == Some(YesNo::Yes) {
+        if let Some(reply) = move_on(session, Event::Answered) {
+            return reply;
+        }
+        let mut reply = Reply::new(
+            Intent::Answer,
+            fill(
+                text(lang, Text::TranscriptEmailQueued),
+                &[("email", &email)],
+            ),
+        );
+        reply.transcript_requested = true;
+        return reply;
+    }
+    offer_transcript(session)
+}
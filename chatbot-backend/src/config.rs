@@ -0,0 +1,470 @@
+//! Settings that can change while the server runs.
+//!
+//! The file named by `CONFIG_PATH` is JSON:
+//!
+//! ```json
+//! {
+//!   "webhook_url": "https://crm.example.com/hooks/chatbot",
+//!   "prices": { "seo": 450 },
+//!   "report_format": "docx",
+//!   "qualification": { "min_budget": 2000, "self_service_url": "https://example.com/packages" },
+//!   "page_hints": [
+//!     { "path": "/pricing", "intent": "pricing" },
+//!     { "path": "/services/seo", "service": "seo" }
+//!   ],
+//!   "pipeline": { "pre": ["collapse_whitespace", "redact_card_numbers"], "post": ["signature"] },
+//!   "validation": { "name": { "max_chars": 40 }, "budget": { "min": 500 } },
+//!   "replies": { "en": { "greeting": "Hello! How can we help?" } }
+//! }
+//! ```
+//!
+//! Every key is optional. [`watch`] polls the file and applies a new
+//! version only when it validates; sessions are untouched either way. Each
+//! attempt is kept in an audit log served under `/admin/config`.
+//!
+//! Settings only read at startup come from the environment; where logs go
+//! is one of them, see [`crate::logging`].
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    catalog::ServicesCatalog,
+    chatbot::{PageHint, Qualification, Reply},
+    events::now_ms,
+    i18n::{self, Language, Text},
+    pipeline::{MessagePreprocessor, PipelineConfig, Processors, ReplyPostprocessor},
+    report::ReportFormat,
+    session::Session,
+    state::SharedState,
+    template,
+    trace::StepTrace,
+    validation::ValidationRules,
+};
+
+/// Audit entries kept in memory; older ones are dropped.
+const AUDIT_CAPACITY: usize = 100;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RuntimeConfig {
+    /// Takes precedence over `WEBHOOK_URL`.
+    pub webhook_url: Option<String>,
+    /// Starting price per catalog item id, replacing the catalog's own.
+    pub prices: BTreeMap<String, u32>,
+    /// Reply texts per language, replacing the built-in ones.
+    pub replies: BTreeMap<Language, BTreeMap<Text, String>>,
+    /// How inquiry reports are written; PDF unless set.
+    pub report_format: ReportFormat,
+    /// The minimum budget for an inquiry; any budget qualifies unless set.
+    pub qualification: Option<Qualification>,
+    /// How messages from the widget on some pages are read; the longest
+    /// matching path wins.
+    pub page_hints: Vec<PageHint>,
+    /// The steps run on each message and reply, by name and in order.
+    pub pipeline: PipelineConfig,
+    /// What counts as an answer to each inquiry question; see
+    /// [`crate::validation`].
+    pub validation: ValidationRules,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(serde_json::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "cannot read config: {e}"),
+            ConfigError::Parse(e) => write!(f, "invalid config: {e}"),
+            ConfigError::Invalid(m) => write!(f, "invalid config: {m}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl RuntimeConfig {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let raw = fs::read_to_string(path).map_err(ConfigError::Io)?;
+        serde_json::from_str(&raw).map_err(ConfigError::Parse)
+    }
+
+    fn validate(
+        &self,
+        catalog: &ServicesCatalog,
+        processors: &Processors,
+    ) -> Result<(), ConfigError> {
+        let invalid = |m: String| Err(ConfigError::Invalid(m));
+        if let Some(name) = processors.unknown(&self.pipeline) {
+            return invalid(format!("pipeline: no step named {name:?}"));
+        }
+        if let Some(url) = &self.webhook_url
+            && !(url.starts_with("http://") || url.starts_with("https://"))
+        {
+            return invalid(format!("webhook_url must be an http(s) URL, got {url:?}"));
+        }
+        if let Some(qualification) = &self.qualification {
+            if qualification.min_budget == 0 {
+                return invalid("qualification.min_budget must be above zero".into());
+            }
+            if let Some(url) = &qualification.self_service_url
+                && !(url.starts_with("http://") || url.starts_with("https://"))
+            {
+                return invalid(format!(
+                    "qualification.self_service_url must be an http(s) URL, got {url:?}"
+                ));
+            }
+        }
+        if let Err(e) = self.validation.validate() {
+            return invalid(e);
+        }
+        for (i, hint) in self.page_hints.iter().enumerate() {
+            if !hint.path.starts_with('/') {
+                return invalid(format!(
+                    "page_hints[{i}].path must start with '/', got {:?}",
+                    hint.path
+                ));
+            }
+            if hint.intent.is_none() && hint.service.is_none() {
+                return invalid(format!("page_hints[{i}] needs an intent or a service"));
+            }
+            if let Some(id) = &hint.service
+                && catalog.get(id).is_none()
+            {
+                return invalid(format!("page_hints[{i}].service: no catalog item {id:?}"));
+            }
+        }
+        for (id, price) in &self.prices {
+            if catalog.get(id).is_none() {
+                return invalid(format!("prices: no catalog item {id:?}"));
+            }
+            if *price == 0 {
+                return invalid(format!("prices: {id} must be above zero"));
+            }
+        }
+        for (lang, texts) in &self.replies {
+            for (key, text) in texts {
+                if text.trim().is_empty() {
+                    return invalid(format!(
+                        "replies.{}.{} is empty",
+                        lang.code(),
+                        text_key(*key)
+                    ));
+                }
+                if let Err(e) = template::check(text) {
+                    return invalid(format!("replies.{}.{}: {e}", lang.code(), text_key(*key)));
+                }
+                let known = i18n::placeholders(i18n::builtin(*lang, *key));
+                if let Some(unknown) = i18n::placeholders(text)
+                    .into_iter()
+                    .find(|p| !known.contains(p) && !template::is_tag(p))
+                {
+                    return invalid(format!(
+                        "replies.{}.{} uses unknown placeholder {{{unknown}}}",
+                        lang.code(),
+                        text_key(*key)
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Names of the settings that differ from `old`, for the audit log.
+    fn changes_from(&self, old: &RuntimeConfig) -> Vec<String> {
+        let mut changes = Vec::new();
+        if self.webhook_url != old.webhook_url {
+            changes.push("webhook_url".to_string());
+        }
+        if self.qualification != old.qualification {
+            changes.push("qualification".to_string());
+        }
+        if self.report_format != old.report_format {
+            changes.push("report_format".to_string());
+        }
+        if self.page_hints != old.page_hints {
+            changes.push("page_hints".to_string());
+        }
+        if self.pipeline != old.pipeline {
+            changes.push("pipeline".to_string());
+        }
+        if self.validation != old.validation {
+            changes.push("validation".to_string());
+        }
+        let ids: BTreeSet<&String> = old.prices.keys().chain(self.prices.keys()).collect();
+        for id in ids {
+            if old.prices.get(id) != self.prices.get(id) {
+                changes.push(format!("prices.{id}"));
+            }
+        }
+        for lang in Language::ALL {
+            let (before, after) = (old.replies.get(&lang), self.replies.get(&lang));
+            let keys: BTreeSet<Text> = before
+                .into_iter()
+                .chain(after)
+                .flat_map(|texts| texts.keys().copied())
+                .collect();
+            for key in keys {
+                if before.and_then(|t| t.get(&key)) != after.and_then(|t| t.get(&key)) {
+                    changes.push(format!("replies.{}.{}", lang.code(), text_key(key)));
+                }
+            }
+        }
+        changes
+    }
+}
+
+/// The key as written in the config file, e.g. `ask_name`.
+fn text_key(key: Text) -> String {
+    serde_json::to_value(key)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Applied { changes: Vec<String> },
+    Rejected { error: String },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEntry {
+    pub at_ms: u64,
+    pub source: String,
+    #[serde(flatten)]
+    pub outcome: AuditOutcome,
+}
+
+/// The configuration currently in force, swapped atomically on reload.
+pub struct LiveConfig {
+    base_catalog: ServicesCatalog,
+    default_webhook_url: Option<String>,
+    current: RwLock<RuntimeConfig>,
+    catalog: RwLock<Arc<ServicesCatalog>>,
+    audit: Mutex<VecDeque<AuditEntry>>,
+    processors: Processors,
+    /// One bot's config, which can't set the reply texts every bot shares;
+    /// see [`crate::bots`].
+    bot: bool,
+}
+
+impl LiveConfig {
+    pub fn new(catalog: ServicesCatalog) -> Self {
+        Self {
+            catalog: RwLock::new(Arc::new(catalog.clone())),
+            base_catalog: catalog,
+            default_webhook_url: None,
+            current: RwLock::new(RuntimeConfig::default()),
+            audit: Mutex::new(VecDeque::new()),
+            processors: Processors::default(),
+            bot: false,
+        }
+    }
+
+    /// A config for one bot, with `catalog` and this config's steps and
+    /// default webhook URL.
+    pub fn for_bot(&self, catalog: ServicesCatalog) -> Self {
+        Self {
+            default_webhook_url: self.default_webhook_url.clone(),
+            processors: self.processors.clone(),
+            bot: true,
+            ..Self::new(catalog)
+        }
+    }
+
+    /// Makes `step` available to the `pipeline.pre` setting. Register
+    /// before the config file is first loaded, or it will be refused.
+    pub fn register_preprocessor(&mut self, step: Arc<dyn MessagePreprocessor>) {
+        self.processors.register_pre(step);
+    }
+
+    /// Makes `step` available to the `pipeline.post` setting.
+    pub fn register_postprocessor(&mut self, step: Arc<dyn ReplyPostprocessor>) {
+        self.processors.register_post(step);
+    }
+
+    /// The webhook URL used when the config file doesn't set one.
+    pub fn set_default_webhook_url(&mut self, url: Option<String>) {
+        self.default_webhook_url = url;
+    }
+
+    pub fn catalog(&self) -> Arc<ServicesCatalog> {
+        self.catalog.read().unwrap().clone()
+    }
+
+    pub fn webhook_url(&self) -> Option<String> {
+        let current = self.current.read().unwrap();
+        current
+            .webhook_url
+            .clone()
+            .or_else(|| self.default_webhook_url.clone())
+    }
+
+    pub fn qualification(&self) -> Option<Qualification> {
+        self.current.read().unwrap().qualification.clone()
+    }
+
+    pub fn validation(&self) -> ValidationRules {
+        self.current.read().unwrap().validation.clone()
+    }
+
+    /// The hint for the page at `page_url`, if any covers it.
+    pub fn page_hint(&self, page_url: &str) -> Option<PageHint> {
+        let current = self.current.read().unwrap();
+        current
+            .page_hints
+            .iter()
+            .filter(|hint| hint.covers(page_url))
+            .max_by_key(|hint| hint.path.trim_end_matches('/').len())
+            .cloned()
+    }
+
+    /// `message` after the configured preprocessors.
+    pub fn preprocess(&self, session: &Session, message: &str) -> String {
+        self.preprocess_steps(session, message).0
+    }
+
+    /// `message` after the configured preprocessors, and what each did.
+    pub fn preprocess_steps(&self, session: &Session, message: &str) -> (String, Vec<StepTrace>) {
+        let current = self.current.read().unwrap();
+        self.processors
+            .preprocess(&current.pipeline, session, message)
+    }
+
+    /// Runs the configured postprocessors over `reply`, returning what
+    /// each did.
+    pub fn postprocess(&self, session: &Session, reply: &mut Reply) -> Vec<StepTrace> {
+        let current = self.current.read().unwrap();
+        self.processors
+            .postprocess(&current.pipeline, session, reply)
+    }
+
+    pub fn report_format(&self) -> ReportFormat {
+        self.current.read().unwrap().report_format
+    }
+
+    pub fn current(&self) -> RuntimeConfig {
+        self.current.read().unwrap().clone()
+    }
+
+    pub fn audit_log(&self) -> Vec<AuditEntry> {
+        self.audit.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Loads, validates and applies the file at `path`, recording the
+    /// outcome in the audit log. On error the running config is kept.
+    pub fn reload(&self, path: &Path) -> Result<Vec<String>, ConfigError> {
+        let result =
+            RuntimeConfig::from_file(path).and_then(|config| self.validate_and_apply(config));
+        self.record(&path.display().to_string(), &result);
+        result
+    }
+
+    /// Validates and applies `config`, which came from `source`, as
+    /// [`LiveConfig::reload`] does with a file.
+    pub fn replace(&self, config: RuntimeConfig, source: &str) -> Result<Vec<String>, ConfigError> {
+        let result = self.validate_and_apply(config);
+        self.record(source, &result);
+        result
+    }
+
+    fn validate_and_apply(&self, config: RuntimeConfig) -> Result<Vec<String>, ConfigError> {
+        config.validate(&self.base_catalog, &self.processors)?;
+        if self.bot && !config.replies.is_empty() {
+            return Err(ConfigError::Invalid(
+                "replies: a bot's replies are set through /admin/tenants/{bot}/replies".into(),
+            ));
+        }
+        Ok(self.apply(config))
+    }
+
+    fn record(&self, source: &str, result: &Result<Vec<String>, ConfigError>) {
+        let outcome = match result {
+            Ok(changes) => AuditOutcome::Applied {
+                changes: changes.clone(),
+            },
+            Err(e) => AuditOutcome::Rejected {
+                error: e.to_string(),
+            },
+        };
+        let mut audit = self.audit.lock().unwrap();
+        if audit.len() == AUDIT_CAPACITY {
+            audit.pop_front();
+        }
+        audit.push_back(AuditEntry {
+            at_ms: now_ms(),
+            source: source.to_string(),
+            outcome,
+        });
+    }
+
+    fn apply(&self, config: RuntimeConfig) -> Vec<String> {
+        let mut current = self.current.write().unwrap();
+        let changes = config.changes_from(&current);
+
+        let mut catalog = self.base_catalog.clone();
+        for item in &mut catalog.items {
+            if let Some(price) = config.prices.get(&item.id) {
+                item.starting_price = *price;
+            }
+        }
+        *self.catalog.write().unwrap() = Arc::new(catalog);
+        if self.bot {
+            *current = config;
+            return changes;
+        }
+
+        let overrides: HashMap<(Language, Text), String> = config
+            .replies
+            .iter()
+            .flat_map(|(lang, texts)| {
+                texts
+                    .iter()
+                    .map(|(key, text)| ((*lang, *key), text.clone()))
+            })
+            .collect();
+        i18n::set_overrides(overrides);
+
+        *current = config;
+        changes
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Polls `path` and reloads it whenever its modification time changes.
+pub async fn watch(state: SharedState, path: PathBuf, interval: Duration) {
+    let mut last_modified = modified(&path);
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let now_modified = modified(&path);
+        if now_modified == last_modified {
+            continue;
+        }
+        last_modified = now_modified;
+        match state.config.reload(&path) {
+            Ok(changes) => info!(?changes, "configuration reloaded"),
+            Err(e) => warn!(
+                "{}: {e}; keeping the previous configuration",
+                path.display()
+            ),
+        }
+    }
+}
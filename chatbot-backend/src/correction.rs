@@ -0,0 +1,127 @@
+//! Detection of corrections to already captured data, such as
+//! "actually my email is bob@example.com".
+
+use crate::{
+    chatbot::extract_email,
+    i18n::Language,
+    session::Field,
+    text::{contains_any, normalize},
+    validation::ValidationRules,
+};
+
+/// A new value for a field the visitor already answered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Correction {
+    pub field: Field,
+    pub value: String,
+}
+
+const CORRECTION_MARKERS: &[&str] = &[
+    "actually",
+    "correction",
+    "sorry",
+    "i meant",
+    "change my",
+    "update my",
+    "właściwie",
+    "poprawka",
+    "przepraszam",
+    "zmień",
+    "pomyliłem się",
+    "pomyliłam się",
+    "en realidad",
+    "perdón",
+    "corrección",
+    "quise decir",
+    "cambia",
+];
+
+const NAME_CUES: &[&str] = &[
+    "my name is",
+    "name to",
+    "call me",
+    "mam na imię",
+    "nazywam się",
+    "me llamo",
+    "mi nombre es",
+];
+
+const EMAIL_CUES: &[&str] = &["email", "e-mail", "mail", "correo"];
+
+const BUDGET_CUES: &[&str] = &["budget", "budżet", "presupuesto"];
+
+/// Filler between a cue and the value ("budget *is* 5000").
+const VALUE_LEAD_INS: &[&str] = &["is", "to", "of", "jest", "wynosi", "na", "es", "de", "a"];
+
+/// Returns the correction carried by `message`, if it has a correction
+/// marker, names a field and the new value passes that field's `rules` in
+/// `lang`.
+pub fn detect_correction(
+    message: &str,
+    lang: Language,
+    rules: &ValidationRules,
+) -> Option<Correction> {
+    let normalized = normalize(message);
+    if !contains_any(&normalized, CORRECTION_MARKERS) {
+        return None;
+    }
+
+    if contains_any(&normalized, EMAIL_CUES)
+        && let Some(email) = extract_email(message)
+        && rules.check_email(&email).is_ok()
+    {
+        return Some(Correction {
+            field: Field::Email,
+            value: email,
+        });
+    }
+
+    if let Some(rest) = text_after_cue(message, NAME_CUES) {
+        let name = rest
+            .split([',', ';', '.', '!'])
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        if rules.check_name(&name, lang).is_ok() {
+            return Some(Correction {
+                field: Field::Name,
+                value: name,
+            });
+        }
+    }
+
+    if let Some(rest) = text_after_cue(message, BUDGET_CUES) {
+        let budget = strip_lead_ins(&rest);
+        if rules.check_budget(&budget).is_ok() {
+            return Some(Correction {
+                field: Field::Budget,
+                value: budget,
+            });
+        }
+    }
+
+    None
+}
+
+/// The original text following the first cue found, matched
+/// case-insensitively.
+fn text_after_cue(message: &str, cues: &[&str]) -> Option<String> {
+    let lower = message.to_lowercase();
+    cues.iter().find_map(|cue| {
+        let start = lower.find(cue)?;
+        let skip = lower[..start].chars().count() + cue.chars().count();
+        Some(message.chars().skip(skip).collect())
+    })
+}
+
+fn strip_lead_ins(text: &str) -> String {
+    let mut rest = text.trim_start_matches(|c: char| !c.is_alphanumeric());
+    while let Some((word, tail)) = rest.split_once(char::is_whitespace) {
+        if !VALUE_LEAD_INS.contains(&word.to_lowercase().as_str()) {
+            break;
+        }
+        rest = tail.trim_start();
+    }
+    rest.trim().trim_end_matches(['.', '!']).to_string()
+}
@@ -0,0 +1,222 @@
+//! A scripted showcase conversation for the marketing site's live
+//! preview, played on `/chat/demo`.
+//!
+//! The script is the visitor's side: each message is shown, then answered
+//! by the bot as it is configured, streamed in parts like a real reply
+//! (see [`crate::render`]), with pauses as if both sides were typing. A
+//! turn may fix the reply instead. The conversation runs in a session of
+//! its own that is never stored, so demos leave no sessions, events,
+//! metrics or webhooks behind.
+//!
+//! `DEMO_SCRIPT` names a JSON file with the script:
+//!
+//! ```json
+//! { "language": "en", "turns": [{ "text": "Hi!" }, { "text": "Prices?", "reply": "..." }] }
+//! ```
+//!
+//! Without it, a built-in script is played in the language asked for.
+
+use std::{fmt, fs, io, path::Path, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::{
+    chatbot::{self, AnswerRules, Vocabulary},
+    i18n::Language,
+    render::{self, STREAM_THRESHOLD},
+    session::Session,
+    state::SharedState,
+    tenant::DEFAULT_TENANT,
+};
+
+/// How long the "visitor" thinks before typing each message.
+const VISITOR_PAUSE: Duration = Duration::from_millis(1_500);
+const MAX_TURNS: usize = 50;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DemoTurn {
+    pub text: String,
+    /// Shown instead of what the bot would answer; the bot then doesn't
+    /// see the message, so its flow stays where it was.
+    #[serde(default)]
+    pub reply: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DemoScript {
+    pub language: Language,
+    pub turns: Vec<DemoTurn>,
+}
+
+#[derive(Debug)]
+pub enum DemoError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for DemoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DemoError::Io(e) => write!(f, "{e}"),
+            DemoError::Json(e) => write!(f, "invalid JSON: {e}"),
+            DemoError::Invalid(reason) => f.write_str(reason),
+        }
+    }
+}
+
+impl std::error::Error for DemoError {}
+
+impl DemoScript {
+    pub fn load(path: &Path) -> Result<Self, DemoError> {
+        let raw = fs::read_to_string(path).map_err(DemoError::Io)?;
+        let script: Self = serde_json::from_str(&raw).map_err(DemoError::Json)?;
+        if script.turns.is_empty() || script.turns.len() > MAX_TURNS {
+            return Err(DemoError::Invalid(format!(
+                "a script has 1 to {MAX_TURNS} turns"
+            )));
+        }
+        if script.turns.iter().any(|t| t.text.trim().is_empty()) {
+            return Err(DemoError::Invalid("every turn needs a text".into()));
+        }
+        Ok(script)
+    }
+
+    /// The script played when `DEMO_SCRIPT` isn't set: a look at the
+    /// services and an inquiry from start to finish.
+    pub fn builtin(language: Language) -> Self {
+        let messages: &[&str] = match language {
+            Language::En => &[
+                "Hi!",
+                "What services do you offer?",
+                "I'd like to start a project",
+                "Ana",
+                "ana@example.com",
+                "An online shop for handmade ceramics, with about 200 products",
+                "15000",
+                "Yes",
+            ],
+            Language::Pl => &[
+                "Cześć!",
+                "Jakie usługi oferujecie?",
+                "Chcę zacząć nowy projekt",
+                "Ana",
+                "ana@example.com",
+                "Sklep internetowy z ręcznie robioną ceramiką, około 200 produktów",
+                "15000",
+                "Tak",
+            ],
+            Language::Es => &[
+                "¡Hola!",
+                "¿Qué servicios ofrecéis?",
+                "Quiero empezar un proyecto",
+                "Ana",
+                "ana@example.com",
+                "Una tienda online de cerámica artesanal, con unos 200 productos",
+                "15000",
+                "Sí",
+            ],
+        };
+        Self {
+            language,
+            turns: messages
+                .iter()
+                .map(|text| DemoTurn {
+                    text: text.to_string(),
+                    reply: None,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// What the preview shows, as server-sent events.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DemoEvent {
+    VisitorMessage {
+        text: String,
+    },
+    /// As the `reply_part` pushed to chat clients.
+    ReplyPart {
+        text: String,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        quick_replies: Vec<String>,
+        last: bool,
+    },
+    /// The script is over.
+    #[serde(rename = "demo_end")]
+    End,
+}
+
+impl DemoEvent {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DemoEvent::VisitorMessage { .. } => "visitor_message",
+            DemoEvent::ReplyPart { .. } => "reply_part",
+            DemoEvent::End => "demo_end",
+        }
+    }
+}
+
+/// Plays `script` into `events`, stopping early if the viewer leaves.
+pub async fn play(state: SharedState, script: DemoScript, events: mpsc::Sender<DemoEvent>) {
+    let mut session = Session::new("demo".into(), script.language);
+    for turn in script.turns {
+        tokio::time::sleep(VISITOR_PAUSE + render::typing_delay(&turn.text)).await;
+        let (text, quick_replies) = match turn.reply {
+            Some(reply) => (reply, Vec::new()),
+            None => {
+                let config = &state.config;
+                let message = config.preprocess(&session, &turn.text);
+                let mut reply = chatbot::generate_reply(
+                    &mut session,
+                    &message,
+                    &config.catalog(),
+                    &state.tenant_replies.for_tenant(DEFAULT_TENANT),
+                    AnswerRules {
+                        validation: &config.validation(),
+                        qualification: config.qualification().as_ref(),
+                    },
+                    None,
+                    Vocabulary {
+                        synonyms: &state.synonyms.table(),
+                        learned: &state.training.learned(),
+                    },
+                );
+                config.postprocess(&session, &mut reply);
+                (reply.text, reply.quick_replies)
+            }
+        };
+        let visitor = DemoEvent::VisitorMessage { text: turn.text };
+        if events.send(visitor).await.is_err() {
+            return;
+        }
+        let parts = if text.chars().count() <= STREAM_THRESHOLD {
+            vec![text]
+        } else {
+            render::parts(&text)
+        };
+        let count = parts.len();
+        for (i, part) in parts.into_iter().enumerate() {
+            tokio::time::sleep(render::typing_delay(&part)).await;
+            let last = i + 1 == count;
+            let part = DemoEvent::ReplyPart {
+                text: part,
+                quick_replies: if last {
+                    quick_replies.clone()
+                } else {
+                    Vec::new()
+                },
+                last,
+            };
+            if events.send(part).await.is_err() {
+                return;
+            }
+        }
+    }
+    let _ = events.send(DemoEvent::End).await;
+}
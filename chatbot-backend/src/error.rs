@@ -0,0 +1,38 @@
+use axum::{
+    Json,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde_json::json;
+
+/// Errors returned by HTTP handlers, rendered as `{"error": "..."}`.
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    Unauthorized,
+    Forbidden(String),
+    NotFound(String),
+    /// Over capacity; the message is meant for the visitor.
+    Busy(String),
+    /// The visitor is on the blocklist: a `403` with a message meant for
+    /// them, or an empty `204` when blocked visitors are dropped.
+    Blocked(Option<String>),
+    /// Something broke on our side; the details are logged, not sent.
+    Internal(String),
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, message) = match self {
+            ApiError::BadRequest(m) => (StatusCode::BAD_REQUEST, m),
+            ApiError::Unauthorized => (StatusCode::UNAUTHORIZED, "unauthorized".to_string()),
+            ApiError::Forbidden(m) => (StatusCode::FORBIDDEN, m),
+            ApiError::NotFound(m) => (StatusCode::NOT_FOUND, m),
+            ApiError::Busy(m) => (StatusCode::SERVICE_UNAVAILABLE, m),
+            ApiError::Blocked(Some(m)) => (StatusCode::FORBIDDEN, m),
+            ApiError::Blocked(None) => return StatusCode::NO_CONTENT.into_response(),
+            ApiError::Internal(m) => (StatusCode::INTERNAL_SERVER_ERROR, m),
+        };
+        (status, Json(json!({ "error": message }))).into_response()
+    }
+}
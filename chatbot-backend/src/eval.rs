@@ -0,0 +1,213 @@
+//! Offline evaluation of intent detection against a labeled dataset.
+//!
+//! The dataset is JSON lines, one utterance per line:
+//!
+//! ```json
+//! {"text": "how much does a website cost?", "intent": "pricing"}
+//! ```
+//!
+//! Run it with `cargo run --bin eval-intents -- eval/intents.jsonl`.
+//!
+//! Only the keyword rules are scored. The AI fallback (see [`crate::ai`])
+//! writes answers rather than picking intents, so there is nothing of its
+//! to score against the labels; how much it covers shows in the
+//! per-language `fallback` counts of the metrics instead.
+
+use std::{collections::BTreeMap, fmt, fs, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::chatbot::{Intent, detect_intent};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sample {
+    pub text: String,
+    /// Expected intent name, as in [`Intent::as_str`].
+    pub intent: String,
+}
+
+#[derive(Debug)]
+pub enum EvalError {
+    Io(io::Error),
+    Parse {
+        line: usize,
+        error: serde_json::Error,
+    },
+    UnknownIntent {
+        line: usize,
+        intent: String,
+    },
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::Io(e) => write!(f, "cannot read dataset: {e}"),
+            EvalError::Parse { line, error } => write!(f, "line {line}: {error}"),
+            EvalError::UnknownIntent { line, intent } => {
+                write!(f, "line {line}: unknown intent {intent:?}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+/// Reads a JSON-lines dataset, skipping blank lines and `#` comments.
+pub fn load_dataset(path: impl AsRef<Path>) -> Result<Vec<(Sample, Intent)>, EvalError> {
+    let raw = fs::read_to_string(path).map_err(EvalError::Io)?;
+    let mut samples = Vec::new();
+    for (index, line) in raw.lines().enumerate() {
+        let line_no = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let sample: Sample = serde_json::from_str(line).map_err(|error| EvalError::Parse {
+            line: line_no,
+            error,
+        })?;
+        let expected =
+            Intent::from_name(&sample.intent).ok_or_else(|| EvalError::UnknownIntent {
+                line: line_no,
+                intent: sample.intent.clone(),
+            })?;
+        samples.push((sample, expected));
+    }
+    Ok(samples)
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct IntentScore {
+    pub support: usize,
+    pub true_positives: usize,
+    pub false_positives: usize,
+    pub false_negatives: usize,
+}
+
+impl IntentScore {
+    pub fn precision(&self) -> f64 {
+        ratio(
+            self.true_positives,
+            self.true_positives + self.false_positives,
+        )
+    }
+
+    pub fn recall(&self) -> f64 {
+        ratio(
+            self.true_positives,
+            self.true_positives + self.false_negatives,
+        )
+    }
+
+    pub fn f1(&self) -> f64 {
+        let (p, r) = (self.precision(), self.recall());
+        if p + r == 0.0 {
+            0.0
+        } else {
+            2.0 * p * r / (p + r)
+        }
+    }
+}
+
+fn ratio(a: usize, b: usize) -> f64 {
+    if b == 0 { 0.0 } else { a as f64 / b as f64 }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Misclassified {
+    pub text: String,
+    pub expected: &'static str,
+    pub predicted: &'static str,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct EvalReport {
+    pub total: usize,
+    pub correct: usize,
+    pub per_intent: BTreeMap<&'static str, IntentScore>,
+    pub errors: Vec<Misclassified>,
+}
+
+impl EvalReport {
+    pub fn accuracy(&self) -> f64 {
+        ratio(self.correct, self.total)
+    }
+}
+
+/// Scores `detect_intent` on every sample.
+pub fn evaluate(samples: &[(Sample, Intent)]) -> EvalReport {
+    let mut report = EvalReport::default();
+    for (sample, expected) in samples {
+        let predicted = detect_intent(&sample.text);
+        report.total += 1;
+        report
+            .per_intent
+            .entry(expected.as_str())
+            .or_default()
+            .support += 1;
+        if predicted == *expected {
+            report.correct += 1;
+            report
+                .per_intent
+                .entry(expected.as_str())
+                .or_default()
+                .true_positives += 1;
+        } else {
+            report
+                .per_intent
+                .entry(expected.as_str())
+                .or_default()
+                .false_negatives += 1;
+            report
+                .per_intent
+                .entry(predicted.as_str())
+                .or_default()
+                .false_positives += 1;
+            report.errors.push(Misclassified {
+                text: sample.text.clone(),
+                expected: expected.as_str(),
+                predicted: predicted.as_str(),
+            });
+        }
+    }
+    report
+}
+
+impl fmt::Display for EvalReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<18} {:>9} {:>9} {:>9} {:>9}",
+            "intent", "precision", "recall", "f1", "support"
+        )?;
+        for (intent, score) in &self.per_intent {
+            writeln!(
+                f,
+                "{intent:<18} {:>9.3} {:>9.3} {:>9.3} {:>9}",
+                score.precision(),
+                score.recall(),
+                score.f1(),
+                score.support
+            )?;
+        }
+        writeln!(
+            f,
+            "\naccuracy {:.3} ({}/{})",
+            self.accuracy(),
+            self.correct,
+            self.total
+        )?;
+        if !self.errors.is_empty() {
+            writeln!(f, "\nmisclassified:")?;
+            for e in &self.errors {
+                writeln!(
+                    f,
+                    "  {:?}: expected {}, got {}",
+                    e.text, e.expected, e.predicted
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
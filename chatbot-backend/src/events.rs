@@ -0,0 +1,621 @@
+//! Append-only log of what happened in each conversation.
+//!
+//! Every turn appends events describing the message, the state and data
+//! changes it caused, and the reply. [`project`] folds a session's events
+//! back into its current state, so a conversation can be replayed for
+//! debugging. The global sequence number gives consumers such as webhook
+//! delivery a cursor to resume from.
+//!
+//! With a log file, events are written behind by [`write_behind`], as the
+//! outbox is: appending them, often with a session locked, never waits
+//! for the disk.
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    mem,
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+use tracing::warn;
+
+use crate::{
+    anonymize::{Anonymization, Anonymizer},
+    i18n::Language,
+    session::{
+        ConversationState, Field, FieldValue, LeadTag, MessageMetadata, ReceiptStatus, Role,
+        Session, SessionData,
+    },
+    state::SharedState,
+    trace::IntentSource,
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ConversationEvent {
+    SessionStarted {
+        language: Language,
+    },
+    MessageReceived {
+        text: String,
+        /// Empty in logs written before messages had ids.
+        #[serde(default)]
+        message_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        metadata: Option<MessageMetadata>,
+    },
+    ReplySent {
+        text: String,
+        intent: String,
+        #[serde(default)]
+        message_id: String,
+    },
+    StateChanged {
+        from: ConversationState,
+        to: ConversationState,
+    },
+    /// `value` is `None` when the visitor skipped the question.
+    FieldCaptured {
+        field: Field,
+        value: Option<String>,
+    },
+    FieldCleared {
+        field: Field,
+    },
+    /// The visitor confirmed the inquiry summary; the session is a lead.
+    InquiryConfirmed,
+    ReportGenerated {
+        path: String,
+    },
+    /// The lead's tag changed; `None` when it was cleared.
+    LeadTagged {
+        tag: Option<LeadTag>,
+    },
+    /// An operator labelled the lead.
+    LeadLabelsChanged {
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
+    /// The visitor consented to a transcript email, now queued for `to`.
+    TranscriptEmailQueued {
+        to: String,
+    },
+    /// The visitor asked to talk to a person.
+    EscalationRequested,
+    /// The visitor asked for a person while nobody was online and left
+    /// their address to be emailed back at.
+    CallbackRequested,
+    /// The conversation was handed to an external live-chat tool, where it
+    /// continues as `external_id`.
+    Transferred {
+        provider: String,
+        external_id: String,
+    },
+    /// An operator took over the conversation.
+    AgentJoined,
+    AgentMessage {
+        text: String,
+        #[serde(default)]
+        message_id: String,
+    },
+    /// The operator handed the conversation back to the bot.
+    AgentLeft,
+    /// The visitor was asked whether they are still there.
+    InactivityWarning {
+        text: String,
+    },
+    /// The visitor was asked to rate the conversation.
+    SurveyAsked {
+        text: String,
+    },
+    /// The visitor rated the conversation, from 1 to 5.
+    SatisfactionRated {
+        score: u8,
+    },
+    /// The visitor corrected their message.
+    MessageEdited {
+        message_id: String,
+        text: String,
+    },
+    /// A message was taken back: by the visitor, or as part of a turn that
+    /// was undone.
+    MessageDeleted {
+        message_id: String,
+    },
+    /// Session `from`, started by the same visitor, was merged into this
+    /// one. Its events precede this one, interleaved with the session's
+    /// own by time.
+    SessionMerged {
+        from: String,
+    },
+    /// An operator deleted the session; it can be restored for a while.
+    SessionDeleted,
+    SessionRestored,
+    /// The conversation reached its limit and the bot ended it.
+    SessionClosed,
+    /// An operator was offered a draft answer to the visitor's message
+    /// `message_id`; see [`crate::handoff::suggest_reply`].
+    ReplySuggested {
+        message_id: String,
+        intent: String,
+        source: IntentSource,
+    },
+    /// The visitor's client reported a bot or operator message delivered
+    /// or read.
+    ReceiptRecorded {
+        message_id: String,
+        status: ReceiptStatus,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredEvent {
+    /// Position in the global log, strictly increasing.
+    pub seq: u64,
+    pub session_id: String,
+    /// Milliseconds since the Unix epoch.
+    pub at_ms: u64,
+    #[serde(flatten)]
+    pub event: ConversationEvent,
+}
+
+/// A session's request for an operator that no operator has taken yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Escalation {
+    requested_at_ms: u64,
+    /// The session was deleted while waiting; restoring it puts it back in
+    /// line.
+    deleted: bool,
+}
+
+impl Escalation {
+    fn after(open: Option<Self>, stored: &StoredEvent) -> Option<Self> {
+        match stored.event {
+            ConversationEvent::EscalationRequested => Some(Self {
+                requested_at_ms: stored.at_ms,
+                deleted: false,
+            }),
+            ConversationEvent::AgentJoined
+            | ConversationEvent::Transferred { .. }
+            | ConversationEvent::SessionClosed => None,
+            ConversationEvent::SessionDeleted => open.map(|e| Self { deleted: true, ..e }),
+            ConversationEvent::SessionRestored => open.map(|e| Self {
+                deleted: false,
+                ..e
+            }),
+            _ => open,
+        }
+    }
+
+    fn of(events: &[StoredEvent]) -> Option<Self> {
+        events.iter().fold(None, Self::after)
+    }
+}
+
+struct Inner {
+    next_seq: u64,
+    by_session: HashMap<String, Vec<StoredEvent>>,
+    /// The sessions with an open [`Escalation`], kept as events come in so
+    /// the operator queue doesn't read every session's events.
+    escalations: HashMap<String, Escalation>,
+    /// Every event since the last warehouse export, when exports are on;
+    /// see [`crate::warehouse`].
+    unexported: Option<Vec<StoredEvent>>,
+}
+
+/// Keeps each live session's events in memory and, optionally, appends
+/// every event as a JSON line to a file that outlives the process.
+pub struct EventStore {
+    inner: Mutex<Inner>,
+    log_file: Option<Mutex<File>>,
+    /// What the log file keeps of each event; everything unless set.
+    log_anonymizer: Option<Anonymizer>,
+    /// Lines for the log file not written yet, oldest first.
+    unwritten: Mutex<Vec<String>>,
+    appended: Notify,
+}
+
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Writes events to the log file as they are appended, until the process
+/// ends. Only needed with a log file.
+pub async fn write_behind(state: SharedState) {
+    loop {
+        state.events.appended.notified().await;
+        let state = state.clone();
+        tokio::task::spawn_blocking(move || state.events.flush_log())
+            .await
+            .expect("event log write panicked");
+    }
+}
+
+impl EventStore {
+    pub fn in_memory() -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                next_seq: 1,
+                by_session: HashMap::new(),
+                escalations: HashMap::new(),
+                unexported: None,
+            }),
+            log_file: None,
+            log_anonymizer: None,
+            unwritten: Mutex::default(),
+            appended: Notify::new(),
+        }
+    }
+
+    pub fn with_log_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            log_file: Some(Mutex::new(file)),
+            ..Self::in_memory()
+        })
+    }
+
+    /// Anonymizes what is appended to the log file from now on; memory
+    /// keeps every event whole, as the live conversation needs it.
+    pub fn set_log_anonymizer(&mut self, anonymizer: Anonymizer) {
+        self.log_anonymizer = (anonymizer.mode != Anonymization::None).then_some(anonymizer);
+    }
+
+    pub fn append(&self, session_id: &str, events: Vec<ConversationEvent>) {
+        let mut inner = self.inner.lock().unwrap();
+        let at_ms = now_ms();
+        let mut stored = Vec::with_capacity(events.len());
+        for event in events {
+            stored.push(StoredEvent {
+                seq: inner.next_seq,
+                session_id: session_id.to_string(),
+                at_ms,
+                event,
+            });
+            inner.next_seq += 1;
+        }
+
+        if self.log_file.is_some() {
+            // Queued with the store locked, so lines go out in `seq` order.
+            let mut unwritten = self.unwritten.lock().unwrap();
+            unwritten.extend(stored.iter().map(|event| match &self.log_anonymizer {
+                Some(anonymizer) => anonymizer.record(event).to_string(),
+                None => serde_json::to_string(event).expect("events serialize"),
+            }));
+            self.appended.notify_one();
+        }
+
+        if let Some(unexported) = &mut inner.unexported {
+            unexported.extend(stored.iter().cloned());
+        }
+        let open = inner.escalations.get(session_id).copied();
+        inner.set_escalation(session_id, stored.iter().fold(open, Escalation::after));
+        inner
+            .by_session
+            .entry(session_id.to_string())
+            .or_default()
+            .extend(stored);
+    }
+
+    /// Writes the events appended since the last write to the log file, if
+    /// there is one. Blocks on the disk; see [`write_behind`].
+    pub fn flush_log(&self) {
+        let Some(file) = &self.log_file else { return };
+        let mut file = file.lock().unwrap();
+        let lines = mem::take(&mut *self.unwritten.lock().unwrap());
+        for line in lines {
+            if let Err(e) = writeln!(file, "{line}") {
+                warn!("cannot append to event log: {e}");
+                break;
+            }
+        }
+    }
+
+    /// Starts keeping events for [`EventStore::take_unexported`], including
+    /// those of sessions forgotten in the meantime.
+    pub fn keep_for_export(&self) {
+        self.inner
+            .lock()
+            .unwrap()
+            .unexported
+            .get_or_insert_default();
+    }
+
+    /// The events since the previous call, oldest first.
+    pub fn take_unexported(&self) -> Vec<StoredEvent> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.unexported.as_mut().map(mem::take).unwrap_or_default()
+    }
+
+    /// Puts back events an export failed to deliver, ahead of newer ones.
+    pub fn return_unexported(&self, mut events: Vec<StoredEvent>) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(unexported) = &mut inner.unexported {
+            events.append(unexported);
+            *unexported = events;
+        }
+    }
+
+    pub fn session_events(&self, session_id: &str) -> Vec<StoredEvent> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .by_session
+            .get(session_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// The sessions waiting for an operator, with when they asked.
+    pub fn waiting(&self) -> Vec<(String, u64)> {
+        self.inner
+            .lock()
+            .unwrap()
+            .escalations
+            .iter()
+            .filter(|(_, e)| !e.deleted)
+            .map(|(id, e)| (id.clone(), e.requested_at_ms))
+            .collect()
+    }
+
+    /// Ids of the sessions with events in memory.
+    pub fn session_ids(&self) -> Vec<String> {
+        self.inner
+            .lock()
+            .unwrap()
+            .by_session
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    /// Moves the events of session `from` into `into`'s, in sequence order.
+    /// Only memory is changed; the log file keeps them under `from`.
+    pub fn merge(&self, from: &str, into: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        let moved = inner.by_session.remove(from).unwrap_or_default();
+        let events = inner.by_session.entry(into.to_string()).or_default();
+        events.extend(
+            moved
+                .into_iter()
+                .filter(|e| !matches!(e.event, ConversationEvent::SessionStarted { .. }))
+                .map(|e| StoredEvent {
+                    session_id: into.to_string(),
+                    ..e
+                }),
+        );
+        events.sort_by_key(|e| e.seq);
+        let escalation = Escalation::of(events);
+        inner.escalations.remove(from);
+        inner.set_escalation(into, escalation);
+    }
+
+    /// Takes in events another instance recorded, for sessions carried
+    /// over in a snapshot. They are numbered anew after the events already
+    /// here, in their original order, and keep their times. The log file
+    /// already has them.
+    pub fn restore(&self, mut events: Vec<StoredEvent>) {
+        events.sort_by_key(|e| e.seq);
+        let mut inner = self.inner.lock().unwrap();
+        let mut touched = HashSet::new();
+        for mut event in events {
+            event.seq = inner.next_seq;
+            inner.next_seq += 1;
+            touched.insert(event.session_id.clone());
+            inner
+                .by_session
+                .entry(event.session_id.clone())
+                .or_default()
+                .push(event);
+        }
+        for id in touched {
+            let escalation = Escalation::of(&inner.by_session[&id]);
+            inner.set_escalation(&id, escalation);
+        }
+    }
+
+    /// Drops a session's events from memory; the log file keeps them.
+    pub fn forget(&self, session_id: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.by_session.remove(session_id);
+        inner.escalations.remove(session_id);
+    }
+}
+
+impl Inner {
+    fn set_escalation(&mut self, session_id: &str, escalation: Option<Escalation>) {
+        match escalation {
+            Some(escalation) => {
+                self.escalations.insert(session_id.to_string(), escalation);
+            }
+            None => {
+                self.escalations.remove(session_id);
+            }
+        }
+    }
+}
+
+/// Events for the state and data changes between two snapshots of a
+/// session.
+pub fn changes(
+    before_state: ConversationState,
+    before_data: &SessionData,
+    after: &Session,
+) -> Vec<ConversationEvent> {
+    let mut events = Vec::new();
+    for field in Field::ALL {
+        let (old, new) = (before_data.get(field), after.data.get(field));
+        if old == new {
+            continue;
+        }
+        events.push(match new {
+            Some(value) => ConversationEvent::FieldCaptured {
+                field,
+                value: value.given().cloned(),
+            },
+            None => ConversationEvent::FieldCleared { field },
+        });
+    }
+    if before_data.tag != after.data.tag {
+        events.push(ConversationEvent::LeadTagged {
+            tag: after.data.tag,
+        });
+    }
+    if before_state != after.state {
+        events.push(ConversationEvent::StateChanged {
+            from: before_state,
+            to: after.state,
+        });
+    }
+    events
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TranscriptEntry {
+    /// Empty for inactivity warnings, which are not conversation messages.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub id: String,
+    pub role: Role,
+    pub text: String,
+    pub at_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub receipt: Option<ReceiptStatus>,
+    /// What the visitor's client sent with their message.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<MessageMetadata>,
+    /// Sequence number of the event that wrote the entry; orders entries
+    /// for paging.
+    #[serde(skip)]
+    pub seq: u64,
+}
+
+impl TranscriptEntry {
+    pub fn new(id: &str, role: Role, text: &str, at_ms: u64) -> Self {
+        Self {
+            id: id.to_string(),
+            role,
+            text: text.to_string(),
+            at_ms,
+            receipt: None,
+            metadata: None,
+            seq: 0,
+        }
+    }
+}
+
+/// A conversation's current state, rebuilt from its events alone.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ConversationProjection {
+    pub language: Language,
+    pub state: ConversationState,
+    pub data: SessionData,
+    pub transcript: Vec<TranscriptEntry>,
+    pub reports: Vec<String>,
+    /// When the inquiry was last confirmed, if ever.
+    pub confirmed_at_ms: Option<u64>,
+    /// The visitor's latest rating of the conversation.
+    pub satisfaction: Option<u8>,
+    /// Labels operators gave the lead.
+    pub labels: BTreeSet<String>,
+    pub last_seq: u64,
+}
+
+pub fn project(events: &[StoredEvent]) -> ConversationProjection {
+    let mut projection = ConversationProjection::default();
+    for stored in events {
+        projection.last_seq = stored.seq;
+        match &stored.event {
+            ConversationEvent::SessionStarted { language } => projection.language = *language,
+            ConversationEvent::MessageReceived {
+                text,
+                message_id,
+                metadata,
+            } => projection.transcript.push(TranscriptEntry {
+                metadata: metadata.clone(),
+                ..TranscriptEntry::new(message_id, Role::User, text, stored.at_ms)
+            }),
+            ConversationEvent::ReplySent {
+                text, message_id, ..
+            } => projection.transcript.push(TranscriptEntry::new(
+                message_id,
+                Role::Bot,
+                text,
+                stored.at_ms,
+            )),
+            ConversationEvent::InactivityWarning { text }
+            | ConversationEvent::SurveyAsked { text } => projection
+                .transcript
+                .push(TranscriptEntry::new("", Role::Bot, text, stored.at_ms)),
+            ConversationEvent::SatisfactionRated { score } => {
+                projection.satisfaction = Some(*score)
+            }
+            ConversationEvent::AgentMessage { text, message_id } => projection.transcript.push(
+                TranscriptEntry::new(message_id, Role::Agent, text, stored.at_ms),
+            ),
+            ConversationEvent::ReceiptRecorded { message_id, status } => {
+                if let Some(entry) = projection
+                    .transcript
+                    .iter_mut()
+                    .find(|e| &e.id == message_id)
+                {
+                    entry.receipt = entry.receipt.max(Some(*status));
+                }
+            }
+            ConversationEvent::MessageEdited { message_id, text } => {
+                if let Some(entry) = projection
+                    .transcript
+                    .iter_mut()
+                    .find(|e| &e.id == message_id)
+                {
+                    entry.text = text.clone();
+                }
+            }
+            ConversationEvent::MessageDeleted { message_id } => {
+                projection.transcript.retain(|e| &e.id != message_id)
+            }
+            ConversationEvent::AgentJoined
+            | ConversationEvent::AgentLeft
+            | ConversationEvent::SessionMerged { .. }
+            | ConversationEvent::SessionDeleted
+            | ConversationEvent::SessionRestored
+            | ConversationEvent::SessionClosed
+            | ConversationEvent::ReplySuggested { .. }
+            | ConversationEvent::EscalationRequested
+            | ConversationEvent::CallbackRequested
+            | ConversationEvent::Transferred { .. } => {}
+            ConversationEvent::StateChanged { to, .. } => projection.state = *to,
+            ConversationEvent::FieldCaptured { field, value } => match value {
+                Some(value) => projection.data.set(*field, value.clone()),
+                None => projection.data.budget = Some(FieldValue::Unspecified),
+            },
+            ConversationEvent::FieldCleared { field } => match field {
+                Field::Name => projection.data.name = None,
+                Field::Email => projection.data.email = None,
+                Field::ProjectDetails => projection.data.project_details = None,
+                Field::Budget => projection.data.budget = None,
+            },
+            ConversationEvent::InquiryConfirmed => projection.confirmed_at_ms = Some(stored.at_ms),
+            ConversationEvent::ReportGenerated { path } => projection.reports.push(path.clone()),
+            ConversationEvent::LeadTagged { tag } => projection.data.tag = *tag,
+            ConversationEvent::LeadLabelsChanged { added, removed } => {
+                projection.labels.extend(added.iter().cloned());
+                projection.labels.retain(|l| !removed.contains(l));
+            }
+            ConversationEvent::TranscriptEmailQueued { .. } => {}
+        }
+        // Stamp the entry this event wrote, if it wrote one.
+        if let Some(entry) = projection.transcript.last_mut()
+            && entry.seq == 0
+        {
+            entry.seq = stored.seq;
+        }
+    }
+    projection
+}
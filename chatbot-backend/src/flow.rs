@@ -0,0 +1,379 @@
+//! The conversation flow as data: every state change the bot may make,
+//! the event that triggers it and the condition on the captured data that
+//! picks it.
+//!
+//! [`TRANSITIONS`] is what `/admin/flow/diagram` draws, and what decides
+//! where a session goes next: `chatbot` only says what happened, as an
+//! [`Event`], and [`fire`] moves the session along the first row for its
+//! state and that event whose guard holds. When no row does, the session
+//! stays where it is and `chatbot` asks the pending question again
+//! instead of sending the reply written for the move.
+//!
+//! Two changes are not moves of the flow and go through [`restore`]
+//! instead: undoing a turn puts back the state from before it, and merging
+//! sessions gives the survivor the state of the session merged into it.
+//! Both only put a session in a state some row leads to with its data.
+//! Sessions loaded from a snapshot, or built by `test_support`, start in
+//! the state they were saved or built with.
+
+use std::fmt::{self, Write};
+
+use tracing::{debug, error};
+
+use crate::session::{
+    ConversationState::{self, *},
+    Field, LeadTag, Session, SessionData,
+};
+
+use Event::*;
+
+/// What happened in a turn, as far as the flow is concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    StartInquiry,
+    /// A valid answer to the pending question, or a skip where one is
+    /// allowed.
+    Answered,
+    /// The visitor asked to change a field from the summary.
+    Edit(Field),
+    /// The visitor confirmed the summary.
+    Confirmed,
+    TranscriptRequested,
+    /// The visitor asked for a person while nobody is online.
+    CallbackRequested,
+    Reset,
+}
+
+/// When a transition applies, judged on the data captured so far.
+pub type Guard = fn(&SessionData) -> bool;
+
+pub struct Transition {
+    pub from: ConversationState,
+    pub on: Event,
+    pub to: ConversationState,
+    pub guard: Guard,
+    /// The event and guard in a few words, for the diagram.
+    pub label: &'static str,
+}
+
+impl fmt::Debug for Transition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Transition")
+            .field("from", &self.from)
+            .field("on", &self.on)
+            .field("to", &self.to)
+            .field("label", &self.label)
+            .finish()
+    }
+}
+
+impl PartialEq for Transition {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Eq for Transition {}
+
+const fn t(
+    from: ConversationState,
+    on: Event,
+    to: ConversationState,
+    label: &'static str,
+    guard: Guard,
+) -> Transition {
+    Transition {
+        from,
+        on,
+        to,
+        guard,
+        label,
+    }
+}
+
+pub const STATES: [ConversationState; 8] = [
+    Idle,
+    AskingName,
+    AskingEmail,
+    AskingProjectDetails,
+    AskingBudget,
+    ConfirmingSummary,
+    ConfirmingTranscriptEmail,
+    AskingCallbackEmail,
+];
+
+/// The first field the inquiry still has to ask for.
+fn missing(data: &SessionData) -> Option<Field> {
+    Field::ALL.into_iter().find(|field| !data.has(*field))
+}
+
+fn always(_: &SessionData) -> bool {
+    true
+}
+
+fn email_missing(data: &SessionData) -> bool {
+    missing(data) == Some(Field::Email)
+}
+
+fn details_missing(data: &SessionData) -> bool {
+    missing(data) == Some(Field::ProjectDetails)
+}
+
+fn budget_missing(data: &SessionData) -> bool {
+    missing(data) == Some(Field::Budget)
+}
+
+fn self_service(data: &SessionData) -> bool {
+    data.tag == Some(LeadTag::SelfService)
+}
+
+fn complete(data: &SessionData) -> bool {
+    missing(data).is_none() && !self_service(data)
+}
+
+fn email_known(data: &SessionData) -> bool {
+    data.email.is_some()
+}
+
+fn email_unknown(data: &SessionData) -> bool {
+    data.email.is_none()
+}
+
+pub const TRANSITIONS: &[Transition] = &[
+    t(Idle, StartInquiry, AskingName, "start inquiry", always),
+    t(
+        Idle,
+        TranscriptRequested,
+        ConfirmingTranscriptEmail,
+        "transcript requested, email known",
+        email_known,
+    ),
+    t(
+        AskingName,
+        Answered,
+        AskingEmail,
+        "valid name",
+        email_missing,
+    ),
+    t(
+        AskingEmail,
+        Answered,
+        AskingProjectDetails,
+        "valid email",
+        details_missing,
+    ),
+    t(
+        AskingProjectDetails,
+        Answered,
+        AskingBudget,
+        "details long enough",
+        budget_missing,
+    ),
+    t(
+        AskingBudget,
+        Answered,
+        ConfirmingSummary,
+        "budget given or skipped",
+        complete,
+    ),
+    t(
+        AskingBudget,
+        Answered,
+        Idle,
+        "budget below minimum, self-service offered",
+        self_service,
+    ),
+    t(
+        AskingName,
+        Answered,
+        AskingProjectDetails,
+        "valid name, email known",
+        details_missing,
+    ),
+    t(
+        AskingName,
+        Answered,
+        AskingBudget,
+        "valid name, email and details known",
+        budget_missing,
+    ),
+    t(
+        AskingEmail,
+        Answered,
+        AskingBudget,
+        "valid email, details known",
+        budget_missing,
+    ),
+    t(ConfirmingSummary, Confirmed, Idle, "confirmed", always),
+    t(
+        ConfirmingSummary,
+        Edit(Field::Name),
+        AskingName,
+        "edit name",
+        always,
+    ),
+    t(
+        ConfirmingSummary,
+        Edit(Field::Email),
+        AskingEmail,
+        "edit email",
+        always,
+    ),
+    t(
+        ConfirmingSummary,
+        Edit(Field::ProjectDetails),
+        AskingProjectDetails,
+        "edit details",
+        always,
+    ),
+    t(
+        ConfirmingSummary,
+        Edit(Field::Budget),
+        AskingBudget,
+        "edit budget",
+        always,
+    ),
+    t(
+        AskingName,
+        Answered,
+        ConfirmingSummary,
+        "edited name",
+        complete,
+    ),
+    t(
+        AskingEmail,
+        Answered,
+        ConfirmingSummary,
+        "edited email",
+        complete,
+    ),
+    t(
+        AskingProjectDetails,
+        Answered,
+        ConfirmingSummary,
+        "edited details",
+        complete,
+    ),
+    t(
+        ConfirmingTranscriptEmail,
+        Answered,
+        Idle,
+        "consent given or declined",
+        always,
+    ),
+    t(
+        Idle,
+        CallbackRequested,
+        AskingCallbackEmail,
+        "person asked for, nobody online, email unknown",
+        email_unknown,
+    ),
+    t(
+        AskingCallbackEmail,
+        Answered,
+        Idle,
+        "valid email, or callback declined",
+        always,
+    ),
+    t(AskingName, Reset, Idle, "reset", always),
+    t(AskingEmail, Reset, Idle, "reset", always),
+    t(AskingProjectDetails, Reset, Idle, "reset", always),
+    t(AskingBudget, Reset, Idle, "reset", always),
+    t(ConfirmingSummary, Reset, Idle, "reset", always),
+    t(ConfirmingTranscriptEmail, Reset, Idle, "reset", always),
+    t(AskingCallbackEmail, Reset, Idle, "reset", always),
+];
+
+/// What [`fire`] did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransitionResult {
+    /// The session moved along this row of [`TRANSITIONS`].
+    Moved(&'static Transition),
+    /// The table has no row for the event in this state whose guard holds;
+    /// the session kept its state.
+    Refused {
+        from: ConversationState,
+        event: Event,
+    },
+}
+
+/// Moves the session along the row of the table for `event` whose guard
+/// holds for its data. Moving on to another question clears the count of
+/// failed answers to the previous one.
+pub fn fire(session: &mut Session, event: Event) -> TransitionResult {
+    let from = session.state;
+    let Some(transition) = TRANSITIONS
+        .iter()
+        .find(|t| t.from == from && t.on == event && (t.guard)(&session.data))
+    else {
+        error!(session_id = %session.id, ?from, ?event, "event missing from the flow definition");
+        return TransitionResult::Refused { from, event };
+    };
+    session.state = transition.to;
+    session.failed_answers = 0;
+    TransitionResult::Moved(transition)
+}
+
+/// Why [`restore`] set a state outside the table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Restore {
+    /// The turn that left the state was undone.
+    Undo,
+    /// The state of a session merged into this one.
+    Merge,
+}
+
+/// Puts the session in `to`, a state reached through [`TRANSITIONS`]
+/// before, for `why`. A state no row leads to with the session's data is
+/// refused, and the session keeps its state.
+pub fn restore(session: &mut Session, to: ConversationState, why: Restore) -> bool {
+    if session.state == to {
+        return true;
+    }
+    let reachable = to == Idle
+        || TRANSITIONS
+            .iter()
+            .any(|t| t.to == to && (t.guard)(&session.data));
+    if !reachable {
+        error!(session_id = %session.id, from = ?session.state, ?to, ?why, "restored state missing from the flow definition");
+        return false;
+    }
+    debug!(session_id = %session.id, from = ?session.state, ?to, ?why, "state restored");
+    session.state = to;
+    true
+}
+
+fn name(state: ConversationState) -> String {
+    format!("{state:?}")
+}
+
+/// A Mermaid state diagram of the flow.
+pub fn mermaid() -> String {
+    let mut out = String::from("stateDiagram-v2\n");
+    writeln!(out, "    [*] --> {}", name(Idle)).unwrap();
+    for t in TRANSITIONS {
+        writeln!(out, "    {} --> {}: {}", name(t.from), name(t.to), t.label).unwrap();
+    }
+    out
+}
+
+/// The same diagram in Graphviz DOT.
+pub fn dot() -> String {
+    let mut out =
+        String::from("digraph flow {\n    rankdir=LR;\n    node [shape=box, style=rounded];\n");
+    for state in STATES {
+        writeln!(out, "    {};", name(state)).unwrap();
+    }
+    for t in TRANSITIONS {
+        writeln!(
+            out,
+            "    {} -> {} [label=\"{}\"];",
+            name(t.from),
+            name(t.to),
+            t.label
+        )
+        .unwrap();
+    }
+    out.push_str("}\n");
+    out
+}
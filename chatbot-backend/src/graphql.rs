@@ -0,0 +1,366 @@
+//! `/admin/graphql`: sessions, leads, metrics and analytics in one query,
+//! so the dashboard fetches only the fields it shows.
+//!
+//! Lists take `offset` and `limit` (at most [`MAX_PAGE`]) and return
+//! `totalCount` with the page, so the client can page through filtered
+//! results.
+
+use std::{cmp::Reverse, collections::BTreeMap, sync::LazyLock, time::Duration};
+
+use async_graphql::{
+    ComplexObject, Context, EmptyMutation, EmptySubscription, Error, Object, Result, Schema,
+    SimpleObject,
+};
+
+use crate::{
+    analytics,
+    events::{self, TranscriptEntry, now_ms},
+    i18n::Language,
+    session::{ConversationState, FieldValue, Session},
+    state::SharedState,
+};
+
+const DEFAULT_PAGE: usize = 50;
+const MAX_PAGE: usize = 500;
+
+pub type AdminSchema = Schema<Query, EmptyMutation, EmptySubscription>;
+
+/// The schema; queries run with the [`SharedState`] as request data.
+pub static SCHEMA: LazyLock<AdminSchema> =
+    LazyLock::new(|| Schema::build(Query, EmptyMutation, EmptySubscription).finish());
+
+fn state<'a>(ctx: &Context<'a>) -> &'a SharedState {
+    ctx.data_unchecked::<SharedState>()
+}
+
+fn parse_language(code: Option<String>) -> Result<Option<Language>> {
+    code.map(|code| {
+        Language::from_code(&code).ok_or_else(|| Error::new(format!("unknown language {code:?}")))
+    })
+    .transpose()
+}
+
+/// The snake_case name used in the REST API and event log.
+fn state_name(state: ConversationState) -> String {
+    serde_json::to_value(state)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+fn page<T>(mut items: Vec<T>, offset: usize, limit: Option<usize>) -> (usize, Vec<T>) {
+    let total = items.len();
+    let limit = limit.unwrap_or(DEFAULT_PAGE).min(MAX_PAGE);
+    let items = items.drain(offset.min(total)..).take(limit).collect();
+    (total, items)
+}
+
+/// Wall-clock milliseconds of a moment `elapsed` ago.
+fn ms_ago(elapsed: Duration) -> u64 {
+    now_ms().saturating_sub(elapsed.as_millis() as u64)
+}
+
+#[derive(SimpleObject)]
+pub struct TranscriptMessage {
+    /// `null` for inactivity warnings.
+    id: Option<String>,
+    role: String,
+    text: String,
+    at_ms: u64,
+    /// `"delivered"` or `"read"` once the visitor's client reported it.
+    receipt: Option<String>,
+}
+
+impl From<TranscriptEntry> for TranscriptMessage {
+    fn from(entry: TranscriptEntry) -> Self {
+        Self {
+            id: Some(entry.id).filter(|id| !id.is_empty()),
+            role: format!("{:?}", entry.role).to_lowercase(),
+            text: entry.text,
+            at_ms: entry.at_ms,
+            receipt: entry.receipt.map(|r| format!("{r:?}").to_lowercase()),
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+#[graphql(complex)]
+pub struct SessionNode {
+    id: String,
+    language: String,
+    state: String,
+    message_count: usize,
+    handed_off: bool,
+    started_at_ms: u64,
+    last_activity_ms: u64,
+    name: Option<String>,
+    email: Option<String>,
+}
+
+impl From<&Session> for SessionNode {
+    fn from(session: &Session) -> Self {
+        Self {
+            id: session.id.clone(),
+            language: session.language.code().to_string(),
+            state: state_name(session.state),
+            message_count: session.messages.len(),
+            handed_off: session.handed_off,
+            started_at_ms: ms_ago(session.created_at.elapsed()),
+            last_activity_ms: ms_ago(session.last_activity.elapsed()),
+            name: session.data.name.clone(),
+            email: session.data.email.clone(),
+        }
+    }
+}
+
+#[ComplexObject]
+impl SessionNode {
+    /// The transcript, most recent `last` lines only when given.
+    async fn messages(&self, ctx: &Context<'_>, last: Option<usize>) -> Vec<TranscriptMessage> {
+        let mut transcript =
+            events::project(&state(ctx).events.session_events(&self.id)).transcript;
+        if let Some(last) = last {
+            transcript.drain(..transcript.len().saturating_sub(last));
+        }
+        transcript.into_iter().map(Into::into).collect()
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct SessionPage {
+    total_count: usize,
+    items: Vec<SessionNode>,
+}
+
+/// A session whose visitor confirmed an inquiry.
+#[derive(SimpleObject)]
+pub struct Lead {
+    session_id: String,
+    language: String,
+    name: Option<String>,
+    email: Option<String>,
+    project_details: Option<String>,
+    /// `null` when not answered yet, `"unspecified"` when skipped.
+    budget: Option<String>,
+    confirmed_at_ms: u64,
+    reports: Vec<String>,
+    /// `"below_minimum_budget"` when the budget fell short of the minimum.
+    tag: Option<String>,
+    /// Labels operators gave the lead.
+    labels: Vec<String>,
+}
+
+#[derive(SimpleObject)]
+pub struct LeadPage {
+    total_count: usize,
+    items: Vec<Lead>,
+}
+
+#[derive(SimpleObject)]
+pub struct Counter {
+    name: String,
+    count: u64,
+}
+
+fn counters(map: BTreeMap<String, u64>) -> Vec<Counter> {
+    map.into_iter()
+        .map(|(name, count)| Counter { name, count })
+        .collect()
+}
+
+#[derive(SimpleObject)]
+pub struct LanguageMetrics {
+    /// ISO 639-1 code.
+    language: String,
+    messages: u64,
+    /// Messages the rules didn't understand.
+    unknown: u64,
+    /// Of those, messages the AI fallback answered.
+    fallback: u64,
+}
+
+#[derive(SimpleObject)]
+pub struct Memory {
+    sessions: usize,
+    messages: usize,
+    message_bytes: usize,
+    approx_bytes: usize,
+}
+
+#[derive(SimpleObject)]
+pub struct Metrics {
+    messages_total: u64,
+    sessions_created: u64,
+    intents: Vec<Counter>,
+    /// Named `"intent/sub_intent"`.
+    sub_intents: Vec<Counter>,
+    sessions_rejected: Vec<Counter>,
+    messages_delivered: u64,
+    messages_read: u64,
+    languages: Vec<LanguageMetrics>,
+    memory: Memory,
+    queued_deliveries: usize,
+}
+
+#[derive(SimpleObject)]
+pub struct UnansweredQuestion {
+    example: String,
+    language: String,
+    count: u64,
+    first_seen_ms: u64,
+    last_seen_ms: u64,
+}
+
+impl From<analytics::UnansweredQuestion> for UnansweredQuestion {
+    fn from(q: analytics::UnansweredQuestion) -> Self {
+        Self {
+            example: q.example,
+            language: q.language.code().to_string(),
+            count: q.count,
+            first_seen_ms: q.first_seen_ms,
+            last_seen_ms: q.last_seen_ms,
+        }
+    }
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    /// Live sessions, most recently active first.
+    async fn sessions(
+        &self,
+        ctx: &Context<'_>,
+        language: Option<String>,
+        #[graphql(desc = "E.g. `asking_email`.")] state: Option<String>,
+        handed_off: Option<bool>,
+        #[graphql(default)] offset: usize,
+        limit: Option<usize>,
+    ) -> Result<SessionPage> {
+        let language = parse_language(language)?;
+        let mut sessions: Vec<Session> = self::state(ctx)
+            .sessions
+            .export()
+            .await
+            .into_iter()
+            .map(|(session, _)| session)
+            .filter(|s| language.is_none_or(|l| s.language == l))
+            .filter(|s| {
+                state
+                    .as_deref()
+                    .is_none_or(|name| state_name(s.state) == name)
+            })
+            .filter(|s| handed_off.is_none_or(|h| s.handed_off == h))
+            .collect();
+        sessions.sort_by_key(|s| s.last_activity.elapsed());
+        let (total_count, sessions) = page(sessions, offset, limit);
+        Ok(SessionPage {
+            total_count,
+            items: sessions.iter().map(SessionNode::from).collect(),
+        })
+    }
+
+    async fn session(&self, ctx: &Context<'_>, id: String) -> Option<SessionNode> {
+        let session = state(ctx).sessions.lock_session(&id).await?;
+        Some(SessionNode::from(&*session))
+    }
+
+    /// Confirmed inquiries of live sessions, newest first.
+    async fn leads(
+        &self,
+        ctx: &Context<'_>,
+        language: Option<String>,
+        #[graphql(desc = "Confirmed at or after, in Unix ms.")] since_ms: Option<u64>,
+        #[graphql(default)] offset: usize,
+        limit: Option<usize>,
+    ) -> Result<LeadPage> {
+        let language = parse_language(language)?;
+        let store = &state(ctx).events;
+        let mut leads: Vec<Lead> = store
+            .session_ids()
+            .into_iter()
+            .filter_map(|id| {
+                let projection = events::project(&store.session_events(&id));
+                let confirmed_at_ms = projection.confirmed_at_ms?;
+                let data = projection.data;
+                Some(Lead {
+                    session_id: id,
+                    language: projection.language.code().to_string(),
+                    name: data.name,
+                    email: data.email,
+                    project_details: data.project_details,
+                    budget: data.budget.map(|b| match b {
+                        FieldValue::Given(budget) => budget,
+                        FieldValue::Unspecified => "unspecified".to_string(),
+                    }),
+                    confirmed_at_ms,
+                    reports: projection.reports,
+                    tag: data.tag.map(|t| t.as_str().to_string()),
+                    labels: projection.labels.into_iter().collect(),
+                })
+            })
+            .filter(|l| language.is_none_or(|lang| l.language == lang.code()))
+            .filter(|l| since_ms.is_none_or(|since| l.confirmed_at_ms >= since))
+            .collect();
+        leads.sort_by_key(|l| Reverse(l.confirmed_at_ms));
+        let (total_count, items) = page(leads, offset, limit);
+        Ok(LeadPage { total_count, items })
+    }
+
+    /// Counters across all tenants, or for one.
+    async fn metrics(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "Only this tenant's counters.")] tenant: Option<String>,
+    ) -> Metrics {
+        let state = state(ctx);
+        let snapshot = match tenant {
+            Some(tenant) => state.metrics.tenant_snapshot(&tenant).unwrap_or_default(),
+            None => state.metrics.snapshot(),
+        };
+        let memory = state.sessions.memory_usage().await;
+        Metrics {
+            messages_total: snapshot.messages_total,
+            sessions_created: snapshot.sessions_created,
+            intents: counters(snapshot.intents),
+            sub_intents: counters(snapshot.sub_intents),
+            sessions_rejected: counters(snapshot.sessions_rejected),
+            messages_delivered: snapshot.messages_delivered,
+            messages_read: snapshot.messages_read,
+            languages: snapshot
+                .languages
+                .into_iter()
+                .map(|(language, counts)| LanguageMetrics {
+                    language,
+                    messages: counts.messages,
+                    unknown: counts.unknown,
+                    fallback: counts.fallback,
+                })
+                .collect(),
+            memory: Memory {
+                sessions: memory.sessions,
+                messages: memory.messages,
+                message_bytes: memory.message_bytes,
+                approx_bytes: memory.approx_bytes,
+            },
+            queued_deliveries: state.outbox.len(),
+        }
+    }
+
+    /// Questions the bot could not answer, most frequent first.
+    async fn unanswered(
+        &self,
+        ctx: &Context<'_>,
+        language: Option<String>,
+        limit: Option<usize>,
+    ) -> Result<Vec<UnansweredQuestion>> {
+        let language = parse_language(language)?;
+        let limit = limit.unwrap_or(DEFAULT_PAGE).min(MAX_PAGE);
+        Ok(state(ctx)
+            .unanswered
+            .top(language, limit)
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+}
@@ -0,0 +1,127 @@
+//! The gRPC API defined in `proto/chatbot.proto`, served on `GRPC_ADDR`
+//! next to the REST API. Turns go through [`service`] like `/chat` does.
+
+use std::{net::SocketAddr, pin::Pin};
+
+use tokio_stream::{Stream, StreamExt, wrappers::BroadcastStream};
+use tonic::{Request, Response, Status};
+
+use crate::{error::ApiError, events::TranscriptEntry, service, session::Role, state::SharedState};
+
+pub mod proto {
+    tonic::include_proto!("chatbot.v1");
+}
+
+use proto::{
+    ChatRequest, ChatResponse, GetHistoryRequest, GetHistoryResponse, StreamRepliesRequest,
+    TranscriptMessage,
+    chat_server::{Chat, ChatServer},
+};
+
+impl From<ApiError> for Status {
+    fn from(e: ApiError) -> Self {
+        match e {
+            ApiError::BadRequest(m) => Status::invalid_argument(m),
+            ApiError::Unauthorized => Status::unauthenticated("unauthorized"),
+            ApiError::Forbidden(m) => Status::permission_denied(m),
+            ApiError::NotFound(m) => Status::not_found(m),
+            ApiError::Busy(m) => Status::unavailable(m),
+            ApiError::Blocked(m) => Status::permission_denied(m.unwrap_or_default()),
+            ApiError::Internal(m) => Status::internal(m),
+        }
+    }
+}
+
+impl From<Role> for proto::Role {
+    fn from(role: Role) -> Self {
+        match role {
+            Role::User => proto::Role::User,
+            Role::Bot => proto::Role::Bot,
+            Role::Agent => proto::Role::Agent,
+        }
+    }
+}
+
+impl From<TranscriptEntry> for TranscriptMessage {
+    fn from(entry: TranscriptEntry) -> Self {
+        Self {
+            role: proto::Role::from(entry.role).into(),
+            text: entry.text,
+            at_ms: entry.at_ms,
+            id: entry.id,
+        }
+    }
+}
+
+pub struct ChatService {
+    state: SharedState,
+}
+
+impl ChatService {
+    pub fn new(state: SharedState) -> Self {
+        Self { state }
+    }
+}
+
+#[tonic::async_trait]
+impl Chat for ChatService {
+    async fn chat(&self, request: Request<ChatRequest>) -> Result<Response<ChatResponse>, Status> {
+        let client_ip = request.remote_addr().map(|addr| addr.ip());
+        let req = request.into_inner();
+        let message = req.message.trim();
+        if message.is_empty() {
+            return Err(Status::invalid_argument("message must not be empty"));
+        }
+        let (session_id, mut replies) = service::converse(
+            &self.state,
+            req.session_id.as_deref().filter(|id| !id.is_empty()),
+            req.language.as_deref(),
+            req.tenant.as_deref(),
+            &[message],
+            None,
+            client_ip,
+        )
+        .await?;
+        let reply = replies.remove(0);
+        Ok(Response::new(ChatResponse {
+            reply: reply.text,
+            session_id,
+            quick_replies: reply.quick_replies,
+            message_id: reply.message_id.unwrap_or_default(),
+        }))
+    }
+
+    async fn get_history(
+        &self,
+        request: Request<GetHistoryRequest>,
+    ) -> Result<Response<GetHistoryResponse>, Status> {
+        let messages = service::history(&self.state, &request.into_inner().session_id).await?;
+        Ok(Response::new(GetHistoryResponse {
+            messages: messages.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    type StreamRepliesStream =
+        Pin<Box<dyn Stream<Item = Result<TranscriptMessage, Status>> + Send>>;
+
+    /// Everything sent to the visitor; their own messages are left out.
+    async fn stream_replies(
+        &self,
+        request: Request<StreamRepliesRequest>,
+    ) -> Result<Response<Self::StreamRepliesStream>, Status> {
+        let updates = service::subscribe(&self.state, &request.into_inner().session_id).await?;
+        let stream = BroadcastStream::new(updates).filter_map(|entry| {
+            let entry = entry.ok()?;
+            (entry.role != Role::User).then(|| Ok(entry.into()))
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+/// Serves the gRPC API on `addr` until the process exits.
+pub async fn serve(state: SharedState, addr: SocketAddr) -> Result<(), tonic::transport::Error> {
+    tonic::transport::Server::builder()
+        .add_service(ChatServer::new(ChatService::new(state)))
+        .serve(addr)
+        .await
+}
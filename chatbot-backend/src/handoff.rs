@@ -0,0 +1,427 @@
+//! Handing a conversation over to a human operator.
+//!
+//! Operators watch a session's transcript live and may write to the
+//! visitor themselves. Their first message takes the session over: the bot
+//! stops answering until the operator releases it again.
+//!
+//! Visitors who asked for a person wait in a queue, served from
+//! `/admin/handoff/queue` in the order operators should take them: those
+//! who asked within `OPERATOR_HOURS` before those who asked when nobody was
+//! there, and then by wait, with each point of lead score counting as
+//! [`SCORE_HEAD_START`] of waiting, so good leads go first without others
+//! waiting forever. Waiting visitors are told their place in line whenever
+//! it changes.
+//!
+//! An operator can ask for a draft answer to the visitor's latest message
+//! to edit before sending: what the bot would have said, from its rules
+//! and the services catalog. This is a rule-based stand-in for the drafts
+//! asked for originally, written by a language model from a knowledge
+//! base: there is no knowledge base to retrieve from, so the model (see
+//! [`crate::ai`]) only drafts what the rules don't understand, from the
+//! visitor's message alone.
+
+use std::{collections::HashMap, time::Duration};
+
+use serde::Serialize;
+use tracing::info;
+
+use crate::{
+    ai,
+    chatbot::{Intent, Reply, Vocabulary},
+    error::ApiError,
+    events::{self, ConversationEvent, TranscriptEntry, now_ms},
+    flow::{self, Restore},
+    i18n::{Language, Text, fill, format_number, text},
+    plugins::IntentRequest,
+    prompts::PromptUsed,
+    push::PushMessage,
+    service,
+    session::{ConversationState, FieldValue, Role, SessionData},
+    state::{AppState, SharedState},
+    survey,
+    trace::IntentSource,
+};
+
+/// How much waiting a point of lead score is worth in the queue.
+pub const SCORE_HEAD_START: Duration = Duration::from_secs(5 * 60);
+const DAY_MINUTES: u32 = 24 * 60;
+
+/// Shows a new transcript line to everyone watching the session.
+/// `message_id` is empty for lines that are not session messages.
+pub fn publish(state: &AppState, session_id: &str, message_id: &str, role: Role, text: &str) {
+    state.watchers.send(
+        session_id,
+        TranscriptEntry::new(message_id, role, text, now_ms()),
+    );
+}
+
+/// Sends an operator's message to the visitor, taking the session over if
+/// the bot still had it. Returns the message id, or `None` when the
+/// session is gone.
+pub async fn send_agent_message(state: &AppState, session_id: &str, text: &str) -> Option<String> {
+    let message_id = state
+        .sessions
+        .with_session(session_id, |session| {
+            let mut events = Vec::new();
+            if !session.handed_off {
+                session.handed_off = true;
+                events.push(ConversationEvent::AgentJoined);
+            }
+            let message_id = session.push_message(Role::Agent, text);
+            events.push(ConversationEvent::AgentMessage {
+                text: text.to_string(),
+                message_id: message_id.clone(),
+            });
+            state.events.append(session_id, events);
+            message_id
+        })
+        .await?;
+    state.push.send(
+        session_id,
+        PushMessage::AgentMessage {
+            text: text.to_string(),
+            message_id: message_id.clone(),
+        },
+    );
+    publish(state, session_id, &message_id, Role::Agent, text);
+    Some(message_id)
+}
+
+/// Hands the session back to the bot.
+pub async fn release(state: &AppState, session_id: &str) {
+    let released = state
+        .sessions
+        .with_session(session_id, |session| {
+            if !session.handed_off {
+                return false;
+            }
+            session.handed_off = false;
+            state
+                .events
+                .append(session_id, vec![ConversationEvent::AgentLeft]);
+            true
+        })
+        .await;
+    if released == Some(true) {
+        survey::ask(state, session_id).await;
+    }
+}
+
+/// A reply drafted for an operator to edit before sending.
+#[derive(Debug, Clone, Serialize)]
+pub struct SuggestedReply {
+    /// The visitor message it answers.
+    pub message_id: String,
+    pub text: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub quick_replies: Vec<String>,
+    pub intent: String,
+    /// How the message's intent was read.
+    pub source: IntentSource,
+    /// The prompt the AI fallback was asked with, when it was.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<PromptUsed>,
+}
+
+/// Drafts an answer to the visitor's latest message, as the bot would
+/// have given it from where the conversation stood before that message:
+/// from the rules, or from the AI fallback when the rules don't understand
+/// it and the tenant has budget left. The session is left as it is. The
+/// draft and where it came from are recorded in the event log, so how
+/// often operators go with it can be told later.
+pub async fn suggest_reply(
+    state: &SharedState,
+    session_id: &str,
+) -> Result<SuggestedReply, ApiError> {
+    let mut draft = state
+        .sessions
+        .with_session(session_id, |session| session.clone())
+        .await
+        .ok_or_else(|| ApiError::NotFound("session expired".into()))?;
+    let Some(last) = draft.messages.iter().rev().find(|m| m.role == Role::User) else {
+        return Err(ApiError::BadRequest(
+            "the visitor hasn't written anything yet".into(),
+        ));
+    };
+    let (message_id, message, metadata) =
+        (last.id.clone(), last.content.clone(), last.metadata.clone());
+    if let Some(turn) = draft
+        .last_turn
+        .take()
+        .filter(|turn| turn.message_ids.first() == Some(&message_id))
+    {
+        draft.data = turn.data;
+        flow::restore(&mut draft, turn.state, Restore::Undo);
+        draft.failed_answers = turn.failed_answers;
+    }
+    draft.handed_off = false;
+    let idle = draft.state == ConversationState::Idle && !draft.survey_pending;
+    let request = IntentRequest {
+        session_id: session_id.to_string(),
+        tenant: draft.tenant.clone(),
+        language: draft.language,
+        message: message.clone(),
+        metadata: metadata.clone(),
+    };
+
+    let (synonyms, learned) = (state.synonyms.table(), state.training.learned());
+    let vocabulary = Vocabulary {
+        synonyms: &synonyms,
+        learned: &learned,
+    };
+    let (mut source, _) = service::read_intent(&message, None, vocabulary);
+    let mut reply = service::rule_reply(state, &mut draft, &message, metadata.as_ref(), vocabulary);
+    let mut prompt = None;
+    if idle && reply.intent == Intent::Unknown {
+        let asked = state.ai.prompt(&request);
+        if let Some(answer) = ai::answer(state, &request, &asked).await {
+            reply = Reply::custom(ai::FALLBACK_INTENT, answer);
+            source = IntentSource::Fallback;
+            prompt = Some(asked.used());
+        }
+    }
+    state
+        .config_for(&draft.tenant)
+        .postprocess(&draft, &mut reply);
+
+    let intent = reply.intent.as_str().to_string();
+    state.events.append(
+        session_id,
+        vec![ConversationEvent::ReplySuggested {
+            message_id: message_id.clone(),
+            intent: intent.clone(),
+            source,
+        }],
+    );
+    info!(%session_id, %intent, ?source, "reply suggested");
+    Ok(SuggestedReply {
+        message_id,
+        text: reply.text,
+        quick_replies: reply.quick_replies,
+        intent,
+        source,
+        prompt,
+    })
+}
+
+/// Sessions whose visitor asked for a person that neither an operator nor
+/// a live-chat tool has taken yet.
+pub fn waiting(state: &AppState) -> usize {
+    queue(state).len()
+}
+
+/// When operators answer: `OPERATOR_HOURS`, e.g. `mon-fri 09:00-17:00
+/// +01:00`. The days and the offset from UTC are optional; without days
+/// every day counts, without an offset the hours are UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OperatorHours {
+    /// Bit 0 is Monday.
+    days: u8,
+    from_minute: u32,
+    to_minute: u32,
+    utc_offset_minutes: i32,
+}
+
+const WEEKDAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+impl OperatorHours {
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = raw.split_whitespace().collect();
+        let (days, rest) = match parts.first() {
+            Some(first) if first.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+                (parse_days(first)?, &parts[1..])
+            }
+            _ => (0b111_1111, &parts[..]),
+        };
+        let (hours, offset) = match rest {
+            [hours] => (*hours, None),
+            [hours, offset] => (*hours, Some(*offset)),
+            _ => return Err("expected e.g. \"mon-fri 09:00-17:00 +01:00\"".into()),
+        };
+        let (from, to) = hours
+            .split_once('-')
+            .ok_or_else(|| format!("{hours:?} is not a range of hours"))?;
+        let (from_minute, to_minute) = (parse_time(from)?, parse_time(to)?);
+        if from_minute == to_minute {
+            return Err("the hours must not start and end at the same time".into());
+        }
+        let utc_offset_minutes = match offset {
+            Some(offset) => parse_offset(offset)?,
+            None => 0,
+        };
+        Ok(Self {
+            days,
+            from_minute,
+            to_minute,
+            utc_offset_minutes,
+        })
+    }
+
+    /// Whether `at_ms` falls within the hours. Hours that end before they
+    /// start run past midnight, and belong to the day they start on.
+    pub fn contains(&self, at_ms: u64) -> bool {
+        let local_minutes = (at_ms / 60_000) as i64 + i64::from(self.utc_offset_minutes);
+        let day = local_minutes.div_euclid(i64::from(DAY_MINUTES));
+        let minute = local_minutes.rem_euclid(i64::from(DAY_MINUTES)) as u32;
+        // 1970-01-01 was a Thursday.
+        let weekday = |day: i64| (day + 3).rem_euclid(7) as u8;
+        let open_on = |day: i64| self.days & (1 << weekday(day)) != 0;
+        if self.from_minute < self.to_minute {
+            open_on(day) && (self.from_minute..self.to_minute).contains(&minute)
+        } else {
+            open_on(day) && minute >= self.from_minute
+                || open_on(day - 1) && minute < self.to_minute
+        }
+    }
+}
+
+fn parse_days(raw: &str) -> Result<u8, String> {
+    let index = |name: &str| {
+        WEEKDAYS
+            .iter()
+            .position(|d| d.eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("{name:?} is not a day; use mon to sun"))
+    };
+    let mut days = 0u8;
+    for part in raw.split(',') {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (index(first)?, index(last)?),
+            None => (index(part)?, index(part)?),
+        };
+        let mut day = first;
+        loop {
+            days |= 1 << day;
+            if day == last {
+                break;
+            }
+            day = (day + 1) % 7;
+        }
+    }
+    Ok(days)
+}
+
+fn parse_time(raw: &str) -> Result<u32, String> {
+    let invalid = || format!("{raw:?} is not a time like 09:00");
+    let (hours, minutes) = raw.split_once(':').ok_or_else(invalid)?;
+    let hours: u32 = hours.parse().map_err(|_| invalid())?;
+    let minutes: u32 = minutes.parse().map_err(|_| invalid())?;
+    if minutes >= 60 || hours * 60 + minutes > DAY_MINUTES {
+        return Err(invalid());
+    }
+    Ok((hours * 60 + minutes) % DAY_MINUTES)
+}
+
+fn parse_offset(raw: &str) -> Result<i32, String> {
+    let invalid = || format!("{raw:?} is not an offset like +01:00");
+    let (sign, rest) = match raw.as_bytes().first() {
+        Some(b'+') => (1, &raw[1..]),
+        Some(b'-') => (-1, &raw[1..]),
+        _ => return Err(invalid()),
+    };
+    let minutes = parse_time(rest).map_err(|_| invalid())?;
+    if minutes > 14 * 60 {
+        return Err(invalid());
+    }
+    Ok(sign * minutes as i32)
+}
+
+/// How promising a lead is, from 0 to 5: what the visitor has told the
+/// bot, with a point off for a budget below the minimum.
+pub fn lead_score(data: &SessionData) -> u8 {
+    let score = 2 * u8::from(data.email.is_some())
+        + u8::from(data.name.is_some())
+        + u8::from(data.project_details.is_some())
+        + u8::from(matches!(data.budget, Some(FieldValue::Given(_))));
+    score.saturating_sub(u8::from(data.tag.is_some()))
+}
+
+/// A visitor waiting for an operator.
+#[derive(Debug, Clone, Serialize)]
+pub struct QueuedSession {
+    pub session_id: String,
+    /// 1 for the next to be taken.
+    pub position: usize,
+    pub language: Language,
+    pub requested_at_ms: u64,
+    pub waited_secs: u64,
+    pub lead_score: u8,
+    /// Asked outside `OPERATOR_HOURS`.
+    pub after_hours: bool,
+}
+
+/// Everyone waiting for an operator, in the order to take them.
+pub fn queue(state: &AppState) -> Vec<QueuedSession> {
+    let now = now_ms();
+    let mut queue: Vec<QueuedSession> = state
+        .events
+        .waiting()
+        .into_iter()
+        .map(|(id, requested_at_ms)| {
+            let projection = events::project(&state.events.session_events(&id));
+            QueuedSession {
+                session_id: id,
+                position: 0,
+                language: projection.language,
+                requested_at_ms,
+                waited_secs: now.saturating_sub(requested_at_ms) / 1_000,
+                lead_score: lead_score(&projection.data),
+                after_hours: state
+                    .operator_hours
+                    .is_some_and(|hours| !hours.contains(requested_at_ms)),
+            }
+        })
+        .collect();
+    let head_start = SCORE_HEAD_START.as_millis() as u64;
+    queue.sort_by_key(|q| {
+        (
+            q.after_hours,
+            q.requested_at_ms
+                .saturating_sub(u64::from(q.lead_score) * head_start),
+            q.session_id.clone(),
+        )
+    });
+    for (index, queued) in queue.iter_mut().enumerate() {
+        queued.position = index + 1;
+    }
+    queue
+}
+
+/// Tells each waiting visitor their place in line when it has changed
+/// since the last call, which `announced` remembers. Nothing is sent in
+/// maintenance mode.
+pub fn announce_positions(state: &AppState, announced: &mut HashMap<String, usize>) {
+    if state.maintenance.is_on() {
+        return;
+    }
+    let queue = queue(state);
+    announced.retain(|id, _| queue.iter().any(|q| q.session_id == *id));
+    let open = state
+        .operator_hours
+        .is_none_or(|hours| hours.contains(now_ms()))
+        && state.presence.anyone_online();
+    for queued in &queue {
+        if announced.get(&queued.session_id) == Some(&queued.position) {
+            continue;
+        }
+        announced.insert(queued.session_id.clone(), queued.position);
+        let key = if open {
+            Text::QueuePosition
+        } else {
+            Text::QueuePositionAfterHours
+        };
+        let position = format_number(queued.language, queued.position as u64);
+        let update = fill(
+            text(queued.language, key),
+            &[("position", position.as_str())],
+        );
+        let listeners = state.push.send(
+            &queued.session_id,
+            PushMessage::QueuePosition {
+                text: update.clone(),
+                position: queued.position,
+            },
+        );
+        info!(session_id = %queued.session_id, position = queued.position, listeners, "queue position sent");
+        publish(state, &queued.session_id, "", Role::Bot, &update);
+    }
+}
@@ -0,0 +1,40 @@
+//! The `healthcheck` mode of the server binary.
+//!
+//! `chatbot-backend healthcheck` asks the instance running on `BIND_ADDR`
+//! (or at `HEALTHCHECK_URL`) whether it is ready and exits 0 or 1, for a
+//! Docker `HEALTHCHECK` or a systemd `ExecStartPost=`/watchdog script.
+
+use std::{net::SocketAddr, time::Duration};
+
+/// How long to wait for the instance to answer.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The readiness URL of an instance bound to `bind_addr`. Wildcard
+/// addresses are reached over loopback.
+pub fn ready_url(bind_addr: &str) -> Result<String, String> {
+    let addr: SocketAddr = bind_addr
+        .parse()
+        .map_err(|e| format!("BIND_ADDR {bind_addr:?}: {e}"))?;
+    let host = match addr {
+        SocketAddr::V4(a) if a.ip().is_unspecified() => "127.0.0.1".to_string(),
+        SocketAddr::V6(a) if a.ip().is_unspecified() => "[::1]".to_string(),
+        SocketAddr::V4(a) => a.ip().to_string(),
+        SocketAddr::V6(a) => format!("[{}]", a.ip()),
+    };
+    Ok(format!("http://{host}:{}/ready", addr.port()))
+}
+
+/// Calls `url` and succeeds on a 2xx answer within [`TIMEOUT`].
+pub async fn probe(url: &str) -> Result<(), String> {
+    let client = reqwest::Client::builder()
+        .timeout(TIMEOUT)
+        .build()
+        .map_err(|e| e.to_string())?;
+    let response = client.get(url).send().await.map_err(|e| e.to_string())?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(format!("{status} {}", body.trim()))
+}
@@ -0,0 +1,29 @@
+//! The HTTP client for every outgoing call: webhooks, channel APIs, live
+//! chat and Matrix. It is built once and shared through [`AppState`], so
+//! connections to the same host are pooled and kept alive, and HTTP/2 is
+//! used where the server offers it.
+//!
+//! [`AppState`]: crate::state::AppState
+
+use std::time::Duration;
+
+use reqwest::{Client, Proxy};
+
+/// How long an unused pooled connection is kept open.
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+
+/// Builds the shared client. Calls go through `proxy` when it is set, else
+/// through the proxy named by the usual `HTTPS_PROXY`/`HTTP_PROXY`/
+/// `NO_PROXY` variables, if any.
+pub fn client(proxy: Option<&str>) -> reqwest::Result<Client> {
+    let mut builder = Client::builder()
+        .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+        .connect_timeout(CONNECT_TIMEOUT)
+        .tcp_keepalive(TCP_KEEPALIVE);
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(Proxy::all(proxy)?);
+    }
+    builder.build()
+}
@@ -0,0 +1,963 @@
+//! Conversation languages and the localized reply texts.
+//!
+//! Texts may contain `{placeholder}` markers which callers fill with
+//! [`fill`]. Operators can replace individual texts at runtime with
+//! [`set_overrides`].
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{LazyLock, Mutex, RwLock},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Languages the bot can hold a conversation in.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    #[default]
+    En,
+    Pl,
+    Es,
+}
+
+const POLISH_HINTS: &[&str] = &[
+    "cześć",
+    "czesc",
+    "dzień dobry",
+    "dzien dobry",
+    "witam",
+    "chcę",
+    "chce",
+    "potrzebuję",
+    "jestem",
+    "proszę",
+    "prosze",
+    "dziękuję",
+    "dziekuje",
+    "dzięki",
+    "ile kosztuje",
+];
+
+const SPANISH_HINTS: &[&str] = &[
+    "hola",
+    "buenos días",
+    "buenas",
+    "quiero",
+    "necesito",
+    "gracias",
+    "por favor",
+    "cuánto",
+    "cuanto",
+    "me llamo",
+];
+
+impl Language {
+    pub const ALL: [Language; 3] = [Language::En, Language::Pl, Language::Es];
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Language::En => "en",
+            Language::Pl => "pl",
+            Language::Es => "es",
+        }
+    }
+
+    /// The language's name in itself.
+    pub fn name(self) -> &'static str {
+        match self {
+            Language::En => "English",
+            Language::Pl => "Polski",
+            Language::Es => "Español",
+        }
+    }
+
+    /// Parses an ISO 639-1 code, ignoring any region suffix ("es-MX").
+    pub fn from_code(code: &str) -> Option<Self> {
+        let primary = code.split(['-', '_']).next()?.trim().to_ascii_lowercase();
+        Language::ALL.into_iter().find(|l| l.code() == primary)
+    }
+
+    /// Best-effort guess from a single message. Returns `None` when nothing
+    /// in the text points to a particular language.
+    pub fn detect(text: &str) -> Option<Self> {
+        let lower = text.to_lowercase();
+        if lower.chars().any(|c| "ąęłśżźćń".contains(c)) {
+            return Some(Language::Pl);
+        }
+        if lower.chars().any(|c| "ñ¿¡".contains(c)) {
+            return Some(Language::Es);
+        }
+        let normalized = crate::text::normalize(&lower);
+        if crate::text::contains_any(&normalized, POLISH_HINTS) {
+            Some(Language::Pl)
+        } else if crate::text::contains_any(&normalized, SPANISH_HINTS) {
+            Some(Language::Es)
+        } else {
+            None
+        }
+    }
+}
+
+/// Keys of every localized text the bot can send.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Text {
+    Greeting,
+    Help,
+    Contact,
+    Pricing,
+    ServicesIntro,
+    ServicesOutro,
+    FromPrice,
+    ServiceDetail,
+    ServiceFollowUp,
+    MoreAbout,
+    StartProject,
+    OtherServices,
+    AskName,
+    InvalidName,
+    NameLength,
+    NameCharacters,
+    NameLettersOnly,
+    AskEmail,
+    InvalidEmail,
+    AskProjectDetails,
+    ProjectDetailsTooShort,
+    AskBudget,
+    InvalidBudget,
+    BudgetBelowRange,
+    BudgetAboveRange,
+    BudgetBelowMinimum,
+    SurveyQuestion,
+    SurveyThanks,
+    SelfServiceOffer,
+    NotSureYet,
+    FieldRequired,
+    OfferPerson,
+    TalkToPerson,
+    OfferCallback,
+    RequestCallback,
+    AskCallbackEmail,
+    CallbackQueued,
+    CallbackAfterInquiry,
+    CallbackDeclined,
+    FieldUpdated,
+    FieldName,
+    FieldEmail,
+    FieldProjectDetails,
+    FieldBudget,
+    SummaryIntro,
+    NotSpecified,
+    ConfirmSend,
+    ChangeSomething,
+    RemindConfirm,
+    WhatToChange,
+    AskNewValue,
+    InquiryComplete,
+    RemindName,
+    RemindEmail,
+    RemindProjectDetails,
+    RemindBudget,
+    EmailTranscriptOption,
+    AskTranscriptConsent,
+    TranscriptEmailQueued,
+    TranscriptEmailDeclined,
+    TranscriptNeedsEmail,
+    NoThanks,
+    TranscriptSubject,
+    TranscriptIntro,
+    TranscriptVisitor,
+    TranscriptBot,
+    TranscriptAgent,
+    TranscriptReportAttached,
+    Reset,
+    Busy,
+    Maintenance,
+    Blocked,
+    ConversationLimit,
+    StillThere,
+    Escalated,
+    QueuePosition,
+    QueuePositionAfterHours,
+    Thanks,
+    Goodbye,
+    Signature,
+    Unknown,
+    ReportTitle,
+    ReportName,
+    ReportEmail,
+    ReportProject,
+    ReportBudget,
+    ReportLanguage,
+    ReportStarted,
+    ReportEnded,
+    ReportSession,
+    ReportLeadQuality,
+    ReportLeadScore,
+    ReportReplyTime,
+}
+
+type Overrides = HashMap<(Language, Text), &'static str>;
+
+static OVERRIDES: LazyLock<RwLock<Overrides>> = LazyLock::new(Default::default);
+static INTERNED: LazyLock<Mutex<HashSet<&'static str>>> = LazyLock::new(Default::default);
+
+/// Gives an override text a `'static` lifetime. Identical texts share one
+/// allocation, so reapplying the same configuration doesn't grow memory.
+fn intern(text: String) -> &'static str {
+    let mut interned = INTERNED.lock().unwrap();
+    if let Some(existing) = interned.get(text.as_str()) {
+        return existing;
+    }
+    let leaked: &'static str = Box::leak(text.into_boxed_str());
+    interned.insert(leaked);
+    leaked
+}
+
+/// Replaces all text overrides; keys not listed fall back to the built-in
+/// texts.
+pub fn set_overrides(overrides: HashMap<(Language, Text), String>) {
+    let overrides = overrides
+        .into_iter()
+        .map(|(key, text)| (key, intern(text)))
+        .collect();
+    *OVERRIDES.write().unwrap() = overrides;
+}
+
+/// Returns the text for `key` in `lang`.
+pub fn text(lang: Language, key: Text) -> &'static str {
+    if let Some(text) = OVERRIDES.read().unwrap().get(&(lang, key)) {
+        return text;
+    }
+    builtin(lang, key)
+}
+
+/// Names of the `{placeholder}` markers in `template`.
+pub fn placeholders(template: &str) -> Vec<&str> {
+    template
+        .split('{')
+        .skip(1)
+        .filter_map(|rest| rest.split_once('}').map(|(name, _)| name))
+        .collect()
+}
+
+/// The text shipped with the bot, ignoring overrides.
+pub fn builtin(lang: Language, key: Text) -> &'static str {
+    use Language::*;
+    match (key, lang) {
+        (Text::Greeting, En) => {
+            "Hi! I'm the studio assistant. I can tell you about our services and pricing, or help you start a project. What can I do for you?"
+        }
+        (Text::Greeting, Pl) => {
+            "Cześć! Jestem asystentem studia. Mogę opowiedzieć o naszych usługach i cenach albo pomóc rozpocząć projekt. W czym mogę pomóc?"
+        }
+        (Text::Greeting, Es) => {
+            "¡Hola! Soy el asistente del estudio. Puedo contarte sobre nuestros servicios y precios, o ayudarte a empezar un proyecto. ¿En qué puedo ayudarte?"
+        }
+
+        (Text::Help, En) => {
+            "I can describe our services, give pricing information, share our contact details, or collect your project details for a quote. Say \"start a project\" to begin, or \"reset\" at any time to start over."
+        }
+        (Text::Help, Pl) => {
+            "Mogę opisać nasze usługi, podać informacje o cenach, udostępnić dane kontaktowe albo zebrać szczegóły projektu do wyceny. Napisz \"rozpocznij projekt\", aby zacząć, lub \"od nowa\", aby zacząć od początku."
+        }
+        (Text::Help, Es) => {
+            "Puedo describir nuestros servicios, darte información de precios, compartir nuestros datos de contacto o recoger los detalles de tu proyecto para un presupuesto. Escribe \"empezar un proyecto\" para comenzar, o \"reiniciar\" en cualquier momento."
+        }
+
+        (Text::Contact, En) => {
+            "You can reach us at hello@example.com or +1 555 0100, Monday to Friday, 9:00–17:00."
+        }
+        (Text::Contact, Pl) => {
+            "Możesz się z nami skontaktować pod adresem hello@example.com lub telefonicznie: +1 555 0100, od poniedziałku do piątku, 9:00–17:00."
+        }
+        (Text::Contact, Es) => {
+            "Puedes contactarnos en hello@example.com o al +1 555 0100, de lunes a viernes, de 9:00 a 17:00."
+        }
+
+        (Text::Pricing, En) => {
+            "Pricing depends on scope. Ask about our \"services\" to see starting prices, or say \"start a project\" and I'll collect the details for a tailored quote."
+        }
+        (Text::Pricing, Pl) => {
+            "Cena zależy od zakresu prac. Zapytaj o \"usługi\", aby zobaczyć ceny początkowe, albo napisz \"rozpocznij projekt\", a zbiorę szczegóły do indywidualnej wyceny."
+        }
+        (Text::Pricing, Es) => {
+            "El precio depende del alcance. Pregunta por nuestros \"servicios\" para ver los precios iniciales, o escribe \"empezar un proyecto\" y recogeré los detalles para un presupuesto a medida."
+        }
+
+        (Text::ServicesIntro, En) => "Here's what we offer:",
+        (Text::ServicesIntro, Pl) => "Oto, co oferujemy:",
+        (Text::ServicesIntro, Es) => "Esto es lo que ofrecemos:",
+
+        (Text::ServicesOutro, En) => {
+            "Want details on any of these? Just ask, e.g. \"tell me more about {example}\"."
+        }
+        (Text::ServicesOutro, Pl) => {
+            "Chcesz poznać szczegóły? Zapytaj, np. \"opowiedz więcej o {example}\"."
+        }
+        (Text::ServicesOutro, Es) => {
+            "¿Quieres detalles de alguno? Pregunta, por ejemplo: \"cuéntame más sobre {example}\"."
+        }
+
+        (Text::FromPrice, En) => "from {price}",
+        (Text::FromPrice, Pl) => "od {price}",
+        (Text::FromPrice, Es) => "desde {price}",
+
+        (Text::ServiceDetail, En) => "{service}: {description} Prices start at {price}.",
+        (Text::ServiceDetail, Pl) => "{service}: {description} Ceny zaczynają się od {price}.",
+        (Text::ServiceDetail, Es) => "{service}: {description} Los precios empiezan en {price}.",
+
+        (Text::ServiceFollowUp, En) => "Would you like to start a project?",
+        (Text::ServiceFollowUp, Pl) => "Czy chcesz rozpocząć projekt?",
+        (Text::ServiceFollowUp, Es) => "¿Te gustaría empezar un proyecto?",
+
+        (Text::MoreAbout, En) => "Tell me more about {service}",
+        (Text::MoreAbout, Pl) => "Opowiedz więcej o {service}",
+        (Text::MoreAbout, Es) => "Cuéntame más sobre {service}",
+
+        (Text::StartProject, En) => "Start a project",
+        (Text::StartProject, Pl) => "Rozpocznij projekt",
+        (Text::StartProject, Es) => "Empezar un proyecto",
+
+        (Text::OtherServices, En) => "Other services",
+        (Text::OtherServices, Pl) => "Inne usługi",
+        (Text::OtherServices, Es) => "Otros servicios",
+
+        (Text::AskName, En) => "Great, let's get started! What's your name?",
+        (Text::AskName, Pl) => "Świetnie, zaczynajmy! Jak masz na imię?",
+        (Text::AskName, Es) => "¡Genial, empecemos! ¿Cómo te llamas?",
+
+        (Text::InvalidName, En) => {
+            "Sorry, that doesn't look like a name. Could you tell me your name?"
+        }
+        (Text::InvalidName, Pl) => "Przepraszam, to nie wygląda na imię. Jak masz na imię?",
+        (Text::InvalidName, Es) => "Perdona, eso no parece un nombre. ¿Cómo te llamas?",
+
+        (Text::NameLength, En) => {
+            "A name here is {min} to {max} characters long. What's your name?"
+        }
+        (Text::NameLength, Pl) => "Imię powinno mieć od {min} do {max} znaków. Jak masz na imię?",
+        (Text::NameLength, Es) => {
+            "El nombre debe tener entre {min} y {max} caracteres. ¿Cómo te llamas?"
+        }
+
+        (Text::NameCharacters, En) => {
+            "A name can only have letters, spaces and {allowed}. What's your name?"
+        }
+        (Text::NameCharacters, Pl) => {
+            "Imię może zawierać tylko litery, spacje i {allowed}. Jak masz na imię?"
+        }
+        (Text::NameCharacters, Es) => {
+            "Un nombre solo puede tener letras, espacios y {allowed}. ¿Cómo te llamas?"
+        }
+
+        (Text::NameLettersOnly, En) => "A name can only have letters and spaces. What's your name?",
+        (Text::NameLettersOnly, Pl) => {
+            "Imię może zawierać tylko litery i spacje. Jak masz na imię?"
+        }
+        (Text::NameLettersOnly, Es) => {
+            "Un nombre solo puede tener letras y espacios. ¿Cómo te llamas?"
+        }
+
+        (Text::AskEmail, En) => "Nice to meet you, {name}! What's your email address?",
+        (Text::AskEmail, Pl) => "Miło mi, {name}! Jaki jest Twój adres e-mail?",
+        (Text::AskEmail, Es) => "¡Encantado, {name}! ¿Cuál es tu correo electrónico?",
+
+        (Text::InvalidEmail, En) => {
+            "That email address doesn't look right. Could you double-check it?"
+        }
+        (Text::InvalidEmail, Pl) => {
+            "Ten adres e-mail nie wygląda poprawnie. Czy możesz go sprawdzić?"
+        }
+        (Text::InvalidEmail, Es) => "Ese correo electrónico no parece correcto. ¿Puedes revisarlo?",
+
+        (Text::AskProjectDetails, En) => {
+            "Thanks! Tell me a bit about your project: what would you like us to build?"
+        }
+        (Text::AskProjectDetails, Pl) => {
+            "Dziękuję! Opowiedz trochę o swoim projekcie: co mamy dla Ciebie zbudować?"
+        }
+        (Text::AskProjectDetails, Es) => {
+            "¡Gracias! Cuéntame un poco sobre tu proyecto: ¿qué te gustaría que construyamos?"
+        }
+
+        (Text::ProjectDetailsTooShort, En) => {
+            "Could you describe your project in a little more detail?"
+        }
+        (Text::ProjectDetailsTooShort, Pl) => "Czy możesz opisać projekt nieco dokładniej?",
+        (Text::ProjectDetailsTooShort, Es) => {
+            "¿Podrías describir tu proyecto con un poco más de detalle?"
+        }
+
+        (Text::AskBudget, En) => "Got it. What budget do you have in mind?",
+        (Text::AskBudget, Pl) => "Rozumiem. Jaki budżet przewidujesz?",
+        (Text::AskBudget, Es) => "Entendido. ¿Qué presupuesto tienes en mente?",
+
+        (Text::SurveyQuestion, En) => {
+            "Before you go: how would you rate this conversation, from 1 (poor) to 5 (excellent)?"
+        }
+        (Text::SurveyQuestion, Pl) => {
+            "Zanim się pożegnamy: jak oceniasz tę rozmowę w skali od 1 (słabo) do 5 (świetnie)?"
+        }
+        (Text::SurveyQuestion, Es) => {
+            "Antes de que te vayas: ¿cómo valorarías esta conversación, del 1 (mala) al 5 (excelente)?"
+        }
+
+        (Text::SurveyThanks, En) => "Thank you for your feedback!",
+        (Text::SurveyThanks, Pl) => "Dziękujemy za opinię!",
+        (Text::SurveyThanks, Es) => "¡Gracias por tu opinión!",
+
+        (Text::BudgetBelowMinimum, En) => {
+            "Thanks! Just so you know, our projects usually start at {min_budget}, so we may suggest a smaller scope."
+        }
+        (Text::BudgetBelowMinimum, Pl) => {
+            "Dziękuję! Dla jasności: nasze projekty zaczynają się zwykle od {min_budget}, więc możemy zaproponować mniejszy zakres."
+        }
+        (Text::BudgetBelowMinimum, Es) => {
+            "¡Gracias! Para que lo sepas, nuestros proyectos suelen empezar en {min_budget}, así que quizá te propongamos un alcance menor."
+        }
+
+        (Text::SelfServiceOffer, En) => {
+            "Thanks, {name}! Our custom projects start at {min_budget}, so for a budget like yours our ready-made packages are a better fit: {url}\nIf you'd still like a custom quote, just say \"start a project\"."
+        }
+        (Text::SelfServiceOffer, Pl) => {
+            "Dziękuję, {name}! Nasze projekty na zamówienie zaczynają się od {min_budget}, więc przy takim budżecie lepiej sprawdzą się nasze gotowe pakiety: {url}\nJeśli nadal chcesz wycenę indywidualną, napisz \"rozpocznij projekt\"."
+        }
+        (Text::SelfServiceOffer, Es) => {
+            "¡Gracias, {name}! Nuestros proyectos a medida empiezan en {min_budget}, así que para un presupuesto así te irán mejor nuestros paquetes listos: {url}\nSi aun así quieres un presupuesto a medida, di \"empezar un proyecto\"."
+        }
+
+        (Text::InvalidBudget, En) => {
+            "Please give an approximate budget, e.g. \"5000\" or \"3-5k\"."
+        }
+        (Text::InvalidBudget, Pl) => "Podaj przybliżony budżet, np. \"5000\" lub \"3-5 tys.\".",
+        (Text::InvalidBudget, Es) => {
+            "Indica un presupuesto aproximado, por ejemplo \"5000\" o \"3-5k\"."
+        }
+
+        (Text::BudgetBelowRange, En) => {
+            "We take on projects from {min}. What budget do you have in mind?"
+        }
+        (Text::BudgetBelowRange, Pl) => "Przyjmujemy projekty od {min}. Jaki budżet przewidujesz?",
+        (Text::BudgetBelowRange, Es) => {
+            "Aceptamos proyectos desde {min}. ¿Qué presupuesto tienes en mente?"
+        }
+
+        (Text::BudgetAboveRange, En) => {
+            "For budgets above {max} we'd rather talk in person. Could you give a budget up to {max}, or ask to talk to a person?"
+        }
+        (Text::BudgetAboveRange, Pl) => {
+            "Przy budżetach powyżej {max} wolimy porozmawiać osobiście. Podaj budżet do {max} albo poproś o rozmowę z konsultantem."
+        }
+        (Text::BudgetAboveRange, Es) => {
+            "Para presupuestos de más de {max} preferimos hablar en persona. Indica un presupuesto de hasta {max} o pide hablar con una persona."
+        }
+
+        (Text::NotSureYet, En) => "Not sure yet",
+        (Text::NotSureYet, Pl) => "Jeszcze nie wiem",
+        (Text::NotSureYet, Es) => "Aún no lo sé",
+
+        (Text::FieldRequired, En) => "I'm afraid I need this one to get back to you.",
+        (Text::FieldRequired, Pl) => {
+            "Niestety bez tej informacji nie będziemy mogli się z Tobą skontaktować."
+        }
+        (Text::FieldRequired, Es) => "Me temo que necesito este dato para poder responderte.",
+
+        (Text::OfferPerson, En) => {
+            "This doesn't seem to be working. Would you rather talk to a person?"
+        }
+        (Text::OfferPerson, Pl) => {
+            "Chyba nie idzie nam najlepiej. Wolisz porozmawiać z konsultantem?"
+        }
+        (Text::OfferPerson, Es) => {
+            "Parece que no nos entendemos. ¿Prefieres hablar con una persona?"
+        }
+
+        (Text::TalkToPerson, En) => "Talk to a person",
+        (Text::TalkToPerson, Pl) => "Rozmowa z konsultantem",
+        (Text::TalkToPerson, Es) => "Hablar con una persona",
+
+        (Text::OfferCallback, En) => {
+            "This doesn't seem to be working. Would you like someone from the team to email you?"
+        }
+        (Text::OfferCallback, Pl) => {
+            "Chyba nie idzie nam najlepiej. Czy ktoś z zespołu ma napisać do Ciebie e-mail?"
+        }
+        (Text::OfferCallback, Es) => {
+            "Parece que no nos entendemos. ¿Quieres que alguien del equipo te escriba por correo?"
+        }
+        (Text::RequestCallback, En) => "Email me back",
+        (Text::RequestCallback, Pl) => "Proszę o kontakt mailowy",
+        (Text::RequestCallback, Es) => "Que me escriban por correo",
+        (Text::AskCallbackEmail, En) => {
+            "Nobody from the team is online right now. Leave your email address and someone will get back to you as soon as they're in."
+        }
+        (Text::AskCallbackEmail, Pl) => {
+            "Nikogo z zespołu nie ma teraz online. Zostaw swój adres e-mail, a ktoś odezwie się, gdy tylko będzie dostępny."
+        }
+        (Text::AskCallbackEmail, Es) => {
+            "Ahora mismo no hay nadie del equipo en línea. Deja tu correo y alguien te escribirá en cuanto esté disponible."
+        }
+        (Text::CallbackQueued, En) => {
+            "Thanks! Someone from the team will email you at {email} as soon as they're in."
+        }
+        (Text::CallbackQueued, Pl) => {
+            "Dziękujemy! Ktoś z zespołu napisze do Ciebie na {email}, gdy tylko będzie dostępny."
+        }
+        (Text::CallbackQueued, Es) => {
+            "¡Gracias! Alguien del equipo te escribirá a {email} en cuanto esté disponible."
+        }
+        (Text::CallbackAfterInquiry, En) => {
+            "Nobody from the team is online right now, but once we've finished here they'll get back to you by email."
+        }
+        (Text::CallbackAfterInquiry, Pl) => {
+            "Nikogo z zespołu nie ma teraz online, ale gdy skończymy, odezwą się do Ciebie mailowo."
+        }
+        (Text::CallbackAfterInquiry, Es) => {
+            "Ahora mismo no hay nadie del equipo en línea, pero cuando terminemos te escribirán por correo."
+        }
+        (Text::CallbackDeclined, En) => "No problem. Just ask if you'd like a person later.",
+        (Text::CallbackDeclined, Pl) => {
+            "Nie ma sprawy. Daj znać, jeśli później zechcesz porozmawiać z konsultantem."
+        }
+        (Text::CallbackDeclined, Es) => {
+            "No hay problema. Avísame si más tarde quieres hablar con una persona."
+        }
+
+        (Text::FieldUpdated, En) => "Got it, I've updated your {field} to {value}.",
+        (Text::FieldUpdated, Pl) => "Jasne, zmieniłem {field} na {value}.",
+        (Text::FieldUpdated, Es) => "Entendido, he actualizado tu {field} a {value}.",
+
+        (Text::FieldName, En) => "name",
+        (Text::FieldName, Pl) => "imię",
+        (Text::FieldName, Es) => "nombre",
+
+        (Text::FieldEmail, En) => "email address",
+        (Text::FieldEmail, Pl) => "adres e-mail",
+        (Text::FieldEmail, Es) => "correo electrónico",
+
+        (Text::FieldProjectDetails, En) => "project description",
+        (Text::FieldProjectDetails, Pl) => "opis projektu",
+        (Text::FieldProjectDetails, Es) => "descripción del proyecto",
+
+        (Text::FieldBudget, En) => "budget",
+        (Text::FieldBudget, Pl) => "budżet",
+        (Text::FieldBudget, Es) => "presupuesto",
+
+        (Text::SummaryIntro, En) => "Here's a summary of your inquiry:",
+        (Text::SummaryIntro, Pl) => "Oto podsumowanie Twojego zapytania:",
+        (Text::SummaryIntro, Es) => "Este es el resumen de tu consulta:",
+
+        (Text::NotSpecified, En) => "not specified",
+        (Text::NotSpecified, Pl) => "nie podano",
+        (Text::NotSpecified, Es) => "sin especificar",
+
+        (Text::ConfirmSend, En) => "Yes, send it",
+        (Text::ConfirmSend, Pl) => "Tak, wyślij",
+        (Text::ConfirmSend, Es) => "Sí, envíalo",
+
+        (Text::ChangeSomething, En) => "I need to change something",
+        (Text::ChangeSomething, Pl) => "Chcę coś zmienić",
+        (Text::ChangeSomething, Es) => "Quiero cambiar algo",
+
+        (Text::RemindConfirm, En) => {
+            "Is everything correct? Reply \"yes\" to send your inquiry, or tell me what to change."
+        }
+        (Text::RemindConfirm, Pl) => {
+            "Czy wszystko się zgadza? Odpowiedz \"tak\", aby wysłać zapytanie, lub napisz, co zmienić."
+        }
+        (Text::RemindConfirm, Es) => {
+            "¿Está todo correcto? Responde \"sí\" para enviar tu consulta, o dime qué quieres cambiar."
+        }
+
+        (Text::WhatToChange, En) => "No problem. What would you like to change?",
+        (Text::WhatToChange, Pl) => "Nie ma sprawy. Co chcesz zmienić?",
+        (Text::WhatToChange, Es) => "Sin problema. ¿Qué te gustaría cambiar?",
+
+        (Text::AskNewValue, En) => "Sure, what should the new {field} be?",
+        (Text::AskNewValue, Pl) => "Jasne, jakie ma być nowe pole \"{field}\"?",
+        (Text::AskNewValue, Es) => "Claro, ¿cuál debería ser el nuevo valor de \"{field}\"?",
+
+        (Text::InquiryComplete, En) => {
+            "Thank you, {name}! We have {if budget}everything{else}what{end} we need and will send a proposal to {email} shortly.{if not budget} We can work out the budget together.{end}"
+        }
+        (Text::InquiryComplete, Pl) => {
+            "Dziękuję, {name}! Mamy {if budget}wszystko, czego{else}to, czego{end} potrzebujemy, i wkrótce wyślemy ofertę na {email}.{if not budget} Budżet możemy ustalić wspólnie.{end}"
+        }
+        (Text::InquiryComplete, Es) => {
+            "¡Gracias, {name}! Tenemos {if budget}todo lo{else}lo{end} necesario y pronto enviaremos una propuesta a {email}.{if not budget} El presupuesto lo podemos definir juntos.{end}"
+        }
+
+        (Text::RemindName, En) => "Now, back to your inquiry: what's your name?",
+        (Text::RemindName, Pl) => "Wracając do zapytania: jak masz na imię?",
+        (Text::RemindName, Es) => "Volviendo a tu consulta: ¿cómo te llamas?",
+
+        (Text::RemindEmail, En) => "…and I still need your email address, {name}.",
+        (Text::RemindEmail, Pl) => "…i nadal potrzebuję Twojego adresu e-mail, {name}.",
+        (Text::RemindEmail, Es) => "…y todavía necesito tu correo electrónico, {name}.",
+
+        (Text::RemindProjectDetails, En) => {
+            "…and I still need a short description of your project, {name}."
+        }
+        (Text::RemindProjectDetails, Pl) => {
+            "…i nadal potrzebuję krótkiego opisu Twojego projektu, {name}."
+        }
+        (Text::RemindProjectDetails, Es) => {
+            "…y todavía necesito una breve descripción de tu proyecto, {name}."
+        }
+
+        (Text::RemindBudget, En) => "…and I still need your approximate budget, {name}.",
+        (Text::RemindBudget, Pl) => "…i nadal potrzebuję przybliżonego budżetu, {name}.",
+        (Text::RemindBudget, Es) => "…y todavía necesito tu presupuesto aproximado, {name}.",
+
+        (Text::Reset, En) => "No problem, let's start over. How can I help?",
+        (Text::Reset, Pl) => "Nie ma sprawy, zacznijmy od nowa. W czym mogę pomóc?",
+        (Text::Reset, Es) => "Sin problema, empecemos de nuevo. ¿En qué puedo ayudarte?",
+
+        (Text::EmailTranscriptOption, En) => "Email me a transcript",
+        (Text::EmailTranscriptOption, Pl) => "Wyślij mi transkrypcję",
+        (Text::EmailTranscriptOption, Es) => "Envíame la transcripción",
+
+        (Text::AskTranscriptConsent, En) => {
+            "Shall I email a copy of this conversation to {email}? We'll use the address only for this message."
+        }
+        (Text::AskTranscriptConsent, Pl) => {
+            "Czy wysłać kopię tej rozmowy na adres {email}? Użyjemy go wyłącznie do tej wiadomości."
+        }
+        (Text::AskTranscriptConsent, Es) => {
+            "¿Envío una copia de esta conversación a {email}? Usaremos la dirección solo para este mensaje."
+        }
+
+        (Text::TranscriptEmailQueued, En) => "Done! A copy is on its way to {email}.",
+        (Text::TranscriptEmailQueued, Pl) => "Gotowe! Kopia jest już w drodze na {email}.",
+        (Text::TranscriptEmailQueued, Es) => "¡Listo! Una copia va de camino a {email}.",
+
+        (Text::TranscriptEmailDeclined, En) => "No problem, I won't send anything.",
+        (Text::TranscriptEmailDeclined, Pl) => "Nie ma sprawy, niczego nie wyślę.",
+        (Text::TranscriptEmailDeclined, Es) => "Sin problema, no enviaré nada.",
+
+        (Text::TranscriptNeedsEmail, En) => {
+            "I can email you a copy once I have your email address. Start a project and I'll ask for it."
+        }
+        (Text::TranscriptNeedsEmail, Pl) => {
+            "Mogę wysłać kopię, gdy poznam Twój adres e-mail. Rozpocznij projekt, a o niego zapytam."
+        }
+        (Text::TranscriptNeedsEmail, Es) => {
+            "Puedo enviarte una copia cuando tenga tu correo. Empieza un proyecto y te lo pediré."
+        }
+
+        (Text::NoThanks, En) => "No, thanks",
+        (Text::NoThanks, Pl) => "Nie, dziękuję",
+        (Text::NoThanks, Es) => "No, gracias",
+
+        (Text::TranscriptSubject, En) => "Your conversation with us",
+        (Text::TranscriptSubject, Pl) => "Twoja rozmowa z nami",
+        (Text::TranscriptSubject, Es) => "Tu conversación con nosotros",
+
+        (Text::TranscriptIntro, En) => "Hi {name}, here is a copy of your conversation with us.",
+        (Text::TranscriptIntro, Pl) => "Cześć {name}, oto kopia Twojej rozmowy z nami.",
+        (Text::TranscriptIntro, Es) => {
+            "Hola {name}, aquí tienes una copia de tu conversación con nosotros."
+        }
+
+        (Text::TranscriptVisitor, En) => "You",
+        (Text::TranscriptVisitor, Pl) => "Ty",
+        (Text::TranscriptVisitor, Es) => "Tú",
+
+        (Text::TranscriptBot, En) => "Assistant",
+        (Text::TranscriptBot, Pl) => "Asystent",
+        (Text::TranscriptBot, Es) => "Asistente",
+
+        (Text::TranscriptAgent, En) => "Agent",
+        (Text::TranscriptAgent, Pl) => "Konsultant",
+        (Text::TranscriptAgent, Es) => "Agente",
+
+        (Text::TranscriptReportAttached, En) => "Your inquiry report is attached.",
+        (Text::TranscriptReportAttached, Pl) => "W załączniku znajdziesz raport z zapytania.",
+        (Text::TranscriptReportAttached, Es) => "Adjuntamos el informe de tu consulta.",
+
+        (Text::Busy, En) => "Sorry, we're a bit busy right now. Please try again in a few minutes.",
+        (Text::Busy, Pl) => "Przepraszamy, mamy teraz spory ruch. Spróbuj ponownie za kilka minut.",
+        (Text::Busy, Es) => {
+            "Lo sentimos, ahora mismo estamos un poco ocupados. Inténtalo de nuevo en unos minutos."
+        }
+
+        (Text::Maintenance, En) => {
+            "We're doing some maintenance and will be back soon. Please try again in a little while; your conversation will be right where you left it."
+        }
+        (Text::Maintenance, Pl) => {
+            "Trwają prace konserwacyjne, wkrótce wracamy. Spróbuj ponownie za chwilę; rozmowa będzie czekać tam, gdzie ją przerwano."
+        }
+        (Text::Maintenance, Es) => {
+            "Estamos haciendo tareas de mantenimiento y volveremos pronto. Inténtalo de nuevo en un rato; tu conversación seguirá donde la dejaste."
+        }
+
+        (Text::Blocked, En) => {
+            "Sorry, we can't continue this conversation. If you think this is a mistake, please contact us by email."
+        }
+        (Text::Blocked, Pl) => {
+            "Przepraszamy, nie możemy kontynuować tej rozmowy. Jeśli uważasz, że to pomyłka, napisz do nas e-mail."
+        }
+        (Text::Blocked, Es) => {
+            "Lo sentimos, no podemos continuar esta conversación. Si crees que es un error, escríbenos por correo electrónico."
+        }
+
+        (Text::ConversationLimit, En) => {
+            "We've been chatting for quite a while, so I'll close this conversation here. Thanks for your time! If there's anything else, the team will gladly pick it up by email."
+        }
+        (Text::ConversationLimit, Pl) => {
+            "Rozmawiamy już dość długo, więc zamknę tu tę rozmowę. Dziękuję za poświęcony czas! Jeśli chcesz coś jeszcze omówić, zespół chętnie odpowie e-mailem."
+        }
+        (Text::ConversationLimit, Es) => {
+            "Llevamos un buen rato hablando, así que cerraré aquí esta conversación. ¡Gracias por tu tiempo! Si queda algo pendiente, el equipo lo retomará encantado por correo electrónico."
+        }
+
+        (Text::StillThere, En) => {
+            "Are you still there? This conversation will close soon due to inactivity. Just send a message to keep it going."
+        }
+        (Text::StillThere, Pl) => {
+            "Jesteś tam jeszcze? Ta rozmowa wkrótce zostanie zamknięta z powodu braku aktywności. Wyślij wiadomość, aby ją kontynuować."
+        }
+        (Text::StillThere, Es) => {
+            "¿Sigues ahí? Esta conversación se cerrará pronto por inactividad. Envía un mensaje para continuar."
+        }
+        (Text::Escalated, En) => {
+            "I've passed our conversation to the team. Someone will get back to you as soon as possible."
+        }
+        (Text::Escalated, Pl) => {
+            "Przekazałem naszą rozmowę zespołowi. Ktoś odezwie się do Ciebie najszybciej, jak to możliwe."
+        }
+        (Text::Escalated, Es) => {
+            "He pasado nuestra conversación al equipo. Alguien se pondrá en contacto contigo lo antes posible."
+        }
+        (Text::QueuePosition, En) => {
+            "You're number {position} in line for the team. Thanks for waiting!"
+        }
+        (Text::QueuePosition, Pl) => {
+            "Jesteś {position}. w kolejce do zespołu. Dziękujemy za cierpliwość!"
+        }
+        (Text::QueuePosition, Es) => {
+            "Eres el número {position} en la cola del equipo. ¡Gracias por esperar!"
+        }
+        (Text::QueuePositionAfterHours, En) => {
+            "The team is away right now. You're number {position} in line for when they're back."
+        }
+        (Text::QueuePositionAfterHours, Pl) => {
+            "Zespołu nie ma teraz w pracy. Jesteś {position}. w kolejce na jego powrót."
+        }
+        (Text::QueuePositionAfterHours, Es) => {
+            "El equipo no está disponible ahora. Eres el número {position} en la cola para cuando vuelva."
+        }
+        (Text::Thanks, En) => "You're welcome! Anything else I can help with?",
+        (Text::Thanks, Pl) => "Nie ma za co! Czy mogę pomóc w czymś jeszcze?",
+        (Text::Thanks, Es) => "¡De nada! ¿Puedo ayudarte en algo más?",
+
+        (Text::Goodbye, En) => "Thanks for stopping by. Have a great day!",
+        (Text::Goodbye, Pl) => "Dziękujemy za wizytę. Miłego dnia!",
+        (Text::Goodbye, Es) => "Gracias por tu visita. ¡Que tengas un buen día!",
+
+        (Text::Signature, En) => "— The studio assistant",
+        (Text::Signature, Pl) => "— Asystent studia",
+        (Text::Signature, Es) => "— El asistente del estudio",
+
+        (Text::Unknown, En) => {
+            "Sorry, I didn't quite get that. I can tell you about our services and pricing, or help you start a project."
+        }
+        (Text::Unknown, Pl) => {
+            "Przepraszam, nie do końca rozumiem. Mogę opowiedzieć o naszych usługach i cenach albo pomóc rozpocząć projekt."
+        }
+        (Text::Unknown, Es) => {
+            "Perdona, no lo he entendido bien. Puedo contarte sobre nuestros servicios y precios, o ayudarte a empezar un proyecto."
+        }
+
+        (Text::ReportTitle, En) => "Project inquiry",
+        (Text::ReportTitle, Pl) => "Zapytanie o projekt",
+        (Text::ReportTitle, Es) => "Solicitud de proyecto",
+
+        (Text::ReportName, En) => "Name",
+        (Text::ReportName, Pl) => "Imię",
+        (Text::ReportName, Es) => "Nombre",
+
+        (Text::ReportEmail, En) => "Email",
+        (Text::ReportEmail, Pl) => "E-mail",
+        (Text::ReportEmail, Es) => "Correo",
+
+        (Text::ReportProject, En) => "Project",
+        (Text::ReportProject, Pl) => "Projekt",
+        (Text::ReportProject, Es) => "Proyecto",
+
+        (Text::ReportBudget, En) => "Budget",
+        (Text::ReportBudget, Pl) => "Budżet",
+        (Text::ReportBudget, Es) => "Presupuesto",
+
+        (Text::ReportLanguage, En) => "Language",
+        (Text::ReportLanguage, Pl) => "Język",
+        (Text::ReportLanguage, Es) => "Idioma",
+
+        (Text::ReportStarted, En) => "Started",
+        (Text::ReportStarted, Pl) => "Rozpoczęcie",
+        (Text::ReportStarted, Es) => "Inicio",
+
+        (Text::ReportEnded, En) => "Ended",
+        (Text::ReportEnded, Pl) => "Zakończenie",
+        (Text::ReportEnded, Es) => "Fin",
+
+        (Text::ReportSession, En) => "Session",
+        (Text::ReportSession, Pl) => "Sesja",
+        (Text::ReportSession, Es) => "Sesión",
+
+        (Text::ReportLeadQuality, En) => "Lead quality",
+        (Text::ReportLeadQuality, Pl) => "Jakość kontaktu",
+        (Text::ReportLeadQuality, Es) => "Calidad del contacto",
+
+        (Text::ReportLeadScore, En) => "Score",
+        (Text::ReportLeadScore, Pl) => "Ocena",
+        (Text::ReportLeadScore, Es) => "Puntuación",
+
+        (Text::ReportReplyTime, En) => "Reply time",
+        (Text::ReportReplyTime, Pl) => "Czas odpowiedzi",
+        (Text::ReportReplyTime, Es) => "Tiempo de respuesta",
+    }
+}
+
+const MONTHS_EN: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+/// In the genitive, as used in dates.
+const MONTHS_PL: [&str; 12] = [
+    "stycznia",
+    "lutego",
+    "marca",
+    "kwietnia",
+    "maja",
+    "czerwca",
+    "lipca",
+    "sierpnia",
+    "września",
+    "października",
+    "listopada",
+    "grudnia",
+];
+const MONTHS_ES: [&str; 12] = [
+    "enero",
+    "febrero",
+    "marzo",
+    "abril",
+    "mayo",
+    "junio",
+    "julio",
+    "agosto",
+    "septiembre",
+    "octubre",
+    "noviembre",
+    "diciembre",
+];
+
+/// Year, month and day of a day count since 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, usize, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as usize;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// The calendar month of a Unix timestamp in milliseconds, as `YYYY-MM`
+/// in UTC.
+pub fn format_month(at_ms: u64) -> String {
+    let (year, month, _) = civil_from_days((at_ms / 86_400_000) as i64);
+    format!("{year}-{month:02}")
+}
+
+/// A Unix timestamp in milliseconds as a date and time in UTC, written the
+/// way `lang` writes dates.
+pub fn format_datetime(lang: Language, at_ms: u64) -> String {
+    let secs = at_ms / 1_000;
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let (hour, minute) = (secs % 86_400 / 3_600, secs % 3_600 / 60);
+    match lang {
+        Language::En => format!(
+            "{day} {} {year}, {hour:02}:{minute:02} UTC",
+            MONTHS_EN[month - 1]
+        ),
+        Language::Pl => format!(
+            "{day} {} {year}, {hour:02}:{minute:02} UTC",
+            MONTHS_PL[month - 1]
+        ),
+        Language::Es => format!(
+            "{day} de {} de {year}, {hour:02}:{minute:02} UTC",
+            MONTHS_ES[month - 1]
+        ),
+    }
+}
+
+/// The UTC date of a Unix timestamp in milliseconds, as `2026-01-31`.
+pub fn iso_date(at_ms: u64) -> String {
+    let (year, month, day) = civil_from_days((at_ms / 86_400_000) as i64);
+    format!("{year}-{month:02}-{day:02}")
+}
+
+/// A whole number with `lang`'s digit grouping: `12,500` in English,
+/// `12 500` in Polish and `12.500` in Spanish. Polish and Spanish leave
+/// four-digit numbers ungrouped.
+pub fn format_number(lang: Language, n: u64) -> String {
+    let (separator, min_grouped) = match lang {
+        Language::En => (',', 1_000),
+        Language::Pl => ('\u{a0}', 10_000),
+        Language::Es => ('.', 10_000),
+    };
+    let digits = n.to_string();
+    if n < min_grouped {
+        return digits;
+    }
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(separator);
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// An amount in whole units of `currency`, an ISO 4217 code, written the
+/// way `lang` writes prices: `$1,500` in English, `1500 zł` in Polish.
+/// Currencies with no symbol a reader of `lang` would recognise without
+/// doubt are written with their code, e.g. `1500 USD` in Spanish.
+pub fn format_money(lang: Language, amount: u64, currency: &str) -> String {
+    let number = format_number(lang, amount);
+    match (lang, currency) {
+        (Language::En, "USD") => format!("${number}"),
+        (Language::En, "EUR") => format!("€{number}"),
+        (Language::En, "GBP") => format!("£{number}"),
+        (Language::Pl, "PLN") => format!("{number}\u{a0}zł"),
+        (Language::Pl | Language::Es, "EUR") => format!("{number}\u{a0}€"),
+        _ => format!("{number}\u{a0}{currency}"),
+    }
+}
+
+/// Replaces each `{key}` in `template` with its value.
+pub fn fill(template: &str, vars: &[(&str, &str)]) -> String {
+    vars.iter().fold(template.to_string(), |acc, (key, value)| {
+        acc.replace(&format!("{{{key}}}"), value)
+    })
+}
@@ -0,0 +1,60 @@
+pub mod abandoned;
+pub mod ai;
+pub mod alerts;
+pub mod analytics;
+pub mod anonymize;
+pub mod assets;
+pub mod blocklist;
+pub mod bots;
+pub mod catalog;
+pub mod channels;
+pub mod chatbot;
+pub mod config;
+pub mod correction;
+pub mod demo;
+pub mod durable;
+pub mod error;
+pub mod eval;
+pub mod events;
+pub mod flow;
+pub mod graphql;
+pub mod grpc;
+pub mod handoff;
+pub mod healthcheck;
+pub mod http;
+pub mod i18n;
+pub mod listener;
+pub mod livechat;
+pub mod logging;
+pub mod mail;
+pub mod maintenance;
+pub mod metrics;
+pub mod outbox;
+pub mod pipeline;
+pub mod plugins;
+pub mod presence;
+pub mod prompts;
+pub mod push;
+pub mod render;
+pub mod report;
+pub mod review;
+pub mod routes;
+pub mod saved;
+pub mod selfcheck;
+pub mod service;
+pub mod session;
+pub mod snapshot;
+pub mod state;
+pub mod survey;
+pub mod synonyms;
+pub mod template;
+pub mod tenant;
+#[cfg(feature = "test-support")]
+pub mod test_support;
+pub mod text;
+pub mod trace;
+pub mod training;
+pub mod validation;
+pub mod warehouse;
+pub mod weekly;
+pub mod yesno;
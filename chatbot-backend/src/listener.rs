@@ -0,0 +1,75 @@
+//! Getting the listening socket in a way that survives restarts.
+//!
+//! Under systemd socket activation (`LISTEN_FDS`) the socket belongs to
+//! systemd and is handed to each new process, so connections queue in the
+//! kernel while the service restarts instead of being refused. Otherwise,
+//! with `REUSE_PORT=1`, the socket is bound with `SO_REUSEPORT` so a new
+//! instance can start serving on the same port before the old one stops.
+
+use std::{
+    io,
+    net::{SocketAddr, TcpListener as StdListener},
+};
+
+use socket2::{Domain, Socket, Type};
+use tokio::net::TcpListener;
+
+/// First file descriptor systemd passes, see `sd_listen_fds(3)`.
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: i32 = 3;
+const BACKLOG: i32 = 1024;
+
+/// The socket systemd passed to this process, if any.
+#[cfg(unix)]
+fn inherited() -> Option<StdListener> {
+    use std::os::fd::FromRawFd;
+
+    let pid: u32 = std::env::var("LISTEN_PID").ok()?.parse().ok()?;
+    let fds: i32 = std::env::var("LISTEN_FDS").ok()?.parse().ok()?;
+    if pid != std::process::id() || fds < 1 {
+        return None;
+    }
+    // SAFETY: systemd guarantees the descriptor is open and belongs to this
+    // process; `bind` is called once, so it is not wrapped twice.
+    Some(unsafe { StdListener::from_raw_fd(SD_LISTEN_FDS_START) })
+}
+
+#[cfg(not(unix))]
+fn inherited() -> Option<StdListener> {
+    None
+}
+
+fn bind_std(addr: SocketAddr, reuse_port: bool) -> io::Result<StdListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    if reuse_port {
+        socket.set_reuse_port(true)?;
+    }
+    #[cfg(not(unix))]
+    if reuse_port {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "SO_REUSEPORT is not available on this platform",
+        ));
+    }
+    socket.bind(&addr.into())?;
+    socket.listen(BACKLOG)?;
+    Ok(socket.into())
+}
+
+/// Returns the socket-activated listener when there is one, else binds
+/// `addr`. The flag says whether the socket was inherited.
+pub fn bind(addr: &str, reuse_port: bool) -> io::Result<(TcpListener, bool)> {
+    let (listener, inherited) = match inherited() {
+        Some(listener) => (listener, true),
+        None => {
+            let addr: SocketAddr = addr
+                .parse()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            (bind_std(addr, reuse_port)?, false)
+        }
+    };
+    listener.set_nonblocking(true)?;
+    Ok((TcpListener::from_std(listener)?, inherited))
+}
@@ -0,0 +1,220 @@
+//! Handing conversations to an external live-chat tool.
+//!
+//! When a visitor asks for a person and `LIVE_CHAT` names a provider, the
+//! conversation is opened there with the transcript so far: in Intercom as
+//! a conversation started by the visitor, in Zendesk as a ticket. The
+//! session is then handed off, so the bot stays quiet while the team
+//! answers from their own tool. Without a provider the request is only
+//! recorded in the event log, for operators watching sessions.
+
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::{
+    events::ConversationEvent, mail::transcript_text, session::Session, state::SharedState,
+};
+
+pub const INTERCOM_API_URL: &str = "https://api.intercom.io";
+const INTERCOM_VERSION: &str = "2.11";
+
+#[derive(Debug, Clone)]
+pub enum Provider {
+    Intercom {
+        /// [`INTERCOM_API_URL`] unless testing.
+        api_url: String,
+        access_token: String,
+    },
+    Zendesk {
+        /// The account's URL, e.g. `https://acme.zendesk.com`.
+        url: String,
+        /// The agent the API token belongs to.
+        email: String,
+        api_token: String,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+struct IntercomContact {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IntercomSearch {
+    data: Vec<IntercomContact>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IntercomMessage {
+    conversation_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ZendeskTicket {
+    ticket: ZendeskTicketId,
+}
+
+#[derive(Debug, Deserialize)]
+struct ZendeskTicketId {
+    id: u64,
+}
+
+pub struct LiveChat {
+    http: Client,
+    provider: Provider,
+}
+
+impl LiveChat {
+    /// Calls the provider through `http`, the shared client.
+    pub fn new(provider: Provider, http: Client) -> Self {
+        Self { http, provider }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self.provider {
+            Provider::Intercom { .. } => "intercom",
+            Provider::Zendesk { .. } => "zendesk",
+        }
+    }
+
+    /// Opens the conversation in the provider's tool and returns its id
+    /// there.
+    async fn open(&self, session: &Session) -> Result<String, reqwest::Error> {
+        let mut body = transcript_text(session);
+        body.push_str(&format!("Session: {}", session.id));
+        match &self.provider {
+            Provider::Intercom {
+                api_url,
+                access_token,
+            } => {
+                let api_url = api_url.trim_end_matches('/');
+                let contact = self
+                    .intercom_contact(api_url, access_token, session)
+                    .await?;
+                let message: IntercomMessage = self
+                    .http
+                    .post(format!("{api_url}/conversations"))
+                    .bearer_auth(access_token)
+                    .header("Intercom-Version", INTERCOM_VERSION)
+                    .json(&json!({
+                        "from": { "type": "contact", "id": contact },
+                        "body": body,
+                    }))
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                Ok(message.conversation_id)
+            }
+            Provider::Zendesk {
+                url,
+                email,
+                api_token,
+            } => {
+                let name = session.data.name.as_deref();
+                let subject = match name {
+                    Some(name) => format!("Website chat with {name}"),
+                    None => "Website chat".to_string(),
+                };
+                let mut ticket = json!({
+                    "subject": subject,
+                    "comment": { "body": body },
+                    "external_id": session.id,
+                    "tags": ["chatbot"],
+                });
+                // Without an address the ticket is requested by the API user.
+                if let Some(visitor) = &session.data.email {
+                    ticket["requester"] =
+                        json!({ "name": name.unwrap_or(visitor), "email": visitor });
+                }
+                let ticket: ZendeskTicket = self
+                    .http
+                    .post(format!("{}/api/v2/tickets.json", url.trim_end_matches('/')))
+                    .basic_auth(format!("{email}/token"), Some(api_token))
+                    .json(&json!({ "ticket": ticket }))
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                Ok(ticket.ticket.id.to_string())
+            }
+        }
+    }
+
+    /// The visitor's Intercom contact: the one with their email address if
+    /// there is one, a new lead otherwise.
+    async fn intercom_contact(
+        &self,
+        api_url: &str,
+        access_token: &str,
+        session: &Session,
+    ) -> Result<String, reqwest::Error> {
+        if let Some(email) = &session.data.email {
+            let found: IntercomSearch = self
+                .http
+                .post(format!("{api_url}/contacts/search"))
+                .bearer_auth(access_token)
+                .header("Intercom-Version", INTERCOM_VERSION)
+                .json(&json!({
+                    "query": { "field": "email", "operator": "=", "value": email }
+                }))
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            if let Some(contact) = found.data.into_iter().next() {
+                return Ok(contact.id);
+            }
+        }
+        let contact: IntercomContact = self
+            .http
+            .post(format!("{api_url}/contacts"))
+            .bearer_auth(access_token)
+            .header("Intercom-Version", INTERCOM_VERSION)
+            .json(&json!({
+                "role": "lead",
+                "name": session.data.name,
+                "email": session.data.email,
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(contact.id)
+    }
+}
+
+/// Opens `session` in the configured live-chat tool and hands it off. On
+/// failure the bot keeps the conversation.
+pub async fn transfer(state: &SharedState, session: Session) {
+    let Some(live_chat) = &state.live_chat else {
+        return;
+    };
+    let provider = live_chat.name();
+    let external_id = match live_chat.open(&session).await {
+        Ok(id) => id,
+        Err(e) => {
+            warn!(session_id = %session.id, "cannot open {provider} conversation: {e}");
+            return;
+        }
+    };
+    info!(session_id = %session.id, %external_id, "conversation transferred to {provider}");
+    state
+        .sessions
+        .with_session(&session.id, |live| {
+            live.handed_off = true;
+            state.events.append(
+                &session.id,
+                vec![ConversationEvent::Transferred {
+                    provider: provider.to_string(),
+                    external_id,
+                }],
+            );
+        })
+        .await;
+}
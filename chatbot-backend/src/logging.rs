@@ -0,0 +1,242 @@
+//! Where logs go: stdout always, and a rotating file when `LOG_FILE` is
+//! set, for hosts without a log collector.
+//!
+//! Files rotate by time (`LOG_ROTATION=hourly|daily|never`, daily by
+//! default; the date is appended to the name) or, when `LOG_MAX_BYTES` is
+//! set, by size (`chatbot.log`, `chatbot.log.1`, ...). `LOG_MAX_FILES`
+//! old files are kept. Both outputs use `LOG_FORMAT`.
+//!
+//! These are read from the environment once, before anything else, so
+//! that what happens next is logged; unlike [`crate::config`] they can't
+//! change while the server runs.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+};
+
+use tracing_appender::{
+    non_blocking::WorkerGuard,
+    rolling::{RollingFileAppender, Rotation},
+};
+use tracing_subscriber::{EnvFilter, Layer, Registry, fmt, layer::SubscriberExt};
+
+/// Old files kept unless `LOG_MAX_FILES` says otherwise.
+pub const DEFAULT_MAX_FILES: usize = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    /// One object per line: `timestamp`, `level`, `target`, `message` and
+    /// the event's fields at the top level, the enclosing request's
+    /// `request_id`, `method` and `path` under `span`.
+    Json,
+}
+
+impl LogFormat {
+    /// `LOG_FORMAT`: `text`, the default, or `json`.
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var("LOG_FORMAT").as_deref() {
+            Err(_) | Ok("" | "text") => Ok(LogFormat::Text),
+            Ok("json") => Ok(LogFormat::Json),
+            Ok(other) => Err(format!("LOG_FORMAT must be text or json, got {other:?}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileRotation {
+    Hourly,
+    Daily,
+    Never,
+    /// Once the file would grow past this many bytes.
+    Size(u64),
+}
+
+#[derive(Debug, Clone)]
+pub struct LogFile {
+    pub path: PathBuf,
+    pub rotation: FileRotation,
+    pub max_files: usize,
+}
+
+impl FileRotation {
+    /// By size from `LOG_MAX_BYTES`, otherwise by time from
+    /// `LOG_ROTATION`.
+    fn from_env() -> Result<Self, String> {
+        if let Ok(raw) = std::env::var("LOG_MAX_BYTES") {
+            return match raw.parse() {
+                Ok(bytes) if bytes > 0 => Ok(FileRotation::Size(bytes)),
+                _ => Err(format!(
+                    "LOG_MAX_BYTES must be a positive number, got {raw:?}"
+                )),
+            };
+        }
+        match std::env::var("LOG_ROTATION").as_deref() {
+            Err(_) | Ok("daily") => Ok(FileRotation::Daily),
+            Ok("hourly") => Ok(FileRotation::Hourly),
+            Ok("never") => Ok(FileRotation::Never),
+            Ok(other) => Err(format!(
+                "LOG_ROTATION must be hourly, daily or never, got {other:?}"
+            )),
+        }
+    }
+}
+
+impl LogFile {
+    /// `LOG_FILE` with its rotation settings; `None` when it isn't set.
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Some(path) = std::env::var("LOG_FILE").ok().filter(|p| !p.is_empty()) else {
+            return Ok(None);
+        };
+        let max_files = match std::env::var("LOG_MAX_FILES") {
+            Err(_) => DEFAULT_MAX_FILES,
+            Ok(raw) => raw
+                .parse()
+                .map_err(|_| format!("LOG_MAX_FILES must be a number, got {raw:?}"))?,
+        };
+        Ok(Some(Self {
+            path: path.into(),
+            rotation: FileRotation::from_env()?,
+            max_files,
+        }))
+    }
+}
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+fn layer<W>(format: LogFormat, writer: W, ansi: bool) -> BoxedLayer
+where
+    W: for<'w> fmt::MakeWriter<'w> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Text => fmt::layer().with_writer(writer).with_ansi(ansi).boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .with_writer(writer)
+            .boxed(),
+    }
+}
+
+/// Installs the global subscriber. Keep the returned guard alive: lines
+/// still buffered for the file are flushed when it drops.
+pub fn init(
+    filter: EnvFilter,
+    format: LogFormat,
+    file: Option<&LogFile>,
+) -> io::Result<Option<WorkerGuard>> {
+    // Span fields are formatted once and shared by both layers, so colours
+    // on stdout would end up in the file too.
+    let ansi = file.is_none() && io::stdout().is_terminal();
+    let mut layers = vec![layer(format, io::stdout, ansi)];
+    let mut guard = None;
+    if let Some(file) = file {
+        let (writer, file_guard) = tracing_appender::non_blocking(open(file)?);
+        layers.push(layer(format, writer, false));
+        guard = Some(file_guard);
+    }
+    let subscriber = Registry::default().with(layers).with(filter);
+    tracing::subscriber::set_global_default(subscriber).map_err(io::Error::other)?;
+    Ok(guard)
+}
+
+fn open(file: &LogFile) -> io::Result<Box<dyn Write + Send>> {
+    let directory = file
+        .path
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    fs::create_dir_all(directory)?;
+    let name = file
+        .path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "LOG_FILE needs a file name"))?;
+    let rotation = match file.rotation {
+        FileRotation::Hourly => Rotation::HOURLY,
+        FileRotation::Daily => Rotation::DAILY,
+        FileRotation::Never => Rotation::NEVER,
+        FileRotation::Size(max_bytes) => {
+            return Ok(Box::new(SizeRotatingFile::open(
+                file.path.clone(),
+                max_bytes,
+                file.max_files,
+            )?));
+        }
+    };
+    let appender = RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(name)
+        .max_log_files(file.max_files.max(1))
+        .build(directory)
+        .map_err(io::Error::other)?;
+    Ok(Box::new(appender))
+}
+
+/// A log file that is renamed to `<name>.1` when it is full, shifting
+/// older ones up and dropping those beyond `max_files`.
+struct SizeRotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: File,
+    written: u64,
+}
+
+impl SizeRotatingFile {
+    fn open(path: PathBuf, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_bytes,
+            max_files,
+            file,
+            written,
+        })
+    }
+
+    fn numbered(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{n}"));
+        name.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.max_files == 0 {
+            self.file = File::create(&self.path)?;
+        } else {
+            let _ = fs::remove_file(self.numbered(self.max_files));
+            for n in (1..self.max_files).rev() {
+                let _ = fs::rename(self.numbered(n), self.numbered(n + 1));
+            }
+            fs::rename(&self.path, self.numbered(1))?;
+            self.file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+        }
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for SizeRotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written > 0 && self.written + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
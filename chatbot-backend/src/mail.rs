@@ -0,0 +1,117 @@
+//! Emails sent to visitors.
+//!
+//! Messages go through the outbox to an HTTP mail relay (`MAIL_RELAY_URL`),
+//! which receives the usual webhook envelope with an [`Email`] as `data`.
+//! Delivery therefore gets the outbox's retries and dead-letter handling.
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::Serialize;
+
+use crate::{
+    i18n::{Text, fill, format_datetime, text},
+    session::{Role, Session},
+};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Attachment {
+    pub filename: String,
+    pub content_type: String,
+    pub content_base64: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+    /// Where the visitor's answer should go; see [`reply_address`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<String>,
+    /// Threading headers when answering an email.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub in_reply_to: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub references: Vec<String>,
+}
+
+/// The session's own address on the inbound mailbox: `bot@example.org`
+/// becomes `bot+<session id>@example.org`, so an answer to the email finds
+/// its way back to the conversation.
+pub fn reply_address(inbound: &str, session_id: &str) -> String {
+    match inbound.split_once('@') {
+        Some((local, domain)) => format!("{local}+{session_id}@{domain}"),
+        None => inbound.to_string(),
+    }
+}
+
+/// The session id in an address made by [`reply_address`], bare or as
+/// `Name <address>`.
+pub fn session_from_address<'a>(inbound: &str, address: &'a str) -> Option<&'a str> {
+    let (local, domain) = inbound.split_once('@')?;
+    let address = match address.rsplit_once('<') {
+        Some((_, bracketed)) => bracketed.trim_end().trim_end_matches('>'),
+        None => address.trim(),
+    };
+    let (to_local, to_domain) = address.split_once('@')?;
+    let id = to_local.strip_prefix(local)?.strip_prefix('+')?;
+    (to_domain.eq_ignore_ascii_case(domain) && !id.is_empty()).then_some(id)
+}
+
+/// Every message of the session as `Speaker (when): text` paragraphs,
+/// speakers named and times written in the visitor's language.
+pub fn transcript_text(session: &Session) -> String {
+    let lang = session.language;
+    let mut body = String::new();
+    for message in &session.messages {
+        let speaker = match message.role {
+            Role::User => text(lang, Text::TranscriptVisitor),
+            Role::Bot => text(lang, Text::TranscriptBot),
+            Role::Agent => text(lang, Text::TranscriptAgent),
+        };
+        let sent = format_datetime(lang, message.at_ms());
+        body.push_str(&format!("{speaker} ({sent}): {}\n\n", message.content));
+    }
+    body
+}
+
+/// The conversation so far in the visitor's language, with the inquiry
+/// report attached when there is one. `None` without a captured address.
+/// With an inbound mailbox, answering the email continues the chat.
+pub fn transcript_email(
+    session: &Session,
+    report: Option<Vec<u8>>,
+    inbound_address: Option<&str>,
+) -> Option<Email> {
+    let lang = session.language;
+    let to = session.data.email.clone()?;
+    let name = session.data.name.as_deref().unwrap_or_default();
+
+    let mut body = fill(text(lang, Text::TranscriptIntro), &[("name", name)]);
+    body.push_str("\n\n");
+    body.push_str(&transcript_text(session));
+
+    let attachments: Vec<Attachment> = report
+        .map(|pdf| Attachment {
+            filename: "inquiry.pdf".to_string(),
+            content_type: "application/pdf".to_string(),
+            content_base64: STANDARD.encode(pdf),
+        })
+        .into_iter()
+        .collect();
+    if !attachments.is_empty() {
+        body.push_str(text(lang, Text::TranscriptReportAttached));
+        body.push('\n');
+    }
+
+    Some(Email {
+        to,
+        subject: text(lang, Text::TranscriptSubject).to_string(),
+        text: body,
+        attachments,
+        reply_to: inbound_address.map(|inbound| reply_address(inbound, &session.id)),
+        in_reply_to: None,
+        references: Vec::new(),
+    })
+}
@@ -1,3 +1,850 @@
-fn main() {
-    println!("Hello, world!");
+use std::{
+    collections::HashMap, net::SocketAddr, path::PathBuf, str::FromStr, sync::Arc, time::Duration,
+};
+
+use chatbot_backend::{
+    abandoned,
+    ai::{self, AiFallback, ChatCompletions, LlamaServer},
+    alerts::{self, AlertRules, AlertTargets, Alerter},
+    anonymize::{Anonymization, Anonymizer},
+    assets::AssetSource,
+    blocklist::{BlockResponse, Blocklist},
+    bots::{self, Bots},
+    catalog::ServicesCatalog,
+    channels::{
+        email::EmailChannel,
+        matrix::{self, MatrixConfig},
+        messenger::{Messenger, MessengerConfig},
+        meta,
+        partner::PartnerApi,
+        whatsapp::{WhatsApp, WhatsAppConfig},
+    },
+    chatbot, config,
+    demo::DemoScript,
+    events::{self, EventStore},
+    grpc,
+    handoff::{self, OperatorHours},
+    healthcheck, http, listener,
+    livechat::{self, LiveChat, Provider},
+    logging::{self, LogFile, LogFormat},
+    outbox::{self, Outbox},
+    presence::Presence,
+    prompts::PromptStore,
+    push,
+    review::ReviewQueue,
+    routes,
+    selfcheck::SelfCheck,
+    session::{DEFAULT_SESSION_TTL, SessionLimits},
+    snapshot,
+    state::AppState,
+    synonyms::{self, Synonyms},
+    training::{self, TrainingData},
+    warehouse::{self, ExportSettings, ExportSink, ExportTarget},
+    weekly,
+};
+use tokio::{signal, sync::Notify};
+use tracing::{error, info, warn};
+use tracing_subscriber::EnvFilter;
+
+const DELIVERY_INTERVAL: Duration = Duration::from_secs(5);
+const DELIVERY_BATCH: usize = 20;
+/// How long before expiry an idle visitor is asked whether they are still
+/// there, unless `INACTIVITY_WARNING_SECS` says otherwise.
+const DEFAULT_INACTIVITY_WARNING: Duration = Duration::from_secs(5 * 60);
+const WARNING_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+/// How often visitors waiting for an operator hear their place in line,
+/// when it has changed.
+const QUEUE_UPDATE_INTERVAL: Duration = Duration::from_secs(30);
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(5);
+const MEMORY_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+const SNAPSHOT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How long open connections (event streams, operator sockets) may keep
+/// the server from stopping.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Parses an optional numeric setting, exiting on garbage.
+fn env_number<T: FromStr>(name: &str) -> Option<T> {
+    let raw = std::env::var(name).ok()?;
+    match raw.parse() {
+        Ok(value) => Some(value),
+        Err(_) => {
+            error!("{name} must be a number, got {raw:?}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// A duration in seconds from `name`, exiting unless it is above zero.
+fn env_secs(name: &str) -> Option<Duration> {
+    let secs: u64 = env_number(name)?;
+    if secs == 0 {
+        error!("{name} must be above zero");
+        std::process::exit(1);
+    }
+    Some(Duration::from_secs(secs))
+}
+
+/// An anonymization mode from `name`, `default` unless set, exiting on
+/// garbage.
+fn env_anonymization(name: &str, default: Anonymization) -> Anonymization {
+    let Ok(raw) = std::env::var(name) else {
+        return default;
+    };
+    raw.parse().unwrap_or_else(|e| {
+        error!("{name}: {e}");
+        std::process::exit(1);
+    })
+}
+
+/// Hashes salt from `EXPORT_SALT`, or a random one that lasts until
+/// restart.
+fn export_salt() -> String {
+    std::env::var("EXPORT_SALT")
+        .ok()
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| {
+            warn!("EXPORT_SALT is not set; hashed ids will change on restart");
+            uuid::Uuid::new_v4().to_string()
+        })
+}
+
+/// Parses `METRICS_LABELS`, e.g. `region=eu,env=prod`, exiting on garbage.
+fn metric_labels() -> Vec<(String, String)> {
+    let Ok(raw) = std::env::var("METRICS_LABELS") else {
+        return Vec::new();
+    };
+    raw.split(',')
+        .filter(|pair| !pair.trim().is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let name = name.trim();
+            let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                && !matches!(
+                    name,
+                    "tenant"
+                        | "reason"
+                        | "intent"
+                        | "sub_intent"
+                        | "language"
+                        | "method"
+                        | "route"
+                        | "status"
+                        | "le"
+                        | "score"
+                );
+            if !valid {
+                error!("METRICS_LABELS: {name:?} is not a usable label name");
+                std::process::exit(1);
+            }
+            (name.to_string(), value.trim().to_string())
+        })
+        .collect()
+}
+
+/// Runs `chatbot-backend healthcheck`: exits 0 when the local instance
+/// is ready, 1 when it is not or cannot be reached.
+async fn healthcheck() -> ! {
+    let url = match std::env::var("HEALTHCHECK_URL") {
+        Ok(url) => Ok(url),
+        Err(_) => healthcheck::ready_url(
+            &std::env::var("BIND_ADDR").unwrap_or_else(|_| "0.0.0.0:3000".into()),
+        ),
+    };
+    let result = match url {
+        Ok(url) => healthcheck::probe(&url)
+            .await
+            .map_err(|e| format!("{url}: {e}")),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => std::process::exit(0),
+        Err(e) => {
+            eprintln!("unhealthy: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Resolves on Ctrl-C or SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    match std::env::args().nth(1).as_deref() {
+        None => {}
+        Some("healthcheck") => healthcheck().await,
+        Some(other) => {
+            eprintln!("unknown command {other:?}; the only one is healthcheck");
+            std::process::exit(2);
+        }
+    }
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into());
+    // Logging isn't set up yet, so errors go to stderr.
+    let (log_format, log_file) = LogFormat::from_env()
+        .and_then(|format| Ok((format, LogFile::from_env()?)))
+        .unwrap_or_else(|e| {
+            eprintln!("{e}");
+            std::process::exit(1);
+        });
+    let _log_guard = logging::init(filter, log_format, log_file.as_ref()).unwrap_or_else(|e| {
+        eprintln!("cannot set up logging: {e}");
+        std::process::exit(1);
+    });
+
+    let catalog = match std::env::var("SERVICES_CATALOG") {
+        Ok(path) => ServicesCatalog::from_file(&path).unwrap_or_else(|e| {
+            error!("{path}: {e}");
+            std::process::exit(1);
+        }),
+        Err(_) => ServicesCatalog::default(),
+    };
+
+    chatbot::prepare();
+    catalog.prepare();
+    let reports_dir = std::env::var("REPORTS_DIR").unwrap_or_else(|_| "reports".into());
+    let session_ttl = env_secs("SESSION_TTL_SECS").unwrap_or(DEFAULT_SESSION_TTL);
+    let mut state = AppState::new(session_ttl, catalog, reports_dir.into());
+    let proxy = std::env::var("OUTBOUND_PROXY")
+        .ok()
+        .filter(|p| !p.is_empty());
+    state.http = http::client(proxy.as_deref()).unwrap_or_else(|e| {
+        error!("OUTBOUND_PROXY: {e}");
+        std::process::exit(1);
+    });
+    state.metrics.set_labels(&metric_labels());
+    state.sessions.set_limits(SessionLimits {
+        max_sessions: env_number("MAX_SESSIONS"),
+        max_sessions_per_ip: env_number("MAX_SESSIONS_PER_IP"),
+        max_conversation_turns: env_number("MAX_CONVERSATION_TURNS").filter(|&n| n > 0),
+        max_conversation_secs: env_secs("MAX_CONVERSATION_SECS").map(|d| d.as_secs()),
+    });
+    if let Some(interval) = env_secs("SESSION_PURGE_INTERVAL_SECS") {
+        state.sessions.set_purge_interval(interval);
+    }
+    let memory_warning = env_number::<usize>("SESSION_MEMORY_WARNING_MB").map(|mb| mb << 20);
+    state.sessions.set_memory_warning(memory_warning);
+    if let Some(secs) = env_number("SESSION_RESTORE_GRACE_SECS") {
+        state.sessions.set_restore_grace(Duration::from_secs(secs));
+    }
+    // The number of proxies in front of the server; "1" for a single one.
+    state.trusted_proxies = env_number("TRUST_FORWARDED_FOR").unwrap_or(0);
+    // Shared by the event log and the exports, so their hashes match.
+    let mut hash_salt: Option<String> = None;
+    if let Ok(path) = std::env::var("EVENT_LOG") {
+        state.events = EventStore::with_log_file(&path).unwrap_or_else(|e| {
+            error!("{path}: {e}");
+            std::process::exit(1);
+        });
+        let mode = env_anonymization("EVENT_LOG_ANONYMIZATION", Anonymization::None);
+        if mode != Anonymization::None {
+            state.events.set_log_anonymizer(Anonymizer {
+                mode,
+                salt: hash_salt.get_or_insert_with(export_salt).clone(),
+            });
+        }
+    }
+    state.admin_token = std::env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty());
+    if state.admin_token.is_none() {
+        warn!("ADMIN_TOKEN is not set; the admin API is disabled");
+    }
+    if let Ok(path) = std::env::var("OUTBOX_PATH") {
+        state.outbox = Outbox::open(&path).unwrap_or_else(|e| {
+            error!("{path}: {e}");
+            std::process::exit(1);
+        });
+        if !state.outbox.is_empty() {
+            info!(jobs = state.outbox.len(), "resuming queued webhooks");
+        }
+    }
+    state
+        .config
+        .set_default_webhook_url(std::env::var("WEBHOOK_URL").ok().filter(|u| !u.is_empty()));
+    state.mail_relay_url = std::env::var("MAIL_RELAY_URL")
+        .ok()
+        .filter(|u| !u.is_empty());
+    state.marketing_webhook_url = std::env::var("MARKETING_WEBHOOK_URL")
+        .ok()
+        .filter(|u| !u.is_empty());
+    if let Ok(raw) = std::env::var("OPERATOR_HOURS") {
+        state.operator_hours = Some(OperatorHours::parse(&raw).unwrap_or_else(|e| {
+            error!("OPERATOR_HOURS: {e}");
+            std::process::exit(1);
+        }));
+    }
+    if let Ok(path) = std::env::var("DEMO_SCRIPT") {
+        state.demo_script = Some(DemoScript::load(&PathBuf::from(&path)).unwrap_or_else(|e| {
+            error!("DEMO_SCRIPT {path}: {e}");
+            std::process::exit(1);
+        }));
+    }
+    if let Some(timeout) = env_secs("OPERATOR_PRESENCE_TIMEOUT_SECS") {
+        state.presence = Presence::new(timeout);
+    }
+    let review_dataset = std::env::var("REVIEW_DATASET").ok().map(PathBuf::from);
+    if let Some(percent) = env_number::<f64>("REVIEW_SAMPLE_PERCENT") {
+        if !(0.0..=100.0).contains(&percent) {
+            error!("REVIEW_SAMPLE_PERCENT must be from 0 to 100");
+            std::process::exit(1);
+        }
+        state.review = ReviewQueue::new(percent, review_dataset.clone());
+    }
+    if let Ok(access_token) = std::env::var("WHATSAPP_ACCESS_TOKEN") {
+        let Ok(verify_token) = std::env::var("WHATSAPP_VERIFY_TOKEN") else {
+            error!("WHATSAPP_ACCESS_TOKEN needs WHATSAPP_VERIFY_TOKEN");
+            std::process::exit(1);
+        };
+        let app_secret = std::env::var("WHATSAPP_APP_SECRET").ok();
+        if app_secret.is_none() {
+            warn!("WHATSAPP_APP_SECRET is not set; WhatsApp webhooks are not authenticated");
+        }
+        state.whatsapp = Some(WhatsApp::new(
+            WhatsAppConfig {
+                api_url: std::env::var("WHATSAPP_API_URL")
+                    .unwrap_or_else(|_| meta::GRAPH_API_URL.into()),
+                access_token,
+                verify_token,
+                app_secret,
+            },
+            state.http.clone(),
+        ));
+    }
+    if let Ok(page_token) = std::env::var("MESSENGER_PAGE_TOKEN") {
+        let Ok(verify_token) = std::env::var("MESSENGER_VERIFY_TOKEN") else {
+            error!("MESSENGER_PAGE_TOKEN needs MESSENGER_VERIFY_TOKEN");
+            std::process::exit(1);
+        };
+        let app_secret = std::env::var("MESSENGER_APP_SECRET").ok();
+        if app_secret.is_none() {
+            warn!("MESSENGER_APP_SECRET is not set; Messenger webhooks are not authenticated");
+        }
+        state.messenger = Some(Messenger::new(
+            MessengerConfig {
+                api_url: std::env::var("MESSENGER_API_URL")
+                    .unwrap_or_else(|_| meta::GRAPH_API_URL.into()),
+                page_token,
+                verify_token,
+                app_secret,
+            },
+            state.http.clone(),
+        ));
+    }
+    if let Ok(address) = std::env::var("INBOUND_EMAIL_ADDRESS") {
+        let Some(token) = std::env::var("INBOUND_EMAIL_TOKEN")
+            .ok()
+            .filter(|t| !t.is_empty())
+        else {
+            error!("INBOUND_EMAIL_ADDRESS needs INBOUND_EMAIL_TOKEN");
+            std::process::exit(1);
+        };
+        if state.mail_relay_url.is_none() {
+            warn!("MAIL_RELAY_URL is not set; emails to {address} won't be answered");
+        }
+        state.email = Some(EmailChannel::new(address, token));
+    }
+    if let Ok(token) = std::env::var("PARTNER_API_TOKEN")
+        && !token.is_empty()
+    {
+        let webhook_url = std::env::var("PARTNER_WEBHOOK_URL").ok();
+        state.partner = Some(PartnerApi::new(token, webhook_url));
+    }
+    match std::env::var("LIVE_CHAT").as_deref() {
+        Err(_) => {}
+        Ok("intercom") => {
+            let Ok(access_token) = std::env::var("INTERCOM_ACCESS_TOKEN") else {
+                error!("LIVE_CHAT=intercom needs INTERCOM_ACCESS_TOKEN");
+                std::process::exit(1);
+            };
+            state.live_chat = Some(LiveChat::new(
+                Provider::Intercom {
+                    api_url: std::env::var("INTERCOM_API_URL")
+                        .unwrap_or_else(|_| livechat::INTERCOM_API_URL.to_string()),
+                    access_token,
+                },
+                state.http.clone(),
+            ));
+        }
+        Ok("zendesk") => {
+            let (Ok(url), Ok(email), Ok(api_token)) = (
+                std::env::var("ZENDESK_URL"),
+                std::env::var("ZENDESK_EMAIL"),
+                std::env::var("ZENDESK_API_TOKEN"),
+            ) else {
+                error!("LIVE_CHAT=zendesk needs ZENDESK_URL, ZENDESK_EMAIL and ZENDESK_API_TOKEN");
+                std::process::exit(1);
+            };
+            state.live_chat = Some(LiveChat::new(
+                Provider::Zendesk {
+                    url,
+                    email,
+                    api_token,
+                },
+                state.http.clone(),
+            ));
+        }
+        Ok(other) => {
+            error!("LIVE_CHAT must be intercom or zendesk, got {other:?}");
+            std::process::exit(1);
+        }
+    }
+    match std::env::var("STATIC_ASSETS").as_deref() {
+        Err(_) | Ok("" | "directory") => {}
+        Ok("embedded") => state.assets = AssetSource::Embedded,
+        Ok(other) => {
+            error!("STATIC_ASSETS must be directory or embedded, got {other:?}");
+            std::process::exit(1);
+        }
+    }
+    let blocklist_path = std::env::var("BLOCKLIST_PATH").ok().map(PathBuf::from);
+    if let Some(path) = &blocklist_path {
+        state.blocklist = match Blocklist::load(path.clone()) {
+            Ok(blocklist) => blocklist,
+            Err(e) => {
+                error!("{}: {e}", path.display());
+                std::process::exit(1);
+            }
+        };
+    }
+    match std::env::var("BLOCKLIST_RESPONSE").as_deref() {
+        Err(_) | Ok("" | "refuse") => {}
+        Ok("drop") => state.blocklist.set_response(BlockResponse::Drop),
+        Ok(other) => {
+            error!("BLOCKLIST_RESPONSE must be drop or refuse, got {other:?}");
+            std::process::exit(1);
+        }
+    }
+    state.spa_fallback = std::env::var("SPA_FALLBACK").is_ok_and(|v| v == "1");
+    let tenant_ai_path = std::env::var("TENANT_AI_PATH").ok().map(PathBuf::from);
+    if let Some(path) = &tenant_ai_path {
+        state.ai = match AiFallback::load(path.clone()) {
+            Ok(ai) => ai,
+            Err(e) => {
+                error!("{}: {e}", path.display());
+                std::process::exit(1);
+            }
+        };
+    }
+    let prompts_path = std::env::var("PROMPTS_PATH").ok().map(PathBuf::from);
+    if let Some(path) = &prompts_path {
+        state.ai.prompts = match PromptStore::load(path.clone()) {
+            Ok(prompts) => prompts,
+            Err(e) => {
+                error!("{}: {e}", path.display());
+                std::process::exit(1);
+            }
+        };
+    }
+    let ai_api_url = std::env::var("AI_API_URL").ok().filter(|u| !u.is_empty());
+    match (std::env::var("AI_PROVIDER").as_deref(), &ai_api_url) {
+        (Ok(other), _) if !matches!(other, "" | "chat_completions" | "llama_server") => {
+            error!("AI_PROVIDER must be chat_completions or llama_server, got {other:?}");
+            std::process::exit(1);
+        }
+        (Err(_) | Ok(""), None) => {}
+        (Ok(provider), None) => {
+            error!("AI_PROVIDER={provider} needs AI_API_URL");
+            std::process::exit(1);
+        }
+        (Ok("llama_server"), Some(url)) => {
+            state
+                .ai
+                .set_handler(Arc::new(LlamaServer::new(state.http.clone(), url)));
+        }
+        (_, Some(url)) => {
+            let model = std::env::var("AI_MODEL").unwrap_or_else(|_| ai::DEFAULT_MODEL.into());
+            state.ai.set_handler(Arc::new(ChatCompletions::new(
+                state.http.clone(),
+                url.clone(),
+                model,
+            )));
+        }
+    }
+
+    let config_path = std::env::var("CONFIG_PATH").ok().map(PathBuf::from);
+    if let Some(path) = &config_path
+        && let Err(e) = state.config.reload(path)
+    {
+        error!("{}: {e}", path.display());
+        std::process::exit(1);
+    }
+    let alert_rules = AlertRules {
+        delivery_failure_rate: env_number("ALERT_DELIVERY_FAILURE_RATE"),
+        unknown_rate: env_number("ALERT_UNKNOWN_RATE"),
+        handoff_queue: env_number("ALERT_HANDOFF_QUEUE"),
+    };
+    let alert_targets = AlertTargets {
+        webhook_url: std::env::var("ALERT_WEBHOOK_URL")
+            .ok()
+            .filter(|u| !u.is_empty()),
+        slack_url: std::env::var("ALERT_SLACK_WEBHOOK_URL")
+            .ok()
+            .filter(|u| !u.is_empty()),
+    };
+    state.ai.alerts = alert_targets.clone();
+    let snapshot_path = std::env::var("SESSION_SNAPSHOT").ok().map(PathBuf::from);
+    let synonyms_path = std::env::var("SYNONYMS_PATH").ok().map(PathBuf::from);
+    if let Some(path) = &synonyms_path {
+        state.synonyms = match Synonyms::load(path.clone()) {
+            Ok(synonyms) => synonyms,
+            Err(e) => {
+                error!("{}: {e}", path.display());
+                std::process::exit(1);
+            }
+        };
+    }
+    let training_path = std::env::var("INTENT_TRAINING_PATH")
+        .ok()
+        .map(PathBuf::from);
+    if let Some(path) = &training_path {
+        state.training = match TrainingData::load(path.clone()) {
+            Ok(training) => training,
+            Err(e) => {
+                error!("{}: {e}", path.display());
+                std::process::exit(1);
+            }
+        };
+    }
+
+    if let Ok(dir) = std::env::var("BOTS_DIR") {
+        state.bots = Bots::load(dir.as_ref(), &state.config).unwrap_or_else(|e| {
+            error!("{e}");
+            std::process::exit(1);
+        });
+        info!(bots = ?state.bots.ids().collect::<Vec<_>>(), "bots loaded");
+    }
+
+    let mut check = SelfCheck::default();
+    check.writable_dir("REPORTS_DIR", &state.reports_dir);
+    if let Some(path) = &snapshot_path {
+        check.writable_file("SESSION_SNAPSHOT", path);
+    }
+    if let Ok(path) = std::env::var("OUTBOX_PATH") {
+        check.writable_file("OUTBOX_PATH", path.as_ref());
+    }
+    if let Some(path) = &synonyms_path {
+        check.writable_file("SYNONYMS_PATH", path);
+    }
+    if let Some(path) = &blocklist_path {
+        check.writable_file("BLOCKLIST_PATH", path);
+    }
+    if let Some(path) = &tenant_ai_path {
+        check.writable_file("TENANT_AI_PATH", path);
+    }
+    if let Some(path) = &prompts_path {
+        check.writable_file("PROMPTS_PATH", path);
+    }
+    if let Some(path) = &review_dataset {
+        check.writable_file("REVIEW_DATASET", path);
+    }
+    if let Some(path) = &training_path {
+        check.writable_file("INTENT_TRAINING_PATH", path);
+    }
+    let export_dir = std::env::var("EXPORT_DIR").ok().filter(|d| !d.is_empty());
+    let export_url = std::env::var("EXPORT_URL").ok().filter(|u| !u.is_empty());
+    if let Some(dir) = &export_dir {
+        check.writable_dir("EXPORT_DIR", dir.as_ref());
+    }
+    check.url("EXPORT_URL", export_url.as_deref());
+    check.url("WEBHOOK_URL", state.config.webhook_url().as_deref());
+    check.url("MAIL_RELAY_URL", state.mail_relay_url.as_deref());
+    check.url(
+        "MARKETING_WEBHOOK_URL",
+        state.marketing_webhook_url.as_deref(),
+    );
+    check.url(
+        "PARTNER_WEBHOOK_URL",
+        std::env::var("PARTNER_WEBHOOK_URL").ok().as_deref(),
+    );
+    check.url("AI_API_URL", ai_api_url.as_deref());
+    check.url("ALERT_WEBHOOK_URL", alert_targets.webhook_url.as_deref());
+    check.url(
+        "ALERT_SLACK_WEBHOOK_URL",
+        alert_targets.slack_url.as_deref(),
+    );
+    check.assets(&state.assets);
+    if let Err(problems) = check.finish() {
+        for problem in &problems {
+            error!("{problem}");
+        }
+        error!(problems = problems.len(), "startup self-check failed");
+        std::process::exit(1);
+    }
+
+    // Bound before any background work starts, so a taken port stops the
+    // server at once.
+    let addr = std::env::var("BIND_ADDR").unwrap_or_else(|_| "0.0.0.0:3000".into());
+    let reuse_port = std::env::var("REUSE_PORT").is_ok_and(|v| v == "1");
+    let (listener, inherited) = listener::bind(&addr, reuse_port).unwrap_or_else(|e| {
+        error!("cannot listen on {addr}: {e}");
+        std::process::exit(1);
+    });
+    match listener.local_addr() {
+        Ok(local) if inherited => info!("listening on {local} (socket activation)"),
+        Ok(local) => info!("listening on {local}"),
+        Err(_) => info!("listening on {addr}"),
+    }
+
+    let state = Arc::new(state);
+
+    match (alert_rules.is_empty(), alert_targets.is_empty()) {
+        (false, false) => {
+            tokio::spawn(alerts::run(
+                state.clone(),
+                Alerter::new(alert_rules, alert_targets),
+                env_number("ALERT_CHECK_SECS")
+                    .map(Duration::from_secs)
+                    .unwrap_or(alerts::CHECK_INTERVAL),
+            ));
+        }
+        (false, true) => {
+            warn!(
+                "alert thresholds are set but neither ALERT_WEBHOOK_URL nor ALERT_SLACK_WEBHOOK_URL; alerts are disabled"
+            )
+        }
+        (true, false) => warn!("an alert target is set but no ALERT_* thresholds"),
+        (true, true) => {}
+    }
+
+    let weekly_recipients: Vec<String> = std::env::var("WEEKLY_REPORT_RECIPIENTS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|r| !r.is_empty())
+        .map(str::to_string)
+        .collect();
+    if !weekly_recipients.is_empty() {
+        if state.mail_relay_url.is_none() {
+            warn!(
+                "WEEKLY_REPORT_RECIPIENTS is set but MAIL_RELAY_URL is not; weekly reports will be dropped"
+            );
+        }
+        tokio::spawn(weekly::run(state.clone(), weekly_recipients));
+    }
+
+    let export_sinks: Vec<ExportSink> = export_dir
+        .map(|dir| ExportSink {
+            target: ExportTarget::Directory(dir.into()),
+            anonymization: env_anonymization("EXPORT_DIR_ANONYMIZATION", Anonymization::Strip),
+        })
+        .into_iter()
+        .chain(export_url.map(|url| ExportSink {
+            target: ExportTarget::Http {
+                url,
+                token: std::env::var("EXPORT_TOKEN").ok().filter(|t| !t.is_empty()),
+            },
+            anonymization: env_anonymization("EXPORT_URL_ANONYMIZATION", Anonymization::Strip),
+        }))
+        .collect();
+    if !export_sinks.is_empty() {
+        state.events.keep_for_export();
+        let settings = ExportSettings {
+            sinks: export_sinks,
+            salt: hash_salt.get_or_insert_with(export_salt).clone(),
+        };
+        tokio::spawn(warehouse::run(state.clone(), settings));
+    }
+
+    if let Some(path) = &snapshot_path {
+        match snapshot::restore(&state, path).await {
+            Ok(n) => info!(sessions = n, "restored sessions"),
+            Err(e) => {
+                error!("{}: {e}", path.display());
+                std::process::exit(1);
+            }
+        }
+        tokio::spawn(snapshot::watch(
+            state.clone(),
+            path.clone(),
+            SNAPSHOT_POLL_INTERVAL,
+        ));
+    }
+
+    if let Some(path) = config_path {
+        tokio::spawn(config::watch(state.clone(), path, CONFIG_POLL_INTERVAL));
+    }
+    if let Some(path) = synonyms_path {
+        tokio::spawn(synonyms::watch(state.clone(), path, CONFIG_POLL_INTERVAL));
+    }
+    if !state.bots.is_empty() {
+        tokio::spawn(bots::watch(state.clone(), CONFIG_POLL_INTERVAL));
+    }
+    if let Some(path) = training_path {
+        tokio::spawn(training::watch(state.clone(), path, CONFIG_POLL_INTERVAL));
+    }
+
+    let delivery_state = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(DELIVERY_INTERVAL);
+        loop {
+            interval.tick().await;
+            outbox::deliver_due(&delivery_state, DELIVERY_BATCH).await;
+        }
+    });
+    tokio::spawn(outbox::write_behind(state.clone()));
+    tokio::spawn(events::write_behind(state.clone()));
+
+    let purge_state = state.clone();
+    tokio::spawn(async move {
+        loop {
+            // Read every round, so a new interval set through the admin
+            // API applies from the next purge.
+            tokio::time::sleep(purge_state.sessions.purge_interval()).await;
+            if purge_state.maintenance.is_on() {
+                continue;
+            }
+            let removed = purge_state.sessions.purge_expired().await;
+            purge_state.metrics.record_purge(&removed.stats);
+            abandoned::notify_sales(&purge_state, &removed.expired);
+            abandoned::notify_marketing(&purge_state, &removed.expired);
+            for session in &removed.expired {
+                let events = purge_state.events.session_events(&session.id);
+                purge_state.review.offer(session, &events);
+            }
+            for session in removed.iter() {
+                purge_state.events.forget(&session.id);
+                purge_state.push.forget(&session.id);
+                purge_state.watchers.forget(&session.id);
+                purge_state.traces.forget(&session.id);
+            }
+            if !removed.is_empty() {
+                let stats = removed.stats;
+                info!(
+                    expired = stats.expired,
+                    deleted = stats.deleted,
+                    sessions = stats.sessions_after,
+                    duration_ms = stats.duration.as_millis() as u64,
+                    "purged expired sessions"
+                );
+            }
+        }
+    });
+
+    if memory_warning.is_some() {
+        let memory_state = state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(MEMORY_CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                memory_state.sessions.check_memory().await;
+            }
+        });
+    }
+
+    let warning = env_number("INACTIVITY_WARNING_SECS")
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_INACTIVITY_WARNING);
+    if warning >= session_ttl {
+        warn!(
+            "INACTIVITY_WARNING_SECS is not below the session TTL; no inactivity warnings are sent"
+        );
+    }
+    let warning_state = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(WARNING_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            // The TTL can change at runtime; warnings pause while it is
+            // not above the warning period.
+            let ttl = warning_state.sessions.ttl();
+            if let Some(idle_for) = ttl.checked_sub(warning).filter(|d| !d.is_zero()) {
+                push::warn_idle_sessions(&warning_state, idle_for).await;
+            }
+        }
+    });
+
+    let queue_state = state.clone();
+    tokio::spawn(async move {
+        let mut announced = HashMap::new();
+        let mut interval = tokio::time::interval(QUEUE_UPDATE_INTERVAL);
+        loop {
+            interval.tick().await;
+            handoff::announce_positions(&queue_state, &mut announced);
+        }
+    });
+
+    if let Ok(homeserver) = std::env::var("MATRIX_HOMESERVER") {
+        let homeserver = reqwest::Url::parse(&homeserver)
+            .ok()
+            .filter(|u| matches!(u.scheme(), "http" | "https"))
+            .unwrap_or_else(|| {
+                error!("MATRIX_HOMESERVER must be an http(s) URL, got {homeserver:?}");
+                std::process::exit(1);
+            });
+        let (Ok(access_token), Ok(user_id)) = (
+            std::env::var("MATRIX_ACCESS_TOKEN"),
+            std::env::var("MATRIX_USER_ID"),
+        ) else {
+            error!("MATRIX_HOMESERVER needs MATRIX_ACCESS_TOKEN and MATRIX_USER_ID");
+            std::process::exit(1);
+        };
+        tokio::spawn(matrix::run(
+            state.clone(),
+            MatrixConfig {
+                homeserver,
+                access_token,
+                user_id,
+            },
+        ));
+    }
+
+    if let Some(grpc_addr) = std::env::var("GRPC_ADDR").ok().filter(|a| !a.is_empty()) {
+        let grpc_addr: SocketAddr = grpc_addr.parse().unwrap_or_else(|e| {
+            error!("GRPC_ADDR {grpc_addr:?}: {e}");
+            std::process::exit(1);
+        });
+        info!("gRPC listening on {grpc_addr}");
+        let grpc_state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = grpc::serve(grpc_state, grpc_addr).await {
+                error!("gRPC server stopped: {e}");
+            }
+        });
+    }
+
+    let stopping = Arc::new(Notify::new());
+    let signal_stopping = stopping.clone();
+    let server = axum::serve(
+        listener,
+        routes::router(state.clone()).into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(async move {
+        shutdown_signal().await;
+        info!("shutting down");
+        signal_stopping.notify_one();
+    });
+    tokio::select! {
+        result = server => result.expect("server error"),
+        _ = async {
+            stopping.notified().await;
+            tokio::time::sleep(DRAIN_TIMEOUT).await;
+        } => warn!("connections still open after {DRAIN_TIMEOUT:?}; stopping anyway"),
+    }
+
+    state.outbox.flush();
+    state.events.flush_log();
+    if let Some(path) = &snapshot_path {
+        match snapshot::save(&state, path).await {
+            Ok(n) => info!(sessions = n, "saved sessions to {}", path.display()),
+            Err(e) => error!("cannot save sessions to {}: {e}", path.display()),
+        }
+    }
 }
@@ -0,0 +1,67 @@
+//! Maintenance mode, switched on and off through `/admin/maintenance`.
+//!
+//! While it is on, every channel answers visitors with a localized "we'll
+//! be back soon" instead of running the bot, and no sessions are created.
+//! Existing sessions are left alone: they don't expire or get inactivity
+//! warnings, and when maintenance ends their idle time is moved forward by
+//! its length, so visitors continue where they left off.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::events::now_ms;
+
+#[derive(Default)]
+pub struct Maintenance {
+    since: Mutex<Option<Since>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Since {
+    at: Instant,
+    at_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct MaintenanceStatus {
+    pub enabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since_ms: Option<u64>,
+}
+
+impl Maintenance {
+    pub fn is_on(&self) -> bool {
+        self.since.lock().unwrap().is_some()
+    }
+
+    pub fn status(&self) -> MaintenanceStatus {
+        let since = *self.since.lock().unwrap();
+        MaintenanceStatus {
+            enabled: since.is_some(),
+            since_ms: since.map(|s| s.at_ms),
+        }
+    }
+
+    /// Turns maintenance on; `false` if it already was.
+    pub fn start(&self) -> bool {
+        let mut since = self.since.lock().unwrap();
+        if since.is_some() {
+            return false;
+        }
+        *since = Some(Since {
+            at: Instant::now(),
+            at_ms: now_ms(),
+        });
+        true
+    }
+
+    /// Turns maintenance off and returns how long it lasted; `None` if it
+    /// was not on.
+    pub fn end(&self) -> Option<Duration> {
+        self.since.lock().unwrap().take().map(|s| s.at.elapsed())
+    }
+}
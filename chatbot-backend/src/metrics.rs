@@ -0,0 +1,502 @@
+use std::{collections::BTreeMap, fmt::Write, sync::Mutex, time::Duration};
+
+use serde::Serialize;
+
+use crate::session::{MemoryUsage, PurgeStats};
+
+/// Upper bounds of the request latency histogram, in seconds.
+const LATENCY_BUCKETS: [f64; 9] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0];
+
+/// Usage counters, kept per tenant, and HTTP counters, kept per route.
+#[derive(Default)]
+pub struct MetricsManager {
+    tenants: Mutex<BTreeMap<String, Counters>>,
+    /// Keyed by method and route pattern.
+    routes: Mutex<BTreeMap<(String, String), RouteCounters>>,
+    purges: Mutex<PurgeCounters>,
+    /// `name="value"` pairs added to every Prometheus series.
+    labels: String,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Counters {
+    messages_total: u64,
+    sessions_created: u64,
+    inquiries_started: u64,
+    inquiries_confirmed: u64,
+    inquiries_abandoned: u64,
+    intents: BTreeMap<String, u64>,
+    sub_intents: BTreeMap<(String, String), u64>,
+    sessions_rejected: BTreeMap<String, u64>,
+    messages_delivered: u64,
+    messages_read: u64,
+    languages: BTreeMap<String, LanguageCounts>,
+    blocked: BTreeMap<String, u64>,
+    satisfaction: [u64; 5],
+}
+
+/// How well the bot understands visitors writing in one language.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct LanguageCounts {
+    pub messages: u64,
+    /// Messages the rules didn't understand, whether or not the AI
+    /// fallback answered them; a high share means the language's keywords
+    /// need work.
+    pub unknown: u64,
+    /// Of those, messages the AI fallback answered; see [`crate::ai`].
+    pub fallback: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MetricsSnapshot {
+    pub messages_total: u64,
+    pub sessions_created: u64,
+    /// Sessions that went into the inquiry flow, and inquiries confirmed.
+    pub inquiries_started: u64,
+    pub inquiries_confirmed: u64,
+    /// Inquiries left with an email address captured when the session
+    /// expired; see [`crate::abandoned`].
+    pub inquiries_abandoned: u64,
+    pub intents: BTreeMap<String, u64>,
+    /// Keyed by `"intent/sub_intent"`.
+    pub sub_intents: BTreeMap<String, u64>,
+    /// New sessions refused, by reason.
+    pub sessions_rejected: BTreeMap<String, u64>,
+    /// Bot and operator messages the visitor's client reported delivered
+    /// (read ones included) and read.
+    pub messages_delivered: u64,
+    pub messages_read: u64,
+    /// Keyed by ISO 639-1 code.
+    pub languages: BTreeMap<String, LanguageCounts>,
+    /// Requests refused or dropped by the blocklist, by what matched.
+    pub blocked_requests: BTreeMap<String, u64>,
+    /// Satisfaction survey answers giving each score, from 1 to 5.
+    pub satisfaction: [u64; 5],
+}
+
+#[derive(Debug, Default)]
+struct RouteCounters {
+    /// Keyed by status class: `2xx`, `4xx`, ...
+    responses: BTreeMap<&'static str, u64>,
+    /// Requests that took at most each of [`LATENCY_BUCKETS`].
+    latency_buckets: [u64; LATENCY_BUCKETS.len()],
+    latency_seconds: f64,
+    requests: u64,
+}
+
+/// Totals over all session purges, and the last one.
+#[derive(Debug, Default)]
+struct PurgeCounters {
+    runs: u64,
+    expired: u64,
+    deleted: u64,
+    duration_seconds: f64,
+    last: Option<PurgeStats>,
+}
+
+fn status_class(status: u16) -> &'static str {
+    match status {
+        100..=199 => "1xx",
+        200..=299 => "2xx",
+        300..=399 => "3xx",
+        400..=499 => "4xx",
+        _ => "5xx",
+    }
+}
+
+impl Counters {
+    fn add(&mut self, other: &Counters) {
+        fn sum<K: Ord + Clone>(into: &mut BTreeMap<K, u64>, from: &BTreeMap<K, u64>) {
+            for (key, n) in from {
+                *into.entry(key.clone()).or_default() += n;
+            }
+        }
+        self.messages_total += other.messages_total;
+        self.sessions_created += other.sessions_created;
+        self.inquiries_started += other.inquiries_started;
+        self.inquiries_confirmed += other.inquiries_confirmed;
+        self.inquiries_abandoned += other.inquiries_abandoned;
+        sum(&mut self.intents, &other.intents);
+        sum(&mut self.sub_intents, &other.sub_intents);
+        sum(&mut self.sessions_rejected, &other.sessions_rejected);
+        sum(&mut self.blocked, &other.blocked);
+        for (into, n) in self.satisfaction.iter_mut().zip(other.satisfaction) {
+            *into += n;
+        }
+        self.messages_delivered += other.messages_delivered;
+        self.messages_read += other.messages_read;
+        for (language, counts) in &other.languages {
+            let into = self.languages.entry(language.clone()).or_default();
+            into.messages += counts.messages;
+            into.unknown += counts.unknown;
+            into.fallback += counts.fallback;
+        }
+    }
+
+    fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            messages_total: self.messages_total,
+            sessions_created: self.sessions_created,
+            inquiries_started: self.inquiries_started,
+            inquiries_confirmed: self.inquiries_confirmed,
+            inquiries_abandoned: self.inquiries_abandoned,
+            intents: self.intents.clone(),
+            sub_intents: self
+                .sub_intents
+                .iter()
+                .map(|((intent, sub), n)| (format!("{intent}/{sub}"), *n))
+                .collect(),
+            sessions_rejected: self.sessions_rejected.clone(),
+            messages_delivered: self.messages_delivered,
+            messages_read: self.messages_read,
+            languages: self.languages.clone(),
+            blocked_requests: self.blocked.clone(),
+            satisfaction: self.satisfaction,
+        }
+    }
+}
+
+impl MetricsManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds constant labels, such as the region or environment, to every
+    /// series [`MetricsManager::render_prometheus`] writes.
+    pub fn set_labels(&mut self, labels: &[(String, String)]) {
+        self.labels = labels
+            .iter()
+            .map(|(name, value)| format!(",{name}=\"{}\"", escape_label(value)))
+            .collect();
+    }
+
+    fn with(&self, tenant: &str, f: impl FnOnce(&mut Counters)) {
+        let mut tenants = self.tenants.lock().unwrap();
+        f(tenants.entry(tenant.to_string()).or_default());
+    }
+
+    pub fn record_message(&self, tenant: &str) {
+        self.with(tenant, |c| c.messages_total += 1);
+    }
+
+    pub fn record_session_created(&self, tenant: &str) {
+        self.with(tenant, |c| c.sessions_created += 1);
+    }
+
+    pub fn record_inquiry_started(&self, tenant: &str) {
+        self.with(tenant, |c| c.inquiries_started += 1);
+    }
+
+    pub fn record_inquiry_confirmed(&self, tenant: &str) {
+        self.with(tenant, |c| c.inquiries_confirmed += 1);
+    }
+
+    pub fn record_inquiry_abandoned(&self, tenant: &str) {
+        self.with(tenant, |c| c.inquiries_abandoned += 1);
+    }
+
+    /// Counts a session that was not created because a cap was reached.
+    pub fn record_session_rejected(&self, tenant: &str, reason: &str) {
+        self.with(tenant, |c| {
+            *c.sessions_rejected.entry(reason.to_string()).or_default() += 1
+        });
+    }
+
+    /// Counts a request turned away by the blocklist; `reason` is the kind
+    /// of entry that matched.
+    pub fn record_blocked(&self, tenant: &str, reason: &str) {
+        self.with(tenant, |c| {
+            *c.blocked.entry(reason.to_string()).or_default() += 1
+        });
+    }
+
+    /// Counts an answer to the satisfaction survey; `score` is 1 to 5.
+    pub fn record_satisfaction(&self, tenant: &str, score: u8) {
+        let Some(i) = (score as usize).checked_sub(1).filter(|i| *i < 5) else {
+            return;
+        };
+        self.with(tenant, |c| c.satisfaction[i] += 1);
+    }
+
+    /// Counts messages newly reported delivered and read. A message
+    /// first reported read counts as delivered too.
+    pub fn record_receipts(&self, tenant: &str, delivered: u64, read: u64) {
+        self.with(tenant, |c| {
+            c.messages_delivered += delivered;
+            c.messages_read += read;
+        });
+    }
+
+    /// Counts a visitor message in the session's language, and whether the
+    /// rules understood it.
+    pub fn record_language(&self, tenant: &str, language: &str, unknown: bool) {
+        self.with(tenant, |c| {
+            let counts = c.languages.entry(language.to_string()).or_default();
+            counts.messages += 1;
+            counts.unknown += u64::from(unknown);
+        });
+    }
+
+    /// Counts a visitor message in the session's language that the AI
+    /// fallback answered.
+    pub fn record_fallback(&self, tenant: &str, language: &str) {
+        self.with(tenant, |c| {
+            c.languages
+                .entry(language.to_string())
+                .or_default()
+                .fallback += 1;
+        });
+    }
+
+    pub fn record_intent(&self, tenant: &str, intent: &str) {
+        self.with(tenant, |c| {
+            *c.intents.entry(intent.to_string()).or_default() += 1
+        });
+    }
+
+    /// Records a finer-grained choice within an intent, e.g. which catalog
+    /// item was browsed under `services`.
+    pub fn record_sub_intent(&self, tenant: &str, intent: &str, sub_intent: &str) {
+        self.with(tenant, |c| {
+            *c.sub_intents
+                .entry((intent.to_string(), sub_intent.to_string()))
+                .or_default() += 1
+        });
+    }
+
+    /// Counts a request answered by `route`, the pattern it matched (e.g.
+    /// `/sessions/{id}/messages`) rather than its path, so ids do not make
+    /// a series each.
+    pub fn record_request(&self, method: &str, route: &str, status: u16, latency: Duration) {
+        let mut routes = self.routes.lock().unwrap();
+        let counters = routes
+            .entry((method.to_string(), route.to_string()))
+            .or_default();
+        *counters.responses.entry(status_class(status)).or_default() += 1;
+        let seconds = latency.as_secs_f64();
+        for (bucket, le) in counters.latency_buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            *bucket += u64::from(seconds <= le);
+        }
+        counters.latency_seconds += seconds;
+        counters.requests += 1;
+    }
+
+    /// Counts a run of the session purge.
+    pub fn record_purge(&self, stats: &PurgeStats) {
+        let mut purges = self.purges.lock().unwrap();
+        purges.runs += 1;
+        purges.expired += stats.expired as u64;
+        purges.deleted += stats.deleted as u64;
+        purges.duration_seconds += stats.duration.as_secs_f64();
+        purges.last = Some(*stats);
+    }
+
+    /// Counters across all tenants.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let mut total = Counters::default();
+        for counters in self.tenants.lock().unwrap().values() {
+            total.add(counters);
+        }
+        total.snapshot()
+    }
+
+    /// One tenant's counters; `None` if it has none yet.
+    pub fn tenant_snapshot(&self, tenant: &str) -> Option<MetricsSnapshot> {
+        self.tenants
+            .lock()
+            .unwrap()
+            .get(tenant)
+            .map(Counters::snapshot)
+    }
+
+    /// Tenants with counters, in order.
+    pub fn tenants(&self) -> Vec<String> {
+        self.tenants.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Renders all counters in the Prometheus text exposition format.
+    /// Counters carry a `tenant` label; HTTP counters are per route and the
+    /// session store and purge series are for the whole instance.
+    pub fn render_prometheus(&self, memory: &MemoryUsage) -> String {
+        let tenants = self.tenants.lock().unwrap();
+        let mut out = String::new();
+        let mut counter = |name: &str, values: &dyn Fn(&Counters) -> Vec<(String, u64)>| {
+            let _ = writeln!(out, "# TYPE {name} counter");
+            for (tenant, counters) in tenants.iter() {
+                for (labels, n) in values(counters) {
+                    let _ = writeln!(
+                        out,
+                        "{name}{{tenant=\"{tenant}\"{labels}{}}} {n}",
+                        self.labels
+                    );
+                }
+            }
+        };
+        counter("chatbot_messages_total", &|c| {
+            vec![(String::new(), c.messages_total)]
+        });
+        counter("chatbot_sessions_created_total", &|c| {
+            vec![(String::new(), c.sessions_created)]
+        });
+        counter("chatbot_inquiries_started_total", &|c| {
+            vec![(String::new(), c.inquiries_started)]
+        });
+        counter("chatbot_inquiries_confirmed_total", &|c| {
+            vec![(String::new(), c.inquiries_confirmed)]
+        });
+        counter("chatbot_inquiries_abandoned_total", &|c| {
+            vec![(String::new(), c.inquiries_abandoned)]
+        });
+        counter("chatbot_messages_delivered_total", &|c| {
+            vec![(String::new(), c.messages_delivered)]
+        });
+        counter("chatbot_messages_read_total", &|c| {
+            vec![(String::new(), c.messages_read)]
+        });
+        counter("chatbot_sessions_rejected_total", &|c| {
+            c.sessions_rejected
+                .iter()
+                .map(|(reason, n)| (format!(",reason=\"{reason}\""), *n))
+                .collect()
+        });
+        counter("chatbot_blocked_requests_total", &|c| {
+            c.blocked
+                .iter()
+                .map(|(reason, n)| (format!(",reason=\"{reason}\""), *n))
+                .collect()
+        });
+        counter("chatbot_satisfaction_responses_total", &|c| {
+            c.satisfaction
+                .iter()
+                .enumerate()
+                .map(|(i, n)| (format!(",score=\"{}\"", i + 1), *n))
+                .collect()
+        });
+        counter("chatbot_intents_total", &|c| {
+            c.intents
+                .iter()
+                .map(|(intent, n)| (format!(",intent=\"{intent}\""), *n))
+                .collect()
+        });
+        counter("chatbot_sub_intents_total", &|c| {
+            c.sub_intents
+                .iter()
+                .map(|((intent, sub), n)| {
+                    let sub = escape_label(sub);
+                    (format!(",intent=\"{intent}\",sub_intent=\"{sub}\""), *n)
+                })
+                .collect()
+        });
+        counter("chatbot_language_messages_total", &|c| {
+            c.languages
+                .iter()
+                .map(|(language, n)| (format!(",language=\"{language}\""), n.messages))
+                .collect()
+        });
+        counter("chatbot_language_unknown_total", &|c| {
+            c.languages
+                .iter()
+                .map(|(language, n)| (format!(",language=\"{language}\""), n.unknown))
+                .collect()
+        });
+        counter("chatbot_language_fallback_total", &|c| {
+            c.languages
+                .iter()
+                .map(|(language, n)| (format!(",language=\"{language}\""), n.fallback))
+                .collect()
+        });
+
+        self.render_routes(&mut out);
+
+        let labels = match self.labels.strip_prefix(',') {
+            Some(labels) => format!("{{{labels}}}"),
+            None => String::new(),
+        };
+        let gauges = [
+            ("chatbot_active_sessions", memory.sessions),
+            ("chatbot_session_messages", memory.messages),
+            ("chatbot_session_message_bytes", memory.message_bytes),
+            ("chatbot_session_memory_bytes", memory.approx_bytes),
+        ];
+        for (name, value) in gauges {
+            let _ = writeln!(out, "# TYPE {name} gauge");
+            let _ = writeln!(out, "{name}{labels} {value}");
+        }
+        self.render_purges(&mut out, &labels);
+        out
+    }
+
+    fn render_purges(&self, out: &mut String, labels: &str) {
+        let purges = self.purges.lock().unwrap();
+        let name = "chatbot_sessions_purged_total";
+        let _ = writeln!(out, "# TYPE {name} counter");
+        for (reason, n) in [("expired", purges.expired), ("deleted", purges.deleted)] {
+            let _ = writeln!(out, "{name}{{reason=\"{reason}\"{}}} {n}", self.labels);
+        }
+        let name = "chatbot_session_purge_duration_seconds";
+        let _ = writeln!(out, "# TYPE {name} summary");
+        let _ = writeln!(out, "{name}_sum{labels} {}", purges.duration_seconds);
+        let _ = writeln!(out, "{name}_count{labels} {}", purges.runs);
+        let Some(last) = purges.last else {
+            return;
+        };
+        let gauges = [
+            (
+                "chatbot_session_purge_last_timestamp_seconds",
+                last.at_ms / 1000,
+            ),
+            (
+                "chatbot_session_purge_last_sessions_before",
+                last.sessions_before as u64,
+            ),
+            (
+                "chatbot_session_purge_last_sessions_after",
+                last.sessions_after as u64,
+            ),
+        ];
+        for (name, value) in gauges {
+            let _ = writeln!(out, "# TYPE {name} gauge");
+            let _ = writeln!(out, "{name}{labels} {value}");
+        }
+    }
+
+    fn render_routes(&self, out: &mut String) {
+        let routes = self.routes.lock().unwrap();
+        let name = "chatbot_http_requests_total";
+        let _ = writeln!(out, "# TYPE {name} counter");
+        for ((method, route), counters) in routes.iter() {
+            let route = escape_label(route);
+            for (status, n) in &counters.responses {
+                let _ = writeln!(
+                    out,
+                    "{name}{{method=\"{method}\",route=\"{route}\",status=\"{status}\"{}}} {n}",
+                    self.labels
+                );
+            }
+        }
+        let name = "chatbot_http_request_duration_seconds";
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        for ((method, route), counters) in routes.iter() {
+            let labels = format!(
+                "method=\"{method}\",route=\"{}\"{}",
+                escape_label(route),
+                self.labels
+            );
+            for (le, n) in LATENCY_BUCKETS.iter().zip(counters.latency_buckets) {
+                let _ = writeln!(out, "{name}_bucket{{{labels},le=\"{le}\"}} {n}");
+            }
+            let n = counters.requests;
+            let _ = writeln!(out, "{name}_bucket{{{labels},le=\"+Inf\"}} {n}");
+            let _ = writeln!(out, "{name}_sum{{{labels}}} {}", counters.latency_seconds);
+            let _ = writeln!(out, "{name}_count{{{labels}}} {n}");
+        }
+    }
+}
+
+/// Escapes a label value for the exposition format.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,361 @@
+//! Durable queue of outbound webhook deliveries.
+//!
+//! Handlers only enqueue a job; a background worker posts it and retries
+//! with exponential backoff. With a backing file, changes are written
+//! behind by [`write_behind`]: handlers never wait for the disk,
+//! and a job is on disk, flushed, moments after it was queued. Jobs queued
+//! before a restart, or a crash that didn't strike in those moments, are
+//! delivered afterwards.
+//!
+//! Dead letters are kept for [`DEAD_LETTER_TTL`], at most
+//! [`MAX_DEAD_LETTERS`] of them.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tokio::{sync::Notify, task::JoinSet};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{durable, events::now_ms, state::SharedState};
+
+/// Attempts before a job is given up on and marked failed.
+pub const MAX_ATTEMPTS: u32 = 8;
+/// How long a failed job is kept for redelivery.
+pub const DEAD_LETTER_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// Failed jobs kept at most; the oldest go first.
+pub const MAX_DEAD_LETTERS: usize = 1_000;
+const BASE_BACKOFF: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Pending,
+    /// Out of attempts; kept as a dead letter until redelivered from the
+    /// admin API.
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboxJob {
+    pub id: String,
+    pub event_type: String,
+    pub target_url: String,
+    /// The JSON body posted to `target_url`.
+    pub payload: Value,
+    pub status: JobStatus,
+    pub attempts: u32,
+    pub created_at_ms: u64,
+    pub next_attempt_at_ms: u64,
+    pub last_error: Option<String>,
+    /// When it ran out of attempts; only for failed jobs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failed_at_ms: Option<u64>,
+}
+
+impl OutboxJob {
+    /// A job delivering `data` wrapped in the standard webhook envelope.
+    pub fn new(event_type: &str, target_url: &str, data: Value) -> Self {
+        let id = Uuid::new_v4().to_string();
+        let now = now_ms();
+        Self {
+            payload: json!({
+                "id": id,
+                "event": event_type,
+                "occurred_at_ms": now,
+                "data": data,
+            }),
+            id,
+            event_type: event_type.to_string(),
+            target_url: target_url.to_string(),
+            status: JobStatus::Pending,
+            attempts: 0,
+            created_at_ms: now,
+            next_attempt_at_ms: now,
+            last_error: None,
+            failed_at_ms: None,
+        }
+    }
+
+    /// A job posting `payload` as is, for receivers that expect their own
+    /// format, such as Slack.
+    pub fn raw(event_type: &str, target_url: &str, payload: Value) -> Self {
+        Self {
+            payload,
+            ..Self::new(event_type, target_url, Value::Null)
+        }
+    }
+}
+
+/// Delay before retrying a job that has failed `attempts` times.
+fn backoff(attempts: u32) -> Duration {
+    BASE_BACKOFF
+        .saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+        .min(MAX_BACKOFF)
+}
+
+pub struct Outbox {
+    jobs: Mutex<Vec<OutboxJob>>,
+    path: Option<PathBuf>,
+    /// Set when the jobs changed since they were last written.
+    dirty: AtomicBool,
+    changed: Notify,
+    /// Held while writing, so writes don't overlap.
+    writing: Mutex<()>,
+    /// Delivery attempts since start: succeeded, failed.
+    attempts: Mutex<(u64, u64)>,
+}
+
+impl Outbox {
+    pub fn in_memory() -> Self {
+        Self {
+            jobs: Mutex::new(Vec::new()),
+            path: None,
+            dirty: AtomicBool::new(false),
+            changed: Notify::new(),
+            writing: Mutex::new(()),
+            attempts: Mutex::default(),
+        }
+    }
+
+    /// Loads the jobs stored at `path`, which is created on first write.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let jobs = match fs::read_to_string(&path) {
+            Ok(raw) => serde_json::from_str(&raw)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            jobs: Mutex::new(jobs),
+            path: Some(path),
+            ..Self::in_memory()
+        })
+    }
+
+    /// Marks the jobs for [`write_behind`] to write; called with
+    /// the jobs locked, after changing them.
+    fn persist(&self) {
+        if self.path.is_some() {
+            self.dirty.store(true, Ordering::Release);
+            self.changed.notify_one();
+        }
+    }
+
+    /// Writes the jobs now if they changed since the last write, replacing
+    /// the file atomically and durably.
+    pub fn flush(&self) {
+        let Some(path) = &self.path else { return };
+        let _writing = self.writing.lock().unwrap();
+        if !self.dirty.swap(false, Ordering::AcqRel) {
+            return;
+        }
+        let jobs = self.jobs.lock().unwrap().clone();
+        let json = serde_json::to_vec(&jobs).expect("jobs serialize");
+        if let Err(e) = durable::replace(path, &json) {
+            warn!("cannot write outbox {}: {e}", path.display());
+            self.dirty.store(true, Ordering::Release);
+        }
+    }
+
+    pub fn enqueue(&self, job: OutboxJob) {
+        let mut jobs = self.jobs.lock().unwrap();
+        info!(job_id = %job.id, event = %job.event_type, "webhook queued");
+        jobs.push(job);
+        self.persist();
+    }
+
+    /// Pending jobs whose next attempt is due, oldest first. A receiver
+    /// with an older job waiting out its backoff gets none, so its jobs go
+    /// out in order.
+    pub fn due(&self, now_ms: u64, limit: usize) -> Vec<OutboxJob> {
+        let jobs = self.jobs.lock().unwrap();
+        let mut waiting: HashSet<&str> = HashSet::new();
+        jobs.iter()
+            .filter(|j| j.status == JobStatus::Pending)
+            .filter(|j| {
+                if waiting.contains(j.target_url.as_str()) {
+                    return false;
+                }
+                if j.next_attempt_at_ms > now_ms {
+                    waiting.insert(&j.target_url);
+                    return false;
+                }
+                true
+            })
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    pub fn mark_delivered(&self, id: &str) {
+        self.attempts.lock().unwrap().0 += 1;
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.retain(|j| j.id != id);
+        self.persist();
+    }
+
+    /// Schedules the next attempt, or marks the job failed once it is out
+    /// of attempts.
+    pub fn record_failure(&self, id: &str, error: String) {
+        self.attempts.lock().unwrap().1 += 1;
+        let mut jobs = self.jobs.lock().unwrap();
+        let Some(job) = jobs.iter_mut().find(|j| j.id == id) else {
+            return;
+        };
+        job.attempts += 1;
+        job.last_error = Some(error);
+        if job.attempts >= MAX_ATTEMPTS {
+            job.status = JobStatus::Failed;
+            job.failed_at_ms = Some(now_ms());
+            warn!(job_id = %job.id, attempts = job.attempts, "webhook delivery failed permanently");
+        } else {
+            job.next_attempt_at_ms = now_ms() + backoff(job.attempts).as_millis() as u64;
+        }
+        self.persist();
+    }
+
+    /// Drops dead letters older than [`DEAD_LETTER_TTL`], then the oldest
+    /// beyond [`MAX_DEAD_LETTERS`]. Returns how many were dropped.
+    pub fn prune_dead_letters(&self, now_ms: u64) -> usize {
+        let mut jobs = self.jobs.lock().unwrap();
+        let before = jobs.len();
+        let ttl = DEAD_LETTER_TTL.as_millis() as u64;
+        // Jobs that failed before the time was kept count from creation.
+        let failed_at = |j: &OutboxJob| j.failed_at_ms.unwrap_or(j.created_at_ms);
+        jobs.retain(|j| j.status != JobStatus::Failed || now_ms.saturating_sub(failed_at(j)) < ttl);
+        let mut failed: Vec<u64> = jobs
+            .iter()
+            .filter(|j| j.status == JobStatus::Failed)
+            .map(failed_at)
+            .collect();
+        if failed.len() > MAX_DEAD_LETTERS {
+            failed.sort_unstable();
+            let cutoff = failed[failed.len() - MAX_DEAD_LETTERS];
+            jobs.retain(|j| j.status != JobStatus::Failed || failed_at(j) >= cutoff);
+        }
+        let dropped = before - jobs.len();
+        if dropped > 0 {
+            info!(dropped, "dead letters pruned");
+            self.persist();
+        }
+        dropped
+    }
+
+    /// Jobs with the given status (all when `None`), oldest first.
+    pub fn list(&self, status: Option<JobStatus>) -> Vec<OutboxJob> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.iter()
+            .filter(|j| status.is_none_or(|s| j.status == s))
+            .cloned()
+            .collect()
+    }
+
+    pub fn get(&self, id: &str) -> Option<OutboxJob> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.iter().find(|j| j.id == id).cloned()
+    }
+
+    /// Puts a job back in the queue with a fresh set of attempts, due
+    /// immediately. Returns the updated job.
+    pub fn redeliver(&self, id: &str) -> Option<OutboxJob> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.iter_mut().find(|j| j.id == id)?;
+        job.status = JobStatus::Pending;
+        job.attempts = 0;
+        job.next_attempt_at_ms = now_ms();
+        job.failed_at_ms = None;
+        let job = job.clone();
+        self.persist();
+        Some(job)
+    }
+
+    /// Delivery attempts since start: how many succeeded and how many
+    /// failed.
+    pub fn attempts(&self) -> (u64, u64) {
+        *self.attempts.lock().unwrap()
+    }
+
+    pub fn len(&self) -> usize {
+        self.jobs.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Writes the outbox whenever it changes, until the process ends. Only
+/// needed with a backing file.
+pub async fn write_behind(state: SharedState) {
+    loop {
+        state.outbox.changed.notified().await;
+        let state = state.clone();
+        tokio::task::spawn_blocking(move || state.outbox.flush())
+            .await
+            .expect("outbox write panicked");
+    }
+}
+
+/// Posts every due job once and prunes the dead letters. A 2xx response
+/// counts as delivered. Each receiver gets its jobs in order, one at a
+/// time: once one fails, the later ones wait until it is delivered or
+/// given up on. Receivers are posted to side by side, so a slow one holds
+/// up only its own.
+pub async fn deliver_due(state: &SharedState, batch: usize) {
+    let mut by_target: HashMap<String, Vec<OutboxJob>> = HashMap::new();
+    for job in state.outbox.due(now_ms(), batch) {
+        by_target
+            .entry(job.target_url.clone())
+            .or_default()
+            .push(job);
+    }
+    let mut deliveries = JoinSet::new();
+    for jobs in by_target.into_values() {
+        let state = state.clone();
+        deliveries.spawn(async move {
+            for job in jobs {
+                if !deliver(&state.outbox, &state.http, &job).await {
+                    break;
+                }
+            }
+        });
+    }
+    deliveries.join_all().await;
+    state.outbox.prune_dead_letters(now_ms());
+}
+
+/// Posts `job` once; `true` when it was delivered.
+async fn deliver(outbox: &Outbox, client: &reqwest::Client, job: &OutboxJob) -> bool {
+    let result = client
+        .post(&job.target_url)
+        .timeout(REQUEST_TIMEOUT)
+        .json(&job.payload)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status());
+    match result {
+        Ok(_) => {
+            info!(job_id = %job.id, event = %job.event_type, "webhook delivered");
+            outbox.mark_delivered(&job.id);
+            true
+        }
+        Err(e) => {
+            outbox.record_failure(&job.id, e.to_string());
+            false
+        }
+    }
+}
@@ -0,0 +1,269 @@
+//! Steps run on every visitor message before the bot reads it, and on
+//! every reply before it is sent.
+//!
+//! Steps are registered by name, the built-in ones below and any a crate
+//! user adds with [`crate::config::LiveConfig::register_preprocessor`] or
+//! [`crate::config::LiveConfig::register_postprocessor`]. The runtime
+//! config chooses which run and in what order:
+//!
+//! ```json
+//! { "pipeline": { "pre": ["collapse_whitespace", "redact_card_numbers"], "post": ["signature"] } }
+//! ```
+//!
+//! A preprocessed message is what the bot answers and what the transcript
+//! keeps; nothing runs unless configured.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    chatbot::Reply,
+    i18n::{Text, text},
+    session::Session,
+    trace::StepTrace,
+};
+
+pub trait MessagePreprocessor: Send + Sync {
+    /// The name the runtime config lists it under.
+    fn name(&self) -> &'static str;
+
+    /// The message as the next step, and then the bot, should see it.
+    fn process(&self, session: &Session, message: &str) -> String;
+}
+
+pub trait ReplyPostprocessor: Send + Sync {
+    /// The name the runtime config lists it under.
+    fn name(&self) -> &'static str;
+
+    fn process(&self, session: &Session, reply: &mut Reply);
+}
+
+/// `pipeline` in the runtime config: step names, in the order they run.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PipelineConfig {
+    pub pre: Vec<String>,
+    pub post: Vec<String>,
+}
+
+/// Every step that can be configured, by name.
+#[derive(Clone)]
+pub struct Processors {
+    pre: BTreeMap<&'static str, Arc<dyn MessagePreprocessor>>,
+    post: BTreeMap<&'static str, Arc<dyn ReplyPostprocessor>>,
+}
+
+impl Default for Processors {
+    fn default() -> Self {
+        let mut processors = Self {
+            pre: BTreeMap::new(),
+            post: BTreeMap::new(),
+        };
+        processors.register_pre(Arc::new(CollapseWhitespace));
+        processors.register_pre(Arc::new(RedactCardNumbers));
+        processors.register_post(Arc::new(Signature));
+        processors
+    }
+}
+
+impl Processors {
+    /// # Panics
+    ///
+    /// If a preprocessor of that name is registered already.
+    pub fn register_pre(&mut self, step: Arc<dyn MessagePreprocessor>) {
+        let name = step.name();
+        assert!(
+            self.pre.insert(name, step).is_none(),
+            "preprocessor {name:?} is registered twice"
+        );
+    }
+
+    /// # Panics
+    ///
+    /// If a postprocessor of that name is registered already.
+    pub fn register_post(&mut self, step: Arc<dyn ReplyPostprocessor>) {
+        let name = step.name();
+        assert!(
+            self.post.insert(name, step).is_none(),
+            "postprocessor {name:?} is registered twice"
+        );
+    }
+
+    /// The first name in `config` that no step is registered under.
+    pub fn unknown<'a>(&self, config: &'a PipelineConfig) -> Option<&'a str> {
+        let pre = config
+            .pre
+            .iter()
+            .find(|n| !self.pre.contains_key(n.as_str()));
+        let post = config
+            .post
+            .iter()
+            .find(|n| !self.post.contains_key(n.as_str()));
+        pre.or(post).map(String::as_str)
+    }
+
+    /// Runs the configured preprocessors over `message`, in order, noting
+    /// which changed it.
+    pub fn preprocess(
+        &self,
+        config: &PipelineConfig,
+        session: &Session,
+        message: &str,
+    ) -> (String, Vec<StepTrace>) {
+        let mut message = message.to_string();
+        let mut steps = Vec::new();
+        for step in config
+            .pre
+            .iter()
+            .filter_map(|name| self.pre.get(name.as_str()))
+        {
+            let processed = step.process(session, &message);
+            steps.push(StepTrace {
+                name: step.name(),
+                changed: processed != message,
+            });
+            message = processed;
+        }
+        (message, steps)
+    }
+
+    /// Runs the configured postprocessors over `reply`, in order, noting
+    /// which changed it.
+    pub fn postprocess(
+        &self,
+        config: &PipelineConfig,
+        session: &Session,
+        reply: &mut Reply,
+    ) -> Vec<StepTrace> {
+        let mut steps = Vec::new();
+        for step in config
+            .post
+            .iter()
+            .filter_map(|name| self.post.get(name.as_str()))
+        {
+            let before = (reply.text.clone(), reply.quick_replies.clone());
+            step.process(session, reply);
+            steps.push(StepTrace {
+                name: step.name(),
+                changed: (&reply.text, &reply.quick_replies) != (&before.0, &before.1),
+            });
+        }
+        steps
+    }
+}
+
+/// Trims every line and collapses runs of spaces and of blank lines, so
+/// pasted text reads the same as typed text.
+struct CollapseWhitespace;
+
+impl MessagePreprocessor for CollapseWhitespace {
+    fn name(&self) -> &'static str {
+        "collapse_whitespace"
+    }
+
+    fn process(&self, _: &Session, message: &str) -> String {
+        let mut out = String::with_capacity(message.len());
+        let mut blank = false;
+        for line in message.lines() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.is_empty() {
+                blank = !out.is_empty();
+                continue;
+            }
+            if !out.is_empty() {
+                out.push_str(if blank { "\n\n" } else { "\n" });
+            }
+            out.push_str(&words.join(" "));
+            blank = false;
+        }
+        out
+    }
+}
+
+/// Shortest and longest card numbers, in digits.
+const CARD_DIGITS: (usize, usize) = (13, 19);
+
+/// Replaces anything that looks like a payment card number, 13 to 19
+/// digits passing the Luhn check with spaces or dashes allowed between
+/// them, before it is stored anywhere.
+struct RedactCardNumbers;
+
+impl MessagePreprocessor for RedactCardNumbers {
+    fn name(&self) -> &'static str {
+        "redact_card_numbers"
+    }
+
+    fn process(&self, _: &Session, message: &str) -> String {
+        let chars: Vec<char> = message.chars().collect();
+        let mut out = String::with_capacity(message.len());
+        let mut i = 0;
+        while i < chars.len() {
+            if let Some(end) = card_number_at(&chars, i) {
+                out.push_str("[card number removed]");
+                i = end;
+            } else {
+                out.push(chars[i]);
+                i += 1;
+            }
+        }
+        out
+    }
+}
+
+/// Where a card number starting at `start` ends, if one does. Only the
+/// longest run of digits and separators is considered.
+fn card_number_at(chars: &[char], start: usize) -> Option<usize> {
+    if !chars[start].is_ascii_digit() || start > 0 && chars[start - 1].is_ascii_digit() {
+        return None;
+    }
+    let mut digits = Vec::new();
+    let mut end = start;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            c if c.is_ascii_digit() => {
+                digits.push(c.to_digit(10).unwrap_or_default());
+                i += 1;
+                end = i;
+            }
+            ' ' | '-' if chars.get(i + 1).is_some_and(char::is_ascii_digit) => i += 1,
+            _ => break,
+        }
+    }
+    let (min, max) = CARD_DIGITS;
+    ((min..=max).contains(&digits.len()) && luhn(&digits)).then_some(end)
+}
+
+fn luhn(digits: &[u32]) -> bool {
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| match (i % 2 == 1, d * 2) {
+            (true, doubled) if doubled > 9 => doubled - 9,
+            (true, doubled) => doubled,
+            (false, _) => d,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+/// Signs every reply with the `signature` text of the conversation's
+/// language.
+struct Signature;
+
+impl ReplyPostprocessor for Signature {
+    fn name(&self) -> &'static str {
+        "signature"
+    }
+
+    fn process(&self, session: &Session, reply: &mut Reply) {
+        // An operator has the conversation and the bot says nothing.
+        if reply.text.is_empty() {
+            return;
+        }
+        reply.text.push_str("\n\n");
+        reply.text.push_str(text(session.language, Text::Signature));
+    }
+}
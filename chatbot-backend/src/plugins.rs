@@ -0,0 +1,97 @@
+//! Intents added by code that uses this crate, without touching `chatbot`.
+//!
+//! An [`IntentHandler`] names its intent, lists the phrases that trigger it
+//! and answers asynchronously, so it can look things up elsewhere first.
+//! Handlers are registered on [`crate::state::AppState::intents`] before
+//! the server starts:
+//!
+//! ```ignore
+//! state.intents.register(Arc::new(JobApplications::new(ats_client)));
+//! ```
+//!
+//! They are only asked about messages sent outside the inquiry flow, where
+//! their phrases take precedence over the built-in rules. A handler that
+//! returns `None` leaves the message to those rules after all.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::{
+    ai::FALLBACK_INTENT,
+    chatbot::Intent,
+    i18n::Language,
+    session::{ConversationState, MessageMetadata, Session},
+    text::{PhraseMatcher, normalize},
+};
+
+/// A message a handler's phrases matched.
+#[derive(Debug, Clone)]
+pub struct IntentRequest {
+    pub session_id: String,
+    pub tenant: String,
+    pub language: Language,
+    pub message: String,
+    pub metadata: Option<MessageMetadata>,
+}
+
+/// What a handler answers.
+#[derive(Debug, Clone, Default)]
+pub struct IntentReply {
+    pub text: String,
+    pub quick_replies: Vec<String>,
+}
+
+#[async_trait]
+pub trait IntentHandler: Send + Sync {
+    /// The intent's name in metrics and events, e.g. `job_application`.
+    fn name(&self) -> &'static str;
+
+    /// Phrases that mean the visitor wants this intent, matched as whole
+    /// words in any case.
+    fn phrases(&self) -> Vec<String>;
+
+    /// Answers `request`; `None` hands it back to the built-in rules.
+    async fn handle(&self, request: &IntentRequest) -> Option<IntentReply>;
+}
+
+#[derive(Default)]
+pub struct IntentRegistry {
+    handlers: Vec<Arc<dyn IntentHandler>>,
+    /// Every handler's phrases, tagged with its index; rebuilt on each
+    /// registration.
+    matcher: Option<PhraseMatcher<usize>>,
+}
+
+impl IntentRegistry {
+    /// Adds a handler. Earlier registrations win when phrases overlap.
+    ///
+    /// # Panics
+    ///
+    /// If the name is taken by a built-in intent or an earlier handler.
+    pub fn register(&mut self, handler: Arc<dyn IntentHandler>) {
+        let name = handler.name();
+        assert!(
+            Intent::from_name(name).is_none() && name != FALLBACK_INTENT,
+            "intent {name:?} is built in"
+        );
+        assert!(
+            self.handlers.iter().all(|h| h.name() != name),
+            "intent {name:?} is registered twice"
+        );
+        self.handlers.push(handler);
+        let phrases: Vec<_> = self.handlers.iter().map(|h| h.phrases()).collect();
+        self.matcher = Some(PhraseMatcher::new(phrases.into_iter().enumerate()));
+    }
+
+    /// The handler for `message`, when the session is somewhere a custom
+    /// intent can start.
+    pub fn find(&self, session: &Session, message: &str) -> Option<Arc<dyn IntentHandler>> {
+        if session.state != ConversationState::Idle || session.handed_off || session.survey_pending
+        {
+            return None;
+        }
+        let index = self.matcher.as_ref()?.first(&normalize(message))?;
+        Some(self.handlers[index].clone())
+    }
+}
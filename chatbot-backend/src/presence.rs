@@ -0,0 +1,129 @@
+//! Which operators are at their desks, from the heartbeats their console
+//! sends to `/admin/operators/{operator}/presence`.
+//!
+//! With `OPERATOR_PRESENCE_TIMEOUT_SECS` set, an operator counts as online
+//! while their latest heartbeat said so and is younger than that. The bot
+//! then only offers visitors a person while someone is online; otherwise
+//! it offers to have the team email them back (see [`crate::chatbot`]).
+//! Without it presence isn't tracked and the team always counts as there.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{error::ApiError, events::now_ms};
+
+/// Operators silent for this long are forgotten.
+const FORGET_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+const MAX_OPERATOR_ID_CHARS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OperatorStatus {
+    Online,
+    /// At the console but not taking conversations.
+    Away,
+    /// Signed off, or no heartbeat in time.
+    Offline,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OperatorPresence {
+    pub operator: String,
+    pub status: OperatorStatus,
+    pub last_seen_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Heartbeat {
+    status: OperatorStatus,
+    at: Instant,
+    at_ms: u64,
+}
+
+#[derive(Default)]
+pub struct Presence {
+    /// How long a heartbeat lasts; `None` when presence isn't tracked.
+    timeout: Option<Duration>,
+    heartbeats: Mutex<HashMap<String, Heartbeat>>,
+}
+
+impl Presence {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout: Some(timeout),
+            heartbeats: Mutex::default(),
+        }
+    }
+
+    pub fn is_tracked(&self) -> bool {
+        self.timeout.is_some()
+    }
+
+    /// Records that `operator` is `status` as of now.
+    pub fn heartbeat(
+        &self,
+        operator: &str,
+        status: OperatorStatus,
+    ) -> Result<OperatorPresence, ApiError> {
+        let valid = !operator.is_empty()
+            && operator.chars().count() <= MAX_OPERATOR_ID_CHARS
+            && !operator.chars().any(char::is_control);
+        if !valid {
+            return Err(ApiError::BadRequest(format!(
+                "operator ids are 1 to {MAX_OPERATOR_ID_CHARS} printable characters"
+            )));
+        }
+        let heartbeat = Heartbeat {
+            status,
+            at: Instant::now(),
+            at_ms: now_ms(),
+        };
+        let mut heartbeats = self.heartbeats.lock().unwrap();
+        heartbeats.retain(|_, h| h.at.elapsed() < FORGET_AFTER);
+        heartbeats.insert(operator.to_string(), heartbeat);
+        Ok(OperatorPresence {
+            operator: operator.to_string(),
+            status,
+            last_seen_ms: heartbeat.at_ms,
+        })
+    }
+
+    fn status(&self, heartbeat: &Heartbeat) -> OperatorStatus {
+        match self.timeout {
+            Some(timeout) if heartbeat.at.elapsed() >= timeout => OperatorStatus::Offline,
+            _ => heartbeat.status,
+        }
+    }
+
+    /// Every operator heard from lately, by id.
+    pub fn operators(&self) -> Vec<OperatorPresence> {
+        let heartbeats = self.heartbeats.lock().unwrap();
+        let mut operators: Vec<OperatorPresence> = heartbeats
+            .iter()
+            .map(|(operator, heartbeat)| OperatorPresence {
+                operator: operator.clone(),
+                status: self.status(heartbeat),
+                last_seen_ms: heartbeat.at_ms,
+            })
+            .collect();
+        operators.sort_by(|a, b| a.operator.cmp(&b.operator));
+        operators
+    }
+
+    /// Whether a visitor asking for a person can get one now.
+    pub fn anyone_online(&self) -> bool {
+        if !self.is_tracked() {
+            return true;
+        }
+        self.heartbeats
+            .lock()
+            .unwrap()
+            .values()
+            .any(|h| self.status(h) == OperatorStatus::Online)
+    }
+}
@@ -0,0 +1,271 @@
+//! The instructions the AI fallback gives the model, editable through
+//! `/admin/prompts` without a release.
+//!
+//! Each prompt has a name and a history of versions. Version 0 is the
+//! built-in text; every `PUT /admin/prompts/{name}` adds the next version
+//! and makes it the one in use, and `POST /admin/prompts/{name}/rollback`
+//! goes back to an earlier one. The fallback is the only call to a model,
+//! so [`FALLBACK`] is the only name for now.
+//!
+//! A prompt may use `{language}`, `{tenant}`, `{page_title}` and
+//! `{page_url}`, filled in for each call; one with no value becomes
+//! empty. The name and version a call was made with are kept in its turn's
+//! trace (see [`crate::trace`]).
+//!
+//! With `PROMPTS_PATH` set the versions are kept in that file and survive
+//! restarts; without it they last until the server stops.
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{durable, error::ApiError, events::now_ms};
+
+/// The instructions for answering what the rules don't understand.
+pub const FALLBACK: &str = "fallback";
+
+pub const VARIABLES: [&str; 4] = ["language", "tenant", "page_title", "page_url"];
+
+/// Longest prompt text, in characters.
+const MAX_PROMPT_CHARS: usize = 4_000;
+
+/// Each prompt's version 0, by name.
+const BUILT_IN: [(&str, &str); 1] = [(
+    FALLBACK,
+    "You answer visitors in a company's website chat. Reply briefly, in {language}. \
+     When you can't help, suggest leaving an inquiry or asking for a person.",
+)];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PromptVersion {
+    pub version: u32,
+    pub text: String,
+    /// When the version was added; 0 for the built-in text.
+    pub created_at_ms: u64,
+}
+
+/// A prompt's versions as `/admin/prompts` shows them, oldest first.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PromptStatus {
+    pub name: String,
+    /// The version calls are made with.
+    pub active: u32,
+    pub versions: Vec<PromptVersion>,
+}
+
+/// Which prompt a call to the model was made with.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PromptUsed {
+    pub name: String,
+    pub version: u32,
+}
+
+/// A prompt with its variables filled in, for one call.
+#[derive(Debug, Clone)]
+pub struct Prompt {
+    pub name: String,
+    pub version: u32,
+    pub text: String,
+}
+
+impl Prompt {
+    pub fn used(&self) -> PromptUsed {
+        PromptUsed {
+            name: self.name.clone(),
+            version: self.version,
+        }
+    }
+}
+
+/// The versions operators added to a prompt; the built-in one isn't kept.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct History {
+    active: u32,
+    versions: Vec<PromptVersion>,
+}
+
+fn built_in(name: &str) -> Option<&'static str> {
+    BUILT_IN
+        .iter()
+        .find(|(known, _)| *known == name)
+        .map(|(_, text)| *text)
+}
+
+/// Refuses texts that are empty, too long or use a variable that is never
+/// filled in.
+fn check(text: &str) -> Result<(), String> {
+    if text.trim().is_empty() {
+        return Err("text is empty".into());
+    }
+    if text.chars().count() > MAX_PROMPT_CHARS {
+        return Err(format!("prompts are at most {MAX_PROMPT_CHARS} characters"));
+    }
+    for placeholder in text.split('{').skip(1).filter_map(|s| s.split_once('}')) {
+        let inner = placeholder.0.trim();
+        let is_variable =
+            !inner.is_empty() && inner.chars().all(|c| c.is_ascii_lowercase() || c == '_');
+        if is_variable && !VARIABLES.contains(&inner) {
+            return Err(format!(
+                "unknown variable {{{inner}}}; use one of {}",
+                VARIABLES.join(", ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+pub struct PromptStore {
+    prompts: RwLock<BTreeMap<String, History>>,
+    /// Where the versions are kept, when `PROMPTS_PATH` is set.
+    path: Option<PathBuf>,
+}
+
+impl PromptStore {
+    /// Loads the versions kept at `path`; a missing file is none.
+    pub fn load(path: PathBuf) -> io::Result<Self> {
+        let prompts: BTreeMap<String, History> = match fs::read(&path) {
+            Ok(raw) => serde_json::from_slice(&raw).map_err(io::Error::other)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        if let Some(name) = prompts.keys().find(|name| built_in(name).is_none()) {
+            return Err(io::Error::other(format!("unknown prompt {name:?}")));
+        }
+        Ok(Self {
+            prompts: RwLock::new(prompts),
+            path: Some(path),
+        })
+    }
+
+    pub fn list(&self) -> Vec<PromptStatus> {
+        let prompts = self.prompts.read().unwrap();
+        BUILT_IN
+            .iter()
+            .map(|(name, text)| status(name, text, prompts.get(*name)))
+            .collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<PromptStatus> {
+        let text = built_in(name)?;
+        Some(status(name, text, self.prompts.read().unwrap().get(name)))
+    }
+
+    /// Adds `text` as the next version of `name` and makes it active.
+    pub fn set(&self, name: &str, text: &str) -> Result<PromptStatus, ApiError> {
+        let built_in = known(name)?;
+        check(text).map_err(ApiError::BadRequest)?;
+        self.update(name, built_in, |history| {
+            let version = history.versions.last().map_or(0, |v| v.version) + 1;
+            history.versions.push(PromptVersion {
+                version,
+                text: text.to_string(),
+                created_at_ms: now_ms(),
+            });
+            history.active = version;
+            Ok(())
+        })
+    }
+
+    /// Makes `version` of `name` the one calls are made with again.
+    pub fn rollback(&self, name: &str, version: u32) -> Result<PromptStatus, ApiError> {
+        let built_in = known(name)?;
+        self.update(name, built_in, |history| {
+            if version != 0 && !history.versions.iter().any(|v| v.version == version) {
+                return Err(ApiError::NotFound(format!(
+                    "prompt {name} has no version {version}"
+                )));
+            }
+            history.active = version;
+            Ok(())
+        })
+    }
+
+    /// Applies `change` to `name`'s history and writes the store through.
+    /// On a write error nothing changes.
+    fn update(
+        &self,
+        name: &str,
+        built_in: &str,
+        change: impl FnOnce(&mut History) -> Result<(), ApiError>,
+    ) -> Result<PromptStatus, ApiError> {
+        let mut prompts = self.prompts.write().unwrap();
+        let mut updated = prompts.clone();
+        let history = updated.entry(name.to_string()).or_default();
+        change(history)?;
+        let status = status(name, built_in, Some(history));
+        if let Some(path) = &self.path
+            && let Err(e) = save(path, &updated)
+        {
+            warn!("cannot write prompts {}: {e}", path.display());
+            return Err(ApiError::Busy("the prompts could not be saved".into()));
+        }
+        *prompts = updated;
+        Ok(status)
+    }
+
+    /// The active version of `name` with `vars` filled in; variables left
+    /// out become empty.
+    pub fn render(&self, name: &str, vars: &[(&str, &str)]) -> Prompt {
+        let prompts = self.prompts.read().unwrap();
+        let history = prompts.get(name);
+        let version = history.map_or(0, |h| h.active);
+        let text = history
+            .and_then(|h| h.versions.iter().find(|v| v.version == version))
+            .map_or_else(|| built_in(name).unwrap_or_default(), |v| v.text.as_str());
+        // One pass, so a value with braces in it is left as it is.
+        let mut filled = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find('{') {
+            filled.push_str(&rest[..start]);
+            let after = &rest[start + 1..];
+            match after.split_once('}') {
+                Some((variable, tail)) if VARIABLES.contains(&variable) => {
+                    let value = vars.iter().find(|(key, _)| *key == variable);
+                    filled.push_str(value.map_or("", |(_, value)| value));
+                    rest = tail;
+                }
+                _ => {
+                    filled.push('{');
+                    rest = after;
+                }
+            }
+        }
+        filled.push_str(rest);
+        Prompt {
+            name: name.to_string(),
+            version,
+            text: filled,
+        }
+    }
+}
+
+fn known(name: &str) -> Result<&'static str, ApiError> {
+    built_in(name).ok_or_else(|| ApiError::NotFound(format!("no prompt named {name}")))
+}
+
+fn status(name: &str, built_in: &str, history: Option<&History>) -> PromptStatus {
+    let original = PromptVersion {
+        version: 0,
+        text: built_in.to_string(),
+        created_at_ms: 0,
+    };
+    PromptStatus {
+        name: name.to_string(),
+        active: history.map_or(0, |h| h.active),
+        versions: std::iter::once(original)
+            .chain(history.into_iter().flat_map(|h| h.versions.iter().cloned()))
+            .collect(),
+    }
+}
+
+fn save(path: &Path, prompts: &BTreeMap<String, History>) -> io::Result<()> {
+    let json = serde_json::to_vec_pretty(prompts).map_err(io::Error::other)?;
+    durable::replace(path, &json)
+}
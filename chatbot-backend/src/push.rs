@@ -0,0 +1,119 @@
+//! Messages the server sends without being asked.
+//!
+//! A [`PushHub`] keeps one broadcast channel per session, created on first
+//! subscription. Visitors' widgets listen on `/chat/events` (server-sent
+//! events); operators watching a session get its transcript through a
+//! second hub. Messages sent while nobody listens are dropped.
+
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tracing::info;
+
+use crate::{
+    events::ConversationEvent,
+    handoff,
+    i18n::{Text, text},
+    session::Role,
+    state::AppState,
+};
+
+/// Messages buffered per listener before slow ones start missing some.
+const CHANNEL_CAPACITY: usize = 16;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PushMessage {
+    /// "Are you still there?" shortly before the session expires.
+    InactivityWarning { text: String },
+    /// Written by an operator who took over the conversation.
+    AgentMessage { text: String, message_id: String },
+    /// The continuation of a reply streamed in parts; see `render`.
+    ReplyPart {
+        text: String,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        quick_replies: Vec<String>,
+        last: bool,
+    },
+    /// "How would you rate this conversation?"; see `survey`.
+    Survey {
+        text: String,
+        quick_replies: Vec<String>,
+    },
+    /// The visitor's place in line for an operator; see `handoff`.
+    QueuePosition { text: String, position: usize },
+}
+
+impl PushMessage {
+    /// Name of the server-sent event carrying this message.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PushMessage::InactivityWarning { .. } => "inactivity_warning",
+            PushMessage::AgentMessage { .. } => "agent_message",
+            PushMessage::ReplyPart { .. } => "reply_part",
+            PushMessage::Survey { .. } => "survey",
+            PushMessage::QueuePosition { .. } => "queue_position",
+        }
+    }
+}
+
+pub struct PushHub<T> {
+    channels: Mutex<HashMap<String, broadcast::Sender<T>>>,
+}
+
+impl<T> Default for PushHub<T> {
+    fn default() -> Self {
+        Self {
+            channels: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T: Clone> PushHub<T> {
+    pub fn subscribe(&self, session_id: &str) -> broadcast::Receiver<T> {
+        let mut channels = self.channels.lock().unwrap();
+        channels
+            .entry(session_id.to_string())
+            .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+            .subscribe()
+    }
+
+    /// Returns how many listeners received the message.
+    pub fn send(&self, session_id: &str, message: T) -> usize {
+        let channels = self.channels.lock().unwrap();
+        channels
+            .get(session_id)
+            .and_then(|tx| tx.send(message).ok())
+            .unwrap_or(0)
+    }
+
+    /// Drops the session's channel, ending its open streams.
+    pub fn forget(&self, session_id: &str) {
+        self.channels.lock().unwrap().remove(session_id);
+    }
+}
+
+/// Warns visitors whose inquiry has been idle for `idle_for`, i.e. some
+/// time before the session TTL runs out. Any message afterwards resets the
+/// idle clock. Nothing is sent in maintenance mode.
+pub async fn warn_idle_sessions(state: &AppState, idle_for: Duration) {
+    if state.maintenance.is_on() {
+        return;
+    }
+    for (session_id, language) in state.sessions.take_idle(idle_for).await {
+        let warning = text(language, Text::StillThere).to_string();
+        let listeners = state.push.send(
+            &session_id,
+            PushMessage::InactivityWarning {
+                text: warning.clone(),
+            },
+        );
+        info!(%session_id, listeners, "inactivity warning sent");
+        handoff::publish(state, &session_id, "", Role::Bot, &warning);
+        state.events.append(
+            &session_id,
+            vec![ConversationEvent::InactivityWarning { text: warning }],
+        );
+    }
+}
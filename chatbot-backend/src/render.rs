@@ -0,0 +1,92 @@
+//! Long replies sent in parts, like someone typing them out.
+//!
+//! Clients that listen on `/chat/events` may ask `/chat` to stream. A
+//! reply longer than [`STREAM_THRESHOLD`] (the services list, the inquiry
+//! summary) is then split at paragraph and line breaks: the response
+//! carries the first part and the rest follow as `reply_part` events, each
+//! after a pause that grows with its length. Quick replies come with the
+//! last part. The transcript keeps the reply whole.
+
+use std::time::Duration;
+
+use crate::{chatbot::Reply, push::PushMessage, state::SharedState};
+
+/// Replies up to this many characters are sent in one piece.
+pub const STREAM_THRESHOLD: usize = 160;
+/// Longest part, unless a single line is longer.
+const MAX_PART_CHARS: usize = 280;
+const DELAY_PER_CHAR: Duration = Duration::from_millis(12);
+const MIN_DELAY: Duration = Duration::from_millis(400);
+const MAX_DELAY: Duration = Duration::from_millis(1_500);
+
+/// Splits `text` into paragraphs, and paragraphs longer than
+/// [`MAX_PART_CHARS`] into runs of whole lines.
+pub fn parts(text: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    for paragraph in text.split("\n\n").filter(|p| !p.trim().is_empty()) {
+        let mut current = String::new();
+        for line in paragraph.lines() {
+            if !current.is_empty()
+                && current.chars().count() + 1 + line.chars().count() > MAX_PART_CHARS
+            {
+                parts.push(std::mem::take(&mut current));
+            }
+            if !current.is_empty() {
+                current.push('\n');
+            }
+            current.push_str(line);
+        }
+        if !current.is_empty() {
+            parts.push(current);
+        }
+    }
+    parts
+}
+
+/// How long to "type" `part` before sending it.
+pub fn typing_delay(part: &str) -> Duration {
+    (DELAY_PER_CHAR * part.chars().count() as u32).clamp(MIN_DELAY, MAX_DELAY)
+}
+
+/// Sends a long `reply` in parts: returns the first part, to answer the
+/// request with, and how many more will follow on the session's push
+/// channel. Short replies are returned whole.
+pub fn stream(state: &SharedState, session_id: &str, reply: Reply) -> (Reply, usize) {
+    if reply.text.chars().count() <= STREAM_THRESHOLD {
+        return (reply, 0);
+    }
+    let mut parts = parts(&reply.text).into_iter();
+    let Some(first) = parts.next() else {
+        return (reply, 0);
+    };
+    let rest: Vec<String> = parts.collect();
+    if rest.is_empty() {
+        return (reply, 0);
+    }
+
+    let more = rest.len();
+    let (state, session_id) = (state.clone(), session_id.to_string());
+    let quick_replies = reply.quick_replies.clone();
+    tokio::spawn(async move {
+        for (i, text) in rest.into_iter().enumerate() {
+            tokio::time::sleep(typing_delay(&text)).await;
+            let last = i + 1 == more;
+            let message = PushMessage::ReplyPart {
+                text,
+                quick_replies: if last {
+                    quick_replies.clone()
+                } else {
+                    Vec::new()
+                },
+                last,
+            };
+            state.push.send(&session_id, message);
+        }
+    });
+    let first = Reply {
+        text: first,
+        quick_replies: Vec::new(),
+        ..reply
+    };
+    (first, more)
+}
@@ -0,0 +1,513 @@
+//! Reports: a confirmed inquiry, handed over to the sales team, and the
+//! weekly analytics report for management (see [`crate::weekly`]).
+//!
+//! An inquiry report is written in the conversation's language, as a PDF
+//! or, for sales to edit before it goes to the client, a Word document. The
+//! built-in PDF fonts only cover Western European letters, so others are
+//! written without their accents (`ł` as `l`).
+//!
+//! Below the inquiry, a lead quality section tells sales how promising the
+//! lead is: its [`lead_score`] and how quickly the visitor answered the
+//! bot, as the median of their reply times.
+
+use std::{
+    borrow::Cow,
+    fmt,
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use docx_rs::{Docx, DocxError, Paragraph, Run, Table, TableCell, TableRow, WidthType};
+use printpdf::{
+    BuiltinFont, IndirectFontRef, Mm, PdfDocument, PdfDocumentReference, PdfLayerReference,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    events::now_ms,
+    handoff::lead_score,
+    i18n::{Text, format_datetime, text},
+    session::{FieldValue, Message, Role, Session},
+    weekly::WeeklyReport,
+};
+
+const PAGE_WIDTH: f32 = 210.0;
+const PAGE_HEIGHT: f32 = 297.0;
+const MARGIN: f32 = 20.0;
+const LABEL_WIDTH: f32 = 40.0;
+const LINE_HEIGHT: f32 = 6.5;
+const FONT_SIZE: f32 = 11.0;
+const TITLE_SIZE: f32 = 18.0;
+const HEADING_SIZE: f32 = 13.0;
+/// Characters per line for wrapped values at `FONT_SIZE` in Helvetica.
+const WRAP_COLUMNS: usize = 80;
+/// Column widths in twentieths of a point, filling an A4 page's text width.
+const DOCX_LABEL_WIDTH: usize = 2_300;
+const DOCX_VALUE_WIDTH: usize = 6_700;
+/// In half-points.
+const DOCX_TITLE_SIZE: usize = 36;
+
+#[derive(Debug)]
+pub enum ReportError {
+    Io(io::Error),
+    Pdf(printpdf::Error),
+    Docx(DocxError),
+}
+
+impl fmt::Display for ReportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReportError::Io(e) => write!(f, "cannot write report: {e}"),
+            ReportError::Pdf(e) => write!(f, "cannot render report: {e}"),
+            ReportError::Docx(e) => write!(f, "cannot render report: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ReportError {}
+
+impl From<io::Error> for ReportError {
+    fn from(e: io::Error) -> Self {
+        ReportError::Io(e)
+    }
+}
+
+impl From<printpdf::Error> for ReportError {
+    fn from(e: printpdf::Error) -> Self {
+        ReportError::Pdf(e)
+    }
+}
+
+/// The format inquiry reports are written in, chosen with `report_format`
+/// in the runtime config.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportFormat {
+    #[default]
+    Pdf,
+    /// A Word document, for sales to edit before sending it to the client.
+    Docx,
+}
+
+impl ReportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ReportFormat::Pdf => "pdf",
+            ReportFormat::Docx => "docx",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ReportFormat::Pdf => "application/pdf",
+            ReportFormat::Docx => {
+                "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+            }
+        }
+    }
+}
+
+/// Where the report for `session_id` is written.
+pub fn report_path(dir: &Path, session_id: &str, format: ReportFormat) -> PathBuf {
+    dir.join(format!("{session_id}.{}", format.extension()))
+}
+
+/// Moves the session's report in `format` aside as
+/// `<session id>.v<n>.<extension>`, numbered from 1, so a new one can take
+/// its place. Returns where it went; `None` if there was no report.
+pub fn archive_report(
+    dir: &Path,
+    session_id: &str,
+    format: ReportFormat,
+) -> io::Result<Option<PathBuf>> {
+    let current = report_path(dir, session_id, format);
+    if !current.exists() {
+        return Ok(None);
+    }
+    let archived = (1..)
+        .map(|n| dir.join(format!("{session_id}.v{n}.{}", format.extension())))
+        .find(|path| !path.exists())
+        .expect("some version number is free");
+    fs::rename(current, &archived)?;
+    Ok(Some(archived))
+}
+
+/// Milliseconds since the Unix epoch at `at`.
+fn wall_clock(at: Instant) -> u64 {
+    now_ms().saturating_sub(at.elapsed().as_millis() as u64)
+}
+
+/// The median of the visitor's reply times, in seconds: from each bot or
+/// operator message to the visitor's next one. `None` when the visitor
+/// never answered one.
+fn median_reply_secs(messages: &[Message]) -> Option<u64> {
+    let mut gaps: Vec<u64> = messages
+        .windows(2)
+        .filter(|pair| pair[0].role != Role::User && pair[1].role == Role::User)
+        .map(|pair| pair[1].at_ms().saturating_sub(pair[0].at_ms()) / 1_000)
+        .collect();
+    gaps.sort_unstable();
+    gaps.get(gaps.len() / 2).copied()
+}
+
+fn format_secs(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{secs} s"),
+        60..3_600 => format!("{} min", secs / 60),
+        _ => format!("{} h", secs / 3_600),
+    }
+}
+
+/// What an inquiry report says, in the conversation's language. Every
+/// format is written from it, as is the preview the visitor sees before
+/// confirming.
+pub struct InquiryReport<'a> {
+    title: &'static str,
+    /// About the conversation.
+    header: [(&'static str, String); 3],
+    /// The captured inquiry data.
+    rows: [(&'static str, &'a str); 5],
+    quality_title: &'static str,
+    /// For sales only, so left out of the visitor's preview.
+    quality: [(&'static str, String); 2],
+}
+
+impl<'a> InquiryReport<'a> {
+    pub fn new(session: &'a Session) -> Self {
+        let lang = session.language;
+        let label = |key| text(lang, key);
+        let header = [
+            (
+                label(Text::ReportLanguage),
+                format!("{} ({})", lang.name(), lang.code()),
+            ),
+            (
+                label(Text::ReportStarted),
+                format_datetime(lang, wall_clock(session.created_at)),
+            ),
+            (
+                label(Text::ReportEnded),
+                format_datetime(lang, wall_clock(session.last_activity)),
+            ),
+        ];
+        let data = &session.data;
+        let budget = match &data.budget {
+            Some(FieldValue::Given(budget)) => budget.as_str(),
+            _ => label(Text::NotSpecified),
+        };
+        let rows = [
+            (
+                label(Text::ReportName),
+                data.name.as_deref().unwrap_or_default(),
+            ),
+            (
+                label(Text::ReportEmail),
+                data.email.as_deref().unwrap_or_default(),
+            ),
+            (
+                label(Text::ReportProject),
+                data.project_details.as_deref().unwrap_or_default(),
+            ),
+            (label(Text::ReportBudget), budget),
+            (label(Text::ReportSession), session.id.as_str()),
+        ];
+        let reply_time = match median_reply_secs(&session.messages) {
+            Some(secs) => format_secs(secs),
+            None => label(Text::NotSpecified).to_string(),
+        };
+        let quality = [
+            (
+                label(Text::ReportLeadScore),
+                format!("{} / 5", lead_score(data)),
+            ),
+            (label(Text::ReportReplyTime), reply_time),
+        ];
+        Self {
+            title: label(Text::ReportTitle),
+            header,
+            rows,
+            quality_title: label(Text::ReportLeadQuality),
+            quality,
+        }
+    }
+
+    /// The lead quality rows, by label.
+    pub fn lead_quality(&self) -> &[(&'static str, String)] {
+        &self.quality
+    }
+
+    /// The report as the visitor previews it, in Markdown: the title, then
+    /// a list per section, without the lead quality section.
+    pub fn markdown(&self) -> String {
+        let mut out = format!("**{}**\n", self.title);
+        let header = self
+            .header
+            .iter()
+            .map(|(label, value)| (*label, value.as_str()));
+        for section in [header.collect::<Vec<_>>(), self.rows.to_vec()] {
+            out.push('\n');
+            for (label, value) in section {
+                // Continuation lines are indented to stay in the item.
+                let value = value.lines().collect::<Vec<_>>().join("\n  ");
+                out.push_str(&format!("- **{label}:** {value}\n"));
+            }
+        }
+        out
+    }
+}
+
+/// Writes `<dir>/<session id>.<extension>` with the captured inquiry data
+/// and returns its path.
+pub fn generate_report(
+    session: &Session,
+    dir: &Path,
+    format: ReportFormat,
+) -> Result<PathBuf, ReportError> {
+    fs::create_dir_all(dir)?;
+    let report = InquiryReport::new(session);
+    let path = report_path(dir, &session.id, format);
+    let file = BufWriter::new(File::create(&path)?);
+    match format {
+        ReportFormat::Pdf => write_pdf(&report, file)?,
+        ReportFormat::Docx => write_docx(&report, file)?,
+    }
+    Ok(path)
+}
+
+fn write_pdf(report: &InquiryReport, mut out: BufWriter<File>) -> Result<(), ReportError> {
+    let mut writer = PageWriter::new(report.title)?;
+    writer.title(report.title);
+    for (label, value) in &report.header {
+        writer.row(label, value);
+    }
+    writer.gap();
+    for (label, value) in report.rows {
+        writer.row(label, value);
+    }
+    writer.gap();
+    writer.heading(report.quality_title);
+    for (label, value) in &report.quality {
+        writer.row(label, value);
+    }
+    writer.doc.save(&mut out)?;
+    Ok(())
+}
+
+/// The same content as the PDF, as a title and a two-column table per
+/// section. Word has the fonts, so nothing is folded.
+fn write_docx(report: &InquiryReport, out: BufWriter<File>) -> Result<(), ReportError> {
+    fn pairs<'a>(rows: &'a [(&'static str, String)]) -> Vec<(&'static str, &'a str)> {
+        rows.iter()
+            .map(|(label, value)| (*label, value.as_str()))
+            .collect()
+    }
+    let table = |rows: Vec<(&str, &str)>| {
+        let rows = rows
+            .into_iter()
+            .map(|(label, value)| {
+                let mut value_cell = TableCell::new().width(DOCX_VALUE_WIDTH, WidthType::Dxa);
+                for line in value.lines() {
+                    value_cell = value_cell
+                        .add_paragraph(Paragraph::new().add_run(Run::new().add_text(line)));
+                }
+                TableRow::new(vec![
+                    TableCell::new()
+                        .width(DOCX_LABEL_WIDTH, WidthType::Dxa)
+                        .add_paragraph(Paragraph::new().add_run(Run::new().add_text(label).bold())),
+                    value_cell,
+                ])
+            })
+            .collect();
+        Table::new(rows).set_grid(vec![DOCX_LABEL_WIDTH, DOCX_VALUE_WIDTH])
+    };
+    Docx::new()
+        .add_paragraph(
+            Paragraph::new().add_run(
+                Run::new()
+                    .add_text(report.title)
+                    .bold()
+                    .size(DOCX_TITLE_SIZE),
+            ),
+        )
+        .add_table(table(pairs(&report.header)))
+        .add_paragraph(Paragraph::new())
+        .add_table(table(report.rows.to_vec()))
+        .add_paragraph(Paragraph::new())
+        .add_paragraph(Paragraph::new().add_run(Run::new().add_text(report.quality_title).bold()))
+        .add_table(table(pairs(&report.quality)))
+        .build()
+        .pack(out)
+        .map_err(|e| ReportError::Docx(e.into()))
+}
+
+/// The weekly analytics report, as PDF bytes.
+pub fn generate_weekly_pdf(report: &WeeklyReport) -> Result<Vec<u8>, ReportError> {
+    let title = "Weekly chatbot report";
+    let mut writer = PageWriter::new(title)?;
+    writer.title(title);
+    writer.row("Period", &report.period());
+    writer.row("Messages", &report.messages.to_string());
+    writer.gap();
+
+    writer.heading("Conversion funnel");
+    for stage in &report.funnel {
+        let share = match report.conversations {
+            0 => String::new(),
+            n => format!(" ({:.0}%)", stage.count as f64 * 100.0 / n as f64),
+        };
+        writer.row(stage.label, &format!("{}{share}", stage.count));
+    }
+    writer.heading("Top intents");
+    for (intent, n) in &report.top_intents {
+        writer.row(intent, &n.to_string());
+    }
+    writer.heading("Top keywords");
+    for (keyword, n) in &report.top_keywords {
+        writer.row(keyword, &n.to_string());
+    }
+    Ok(writer.doc.save_to_bytes()?)
+}
+
+/// Lays text out top to bottom, starting a new page when one fills up.
+struct PageWriter {
+    doc: PdfDocumentReference,
+    layer: PdfLayerReference,
+    regular: IndirectFontRef,
+    bold: IndirectFontRef,
+    y: f32,
+}
+
+impl PageWriter {
+    fn new(title: &str) -> Result<Self, ReportError> {
+        let (doc, page, layer) = PdfDocument::new(title, Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Text");
+        let layer = doc.get_page(page).get_layer(layer);
+        let regular = doc.add_builtin_font(BuiltinFont::Helvetica)?;
+        let bold = doc.add_builtin_font(BuiltinFont::HelveticaBold)?;
+        Ok(Self {
+            doc,
+            layer,
+            regular,
+            bold,
+            y: PAGE_HEIGHT - MARGIN,
+        })
+    }
+
+    fn title(&mut self, title: &str) {
+        self.layer.use_text(
+            printable(title),
+            TITLE_SIZE,
+            Mm(MARGIN),
+            Mm(self.y),
+            &self.bold,
+        );
+        self.y -= LINE_HEIGHT * 2.5;
+    }
+
+    fn heading(&mut self, heading: &str) {
+        self.ensure_space(LINE_HEIGHT * 3.0);
+        self.y -= LINE_HEIGHT / 2.0;
+        self.layer.use_text(
+            printable(heading),
+            HEADING_SIZE,
+            Mm(MARGIN),
+            Mm(self.y),
+            &self.bold,
+        );
+        self.y -= LINE_HEIGHT * 1.5;
+    }
+
+    fn gap(&mut self) {
+        self.y -= LINE_HEIGHT;
+    }
+
+    fn row(&mut self, label: &str, value: &str) {
+        let lines = wrap(value, WRAP_COLUMNS);
+        self.ensure_space(LINE_HEIGHT);
+        self.layer.use_text(
+            printable(label),
+            FONT_SIZE,
+            Mm(MARGIN),
+            Mm(self.y),
+            &self.bold,
+        );
+        for line in lines {
+            self.ensure_space(LINE_HEIGHT);
+            self.layer.use_text(
+                printable(&line),
+                FONT_SIZE,
+                Mm(MARGIN + LABEL_WIDTH),
+                Mm(self.y),
+                &self.regular,
+            );
+            self.y -= LINE_HEIGHT;
+        }
+        self.y -= LINE_HEIGHT / 2.0;
+    }
+
+    fn ensure_space(&mut self, needed: f32) {
+        if self.y - needed < MARGIN {
+            let (page, layer) = self.doc.add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Text");
+            self.layer = self.doc.get_page(page).get_layer(layer);
+            self.y = PAGE_HEIGHT - MARGIN;
+        }
+    }
+}
+
+/// `text` with the letters the built-in fonts lack replaced by their
+/// unaccented forms; other characters they lack would be dropped.
+fn printable(text: &str) -> Cow<'_, str> {
+    const FOLDED: [(char, char); 18] = [
+        ('ą', 'a'),
+        ('ć', 'c'),
+        ('ę', 'e'),
+        ('ł', 'l'),
+        ('ń', 'n'),
+        ('ś', 's'),
+        ('ź', 'z'),
+        ('ż', 'z'),
+        ('Ą', 'A'),
+        ('Ć', 'C'),
+        ('Ę', 'E'),
+        ('Ł', 'L'),
+        ('Ń', 'N'),
+        ('Ś', 'S'),
+        ('Ź', 'Z'),
+        ('Ż', 'Z'),
+        ('ő', 'o'),
+        ('ű', 'u'),
+    ];
+    let fold = |c: char| {
+        FOLDED
+            .iter()
+            .find(|(from, _)| *from == c)
+            .map(|(_, to)| *to)
+    };
+    if !text.chars().any(|c| fold(c).is_some()) {
+        return Cow::Borrowed(text);
+    }
+    Cow::Owned(text.chars().map(|c| fold(c).unwrap_or(c)).collect())
+}
+
+/// Greedy word wrap; words longer than `columns` get a line of their own.
+fn wrap(text: &str, columns: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > columns {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+        lines.push(line);
+    }
+    if lines.is_empty() {
+        lines.push(String::new());
+    }
+    lines
+}
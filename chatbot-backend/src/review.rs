@@ -0,0 +1,327 @@
+//! A sample of finished conversations for people to grade, under
+//! `/admin/review`.
+//!
+//! With `REVIEW_SAMPLE_PERCENT` set, that share of the sessions that
+//! expire with something said in them joins the queue, each visitor
+//! message next to the intent the bot read and the reply it gave. A
+//! reviewer marks every intent right or names the right one, and scores
+//! every reply from 1 to 5. With `REVIEW_DATASET` set, graded messages are
+//! appended to that file in the format of `eval/intents.jsonl`, so what
+//! reviewers find grows the dataset `eval-intents` measures against.
+//! Corrected intents are also learned; see [`crate::training`].
+
+use std::{
+    collections::VecDeque,
+    fs::OpenOptions,
+    io::{self, Write},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    chatbot::Intent,
+    error::ApiError,
+    eval::Sample,
+    events::{ConversationEvent, StoredEvent, now_ms},
+    i18n::Language,
+    session::Session,
+    training::learnable,
+};
+
+/// Most conversations the queue holds; when full, the oldest goes,
+/// graded or not.
+const MAX_ITEMS: usize = 1_000;
+/// Reply scores, worst to best.
+const QUALITY_RANGE: std::ops::RangeInclusive<u8> = 1..=5;
+
+/// A visitor message and how the bot took it.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReviewTurn {
+    pub message_id: String,
+    pub text: String,
+    pub intent: String,
+    pub reply: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReviewItem {
+    pub id: String,
+    pub session_id: String,
+    pub tenant: String,
+    pub language: Language,
+    pub sampled_at_ms: u64,
+    pub turns: Vec<ReviewTurn>,
+    pub grade: Option<ReviewGrade>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TurnGrade {
+    pub message_id: String,
+    pub intent_correct: bool,
+    /// The intent the message should have had; required unless
+    /// `intent_correct`.
+    #[serde(default)]
+    pub expected_intent: Option<String>,
+    /// 1 to 5.
+    pub reply_quality: u8,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReviewGrade {
+    #[serde(default)]
+    pub reviewer: Option<String>,
+    /// One per turn, in any order.
+    pub turns: Vec<TurnGrade>,
+    #[serde(default)]
+    pub note: Option<String>,
+    #[serde(default)]
+    pub graded_at_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewStatus {
+    #[default]
+    Pending,
+    Graded,
+    All,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReviewSummary {
+    pub pending: usize,
+    pub graded: usize,
+    pub turns_graded: usize,
+    /// Share of graded turns whose intent was right.
+    pub intent_accuracy: Option<f64>,
+    pub mean_reply_quality: Option<f64>,
+}
+
+/// Pairs each visitor message in `events` with the reply that answered it.
+/// Messages an operator or nobody answered are left out.
+pub fn turns(events: &[StoredEvent]) -> Vec<ReviewTurn> {
+    let mut turns = Vec::new();
+    let mut pending: Option<(String, String)> = None;
+    for stored in events {
+        match &stored.event {
+            ConversationEvent::MessageReceived {
+                text, message_id, ..
+            } => pending = Some((message_id.clone(), text.clone())),
+            ConversationEvent::MessageEdited { message_id, text } => {
+                if let Some((id, pending_text)) = &mut pending
+                    && id == message_id
+                {
+                    *pending_text = text.clone();
+                }
+            }
+            ConversationEvent::ReplySent { text, intent, .. } => {
+                if let Some((message_id, message)) = pending.take()
+                    && !text.is_empty()
+                {
+                    turns.push(ReviewTurn {
+                        message_id,
+                        text: message,
+                        intent: intent.clone(),
+                        reply: text.clone(),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    turns
+}
+
+pub struct ReviewQueue {
+    /// Share of finished sessions sampled, from 0 to 1.
+    rate: f64,
+    dataset: Option<PathBuf>,
+    items: Mutex<VecDeque<ReviewItem>>,
+}
+
+impl Default for ReviewQueue {
+    fn default() -> Self {
+        Self::new(0.0, None)
+    }
+}
+
+impl ReviewQueue {
+    /// Samples `percent` of finished sessions, appending graded messages to
+    /// `dataset` when given.
+    pub fn new(percent: f64, dataset: Option<PathBuf>) -> Self {
+        Self {
+            rate: (percent / 100.0).clamp(0.0, 1.0),
+            dataset,
+            items: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Queues `session` for review if it is drawn, given its `events`.
+    pub fn offer(&self, session: &Session, events: &[StoredEvent]) {
+        if self.rate <= 0.0 {
+            return;
+        }
+        // The low 53 bits of a v4 uuid are random; as a draw from [0, 1).
+        let bits = uuid::Uuid::new_v4().as_u128() as u64 & ((1 << 53) - 1);
+        let draw = bits as f64 / (1u64 << 53) as f64;
+        if draw >= self.rate {
+            return;
+        }
+        let turns = turns(events);
+        if turns.is_empty() {
+            return;
+        }
+        let mut items = self.items.lock().unwrap();
+        if items.len() >= MAX_ITEMS {
+            items.pop_front();
+        }
+        items.push_back(ReviewItem {
+            id: uuid::Uuid::new_v4().to_string(),
+            session_id: session.id.clone(),
+            tenant: session.tenant.clone(),
+            language: session.language,
+            sampled_at_ms: now_ms(),
+            turns,
+            grade: None,
+        });
+    }
+
+    /// The queued conversations with `status`, oldest first.
+    pub fn list(&self, status: ReviewStatus) -> Vec<ReviewItem> {
+        self.items
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|item| match status {
+                ReviewStatus::Pending => item.grade.is_none(),
+                ReviewStatus::Graded => item.grade.is_some(),
+                ReviewStatus::All => true,
+            })
+            .cloned()
+            .collect()
+    }
+
+    pub fn get(&self, id: &str) -> Option<ReviewItem> {
+        self.items
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|item| item.id == id)
+            .cloned()
+    }
+
+    /// How the graded conversations did.
+    pub fn summary(&self) -> ReviewSummary {
+        let items = self.items.lock().unwrap();
+        let mut summary = ReviewSummary::default();
+        let (mut correct, mut quality) = (0usize, 0u64);
+        for item in items.iter() {
+            let Some(grade) = &item.grade else {
+                summary.pending += 1;
+                continue;
+            };
+            summary.graded += 1;
+            summary.turns_graded += grade.turns.len();
+            correct += grade.turns.iter().filter(|t| t.intent_correct).count();
+            quality += grade
+                .turns
+                .iter()
+                .map(|t| u64::from(t.reply_quality))
+                .sum::<u64>();
+        }
+        if summary.turns_graded > 0 {
+            let turns = summary.turns_graded as f64;
+            summary.intent_accuracy = Some(correct as f64 / turns);
+            summary.mean_reply_quality = Some(quality as f64 / turns);
+        }
+        summary
+    }
+
+    /// Records `grade` for the queued conversation `id`, which must grade
+    /// every turn once, and adds its messages to the dataset.
+    pub fn grade(&self, id: &str, mut grade: ReviewGrade) -> Result<ReviewItem, ApiError> {
+        let mut items = self.items.lock().unwrap();
+        let item = items
+            .iter_mut()
+            .find(|item| item.id == id)
+            .ok_or_else(|| ApiError::NotFound(format!("no review item {id}")))?;
+        if item.grade.is_some() {
+            return Err(ApiError::BadRequest(format!("{id} is graded already")));
+        }
+        let mut samples = Vec::with_capacity(item.turns.len());
+        for turn in &item.turns {
+            let mut graded = grade
+                .turns
+                .iter()
+                .filter(|g| g.message_id == turn.message_id);
+            let (Some(graded), None) = (graded.next(), graded.next()) else {
+                return Err(ApiError::BadRequest(format!(
+                    "grade message {:?} exactly once",
+                    turn.message_id
+                )));
+            };
+            if !QUALITY_RANGE.contains(&graded.reply_quality) {
+                return Err(ApiError::BadRequest(format!(
+                    "reply_quality must be from {} to {}",
+                    QUALITY_RANGE.start(),
+                    QUALITY_RANGE.end()
+                )));
+            }
+            let intent = if graded.intent_correct {
+                turn.intent.as_str()
+            } else {
+                graded.expected_intent.as_deref().ok_or_else(|| {
+                    ApiError::BadRequest(format!(
+                        "give expected_intent for message {:?}",
+                        turn.message_id
+                    ))
+                })?
+            };
+            match Intent::from_name(intent) {
+                Some(intent) if learnable(intent) => samples.push(Sample {
+                    text: turn.text.clone(),
+                    intent: intent.as_str().to_string(),
+                }),
+                Some(_) => {}
+                None if graded.intent_correct => {}
+                None => {
+                    return Err(ApiError::BadRequest(format!("unknown intent {intent:?}")));
+                }
+            }
+        }
+        if grade.turns.len() != item.turns.len() {
+            return Err(ApiError::BadRequest(
+                "grade only the messages of this conversation".into(),
+            ));
+        }
+        grade.graded_at_ms = now_ms();
+        item.grade = Some(grade);
+        let item = item.clone();
+        drop(items);
+        if let Some(path) = &self.dataset
+            && let Err(e) = append(path, &samples)
+        {
+            warn!("cannot add to {}: {e}", path.display());
+        }
+        info!(item = %item.id, samples = samples.len(), "conversation graded");
+        Ok(item)
+    }
+}
+
+fn append(path: &PathBuf, samples: &[Sample]) -> io::Result<()> {
+    let mut lines = String::new();
+    for sample in samples {
+        lines.push_str(&serde_json::to_string(sample).expect("samples serialize"));
+        lines.push('\n');
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(lines.as_bytes())
+}
@@ -0,0 +1,766 @@
+//! Operator endpoints under `/admin`, guarded by the `ADMIN_TOKEN` bearer
+//! token.
+
+use std::collections::BTreeMap;
+
+use axum::{
+    Json, Router,
+    extract::{Path, Query, Request, State},
+    http::{HeaderMap, header::AUTHORIZATION},
+    middleware::{self, Next},
+    response::Response,
+    routing::{delete, get, post, put},
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    ai::{AiSettings, AiStatus},
+    analytics::UnansweredQuestion,
+    blocklist::{BlockKind, BlockResponse, BlocklistEntries},
+    config::{AuditEntry, RuntimeConfig},
+    error::ApiError,
+    events::{self, ConversationEvent, ConversationProjection, StoredEvent, now_ms},
+    flow,
+    graphql::SCHEMA,
+    handoff::{self, QueuedSession, SuggestedReply},
+    i18n::Language,
+    maintenance::MaintenanceStatus,
+    metrics::MetricsSnapshot,
+    outbox::{JobStatus, OutboxJob},
+    presence::{OperatorPresence, OperatorStatus},
+    prompts::PromptStatus,
+    service::{self, RegeneratedReport},
+    session::{MemoryUsage, PurgeStats, RestoreError, SessionSettings},
+    state::SharedState,
+    survey::SatisfactionSummary,
+    tenant::{ReplyOverrides, Theme},
+    trace::{MAX_TRACES_PER_SESSION, TurnTrace},
+};
+
+pub fn router(state: SharedState) -> Router<SharedState> {
+    Router::new()
+        .route(
+            "/sessions/settings",
+            get(session_settings).put(set_session_settings),
+        )
+        .route("/sessions:bulk-delete", post(super::bulk::delete_sessions))
+        .route("/sessions/{id}", delete(delete_session))
+        .route("/sessions/{id}/restore", post(restore_session))
+        .route("/sessions/{id}/merge", post(merge_session))
+        .route("/sessions/{id}/events", get(session_events))
+        .route("/sessions/{id}/replay", get(replay_session))
+        .route("/sessions/{id}/turns/{turn}/trace", get(turn_trace))
+        .route("/sessions/{id}/suggest-reply", post(suggest_reply))
+        .route("/sessions/{id}/report:regenerate", post(regenerate_report))
+        .route("/sessions/{id}/watch", get(super::watch::watch_session))
+        .route("/handoff/queue", get(handoff_queue))
+        .route("/operators", get(operators))
+        .route("/operators/{operator}/presence", put(set_presence))
+        .route("/leads:bulk-tag", post(super::bulk::tag_leads))
+        .route("/stats", get(stats))
+        .route("/stats/purges", get(purges))
+        .route("/graphql", post(graphql))
+        .route("/config", get(config))
+        .route("/bots", get(bots))
+        .route("/bots/{bot_id}/config", get(bot_config))
+        .route("/maintenance", get(maintenance).put(set_maintenance))
+        .route("/blocklist", get(blocklist))
+        .route("/blocklist/response", put(set_block_response))
+        .route(
+            "/blocklist/{kind}/{value}",
+            put(add_block).delete(remove_block),
+        )
+        .route("/tenants/{tenant}/replies", get(tenant_replies))
+        .route(
+            "/tenants/{tenant}/replies/{intent}",
+            put(set_tenant_reply).delete(remove_tenant_reply),
+        )
+        .route(
+            "/tenants/{tenant}/theme",
+            get(tenant_theme)
+                .put(set_tenant_theme)
+                .delete(remove_tenant_theme),
+        )
+        .route(
+            "/tenants/{tenant}/ai",
+            get(tenant_ai).put(set_tenant_ai).delete(remove_tenant_ai),
+        )
+        .route("/prompts", get(prompts))
+        .route("/prompts/{name}", get(prompt).put(set_prompt))
+        .route("/prompts/{name}/rollback", post(rollback_prompt))
+        .route("/synonyms", get(synonyms))
+        .route("/synonyms/{alias}", put(set_synonym).delete(remove_synonym))
+        .route("/flow/diagram", get(flow_diagram))
+        .route("/analytics/unanswered", get(unanswered))
+        .route("/analytics/satisfaction", get(satisfaction))
+        .route("/review", get(super::review::list))
+        .route("/review/summary", get(super::review::summary))
+        .route("/review/{id}", get(super::review::get))
+        .route("/review/{id}/grade", post(super::review::grade))
+        .route("/deliveries", get(list_deliveries))
+        .route("/deliveries/{id}", get(get_delivery))
+        .route("/deliveries/{id}/redeliver", post(redeliver))
+        .route_layer(middleware::from_fn_with_state(state, require_admin))
+}
+
+async fn require_admin(
+    State(state): State<SharedState>,
+    req: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let Some(expected) = state.admin_token.as_deref() else {
+        return Err(ApiError::Forbidden("admin API is disabled".into()));
+    };
+    match bearer_token(req.headers()) {
+        Some(token) if constant_time_eq(token.as_bytes(), expected.as_bytes()) => {
+            Ok(next.run(req).await)
+        }
+        _ => Err(ApiError::Unauthorized),
+    }
+}
+
+/// The token of an `Authorization: Bearer <token>` header.
+pub(crate) fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+}
+
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn events_or_404(state: &SharedState, id: &str) -> Result<Vec<StoredEvent>, ApiError> {
+    let events = state.events.session_events(id);
+    if events.is_empty() {
+        return Err(ApiError::NotFound(format!("no events for session {id}")));
+    }
+    Ok(events)
+}
+
+/// The raw event log of one session.
+async fn session_events(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<StoredEvent>>, ApiError> {
+    events_or_404(&state, &id).map(Json)
+}
+
+/// The session's state as rebuilt from its events.
+async fn replay_session(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> Result<Json<ConversationProjection>, ApiError> {
+    events_or_404(&state, &id).map(|events| Json(events::project(&events)))
+}
+
+/// What happened inside turn `turn` of the session, counting from 1.
+async fn turn_trace(
+    State(state): State<SharedState>,
+    Path((id, turn)): Path<(String, usize)>,
+) -> Result<Json<TurnTrace>, ApiError> {
+    state.traces.get(&id, turn).map(Json).ok_or_else(|| {
+        ApiError::NotFound(format!(
+            "no trace of turn {turn} in session {id}; the latest {MAX_TRACES_PER_SESSION} turns of live sessions are kept"
+        ))
+    })
+}
+
+/// A draft answer to the visitor's latest message for the operator to
+/// edit before sending.
+async fn suggest_reply(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> Result<Json<SuggestedReply>, ApiError> {
+    handoff::suggest_reply(&state, &id).await.map(Json)
+}
+
+async fn session_settings(State(state): State<SharedState>) -> Json<SessionSettings> {
+    Json(state.sessions.settings())
+}
+
+/// Replaces the session TTL, purge interval and caps, e.g.
+/// `{"ttl_secs": 1800, "purge_interval_secs": 60, "max_sessions": 5000,
+/// "max_sessions_per_ip": null, "max_conversation_turns": 100,
+/// "max_conversation_secs": null}`. They start at `SESSION_TTL_SECS`,
+/// `SESSION_PURGE_INTERVAL_SECS`, `MAX_SESSIONS`, `MAX_SESSIONS_PER_IP`,
+/// `MAX_CONVERSATION_TURNS` and `MAX_CONVERSATION_SECS` and are lost on
+/// restart.
+async fn set_session_settings(
+    State(state): State<SharedState>,
+    Json(settings): Json<SessionSettings>,
+) -> Result<Json<SessionSettings>, ApiError> {
+    settings.validate().map_err(ApiError::BadRequest)?;
+    state.sessions.set_settings(settings);
+    info!(?settings, "session settings changed");
+    Ok(Json(settings))
+}
+
+#[derive(Debug, Serialize)]
+struct DeletedSession {
+    session_id: String,
+    /// Until when `/admin/sessions/{id}/restore` can bring it back.
+    restorable_until_ms: u64,
+}
+
+/// Deletes a session: the visitor's next message starts a new one, and
+/// their event stream and operators watching are disconnected. The
+/// session can be restored during the grace period
+/// (`SESSION_RESTORE_GRACE_SECS`).
+async fn delete_session(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> Result<Json<DeletedSession>, ApiError> {
+    if !delete_one(&state, &id).await {
+        return Err(ApiError::NotFound(format!("no session {id}")));
+    }
+    let grace = state.sessions.restore_grace().as_millis() as u64;
+    Ok(Json(DeletedSession {
+        session_id: id,
+        restorable_until_ms: now_ms() + grace,
+    }))
+}
+
+/// Takes session `id` out of service; `false` if there is none.
+pub(super) async fn delete_one(state: &SharedState, id: &str) -> bool {
+    if !state.sessions.remove_session(id).await {
+        return false;
+    }
+    state
+        .events
+        .append(id, vec![ConversationEvent::SessionDeleted]);
+    state.push.forget(id);
+    state.watchers.forget(id);
+    state.traces.forget(id);
+    true
+}
+
+/// Brings back a deleted session as it was, within the grace period.
+async fn restore_session(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> Result<Json<ConversationProjection>, ApiError> {
+    state
+        .sessions
+        .restore_session(&id)
+        .await
+        .map_err(|e| match e {
+            RestoreError::NotDeleted => {
+                ApiError::NotFound(format!("no deleted session {id} to restore"))
+            }
+            RestoreError::Capacity => ApiError::Busy("session capacity reached".into()),
+        })?;
+    state
+        .events
+        .append(&id, vec![ConversationEvent::SessionRestored]);
+    Ok(Json(events::project(&state.events.session_events(&id))))
+}
+
+#[derive(Debug, Deserialize)]
+struct MergeRequest {
+    /// The session to merge into the one in the path; its id keeps
+    /// working and leads to the merged session.
+    from: String,
+}
+
+/// Merges two sessions of the same visitor into the one in the path.
+async fn merge_session(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+    Json(req): Json<MergeRequest>,
+) -> Result<Json<ConversationProjection>, ApiError> {
+    service::merge_sessions(&state, &req.from, &id)
+        .await
+        .map(Json)
+}
+
+/// Rewrites the session's inquiry report, e.g. after a fix to the report
+/// texts; the previous file is kept as a numbered version.
+async fn regenerate_report(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> Result<Json<RegeneratedReport>, ApiError> {
+    service::regenerate_report(&state, &id).await.map(Json)
+}
+
+#[derive(Debug, Serialize)]
+struct Stats {
+    memory: MemoryUsage,
+    metrics: MetricsSnapshot,
+    /// Tenants with counters; pass one as `?tenant=` for its share.
+    tenants: Vec<String>,
+    queued_deliveries: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct StatsQuery {
+    tenant: Option<String>,
+}
+
+/// Usage counters, for all tenants or one, and the session store's
+/// footprint, for tuning the TTL and session caps.
+async fn stats(
+    State(state): State<SharedState>,
+    Query(query): Query<StatsQuery>,
+) -> Result<Json<Stats>, ApiError> {
+    let metrics = match &query.tenant {
+        Some(tenant) => state
+            .metrics
+            .tenant_snapshot(tenant)
+            .ok_or_else(|| ApiError::NotFound(format!("no metrics for tenant {tenant}")))?,
+        None => state.metrics.snapshot(),
+    };
+    Ok(Json(Stats {
+        memory: state.sessions.memory_usage().await,
+        metrics,
+        tenants: state.metrics.tenants(),
+        queued_deliveries: state.outbox.len(),
+    }))
+}
+
+/// The latest runs of the session purge, newest first.
+async fn purges(State(state): State<SharedState>) -> Json<Vec<PurgeStats>> {
+    Json(state.sessions.purge_history())
+}
+
+/// Runs a GraphQL query against sessions, leads, metrics and analytics.
+async fn graphql(
+    State(state): State<SharedState>,
+    Json(request): Json<async_graphql::Request>,
+) -> Json<async_graphql::Response> {
+    Json(SCHEMA.execute(request.data(state)).await)
+}
+
+#[derive(Debug, Serialize)]
+struct ConfigView {
+    config: RuntimeConfig,
+    audit: Vec<AuditEntry>,
+}
+
+/// The runtime configuration in force and the history of reloads.
+async fn config(State(state): State<SharedState>) -> Json<ConfigView> {
+    Json(ConfigView {
+        config: state.config.current(),
+        audit: state.config.audit_log(),
+    })
+}
+
+/// Visitors waiting for an operator, the next to take first.
+async fn handoff_queue(State(state): State<SharedState>) -> Json<Vec<QueuedSession>> {
+    Json(handoff::queue(&state))
+}
+
+async fn operators(State(state): State<SharedState>) -> Json<Vec<OperatorPresence>> {
+    Json(state.presence.operators())
+}
+
+#[derive(Deserialize)]
+struct PresenceUpdate {
+    status: OperatorStatus,
+}
+
+/// An operator console's heartbeat; send it well within
+/// `OPERATOR_PRESENCE_TIMEOUT_SECS`, and `offline` when signing off.
+async fn set_presence(
+    State(state): State<SharedState>,
+    Path(operator): Path<String>,
+    Json(update): Json<PresenceUpdate>,
+) -> Result<Json<OperatorPresence>, ApiError> {
+    state.presence.heartbeat(&operator, update.status).map(Json)
+}
+
+/// The ids of the bots with flows of their own; see [`crate::bots`].
+async fn bots(State(state): State<SharedState>) -> Json<Vec<String>> {
+    Json(state.bots.ids().map(str::to_string).collect())
+}
+
+/// One bot's runtime configuration and the history of its reloads.
+async fn bot_config(
+    State(state): State<SharedState>,
+    Path(bot_id): Path<String>,
+) -> Result<Json<ConfigView>, ApiError> {
+    let bot = state
+        .bots
+        .get(&bot_id)
+        .ok_or_else(|| ApiError::NotFound(format!("no bot {bot_id:?}")))?;
+    Ok(Json(ConfigView {
+        config: bot.config.current(),
+        audit: bot.config.audit_log(),
+    }))
+}
+
+async fn maintenance(State(state): State<SharedState>) -> Json<MaintenanceStatus> {
+    Json(state.maintenance.status())
+}
+
+#[derive(Debug, Deserialize)]
+struct MaintenanceRequest {
+    enabled: bool,
+}
+
+/// Puts the bot into maintenance mode or lifts it.
+async fn set_maintenance(
+    State(state): State<SharedState>,
+    Json(request): Json<MaintenanceRequest>,
+) -> Json<MaintenanceStatus> {
+    Json(service::set_maintenance(&state, request.enabled).await)
+}
+
+async fn blocklist(State(state): State<SharedState>) -> Json<BlocklistEntries> {
+    Json(state.blocklist.entries())
+}
+
+#[derive(Debug, Deserialize)]
+struct BlockResponseRequest {
+    response: BlockResponse,
+}
+
+/// Chooses between dropping blocked requests silently and refusing them.
+async fn set_block_response(
+    State(state): State<SharedState>,
+    Json(request): Json<BlockResponseRequest>,
+) -> Json<BlocklistEntries> {
+    state.blocklist.set_response(request.response);
+    Json(state.blocklist.entries())
+}
+
+/// Blocks an IP address, visitor id or email domain, e.g.
+/// `PUT /admin/blocklist/email_domain/spam.example`.
+async fn add_block(
+    State(state): State<SharedState>,
+    Path((kind, value)): Path<(BlockKind, String)>,
+) -> Result<Json<BlocklistEntries>, ApiError> {
+    if state.blocklist.add(kind, &value)? {
+        info!(kind = kind.as_str(), %value, "blocklist entry added");
+    }
+    Ok(Json(state.blocklist.entries()))
+}
+
+async fn remove_block(
+    State(state): State<SharedState>,
+    Path((kind, value)): Path<(BlockKind, String)>,
+) -> Result<Json<BlocklistEntries>, ApiError> {
+    if !state.blocklist.remove(kind, &value)? {
+        return Err(ApiError::NotFound(format!(
+            "{value} is not on the {} blocklist",
+            kind.as_str()
+        )));
+    }
+    info!(kind = kind.as_str(), %value, "blocklist entry removed");
+    Ok(Json(state.blocklist.entries()))
+}
+
+async fn tenant_replies(
+    State(state): State<SharedState>,
+    Path(tenant): Path<String>,
+) -> Json<ReplyOverrides> {
+    Json(ReplyOverrides::clone(
+        &state.tenant_replies.for_tenant(&tenant),
+    ))
+}
+
+/// Replaces a tenant's reply to one intent, with a text per language code,
+/// e.g. `PUT /admin/tenants/acme/replies/pricing` with
+/// `{"en": "Our plans start at $99.", "pl": "..."}`.
+async fn set_tenant_reply(
+    State(state): State<SharedState>,
+    Path((tenant, intent)): Path<(String, String)>,
+    Json(replies): Json<BTreeMap<Language, String>>,
+) -> Result<Json<ReplyOverrides>, ApiError> {
+    let overrides = state.tenant_replies.set(&tenant, &intent, replies)?;
+    info!(%tenant, %intent, "tenant reply overridden");
+    Ok(Json(overrides))
+}
+
+async fn remove_tenant_reply(
+    State(state): State<SharedState>,
+    Path((tenant, intent)): Path<(String, String)>,
+) -> Result<Json<ReplyOverrides>, ApiError> {
+    let overrides = state
+        .tenant_replies
+        .remove(&tenant, &intent)
+        .ok_or_else(|| ApiError::NotFound(format!("tenant {tenant} has no {intent} reply")))?;
+    info!(%tenant, %intent, "tenant reply override removed");
+    Ok(Json(overrides))
+}
+
+async fn tenant_theme(State(state): State<SharedState>, Path(tenant): Path<String>) -> Json<Theme> {
+    Json(state.tenant_themes.for_tenant(&tenant))
+}
+
+/// Replaces a tenant's widget theme, e.g. `PUT /admin/tenants/acme/theme`
+/// with `{"accent": "#e4572e", "font_family": "Inter, sans-serif"}`.
+async fn set_tenant_theme(
+    State(state): State<SharedState>,
+    Path(tenant): Path<String>,
+    Json(theme): Json<Theme>,
+) -> Result<Json<Theme>, ApiError> {
+    let theme = state.tenant_themes.set(&tenant, theme)?;
+    info!(%tenant, "tenant theme set");
+    Ok(Json(theme))
+}
+
+async fn remove_tenant_theme(
+    State(state): State<SharedState>,
+    Path(tenant): Path<String>,
+) -> Result<Json<Theme>, ApiError> {
+    let theme = state
+        .tenant_themes
+        .remove(&tenant)
+        .ok_or_else(|| ApiError::NotFound(format!("tenant {tenant} has no theme")))?;
+    info!(%tenant, "tenant theme removed");
+    Ok(Json(theme))
+}
+
+async fn tenant_ai(
+    State(state): State<SharedState>,
+    Path(tenant): Path<String>,
+) -> Result<Json<AiStatus>, ApiError> {
+    state
+        .ai
+        .status(&tenant)
+        .map(Json)
+        .ok_or_else(|| ApiError::NotFound(format!("tenant {tenant} has no AI settings")))
+}
+
+/// Sets a tenant's model credentials and budget, e.g.
+/// `PUT /admin/tenants/acme/ai` with
+/// `{"api_key": "sk-...", "monthly_token_budget": 2000000}`.
+async fn set_tenant_ai(
+    State(state): State<SharedState>,
+    Path(tenant): Path<String>,
+    Json(settings): Json<AiSettings>,
+) -> Result<Json<AiStatus>, ApiError> {
+    let status = state.ai.configure(&tenant, settings)?;
+    info!(%tenant, budget = ?status.monthly_token_budget, "tenant AI settings set");
+    Ok(Json(status))
+}
+
+async fn remove_tenant_ai(
+    State(state): State<SharedState>,
+    Path(tenant): Path<String>,
+) -> Result<Json<AiStatus>, ApiError> {
+    let status = state
+        .ai
+        .remove(&tenant)?
+        .ok_or_else(|| ApiError::NotFound(format!("tenant {tenant} has no AI settings")))?;
+    info!(%tenant, "tenant AI settings removed");
+    Ok(Json(status))
+}
+
+async fn prompts(State(state): State<SharedState>) -> Json<Vec<PromptStatus>> {
+    Json(state.ai.prompts.list())
+}
+
+async fn prompt(
+    State(state): State<SharedState>,
+    Path(name): Path<String>,
+) -> Result<Json<PromptStatus>, ApiError> {
+    state
+        .ai
+        .prompts
+        .get(&name)
+        .map(Json)
+        .ok_or_else(|| ApiError::NotFound(format!("no prompt named {name}")))
+}
+
+#[derive(Debug, Deserialize)]
+struct PromptRequest {
+    text: String,
+}
+
+/// Adds a version of a prompt and puts it in use, e.g.
+/// `PUT /admin/prompts/fallback` with
+/// `{"text": "Answer in {language}, in two sentences at most."}`.
+async fn set_prompt(
+    State(state): State<SharedState>,
+    Path(name): Path<String>,
+    Json(request): Json<PromptRequest>,
+) -> Result<Json<PromptStatus>, ApiError> {
+    let status = state.ai.prompts.set(&name, &request.text)?;
+    info!(prompt = %name, version = status.active, "prompt set");
+    Ok(Json(status))
+}
+
+#[derive(Debug, Deserialize)]
+struct RollbackRequest {
+    version: u32,
+}
+
+/// Puts an earlier version of a prompt back in use, e.g.
+/// `POST /admin/prompts/fallback/rollback` with `{"version": 0}`.
+async fn rollback_prompt(
+    State(state): State<SharedState>,
+    Path(name): Path<String>,
+    Json(request): Json<RollbackRequest>,
+) -> Result<Json<PromptStatus>, ApiError> {
+    let status = state.ai.prompts.rollback(&name, request.version)?;
+    info!(prompt = %name, version = status.active, "prompt rolled back");
+    Ok(Json(status))
+}
+
+async fn synonyms(State(state): State<SharedState>) -> Json<BTreeMap<String, String>> {
+    Json(state.synonyms.entries())
+}
+
+#[derive(Debug, Deserialize)]
+struct SynonymRequest {
+    canonical: String,
+}
+
+/// Makes an alias stand for a phrase the bot knows, e.g.
+/// `PUT /admin/synonyms/online%20shop` with `{"canonical": "e-commerce"}`.
+async fn set_synonym(
+    State(state): State<SharedState>,
+    Path(alias): Path<String>,
+    Json(request): Json<SynonymRequest>,
+) -> Result<Json<BTreeMap<String, String>>, ApiError> {
+    let entries = state.synonyms.set(&alias, &request.canonical)?;
+    info!(%alias, canonical = %request.canonical, "synonym set");
+    Ok(Json(entries))
+}
+
+async fn remove_synonym(
+    State(state): State<SharedState>,
+    Path(alias): Path<String>,
+) -> Result<Json<BTreeMap<String, String>>, ApiError> {
+    let entries = state.synonyms.remove(&alias)?;
+    info!(%alias, "synonym removed");
+    Ok(Json(entries))
+}
+
+#[derive(Debug, Deserialize)]
+struct DiagramQuery {
+    /// `mermaid` (default) or `dot`.
+    format: Option<String>,
+}
+
+/// The conversation flow as a Mermaid or Graphviz diagram.
+async fn flow_diagram(Query(query): Query<DiagramQuery>) -> Result<String, ApiError> {
+    match query.format.as_deref() {
+        None | Some("mermaid") => Ok(flow::mermaid()),
+        Some("dot") => Ok(flow::dot()),
+        Some(other) => Err(ApiError::BadRequest(format!(
+            "unknown format {other:?}; use mermaid or dot"
+        ))),
+    }
+}
+
+/// Survey answers with CSAT and NPS, for all tenants or one (`?tenant=`).
+async fn satisfaction(
+    State(state): State<SharedState>,
+    Query(query): Query<StatsQuery>,
+) -> Result<Json<SatisfactionSummary>, ApiError> {
+    let metrics = match &query.tenant {
+        Some(tenant) => state
+            .metrics
+            .tenant_snapshot(tenant)
+            .ok_or_else(|| ApiError::NotFound(format!("no metrics for tenant {tenant}")))?,
+        None => state.metrics.snapshot(),
+    };
+    Ok(Json(SatisfactionSummary::new(metrics.satisfaction)))
+}
+
+/// Default and maximum number of entries `/analytics/unanswered` returns.
+const UNANSWERED_LIMIT: usize = 100;
+const MAX_UNANSWERED_LIMIT: usize = 1_000;
+
+#[derive(Debug, Deserialize)]
+struct UnansweredQuery {
+    /// ISO 639-1 code.
+    language: Option<String>,
+    limit: Option<usize>,
+}
+
+/// Messages the bot had no answer for, most frequent first.
+async fn unanswered(
+    State(state): State<SharedState>,
+    Query(query): Query<UnansweredQuery>,
+) -> Result<Json<Vec<UnansweredQuestion>>, ApiError> {
+    let language = match query.language.as_deref() {
+        Some(code) => Some(
+            Language::from_code(code)
+                .ok_or_else(|| ApiError::BadRequest(format!("unsupported language {code:?}")))?,
+        ),
+        None => None,
+    };
+    let limit = query
+        .limit
+        .unwrap_or(UNANSWERED_LIMIT)
+        .min(MAX_UNANSWERED_LIMIT);
+    Ok(Json(state.unanswered.top(language, limit)))
+}
+
+#[derive(Debug, Deserialize)]
+struct DeliveriesQuery {
+    status: Option<JobStatus>,
+}
+
+/// A delivery without its payload, for listings.
+#[derive(Debug, Serialize)]
+struct DeliverySummary {
+    id: String,
+    event_type: String,
+    target_url: String,
+    status: JobStatus,
+    attempts: u32,
+    created_at_ms: u64,
+    next_attempt_at_ms: u64,
+    last_error: Option<String>,
+}
+
+impl From<OutboxJob> for DeliverySummary {
+    fn from(job: OutboxJob) -> Self {
+        Self {
+            id: job.id,
+            event_type: job.event_type,
+            target_url: job.target_url,
+            status: job.status,
+            attempts: job.attempts,
+            created_at_ms: job.created_at_ms,
+            next_attempt_at_ms: job.next_attempt_at_ms,
+            last_error: job.last_error,
+        }
+    }
+}
+
+/// Queued webhook deliveries; `?status=failed` lists the dead letters.
+async fn list_deliveries(
+    State(state): State<SharedState>,
+    Query(query): Query<DeliveriesQuery>,
+) -> Json<Vec<DeliverySummary>> {
+    Json(
+        state
+            .outbox
+            .list(query.status)
+            .into_iter()
+            .map(DeliverySummary::from)
+            .collect(),
+    )
+}
+
+/// One delivery including the payload it posts.
+async fn get_delivery(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> Result<Json<OutboxJob>, ApiError> {
+    state
+        .outbox
+        .get(&id)
+        .map(Json)
+        .ok_or_else(|| ApiError::NotFound(format!("no delivery {id}")))
+}
+
+/// Re-queues a delivery for immediate retry.
+async fn redeliver(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> Result<Json<DeliverySummary>, ApiError> {
+    state
+        .outbox
+        .redeliver(&id)
+        .map(|job| Json(job.into()))
+        .ok_or_else(|| ApiError::NotFound(format!("no delivery {id}")))
+}
@@ -0,0 +1,157 @@
+//! Keeps blocked visitors away from the chat routes; see
+//! [`crate::blocklist`].
+//!
+//! The client address alone decides while the blocklist has no visitor or
+//! email domain entries. Otherwise the request is looked into: the session
+//! id in its path, query or JSON body, the email address that session
+//! gave, and addresses in the message text.
+
+use std::collections::HashMap;
+
+use axum::{
+    body::{Body, to_bytes},
+    extract::{Query, Request, State},
+    http::header::CONTENT_TYPE,
+    middleware::Next,
+    response::Response,
+};
+use serde_json::Value;
+
+use super::client_ip::ClientIp;
+use crate::{
+    blocklist::{BlockKind, Blocklist, refuse},
+    chatbot::extract_email,
+    error::ApiError,
+    i18n::Language,
+    state::SharedState,
+    tenant::{self, DEFAULT_TENANT},
+};
+
+/// Larger bodies are refused rather than buffered to look into them.
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+pub async fn enforce(
+    State(state): State<SharedState>,
+    ClientIp(client_ip): ClientIp,
+    req: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let blocklist = &state.blocklist;
+    let ip_blocked = client_ip.is_some_and(|ip| blocklist.blocks_ip(ip));
+    if !ip_blocked && !blocklist.has_visitor_entries() {
+        return Ok(next.run(req).await);
+    }
+    let (req, visitor) = Visitor::read(&state, req).await?;
+    let blocked_by = if ip_blocked {
+        Some(BlockKind::Ip)
+    } else {
+        visitor.blocked_by(blocklist)
+    };
+    match blocked_by {
+        None => Ok(next.run(req).await),
+        Some(kind) => Err(refuse(
+            &state,
+            kind,
+            &visitor.tenant,
+            visitor.language,
+            client_ip,
+            visitor.session_id.as_deref(),
+        )),
+    }
+}
+
+/// What a request says about who sent it.
+struct Visitor {
+    session_id: Option<String>,
+    tenant: String,
+    language: Language,
+    /// The session's address and any in the message.
+    emails: Vec<String>,
+}
+
+impl Visitor {
+    /// Reads the request's body, if it is JSON, and hands back a request
+    /// carrying the same body.
+    async fn read(state: &SharedState, req: Request) -> Result<(Request, Self), ApiError> {
+        let mut session_id = req
+            .uri()
+            .path()
+            .strip_prefix("/sessions/")
+            .and_then(|rest| rest.split('/').next())
+            .map(str::to_string);
+        if session_id.is_none()
+            && let Ok(Query(query)) = Query::<HashMap<String, String>>::try_from_uri(req.uri())
+        {
+            session_id = query.get("session_id").cloned();
+        }
+
+        let json = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|t| t.starts_with("application/json"));
+        let (req, body) = if json {
+            let (parts, body) = req.into_parts();
+            let bytes = to_bytes(body, MAX_BODY_BYTES)
+                .await
+                .map_err(|_| ApiError::BadRequest("request body is too large".into()))?;
+            let body = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+            (Request::from_parts(parts, Body::from(bytes)), body)
+        } else {
+            (req, Value::Null)
+        };
+
+        let field = |name| body.get(name).and_then(Value::as_str);
+        session_id = session_id.or_else(|| field("session_id").map(str::to_string));
+        let messages: Vec<&str> = ["message", "text"]
+            .into_iter()
+            .filter_map(field)
+            .chain(
+                body.get("messages")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_str),
+            )
+            .collect();
+        let mut emails: Vec<String> = messages.iter().filter_map(|m| extract_email(m)).collect();
+
+        let session = match &session_id {
+            Some(id) => {
+                state
+                    .sessions
+                    .with_session(id, |s| (s.tenant.clone(), s.language, s.data.email.clone()))
+                    .await
+            }
+            None => None,
+        };
+        let (tenant, language) = match session {
+            Some((tenant, language, email)) => {
+                emails.extend(email);
+                (tenant, language)
+            }
+            None => {
+                let tenant = tenant::resolve(field("tenant")).unwrap_or(DEFAULT_TENANT);
+                let language = field("language")
+                    .and_then(Language::from_code)
+                    .or_else(|| messages.first().and_then(|m| Language::detect(m)))
+                    .unwrap_or_default();
+                (tenant.to_string(), language)
+            }
+        };
+        let visitor = Self {
+            session_id,
+            tenant,
+            language,
+            emails,
+        };
+        Ok((req, visitor))
+    }
+
+    fn blocked_by(&self, blocklist: &Blocklist) -> Option<BlockKind> {
+        blocklist.blocked_by(
+            self.session_id.as_deref(),
+            self.emails.iter().map(String::as_str),
+        )
+    }
+}
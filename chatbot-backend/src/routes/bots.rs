@@ -0,0 +1,72 @@
+//! `/bots/{bot_id}/chat`: the chat API of one of the bots in
+//! [`crate::bots`], the same as `/chat` with the bot as the tenant.
+
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+
+use super::{
+    chat::{self, BatchChatRequest, BatchChatResponse, ChatRequest, ChatResponse},
+    client_ip::ClientIp,
+};
+use crate::{error::ApiError, state::SharedState};
+
+/// Refuses unknown bots, requests naming another tenant and sessions of
+/// another bot. An expired session is fine: the new one is the bot's.
+async fn check(
+    state: &SharedState,
+    bot_id: &str,
+    tenant: Option<&str>,
+    session_id: Option<&str>,
+) -> Result<(), ApiError> {
+    if state.bots.get(bot_id).is_none() {
+        return Err(ApiError::NotFound(format!("no bot {bot_id:?}")));
+    }
+    if tenant.is_some_and(|t| t != bot_id) {
+        return Err(ApiError::BadRequest(format!(
+            "tenant must be {bot_id:?} or left out"
+        )));
+    }
+    if let Some(id) = session_id
+        && let Some(tenant) = state.sessions.with_session(id, |s| s.tenant.clone()).await
+        && tenant != bot_id
+    {
+        return Err(ApiError::NotFound("session expired".into()));
+    }
+    Ok(())
+}
+
+pub async fn chat(
+    State(state): State<SharedState>,
+    Path(bot_id): Path<String>,
+    client_ip: ClientIp,
+    Json(mut req): Json<ChatRequest>,
+) -> Result<Json<ChatResponse>, ApiError> {
+    check(
+        &state,
+        &bot_id,
+        req.tenant.as_deref(),
+        req.session_id.as_deref(),
+    )
+    .await?;
+    req.tenant = Some(bot_id);
+    chat::chat(State(state), client_ip, Json(req)).await
+}
+
+pub async fn chat_batch(
+    State(state): State<SharedState>,
+    Path(bot_id): Path<String>,
+    client_ip: ClientIp,
+    Json(mut req): Json<BatchChatRequest>,
+) -> Result<Json<BatchChatResponse>, ApiError> {
+    check(
+        &state,
+        &bot_id,
+        req.tenant.as_deref(),
+        req.session_id.as_deref(),
+    )
+    .await?;
+    req.tenant = Some(bot_id);
+    chat::chat_batch(State(state), client_ip, Json(req)).await
+}
@@ -0,0 +1,225 @@
+//! `/admin` operations on many sessions or leads at once, picked by a
+//! filter rather than one request each.
+//!
+//! Every filter field is optional and the ones given must all match. With
+//! `"dry_run": true` nothing changes and the response lists what would
+//! have, so a filter can be checked before it is applied.
+
+use std::{collections::BTreeSet, time::Duration};
+
+use axum::{Json, extract::State};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    error::ApiError,
+    events::{self, ConversationEvent},
+    i18n::Language,
+    session::{ConversationState, LeadTag, Session},
+    state::SharedState,
+};
+
+/// Longest operator label, in bytes.
+const MAX_LABEL_LEN: usize = 40;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionFilter {
+    pub ids: Option<BTreeSet<String>>,
+    pub tenant: Option<String>,
+    pub language: Option<Language>,
+    pub state: Option<ConversationState>,
+    pub handed_off: Option<bool>,
+    /// Idle for at least this long.
+    pub idle_for_secs: Option<u64>,
+}
+
+impl SessionFilter {
+    fn is_empty(&self) -> bool {
+        self.ids.is_none()
+            && self.tenant.is_none()
+            && self.language.is_none()
+            && self.state.is_none()
+            && self.handed_off.is_none()
+            && self.idle_for_secs.is_none()
+    }
+
+    fn matches(&self, session: &Session) -> bool {
+        self.ids
+            .as_ref()
+            .is_none_or(|ids| ids.contains(&session.id))
+            && self.tenant.as_ref().is_none_or(|t| *t == session.tenant)
+            && self.language.is_none_or(|l| l == session.language)
+            && self.state.is_none_or(|s| s == session.state)
+            && self.handed_off.is_none_or(|h| h == session.handed_off)
+            && self
+                .idle_for_secs
+                .is_none_or(|secs| session.last_activity.elapsed() >= Duration::from_secs(secs))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BulkDeleteRequest {
+    pub filter: SessionFilter,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkResult {
+    pub dry_run: bool,
+    /// The sessions that matched, and were changed unless `dry_run`.
+    pub session_ids: Vec<String>,
+}
+
+/// Deletes every live session the filter matches, e.g.
+/// `POST /admin/sessions:bulk-delete` with
+/// `{"filter": {"tenant": "acme", "idle_for_secs": 600}, "dry_run": true}`.
+/// Each can be restored like a single deletion. An empty filter is refused
+/// rather than taken to mean every session.
+pub async fn delete_sessions(
+    State(state): State<SharedState>,
+    Json(request): Json<BulkDeleteRequest>,
+) -> Result<Json<BulkResult>, ApiError> {
+    if request.filter.is_empty() {
+        return Err(ApiError::BadRequest(
+            "give at least one filter; an empty one would match every session".into(),
+        ));
+    }
+    let mut session_ids: Vec<String> = state
+        .sessions
+        .export()
+        .await
+        .into_iter()
+        .map(|(session, _)| session)
+        .filter(|s| request.filter.matches(s))
+        .map(|s| s.id)
+        .collect();
+    session_ids.sort();
+    if !request.dry_run {
+        let mut deleted = Vec::with_capacity(session_ids.len());
+        for id in session_ids {
+            // It may have expired or been deleted since it was matched.
+            if super::admin::delete_one(&state, &id).await {
+                deleted.push(id);
+            }
+        }
+        session_ids = deleted;
+        info!(sessions = session_ids.len(), "sessions deleted in bulk");
+    }
+    Ok(Json(BulkResult {
+        dry_run: request.dry_run,
+        session_ids,
+    }))
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LeadFilter {
+    pub ids: Option<BTreeSet<String>>,
+    pub language: Option<Language>,
+    /// Confirmed at or after, in Unix ms.
+    pub since_ms: Option<u64>,
+    /// Confirmed before, in Unix ms.
+    pub until_ms: Option<u64>,
+    pub tag: Option<LeadTag>,
+    /// Carrying this operator label.
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BulkTagRequest {
+    pub filter: LeadFilter,
+    #[serde(default)]
+    pub add: BTreeSet<String>,
+    #[serde(default)]
+    pub remove: BTreeSet<String>,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Adds and removes operator labels on every lead the filter matches,
+/// e.g. `POST /admin/leads:bulk-tag` with
+/// `{"filter": {"since_ms": 1767225600000}, "add": ["q1"], "remove": ["new"]}`.
+/// Leads that already carry the labels are left as they are.
+pub async fn tag_leads(
+    State(state): State<SharedState>,
+    Json(request): Json<BulkTagRequest>,
+) -> Result<Json<BulkResult>, ApiError> {
+    if request.add.is_empty() && request.remove.is_empty() {
+        return Err(ApiError::BadRequest("give labels to add or remove".into()));
+    }
+    if let Some(label) = request.add.iter().find(|l| !is_valid_label(l)) {
+        return Err(ApiError::BadRequest(format!(
+            "label {label:?} must be 1 to {MAX_LABEL_LEN} lowercase letters, digits, '-' or '_'"
+        )));
+    }
+    if let Some(label) = request.add.intersection(&request.remove).next() {
+        return Err(ApiError::BadRequest(format!(
+            "label {label:?} is both added and removed"
+        )));
+    }
+    let filter = &request.filter;
+    let store = &state.events;
+    let mut changes = Vec::new();
+    for id in store.session_ids() {
+        if filter.ids.as_ref().is_some_and(|ids| !ids.contains(&id)) {
+            continue;
+        }
+        let projection = events::project(&store.session_events(&id));
+        let Some(confirmed_at_ms) = projection.confirmed_at_ms else {
+            continue;
+        };
+        let matches = filter.language.is_none_or(|l| l == projection.language)
+            && filter.since_ms.is_none_or(|since| confirmed_at_ms >= since)
+            && filter.until_ms.is_none_or(|until| confirmed_at_ms < until)
+            && filter.tag.is_none_or(|t| Some(t) == projection.data.tag)
+            && filter
+                .label
+                .as_ref()
+                .is_none_or(|l| projection.labels.contains(l));
+        if !matches {
+            continue;
+        }
+        let added: Vec<String> = request
+            .add
+            .difference(&projection.labels)
+            .cloned()
+            .collect();
+        let removed: Vec<String> = request
+            .remove
+            .intersection(&projection.labels)
+            .cloned()
+            .collect();
+        if !added.is_empty() || !removed.is_empty() {
+            changes.push((id, added, removed));
+        }
+    }
+    if !request.dry_run {
+        for (id, added, removed) in &changes {
+            store.append(
+                id,
+                vec![ConversationEvent::LeadLabelsChanged {
+                    added: added.clone(),
+                    removed: removed.clone(),
+                }],
+            );
+        }
+        info!(leads = changes.len(), "leads labelled in bulk");
+    }
+    let mut session_ids: Vec<String> = changes.into_iter().map(|(id, ..)| id).collect();
+    session_ids.sort();
+    Ok(Json(BulkResult {
+        dry_run: request.dry_run,
+        session_ids,
+    }))
+}
+
+fn is_valid_label(label: &str) -> bool {
+    (1..=MAX_LABEL_LEN).contains(&label.len())
+        && label
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'_')
+}
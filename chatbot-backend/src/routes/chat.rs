@@ -0,0 +1,347 @@
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::ApiError,
+    events::TranscriptEntry,
+    render,
+    routes::client_ip::ClientIp,
+    service,
+    session::{MessageMetadata, ReceiptStatus},
+    state::SharedState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct ChatRequest {
+    pub message: String,
+    #[serde(default)]
+    pub session_id: Option<String>,
+    /// ISO 639-1 code; when absent the language is guessed from the first
+    /// message of the session.
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Tenant the session belongs to, when it starts one; see
+    /// [`crate::tenant`].
+    #[serde(default)]
+    pub tenant: Option<String>,
+    /// The client listens on `/chat/events`, so long replies may be sent
+    /// in parts.
+    #[serde(default)]
+    pub stream: bool,
+    /// Page context and the like, stored with the message.
+    #[serde(default)]
+    pub metadata: Option<MessageMetadata>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChatResponse {
+    /// Empty while an operator has taken the session over; their messages
+    /// arrive on `/chat/events`.
+    pub reply: String,
+    pub session_id: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub quick_replies: Vec<String>,
+    /// Id of the reply, for `/chat/receipts`; absent when `reply` is empty.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    /// Parts of the reply still to come as `reply_part` events.
+    #[serde(skip_serializing_if = "is_zero")]
+    pub more_parts: usize,
+    /// The inquiry report in Markdown, while the visitor is asked to
+    /// confirm it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preview: Option<String>,
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+/// Most messages a single `/chat/batch` call may carry.
+pub const MAX_BATCH_MESSAGES: usize = 50;
+
+#[derive(Debug, Deserialize)]
+pub struct BatchChatRequest {
+    pub messages: Vec<String>,
+    #[serde(default)]
+    pub session_id: Option<String>,
+    #[serde(default)]
+    pub language: Option<String>,
+    #[serde(default)]
+    pub tenant: Option<String>,
+    /// Stored with every message of the batch.
+    #[serde(default)]
+    pub metadata: Option<MessageMetadata>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchReply {
+    pub reply: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub quick_replies: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preview: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchChatResponse {
+    pub session_id: String,
+    pub replies: Vec<BatchReply>,
+}
+
+pub async fn chat(
+    State(state): State<SharedState>,
+    ClientIp(client_ip): ClientIp,
+    Json(req): Json<ChatRequest>,
+) -> Result<Json<ChatResponse>, ApiError> {
+    let message = req.message.trim();
+    if message.is_empty() {
+        return Err(ApiError::BadRequest("message must not be empty".into()));
+    }
+    if let Some(metadata) = &req.metadata {
+        metadata.validate().map_err(ApiError::BadRequest)?;
+    }
+
+    let (session_id, mut replies) = service::converse(
+        &state,
+        req.session_id.as_deref(),
+        req.language.as_deref(),
+        req.tenant.as_deref(),
+        &[message],
+        req.metadata.as_ref(),
+        client_ip,
+    )
+    .await?;
+    let mut reply = replies.remove(0);
+    let mut more_parts = 0;
+    if req.stream {
+        (reply, more_parts) = render::stream(&state, &session_id, reply);
+    }
+
+    Ok(Json(ChatResponse {
+        reply: reply.text,
+        session_id,
+        quick_replies: reply.quick_replies,
+        message_id: reply.message_id,
+        more_parts,
+        preview: reply.preview,
+    }))
+}
+
+/// Processes several messages for one session in order, holding the
+/// session for the whole batch so no other request can interleave. Clients
+/// that buffer messages while offline send them here on reconnect.
+pub async fn chat_batch(
+    State(state): State<SharedState>,
+    ClientIp(client_ip): ClientIp,
+    Json(req): Json<BatchChatRequest>,
+) -> Result<Json<BatchChatResponse>, ApiError> {
+    let messages: Vec<&str> = req.messages.iter().map(|m| m.trim()).collect();
+    if messages.is_empty() {
+        return Err(ApiError::BadRequest("messages must not be empty".into()));
+    }
+    if messages.len() > MAX_BATCH_MESSAGES {
+        return Err(ApiError::BadRequest(format!(
+            "at most {MAX_BATCH_MESSAGES} messages per batch"
+        )));
+    }
+    if let Some(index) = messages.iter().position(|m| m.is_empty()) {
+        return Err(ApiError::BadRequest(format!(
+            "message {index} must not be empty"
+        )));
+    }
+    if let Some(metadata) = &req.metadata {
+        metadata.validate().map_err(ApiError::BadRequest)?;
+    }
+
+    let (session_id, replies) = service::converse(
+        &state,
+        req.session_id.as_deref(),
+        req.language.as_deref(),
+        req.tenant.as_deref(),
+        &messages,
+        req.metadata.as_ref(),
+        client_ip,
+    )
+    .await?;
+    let replies = replies
+        .into_iter()
+        .map(|reply| BatchReply {
+            reply: reply.text,
+            quick_replies: reply.quick_replies,
+            message_id: reply.message_id,
+            preview: reply.preview,
+        })
+        .collect();
+
+    Ok(Json(BatchChatResponse {
+        session_id,
+        replies,
+    }))
+}
+
+/// Most message ids a single `/chat/receipts` call may carry.
+pub const MAX_RECEIPTS: usize = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct ReceiptsRequest {
+    pub session_id: String,
+    /// Ids of bot and operator messages, from `message_id` in replies and
+    /// `agent_message` events.
+    pub message_ids: Vec<String>,
+    pub status: ReceiptStatus,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReceiptsResponse {
+    /// Receipts that were news; repeats are not counted.
+    pub recorded: usize,
+}
+
+/// Acknowledges messages as delivered or read, for "seen" indicators.
+pub async fn receipts(
+    State(state): State<SharedState>,
+    Json(req): Json<ReceiptsRequest>,
+) -> Result<Json<ReceiptsResponse>, ApiError> {
+    if req.message_ids.len() > MAX_RECEIPTS {
+        return Err(ApiError::BadRequest(format!(
+            "at most {MAX_RECEIPTS} message ids per call"
+        )));
+    }
+    let recorded =
+        service::record_receipts(&state, &req.session_id, &req.message_ids, req.status).await?;
+    Ok(Json(ReceiptsResponse { recorded }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EditMessageRequest {
+    pub text: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CorrectionResponse {
+    /// Id of the edited message, which changes when the bot answered it
+    /// again; absent after a delete.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    /// Messages to remove from the conversation shown to the visitor.
+    pub retracted: Vec<String>,
+    /// Whether the bot answered the corrected message; the new reply
+    /// follows when it did.
+    pub reevaluated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub quick_replies: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preview: Option<String>,
+}
+
+impl From<service::Correction> for CorrectionResponse {
+    fn from(correction: service::Correction) -> Self {
+        let reply = correction.reply;
+        Self {
+            message_id: correction.message_id,
+            retracted: correction.retracted,
+            reevaluated: reply.is_some(),
+            reply_id: reply.as_ref().and_then(|r| r.message_id.clone()),
+            quick_replies: reply
+                .as_ref()
+                .map(|r| r.quick_replies.clone())
+                .unwrap_or_default(),
+            preview: reply.as_ref().and_then(|r| r.preview.clone()),
+            reply: reply.map(|r| r.text),
+        }
+    }
+}
+
+/// Corrects the visitor's last message, within [`service::EDIT_WINDOW`].
+pub async fn edit_message(
+    State(state): State<SharedState>,
+    Path((session_id, message_id)): Path<(String, String)>,
+    Json(req): Json<EditMessageRequest>,
+) -> Result<Json<CorrectionResponse>, ApiError> {
+    let text = req.text.trim();
+    if text.is_empty() {
+        return Err(ApiError::BadRequest("text must not be empty".into()));
+    }
+    service::edit_message(&state, &session_id, &message_id, text)
+        .await
+        .map(|correction| Json(correction.into()))
+}
+
+/// Takes back the visitor's last message, within [`service::EDIT_WINDOW`].
+pub async fn delete_message(
+    State(state): State<SharedState>,
+    Path((session_id, message_id)): Path<(String, String)>,
+) -> Result<Json<CorrectionResponse>, ApiError> {
+    service::delete_message(&state, &session_id, &message_id)
+        .await
+        .map(|correction| Json(correction.into()))
+}
+
+/// Messages per page unless the client asks for fewer or more.
+pub const DEFAULT_PAGE_SIZE: usize = 50;
+pub const MAX_PAGE_SIZE: usize = 200;
+
+#[derive(Debug, Deserialize)]
+pub struct MessagesQuery {
+    /// `next_cursor` of the previous page; the newest messages when absent.
+    #[serde(default)]
+    pub before: Option<String>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MessagesPage {
+    /// Oldest first.
+    pub messages: Vec<TranscriptEntry>,
+    /// Pass as `before` for the page of older messages; absent on the
+    /// first page of the conversation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+/// A page of the conversation, newest first across pages, so a client
+/// can show the latest messages and load older ones as the visitor
+/// scrolls. Cursors stay valid while messages are added, edited or
+/// deleted.
+pub async fn list_messages(
+    State(state): State<SharedState>,
+    Path(session_id): Path<String>,
+    Query(query): Query<MessagesQuery>,
+) -> Result<Json<MessagesPage>, ApiError> {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(ApiError::BadRequest(format!(
+            "limit must be between 1 and {MAX_PAGE_SIZE}"
+        )));
+    }
+    let before = match query.before.as_deref() {
+        Some(cursor) => cursor
+            .parse::<u64>()
+            .map_err(|_| ApiError::BadRequest("invalid cursor".into()))?,
+        None => u64::MAX,
+    };
+
+    let mut messages = service::history(&state, &session_id).await?;
+    messages.retain(|entry| entry.seq < before);
+    let start = messages.len().saturating_sub(limit);
+    let messages = messages.split_off(start);
+    let next_cursor = (start > 0)
+        .then(|| messages.first().map(|entry| entry.seq.to_string()))
+        .flatten();
+    Ok(Json(MessagesPage {
+        messages,
+        next_cursor,
+    }))
+}
@@ -0,0 +1,39 @@
+use std::net::{IpAddr, SocketAddr};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::request::Parts,
+};
+
+use crate::state::SharedState;
+
+/// The visitor's address: the `X-Forwarded-For` entry written by the
+/// outermost of the server's trusted proxies, else the peer address. `None`
+/// when neither is available.
+///
+/// Each proxy appends the address it got the request from, so behind `n`
+/// proxies that entry is the `n`th from the right. Entries left of it were
+/// sent by the client and can say anything; a header with fewer entries
+/// than there are proxies didn't come through them all and is ignored.
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl FromRequestParts<SharedState> for ClientIp {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &SharedState,
+    ) -> Result<Self, Self::Rejection> {
+        let forwarded = state
+            .trusted_proxies
+            .checked_sub(1)
+            .zip(parts.headers.get("x-forwarded-for"))
+            .and_then(|(hops, v)| v.to_str().ok()?.rsplit(',').nth(hops))
+            .and_then(|ip| ip.trim().parse().ok());
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        Ok(ClientIp(forwarded.or(peer)))
+    }
+}
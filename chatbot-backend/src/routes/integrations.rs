@@ -0,0 +1,131 @@
+//! Webhooks of third-party chat channels.
+//!
+//! Chat deliveries are acknowledged right away and answered in the
+//! background, so the platform doesn't time out and deliver them again.
+
+use axum::{
+    Json,
+    body::Bytes,
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+};
+use serde::de::DeserializeOwned;
+use tracing::warn;
+
+use crate::{
+    channels::{email::InboundEmail, messenger, meta, whatsapp},
+    error::ApiError,
+    routes::admin::{bearer_token, constant_time_eq},
+    state::SharedState,
+};
+
+fn disabled() -> ApiError {
+    ApiError::NotFound("channel is not configured".into())
+}
+
+fn verify(query: meta::VerifyQuery, verify_token: &str) -> Result<String, ApiError> {
+    query
+        .accept(verify_token)
+        .ok_or_else(|| ApiError::Forbidden("verify token mismatch".into()))
+}
+
+/// Checks Meta's signature when an app secret is configured and parses the
+/// delivery.
+fn meta_payload<T: DeserializeOwned>(
+    channel: &str,
+    app_secret: Option<&str>,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<T, ApiError> {
+    if let Some(secret) = app_secret {
+        let signature = headers
+            .get("x-hub-signature-256")
+            .and_then(|v| v.to_str().ok());
+        if !meta::signature_valid(secret, body, signature) {
+            return Err(ApiError::Unauthorized);
+        }
+    }
+    serde_json::from_slice(body).map_err(|e| {
+        warn!("unreadable {channel} webhook: {e}");
+        ApiError::BadRequest(e.to_string())
+    })
+}
+
+/// Meta's subscription handshake for the WhatsApp webhook.
+pub async fn whatsapp_verify(
+    State(state): State<SharedState>,
+    Query(query): Query<meta::VerifyQuery>,
+) -> Result<String, ApiError> {
+    let whatsapp = state.whatsapp.as_ref().ok_or_else(disabled)?;
+    verify(query, &whatsapp.config().verify_token)
+}
+
+/// Incoming WhatsApp messages.
+pub async fn whatsapp_webhook(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<StatusCode, ApiError> {
+    let whatsapp = state.whatsapp.as_ref().ok_or_else(disabled)?;
+    let payload: whatsapp::WebhookPayload = meta_payload(
+        "WhatsApp",
+        whatsapp.config().app_secret.as_deref(),
+        &headers,
+        &body,
+    )?;
+    let state = state.clone();
+    tokio::spawn(async move {
+        if let Some(whatsapp) = &state.whatsapp {
+            whatsapp.handle(&state, payload).await;
+        }
+    });
+    Ok(StatusCode::OK)
+}
+
+/// Meta's subscription handshake for the Messenger webhook.
+pub async fn messenger_verify(
+    State(state): State<SharedState>,
+    Query(query): Query<meta::VerifyQuery>,
+) -> Result<String, ApiError> {
+    let messenger = state.messenger.as_ref().ok_or_else(disabled)?;
+    verify(query, &messenger.config().verify_token)
+}
+
+/// Incoming Messenger messages, quick reply taps and postbacks.
+pub async fn messenger_webhook(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<StatusCode, ApiError> {
+    let messenger = state.messenger.as_ref().ok_or_else(disabled)?;
+    let payload: messenger::WebhookPayload = meta_payload(
+        "Messenger",
+        messenger.config().app_secret.as_deref(),
+        &headers,
+        &body,
+    )?;
+    let state = state.clone();
+    tokio::spawn(async move {
+        if let Some(messenger) = &state.messenger {
+            messenger.handle(&state, payload).await;
+        }
+    });
+    Ok(StatusCode::OK)
+}
+
+/// An email that reached the inbound mailbox, posted by the mail provider
+/// with `Authorization: Bearer <INBOUND_EMAIL_TOKEN>`.
+pub async fn inbound_email(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Json(email): Json<InboundEmail>,
+) -> Result<StatusCode, ApiError> {
+    let channel = state.email.as_ref().ok_or_else(disabled)?;
+    if !bearer_token(&headers)
+        .is_some_and(|t| constant_time_eq(t.as_bytes(), channel.token().as_bytes()))
+    {
+        return Err(ApiError::Unauthorized);
+    }
+    channel.handle(&state, email).await?;
+    Ok(StatusCode::OK)
+}
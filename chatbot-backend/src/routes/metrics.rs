@@ -0,0 +1,11 @@
+use axum::{extract::State, http::header, response::IntoResponse};
+
+use crate::state::SharedState;
+
+pub async fn metrics(State(state): State<SharedState>) -> impl IntoResponse {
+    let memory = state.sessions.memory_usage().await;
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render_prometheus(&memory),
+    )
+}
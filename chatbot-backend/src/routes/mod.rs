@@ -0,0 +1,104 @@
+use axum::{
+    Router,
+    extract::Request,
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, patch, post},
+};
+use tower_http::cors::CorsLayer;
+
+use crate::{assets, error::ApiError, state::SharedState};
+
+pub mod access_log;
+pub mod admin;
+pub mod blocklist;
+pub mod bots;
+pub mod bulk;
+pub mod chat;
+pub mod client_ip;
+pub mod integrations;
+pub mod metrics;
+pub mod partner;
+pub mod push;
+pub mod ready;
+pub mod request_metrics;
+pub mod review;
+pub mod watch;
+pub mod widget;
+
+/// Paths under these belong to the API, so unknown ones get a JSON 404
+/// rather than the widget.
+const API_PREFIXES: [&str; 7] = [
+    "/api",
+    "/admin",
+    "/bots",
+    "/chat",
+    "/integrations",
+    "/sessions",
+    "/v1",
+];
+
+async fn api_not_found(req: Request, next: Next) -> Response {
+    let path = req.uri().path();
+    let api = API_PREFIXES.iter().any(|prefix| {
+        path.strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    });
+    if api {
+        return ApiError::NotFound(format!("no API route {path}")).into_response();
+    }
+    next.run(req).await
+}
+
+/// Builds the HTTP API. Anything that isn't an API route is served from
+/// the widget's files; see [`assets`].
+pub fn router(state: SharedState) -> Router {
+    let visitor = Router::new()
+        .route("/chat", post(chat::chat))
+        .route("/chat/batch", post(chat::chat_batch))
+        .route("/chat/events", get(push::chat_events))
+        .route("/chat/demo", get(push::demo))
+        .route("/chat/receipts", post(chat::receipts))
+        .route("/bots/{bot_id}/chat", post(bots::chat))
+        .route("/bots/{bot_id}/chat/batch", post(bots::chat_batch))
+        .route("/sessions/{id}/messages", get(chat::list_messages))
+        .route(
+            "/sessions/{id}/messages/{message_id}",
+            patch(chat::edit_message).delete(chat::delete_message),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            blocklist::enforce,
+        ));
+    Router::new()
+        .route("/health", get(|| async { "ok" }))
+        .route("/ready", get(ready::ready))
+        .route("/widget.css", get(widget::widget_css))
+        .merge(visitor)
+        .route("/metrics", get(metrics::metrics))
+        .route(
+            "/integrations/whatsapp/webhook",
+            get(integrations::whatsapp_verify).post(integrations::whatsapp_webhook),
+        )
+        .route(
+            "/integrations/messenger/webhook",
+            get(integrations::messenger_verify).post(integrations::messenger_webhook),
+        )
+        .route(
+            "/integrations/email/inbound",
+            post(integrations::inbound_email),
+        )
+        .nest("/v1", partner::router(state.clone()))
+        .nest("/admin", admin::router(state.clone()))
+        .fallback_service(
+            assets::router(&state.assets, state.spa_fallback)
+                .layer(middleware::from_fn(api_not_found)),
+        )
+        .layer(CorsLayer::permissive())
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            request_metrics::record_requests,
+        ))
+        .layer(middleware::from_fn(access_log::log_requests))
+        .with_state(state)
+}
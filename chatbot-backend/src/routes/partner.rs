@@ -0,0 +1,74 @@
+//! The partner API under `/v1`, guarded by the `PARTNER_API_TOKEN` bearer
+//! token. See [`crate::channels::partner`].
+
+use axum::{
+    Json, Router,
+    extract::{Path, Request, State},
+    http::header::CONTENT_TYPE,
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+
+use super::admin::{bearer_token, constant_time_eq};
+use crate::{
+    channels::partner::{PartnerApi, PostMessage, PostMessageResponse},
+    error::ApiError,
+    events::TranscriptEntry,
+    state::SharedState,
+};
+
+const OPENAPI: &str = include_str!("../../openapi/partner-v1.json");
+
+pub fn router(state: SharedState) -> Router<SharedState> {
+    Router::new()
+        .route(
+            "/conversations/{id}/messages",
+            get(list_messages).post(post_message),
+        )
+        .route_layer(middleware::from_fn_with_state(state, require_partner))
+        .route("/openapi.json", get(openapi))
+}
+
+async fn require_partner(
+    State(state): State<SharedState>,
+    req: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let expected = partner(&state)?.token();
+    match bearer_token(req.headers()) {
+        Some(token) if constant_time_eq(token.as_bytes(), expected.as_bytes()) => {
+            Ok(next.run(req).await)
+        }
+        _ => Err(ApiError::Unauthorized),
+    }
+}
+
+fn partner(state: &SharedState) -> Result<&PartnerApi, ApiError> {
+    state
+        .partner
+        .as_ref()
+        .ok_or_else(|| ApiError::Forbidden("partner API is disabled".into()))
+}
+
+async fn openapi() -> impl IntoResponse {
+    ([(CONTENT_TYPE, "application/json")], OPENAPI)
+}
+
+async fn post_message(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+    Json(message): Json<PostMessage>,
+) -> Result<Json<PostMessageResponse>, ApiError> {
+    partner(&state)?
+        .post_message(&state, &id, message)
+        .await
+        .map(Json)
+}
+
+async fn list_messages(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<TranscriptEntry>>, ApiError> {
+    partner(&state)?.messages(&state, &id).await.map(Json)
+}
@@ -0,0 +1,78 @@
+use std::{convert::Infallible, time::Duration};
+
+use axum::{
+    extract::{Query, State},
+    response::sse::{Event, KeepAlive, Sse},
+};
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tokio_stream::{
+    Stream, StreamExt,
+    wrappers::{BroadcastStream, ReceiverStream},
+};
+
+use crate::{
+    demo::{self, DemoScript},
+    error::ApiError,
+    i18n::Language,
+    state::SharedState,
+};
+
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Deserialize)]
+pub struct EventsQuery {
+    pub session_id: String,
+}
+
+/// Server-sent events for one session, e.g. inactivity warnings. Listening
+/// does not count as activity.
+pub async fn chat_events(
+    State(state): State<SharedState>,
+    Query(query): Query<EventsQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    if state
+        .sessions
+        .lock_session(&query.session_id)
+        .await
+        .is_none()
+    {
+        return Err(ApiError::NotFound("session expired".into()));
+    }
+    let stream = BroadcastStream::new(state.push.subscribe(&query.session_id)).filter_map(|msg| {
+        let msg = msg.ok()?;
+        Some(Ok(Event::default()
+            .event(msg.kind())
+            .json_data(&msg)
+            .expect("push messages serialize")))
+    });
+    Ok(Sse::new(stream).keep_alive(KeepAlive::new().interval(KEEP_ALIVE)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DemoQuery {
+    /// ISO 639-1 code of the built-in script to play; ignored when
+    /// `DEMO_SCRIPT` is set.
+    #[serde(default)]
+    pub language: Option<String>,
+}
+
+/// The showcase conversation as server-sent events; see [`crate::demo`].
+pub async fn demo(
+    State(state): State<SharedState>,
+    Query(query): Query<DemoQuery>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let script = state.demo_script.clone().unwrap_or_else(|| {
+        let language = query.language.as_deref().and_then(Language::from_code);
+        DemoScript::builtin(language.unwrap_or_default())
+    });
+    let (events, received) = mpsc::channel(1);
+    tokio::spawn(demo::play(state, script, events));
+    let stream = ReceiverStream::new(received).map(|event| {
+        Ok(Event::default()
+            .event(event.kind())
+            .json_data(&event)
+            .expect("demo events serialize"))
+    });
+    Sse::new(stream).keep_alive(KeepAlive::new().interval(KEEP_ALIVE))
+}
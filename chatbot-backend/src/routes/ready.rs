@@ -0,0 +1,36 @@
+use axum::{Json, extract::State, http::StatusCode};
+use serde::Serialize;
+
+use crate::state::SharedState;
+
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    /// `ready`, or `full` when the session cap leaves no room for new
+    /// visitors.
+    status: &'static str,
+    sessions: usize,
+    maintenance: bool,
+}
+
+/// Whether this instance can take new visitors; `503` when the session
+/// store is at `max_sessions`, so a load balancer sends them elsewhere.
+pub async fn ready(State(state): State<SharedState>) -> (StatusCode, Json<Readiness>) {
+    let sessions = state.sessions.session_count().await;
+    let full = state
+        .sessions
+        .settings()
+        .limits
+        .max_sessions
+        .is_some_and(|max| sessions >= max);
+    let (code, status) = if full {
+        (StatusCode::SERVICE_UNAVAILABLE, "full")
+    } else {
+        (StatusCode::OK, "ready")
+    };
+    let readiness = Readiness {
+        status,
+        sessions,
+        maintenance: state.maintenance.is_on(),
+    };
+    (code, Json(readiness))
+}
@@ -0,0 +1,39 @@
+//! Request counts and latencies per route, so `/chat` can be watched apart
+//! from static assets and webhooks.
+//!
+//! Requests are labelled with the route pattern they matched. Everything
+//! else is served from `static/` and counted as `static`.
+
+use std::time::Instant;
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::state::SharedState;
+
+const STATIC_ROUTE: &str = "static";
+
+pub async fn record_requests(
+    State(state): State<SharedState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let method = req.method().clone();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or(STATIC_ROUTE, MatchedPath::as_str)
+        .to_string();
+    let started = Instant::now();
+    let response = next.run(req).await;
+    state.metrics.record_request(
+        method.as_str(),
+        &route,
+        response.status().as_u16(),
+        started.elapsed(),
+    );
+    response
+}
@@ -0,0 +1,74 @@
+//! `/admin/review`: grading the conversations sampled by
+//! [`crate::review::ReviewQueue`].
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+};
+use serde::Deserialize;
+
+use crate::{
+    chatbot::Intent,
+    error::ApiError,
+    review::{ReviewGrade, ReviewItem, ReviewStatus, ReviewSummary},
+    state::SharedState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct ReviewQuery {
+    /// `pending` unless given.
+    #[serde(default)]
+    status: ReviewStatus,
+}
+
+/// The sampled conversations, oldest first, e.g. `GET /admin/review` for
+/// those awaiting a grade or `?status=graded` for the rest.
+pub async fn list(
+    State(state): State<SharedState>,
+    Query(query): Query<ReviewQuery>,
+) -> Json<Vec<ReviewItem>> {
+    Json(state.review.list(query.status))
+}
+
+pub async fn summary(State(state): State<SharedState>) -> Json<ReviewSummary> {
+    Json(state.review.summary())
+}
+
+pub async fn get(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> Result<Json<ReviewItem>, ApiError> {
+    state
+        .review
+        .get(&id)
+        .map(Json)
+        .ok_or_else(|| ApiError::NotFound(format!("no review item {id}")))
+}
+
+/// Grades every turn of a sampled conversation and teaches the bot the
+/// intents the reviewer corrected, e.g.
+/// `POST /admin/review/{id}/grade` with `{"reviewer": "ana", "turns":
+/// [{"message_id": "…", "intent_correct": false, "expected_intent":
+/// "pricing", "reply_quality": 2}]}`.
+pub async fn grade(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+    Json(grade): Json<ReviewGrade>,
+) -> Result<Json<ReviewItem>, ApiError> {
+    let item = state.review.grade(&id, grade)?;
+    let grades = item.grade.iter().flat_map(|g| &g.turns);
+    for graded in grades.filter(|g| !g.intent_correct) {
+        let turn = item
+            .turns
+            .iter()
+            .find(|t| t.message_id == graded.message_id);
+        let intent = graded
+            .expected_intent
+            .as_deref()
+            .and_then(Intent::from_name);
+        if let (Some(turn), Some(intent)) = (turn, intent) {
+            state.training.record(&turn.text, intent);
+        }
+    }
+    Ok(Json(item))
+}
@@ -0,0 +1,115 @@
+//! `/admin/sessions/{id}/watch`: an operator's live view of a session over
+//! WebSocket.
+//!
+//! The server first sends `{"type": "history", "messages": [...]}`, then a
+//! `{"type": "message", ...}` frame for every new transcript line. The
+//! operator may send `{"type": "message", "text": "..."}` to write to the
+//! visitor, which takes the session over, and `{"type": "release"}` to hand
+//! it back to the bot. Closing the socket releases the session too.
+
+use axum::{
+    extract::{
+        Path, State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    error::ApiError,
+    events::{self, TranscriptEntry},
+    handoff,
+    state::SharedState,
+};
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Frame {
+    History {
+        messages: Vec<TranscriptEntry>,
+    },
+    Message {
+        #[serde(flatten)]
+        entry: TranscriptEntry,
+    },
+    Released,
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Command {
+    Message { text: String },
+    Release,
+}
+
+pub async fn watch_session(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, ApiError> {
+    if state.sessions.lock_session(&id).await.is_none() {
+        return Err(ApiError::NotFound(format!("no session {id}")));
+    }
+    Ok(ws.on_upgrade(move |socket| watch(state, id, socket)))
+}
+
+async fn send(socket: &mut WebSocket, frame: &Frame) -> bool {
+    let json = serde_json::to_string(frame).expect("frames serialize");
+    socket.send(Message::Text(json.into())).await.is_ok()
+}
+
+async fn watch(state: SharedState, id: String, mut socket: WebSocket) {
+    // Subscribe before reading the history so no line falls in between.
+    let mut updates = state.watchers.subscribe(&id);
+    let messages = events::project(&state.events.session_events(&id)).transcript;
+    if !send(&mut socket, &Frame::History { messages }).await {
+        return;
+    }
+
+    let mut took_over = false;
+    loop {
+        let frame = tokio::select! {
+            update = updates.recv() => match update {
+                Ok(entry) => Frame::Message { entry },
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            },
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                    Ok(Command::Message { text }) if text.trim().is_empty() => Frame::Error {
+                        message: "text must not be empty".into(),
+                    },
+                    Ok(Command::Message { text }) => {
+                        if handoff::send_agent_message(&state, &id, text.trim()).await.is_none() {
+                            break;
+                        }
+                        took_over = true;
+                        continue;
+                    }
+                    Ok(Command::Release) => {
+                        handoff::release(&state, &id).await;
+                        took_over = false;
+                        Frame::Released
+                    }
+                    Err(e) => Frame::Error {
+                        message: e.to_string(),
+                    },
+                },
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+        };
+        if !send(&mut socket, &frame).await {
+            break;
+        }
+    }
+
+    if took_over {
+        handoff::release(&state, &id).await;
+    }
+}
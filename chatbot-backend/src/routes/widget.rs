@@ -0,0 +1,31 @@
+use axum::{
+    extract::{Query, State},
+    http::header::{CACHE_CONTROL, CONTENT_TYPE},
+    response::IntoResponse,
+};
+use serde::Deserialize;
+
+use crate::{error::ApiError, state::SharedState, tenant};
+
+#[derive(Debug, Deserialize)]
+pub struct WidgetCssQuery {
+    tenant: Option<String>,
+}
+
+/// The tenant's theme as CSS variables, for the widget to load next to
+/// its own stylesheet; see [`crate::tenant::Theme`].
+pub async fn widget_css(
+    State(state): State<SharedState>,
+    Query(query): Query<WidgetCssQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let tenant = tenant::resolve(query.tenant.as_deref())?;
+    let css = state.tenant_themes.for_tenant(tenant).css();
+    Ok((
+        [
+            (CONTENT_TYPE, "text/css; charset=utf-8"),
+            // Short, so a new theme reaches visitors within minutes.
+            (CACHE_CONTROL, "public, max-age=300"),
+        ],
+        css,
+    ))
+}
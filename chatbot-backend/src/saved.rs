@@ -0,0 +1,313 @@
+//! Sessions as they are written down, for snapshots (see
+//! [`crate::snapshot`]) and anything else that keeps them past the process.
+//!
+//! Every saved session carries `schema_version`. The flow state and the
+//! inquiry data have saved forms of their own, so renaming a state or a
+//! field in [`crate::session`] doesn't change what is on disk; a change
+//! that does raises [`SCHEMA_VERSION`] and adds a migration from the
+//! version before, which [`SavedSession::from_value`] applies in turn to
+//! whatever it reads. Sessions written before versioning are version 1.
+//!
+//! Messages keep the wall-clock time they were sent. The session's own
+//! timestamps are stored as ages relative to when it was written and
+//! turned back into `Instant`s on load, so idle expiry carries on where it
+//! left off.
+
+use std::{
+    fmt,
+    net::IpAddr,
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+use crate::{
+    i18n::Language,
+    session::{
+        ConversationState, FieldValue, LeadTag, Message, MessageMetadata, ReceiptStatus, Role,
+        Session, SessionData,
+    },
+    tenant::DEFAULT_TENANT,
+};
+
+pub const SCHEMA_VERSION: u32 = 3;
+
+/// Turns a session of version `n + 1`, written at the given Unix
+/// millisecond, into one of version `n + 2`.
+type Migration = fn(&mut Map<String, Value>, u64);
+
+const MIGRATIONS: [Migration; SCHEMA_VERSION as usize - 1] = [v1_to_v2, v2_to_v3];
+
+/// Version 1 had no tenants, message ids, surveys or failed answer counts.
+fn v1_to_v2(session: &mut Map<String, Value>, _saved_at_ms: u64) {
+    session
+        .entry("tenant")
+        .or_insert_with(|| DEFAULT_TENANT.into());
+    session.entry("survey_pending").or_insert(false.into());
+    session.entry("failed_answers").or_insert(0.into());
+    if let Some(Value::Array(messages)) = session.get_mut("messages") {
+        for message in messages.iter_mut().filter_map(Value::as_object_mut) {
+            if message.get("id").is_none_or(Value::is_null) {
+                message.insert("id".into(), Uuid::new_v4().to_string().into());
+            }
+        }
+    }
+}
+
+/// Version 2 kept message ages instead of when they were sent.
+fn v2_to_v3(session: &mut Map<String, Value>, saved_at_ms: u64) {
+    if let Some(Value::Array(messages)) = session.get_mut("messages") {
+        for message in messages.iter_mut().filter_map(Value::as_object_mut) {
+            let age_ms = message
+                .remove("age_ms")
+                .and_then(|age| age.as_u64())
+                .unwrap_or_default();
+            message.insert("at_ms".into(), saved_at_ms.saturating_sub(age_ms).into());
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SchemaError {
+    /// Not a JSON object with a usable `schema_version`.
+    Malformed,
+    /// Written by a newer version of the server.
+    Newer(u32),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaError::Malformed => f.write_str("a saved session is a JSON object"),
+            SchemaError::Newer(version) => write!(
+                f,
+                "saved with schema version {version}; this server reads up to {SCHEMA_VERSION}"
+            ),
+            SchemaError::Json(e) => write!(f, "invalid saved session: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+/// [`ConversationState`] as saved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SavedState {
+    Idle,
+    AskingName,
+    AskingEmail,
+    AskingProjectDetails,
+    AskingBudget,
+    ConfirmingSummary,
+    ConfirmingTranscriptEmail,
+    AskingCallbackEmail,
+}
+
+impl From<ConversationState> for SavedState {
+    fn from(state: ConversationState) -> Self {
+        match state {
+            ConversationState::Idle => SavedState::Idle,
+            ConversationState::AskingName => SavedState::AskingName,
+            ConversationState::AskingEmail => SavedState::AskingEmail,
+            ConversationState::AskingProjectDetails => SavedState::AskingProjectDetails,
+            ConversationState::AskingBudget => SavedState::AskingBudget,
+            ConversationState::ConfirmingSummary => SavedState::ConfirmingSummary,
+            ConversationState::ConfirmingTranscriptEmail => SavedState::ConfirmingTranscriptEmail,
+            ConversationState::AskingCallbackEmail => SavedState::AskingCallbackEmail,
+        }
+    }
+}
+
+impl From<SavedState> for ConversationState {
+    fn from(state: SavedState) -> Self {
+        match state {
+            SavedState::Idle => ConversationState::Idle,
+            SavedState::AskingName => ConversationState::AskingName,
+            SavedState::AskingEmail => ConversationState::AskingEmail,
+            SavedState::AskingProjectDetails => ConversationState::AskingProjectDetails,
+            SavedState::AskingBudget => ConversationState::AskingBudget,
+            SavedState::ConfirmingSummary => ConversationState::ConfirmingSummary,
+            SavedState::ConfirmingTranscriptEmail => ConversationState::ConfirmingTranscriptEmail,
+            SavedState::AskingCallbackEmail => ConversationState::AskingCallbackEmail,
+        }
+    }
+}
+
+/// [`SessionData`] as saved.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedData {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub project_details: Option<String>,
+    pub budget: Option<FieldValue<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<LeadTag>,
+}
+
+impl From<SessionData> for SavedData {
+    fn from(data: SessionData) -> Self {
+        let SessionData {
+            name,
+            email,
+            project_details,
+            budget,
+            tag,
+        } = data;
+        Self {
+            name,
+            email,
+            project_details,
+            budget,
+            tag,
+        }
+    }
+}
+
+impl From<SavedData> for SessionData {
+    fn from(data: SavedData) -> Self {
+        let SavedData {
+            name,
+            email,
+            project_details,
+            budget,
+            tag,
+        } = data;
+        Self {
+            name,
+            email,
+            project_details,
+            budget,
+            tag,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedMessage {
+    pub id: String,
+    pub role: Role,
+    pub content: String,
+    /// When it was sent, in Unix milliseconds.
+    pub at_ms: u64,
+    pub receipt: Option<ReceiptStatus>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<MessageMetadata>,
+}
+
+/// [`Session`] as saved.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedSession {
+    pub schema_version: u32,
+    pub id: String,
+    pub language: Language,
+    pub tenant: String,
+    pub state: SavedState,
+    pub data: SavedData,
+    pub messages: Vec<SavedMessage>,
+    pub age_ms: u64,
+    pub idle_ms: u64,
+    pub inactivity_warned: bool,
+    pub handed_off: bool,
+    pub survey_pending: bool,
+    pub failed_answers: u8,
+    pub client_ip: Option<IpAddr>,
+}
+
+fn age_ms(at: Instant) -> u64 {
+    at.elapsed().as_millis() as u64
+}
+
+/// `age_ms` before the session was saved plus the time since.
+fn instant_from(age_ms: u64, offline_ms: u64) -> Instant {
+    let now = Instant::now();
+    now.checked_sub(Duration::from_millis(age_ms.saturating_add(offline_ms)))
+        .unwrap_or(now)
+}
+
+impl SavedSession {
+    pub fn from_session(session: &Session, client_ip: Option<IpAddr>) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            id: session.id.clone(),
+            language: session.language,
+            tenant: session.tenant.clone(),
+            state: session.state.into(),
+            data: session.data.clone().into(),
+            messages: session
+                .messages
+                .iter()
+                .map(|m| SavedMessage {
+                    id: m.id.clone(),
+                    role: m.role,
+                    content: m.content.clone(),
+                    at_ms: m.at_ms(),
+                    receipt: m.receipt,
+                    metadata: m.metadata.clone(),
+                })
+                .collect(),
+            age_ms: age_ms(session.created_at),
+            idle_ms: age_ms(session.last_activity),
+            inactivity_warned: session.inactivity_warned,
+            handed_off: session.handed_off,
+            survey_pending: session.survey_pending,
+            failed_answers: session.failed_answers,
+            client_ip,
+        }
+    }
+
+    /// Reads a session saved at `saved_at_ms` with any schema version up
+    /// to this one, migrating it as needed.
+    pub fn from_value(value: Value, saved_at_ms: u64) -> Result<Self, SchemaError> {
+        let Value::Object(mut session) = value else {
+            return Err(SchemaError::Malformed);
+        };
+        let version = match session.get("schema_version") {
+            None => 1,
+            Some(v) => v
+                .as_u64()
+                .and_then(|v| u32::try_from(v).ok())
+                .filter(|&v| v >= 1)
+                .ok_or(SchemaError::Malformed)?,
+        };
+        if version > SCHEMA_VERSION {
+            return Err(SchemaError::Newer(version));
+        }
+        for migrate in &MIGRATIONS[version as usize - 1..] {
+            migrate(&mut session, saved_at_ms);
+        }
+        session.insert("schema_version".into(), SCHEMA_VERSION.into());
+        serde_json::from_value(Value::Object(session)).map_err(SchemaError::Json)
+    }
+
+    /// The live session, `offline_ms` after it was saved.
+    pub fn into_session(self, offline_ms: u64) -> (Session, Option<IpAddr>) {
+        let mut session = Session::new(self.id, self.language);
+        session.tenant = self.tenant;
+        session.state = self.state.into();
+        session.data = self.data.into();
+        session.messages = self
+            .messages
+            .into_iter()
+            .map(|m| Message {
+                id: m.id,
+                role: m.role,
+                content: m.content,
+                timestamp: UNIX_EPOCH + Duration::from_millis(m.at_ms),
+                receipt: m.receipt,
+                metadata: m.metadata,
+            })
+            .collect();
+        session.created_at = instant_from(self.age_ms, offline_ms);
+        session.last_activity = instant_from(self.idle_ms, offline_ms);
+        session.inactivity_warned = self.inactivity_warned;
+        session.survey_pending = self.survey_pending;
+        session.failed_answers = self.failed_answers;
+        // Nobody is watching the new instance yet.
+        session.handed_off = false;
+        (session, self.client_ip)
+    }
+}
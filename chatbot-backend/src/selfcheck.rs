@@ -0,0 +1,89 @@
+//! Checks run at boot, before the server takes any requests.
+//!
+//! Each setting that names a directory, a file to write or a URL to call
+//! is tried once, and every problem found is reported together, so a
+//! broken deployment stops at startup with a list of what to fix rather
+//! than failing on the first inquiry that needs a report written.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::assets::AssetSource;
+
+#[derive(Debug, Default)]
+pub struct SelfCheck {
+    problems: Vec<String>,
+}
+
+impl SelfCheck {
+    /// `dir` exists, or can be created, and accepts new files.
+    pub fn writable_dir(&mut self, setting: &str, dir: &Path) {
+        if let Err(e) = fs::create_dir_all(dir).and_then(|_| probe(dir)) {
+            self.problems.push(format!(
+                "{setting}: cannot write to {}: {e}; point it at a writable directory",
+                dir.display()
+            ));
+        }
+    }
+
+    /// `path` can be created or replaced, as files saved through a
+    /// temporary sibling are.
+    pub fn writable_file(&mut self, setting: &str, path: &Path) {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        if path.is_dir() {
+            self.problems
+                .push(format!("{setting}: {} is a directory", path.display()));
+        } else if let Err(e) = probe(dir) {
+            self.problems.push(format!(
+                "{setting}: cannot write to {}: {e}; create the directory or make it writable",
+                dir.display()
+            ));
+        }
+    }
+
+    /// `url`, when set, is one the HTTP client can call.
+    pub fn url(&mut self, setting: &str, url: Option<&str>) {
+        let Some(url) = url else {
+            return;
+        };
+        let valid = reqwest::Url::parse(url)
+            .is_ok_and(|u| matches!(u.scheme(), "http" | "https") && u.host().is_some());
+        if !valid {
+            self.problems
+                .push(format!("{setting} must be an http(s) URL, got {url:?}"));
+        }
+    }
+
+    /// The widget has an `index.html` to serve.
+    pub fn assets(&mut self, source: &AssetSource) {
+        if let AssetSource::Directory(dir) = source
+            && !dir.join("index.html").is_file()
+        {
+            self.problems.push(format!(
+                "{} has no index.html; run from the directory holding static/ or set STATIC_ASSETS=embedded",
+                dir.display()
+            ));
+        }
+    }
+
+    /// Everything that is wrong, in the order it was checked.
+    pub fn finish(self) -> Result<(), Vec<String>> {
+        if self.problems.is_empty() {
+            Ok(())
+        } else {
+            Err(self.problems)
+        }
+    }
+}
+
+/// Creates and removes a file in `dir`.
+fn probe(dir: &Path) -> io::Result<()> {
+    let path: PathBuf = dir.join(format!(".selfcheck-{}", std::process::id()));
+    fs::write(&path, b"")?;
+    fs::remove_file(&path)
+}
@@ -0,0 +1,943 @@
+//! A chat turn, independent of the transport. The REST handlers in
+//! `routes::chat` and the gRPC service in `grpc` both go through here.
+
+use std::{fs, net::IpAddr, time::Duration};
+
+use serde::Serialize;
+use serde_json::json;
+use tokio::sync::{OwnedMutexGuard, broadcast};
+use tracing::{error, info, warn};
+
+use crate::{
+    ai,
+    chatbot::{self, AnswerRules, Intent, Reply, Vocabulary},
+    error::ApiError,
+    events::{self, ConversationEvent, TranscriptEntry},
+    handoff,
+    i18n::{Language, Text, text},
+    livechat, mail,
+    maintenance::MaintenanceStatus,
+    outbox::OutboxJob,
+    plugins::IntentRequest,
+    report::{ReportError, ReportFormat, archive_report, generate_report, report_path},
+    session::{
+        ConversationState, MergeError, MessageMetadata, ReceiptStatus, Role, Session,
+        TurnCheckpoint,
+    },
+    state::{AppState, SharedState},
+    survey, tenant,
+    trace::{IntentSource, TurnTrace},
+};
+
+/// Runs `messages` through the bot in order for one session, holding it
+/// for the whole batch so no other request can interleave, except while
+/// the AI fallback answers (see [`take_turn`]). A new session is created
+/// when `session_id` is missing or expired, for `tenant` (see
+/// [`crate::tenant`]). `language` is an ISO 639-1 code; unknown codes are
+/// ignored. `metadata` is stored on every message of the batch. In
+/// maintenance mode nothing runs and the visitor is told to come back
+/// later. A conversation past its limit (see
+/// [`crate::session::SessionLimits`]) gets a goodbye and is closed; the
+/// rest of the batch goes unanswered, as it does when the session is
+/// deleted while the AI fallback answers.
+pub async fn converse(
+    state: &SharedState,
+    session_id: Option<&str>,
+    language: Option<&str>,
+    tenant: Option<&str>,
+    messages: &[&str],
+    metadata: Option<&MessageMetadata>,
+    client_ip: Option<IpAddr>,
+) -> Result<(String, Vec<Reply>), ApiError> {
+    let Some(first) = messages.first() else {
+        return Err(ApiError::BadRequest("messages must not be empty".into()));
+    };
+    let tenant = tenant::resolve(tenant)?;
+    let requested_language = language.and_then(Language::from_code);
+    if state.maintenance.is_on() {
+        let session_language = match session_id {
+            Some(id) => state.sessions.with_session(id, |s| s.language).await,
+            None => None,
+        };
+        let language = session_language
+            .or(requested_language)
+            .or_else(|| Language::detect(first))
+            .unwrap_or_default();
+        return Err(ApiError::Busy(
+            text(language, Text::Maintenance).to_string(),
+        ));
+    }
+    let session_id = resolve_session(
+        state,
+        session_id,
+        requested_language,
+        tenant,
+        first,
+        client_ip,
+    )
+    .await?;
+
+    let mut session = state
+        .sessions
+        .lock_session(&session_id)
+        .await
+        .ok_or_else(|| ApiError::NotFound("session expired".into()))?;
+    if let Some(language) = requested_language {
+        session.language = language;
+    }
+    let tenant = session.tenant.clone();
+    let mut held = Some(session);
+    let mut turns = Vec::with_capacity(messages.len());
+    for message in messages {
+        let Some(session) = held.take() else {
+            break;
+        };
+        let (session, turn) = take_turn(state, session, message, metadata).await;
+        held = session;
+        let Some(turn) = turn else {
+            break;
+        };
+        let closed = turn.0.session_closed;
+        turns.push(turn);
+        if closed {
+            break;
+        }
+    }
+    drop(held);
+    if turns.iter().any(|(reply, _)| reply.session_closed) {
+        close_session(state, &session_id).await;
+    }
+
+    let replies = turns.iter().map(|(reply, _)| reply.clone()).collect();
+    spawn_after_turns(state, &session_id, tenant, turns);
+    Ok((session_id, replies))
+}
+
+/// One visitor message through the pipeline: the tenant's preprocessors,
+/// the registered handlers, the AI fallback and the rules, in that order.
+///
+/// The session is let go while the AI fallback answers, which can take
+/// seconds, so other requests for it don't wait on the model; a turn of
+/// theirs that got in meanwhile makes the answer stale, and the rules
+/// answer instead. Returns the session held again, or `None` with no turn
+/// when it was deleted meanwhile.
+async fn take_turn(
+    state: &SharedState,
+    mut session: OwnedMutexGuard<Session>,
+    message: &str,
+    metadata: Option<&MessageMetadata>,
+) -> (
+    Option<OwnedMutexGuard<Session>>,
+    Option<(Reply, Option<Session>)>,
+) {
+    let mut trace = TurnTrace::start(message);
+    let (message, steps) = state
+        .config_for(&session.tenant)
+        .preprocess_steps(&session, message);
+    trace.preprocessed = message.clone();
+    trace.preprocessors = steps;
+    trace.lap("preprocess");
+    let over = state.sessions.settings().limits.conversation_over(&session);
+    let mut custom = None;
+    if !over {
+        custom = handler_reply(&session, &message, metadata, state).await;
+    }
+    if !over
+        && custom.is_none()
+        && let Some(request) = fallback_request(state, &session, &message, metadata)
+    {
+        trace.ai_called = true;
+        let prompt = state.ai.prompt(&request);
+        trace.prompt = Some(prompt.used());
+        let (session_id, last_message) = (
+            session.id.clone(),
+            session.messages.last().map(|m| m.id.clone()),
+        );
+        drop(session);
+        let answer = ai::answer(state, &request, &prompt).await;
+        let Some(relocked) = state.sessions.lock_session(&session_id).await else {
+            warn!(%session_id, "session deleted while the AI fallback answered");
+            return (None, None);
+        };
+        session = relocked;
+        let moved_on = session.messages.last().map(|m| &m.id) != last_message.as_ref()
+            || session.state != ConversationState::Idle;
+        if moved_on {
+            info!(%session_id, "AI answer dropped; the session moved on meanwhile");
+        } else {
+            custom = answer.map(|reply| Reply::custom(ai::FALLBACK_INTENT, reply));
+        }
+    }
+    trace.lap("custom_handler");
+    let turn = run_turn(state, &mut session, &message, metadata, custom, trace);
+    (Some(session), Some(turn))
+}
+
+/// The answer of the registered handler whose phrases `message` matches,
+/// if any (see [`crate::plugins`]). The session stays locked meanwhile, so
+/// the next message waits for it.
+async fn handler_reply(
+    session: &Session,
+    message: &str,
+    metadata: Option<&MessageMetadata>,
+    state: &SharedState,
+) -> Option<Reply> {
+    let handler = state.intents.find(session, message)?;
+    let reply = handler
+        .handle(&request_for(session, message, metadata))
+        .await?;
+    Some(Reply::custom(handler.name(), reply))
+}
+
+fn request_for(
+    session: &Session,
+    message: &str,
+    metadata: Option<&MessageMetadata>,
+) -> IntentRequest {
+    IntentRequest {
+        session_id: session.id.clone(),
+        tenant: session.tenant.clone(),
+        language: session.language,
+        message: message.to_string(),
+        metadata: metadata.cloned(),
+    }
+}
+
+/// What to ask the AI fallback (see [`crate::ai`]), when the rules would
+/// not understand `message` outside the inquiry flow and the tenant has
+/// an allowance.
+fn fallback_request(
+    state: &AppState,
+    session: &Session,
+    message: &str,
+    metadata: Option<&MessageMetadata>,
+) -> Option<IntentRequest> {
+    if session.state != ConversationState::Idle
+        || session.handed_off
+        || session.survey_pending
+        || state.ai.allowance(&session.tenant).is_none()
+    {
+        return None;
+    }
+    // Tried on a copy, as the rules may change the session.
+    let mut draft = session.clone();
+    let (synonyms, learned) = (state.synonyms.table(), state.training.learned());
+    let vocabulary = Vocabulary {
+        synonyms: &synonyms,
+        learned: &learned,
+    };
+    if rule_reply(state, &mut draft, message, metadata, vocabulary).intent != Intent::Unknown {
+        return None;
+    }
+    Some(request_for(session, message, metadata))
+}
+
+/// The transcript of a live session.
+pub async fn history(state: &AppState, session_id: &str) -> Result<Vec<TranscriptEntry>, ApiError> {
+    if state.sessions.lock_session(session_id).await.is_none() {
+        return Err(ApiError::NotFound("session expired".into()));
+    }
+    Ok(events::project(&state.events.session_events(session_id)).transcript)
+}
+
+/// New transcript lines of a live session as they are written.
+pub async fn subscribe(
+    state: &AppState,
+    session_id: &str,
+) -> Result<broadcast::Receiver<TranscriptEntry>, ApiError> {
+    if state.sessions.lock_session(session_id).await.is_none() {
+        return Err(ApiError::NotFound("session expired".into()));
+    }
+    Ok(state.watchers.subscribe(session_id))
+}
+
+/// Records that the visitor's client received or showed bot and operator
+/// messages. Returns how many receipts were news; repeats and downgrades
+/// (delivered after read) are ignored. Receipts don't count as activity.
+pub async fn record_receipts(
+    state: &AppState,
+    session_id: &str,
+    message_ids: &[String],
+    status: ReceiptStatus,
+) -> Result<usize, ApiError> {
+    let (tenant, recorded, delivered, read) = state
+        .sessions
+        .with_session(session_id, |session| {
+            if let Some(unknown) = message_ids.iter().find(|id| {
+                !session
+                    .messages
+                    .iter()
+                    .any(|m| &&m.id == id && m.role != Role::User)
+            }) {
+                return Err(ApiError::BadRequest(format!(
+                    "no message {unknown} to acknowledge"
+                )));
+            }
+            let mut events = Vec::new();
+            let (mut delivered, mut read) = (0, 0);
+            for id in message_ids {
+                let Some(before) = session.record_receipt(id, status) else {
+                    continue;
+                };
+                if before >= Some(status) {
+                    continue;
+                }
+                delivered += u64::from(before.is_none());
+                read += u64::from(status == ReceiptStatus::Read);
+                events.push(ConversationEvent::ReceiptRecorded {
+                    message_id: id.clone(),
+                    status,
+                });
+            }
+            let recorded = events.len();
+            state.events.append(session_id, events);
+            Ok((session.tenant.clone(), recorded, delivered, read))
+        })
+        .await
+        .ok_or_else(|| ApiError::NotFound("session expired".into()))??;
+    state.metrics.record_receipts(&tenant, delivered, read);
+    Ok(recorded)
+}
+
+/// How long after sending it the visitor may edit or delete a message.
+pub const EDIT_WINDOW: Duration = Duration::from_secs(120);
+
+/// The outcome of editing or deleting the visitor's last message.
+#[derive(Debug, Default)]
+pub struct Correction {
+    /// Id of the edited message; a new one when the turn was run again.
+    pub message_id: Option<String>,
+    /// Messages removed with the undone turn, such as the old reply.
+    pub retracted: Vec<String>,
+    /// The answer to the corrected message, when the turn was run again.
+    pub reply: Option<Reply>,
+}
+
+/// Replaces the visitor's last message with `text`. When its turn can be
+/// undone (see [`Session::can_undo`]) the session goes back to where it
+/// was and the corrected message is answered afresh, through the same
+/// pipeline as a new one (see [`take_turn`]); otherwise only the
+/// transcript is corrected and what the bot made of the message stands.
+pub async fn edit_message(
+    state: &SharedState,
+    session_id: &str,
+    message_id: &str,
+    text: &str,
+) -> Result<Correction, ApiError> {
+    let mut session = state
+        .sessions
+        .lock_session(session_id)
+        .await
+        .ok_or_else(|| ApiError::NotFound("session expired".into()))?;
+    let tenant = session.tenant.clone();
+    let index = correctable(&session, message_id)?;
+    if !session.can_undo(message_id) {
+        let (text, _) = state
+            .config_for(&session.tenant)
+            .preprocess_steps(&session, text);
+        session.messages[index].content = text.clone();
+        state.events.append(
+            session_id,
+            vec![ConversationEvent::MessageEdited {
+                message_id: message_id.to_string(),
+                text,
+            }],
+        );
+        return Ok(Correction {
+            message_id: Some(message_id.to_string()),
+            ..Correction::default()
+        });
+    }
+    let metadata = session.messages[index].metadata.clone();
+    let retracted = undo_turn(state, &mut session);
+    let (session, turn) = take_turn(state, session, text, metadata.as_ref()).await;
+    let (Some(session), Some((reply, snapshot))) = (session, turn) else {
+        return Err(ApiError::NotFound("session expired".into()));
+    };
+    let correction = Correction {
+        message_id: session.messages.get(index).map(|m| m.id.clone()),
+        retracted,
+        reply: Some(reply.clone()),
+    };
+    drop(session);
+    if reply.session_closed {
+        close_session(state, session_id).await;
+    }
+    spawn_after_turns(state, session_id, tenant, vec![(reply, snapshot)]);
+    Ok(correction)
+}
+
+/// Takes a conversation that reached its limit out of service. Like a
+/// deleted session it can be restored for a while.
+async fn close_session(state: &AppState, session_id: &str) {
+    if state.sessions.remove_session(session_id).await {
+        state.push.forget(session_id);
+        state.watchers.forget(session_id);
+        state.traces.forget(session_id);
+        info!(%session_id, "conversation limit reached; session closed");
+    }
+}
+
+/// Takes back the visitor's last message. When its turn can be undone the
+/// reply goes too and the session returns to where it was.
+pub async fn delete_message(
+    state: &AppState,
+    session_id: &str,
+    message_id: &str,
+) -> Result<Correction, ApiError> {
+    state
+        .sessions
+        .with_session(session_id, |session| {
+            let index = correctable(session, message_id)?;
+            let retracted = if session.can_undo(message_id) {
+                undo_turn(state, session)
+            } else {
+                session.messages.remove(index);
+                state.events.append(
+                    session_id,
+                    vec![ConversationEvent::MessageDeleted {
+                        message_id: message_id.to_string(),
+                    }],
+                );
+                vec![message_id.to_string()]
+            };
+            Ok(Correction {
+                retracted,
+                ..Correction::default()
+            })
+        })
+        .await
+        .ok_or_else(|| ApiError::NotFound("session expired".into()))?
+}
+
+/// Checks that `message_id` is the visitor's last message and recent
+/// enough to change, and returns its position.
+fn correctable(session: &Session, message_id: &str) -> Result<usize, ApiError> {
+    let Some(index) = session.messages.iter().position(|m| m.id == message_id) else {
+        return Err(ApiError::NotFound(format!("no message {message_id}")));
+    };
+    let message = &session.messages[index];
+    if message.role != Role::User {
+        return Err(ApiError::Forbidden(
+            "only the visitor's own messages can be changed".into(),
+        ));
+    }
+    let later = session.messages[index + 1..]
+        .iter()
+        .any(|m| m.role == Role::User);
+    if later || message.timestamp.elapsed().unwrap_or_default() > EDIT_WINDOW {
+        return Err(ApiError::BadRequest(format!(
+            "message {message_id} can no longer be changed"
+        )));
+    }
+    Ok(index)
+}
+
+/// Undoes the session's last turn and records it: the turn's messages are
+/// deleted and its state and data changes reverted. Returns the ids of the
+/// deleted messages.
+fn undo_turn(state: &AppState, session: &mut Session) -> Vec<String> {
+    let (before_state, before_data) = (session.state, session.data.clone());
+    let retracted = session.undo_turn();
+    let mut events: Vec<ConversationEvent> = retracted
+        .iter()
+        .map(|id| ConversationEvent::MessageDeleted {
+            message_id: id.clone(),
+        })
+        .collect();
+    events.extend(events::changes(before_state, &before_data, session));
+    state.events.append(&session.id, events);
+    retracted
+}
+
+/// Merges session `from` into `into` (see [`SessionManager::merge`]) and
+/// the event logs with them. Events are appended to bring the merged log's
+/// state and data in line with the merged session, so replaying it gives
+/// the surviving session.
+///
+/// [`SessionManager::merge`]: crate::session::SessionManager::merge
+pub async fn merge_sessions(
+    state: &AppState,
+    from: &str,
+    into: &str,
+) -> Result<events::ConversationProjection, ApiError> {
+    let merged = state
+        .sessions
+        .merge(from, into)
+        .await
+        .map_err(|e| match e {
+            MergeError::SameSession => {
+                ApiError::BadRequest("cannot merge a session into itself".into())
+            }
+            MergeError::NotFound(id) => ApiError::NotFound(format!("no session {id}")),
+        })?;
+    state.events.merge(from, into);
+    let replayed = events::project(&state.events.session_events(into));
+    let mut reconciled = vec![ConversationEvent::SessionMerged {
+        from: from.to_string(),
+    }];
+    reconciled.extend(events::changes(replayed.state, &replayed.data, &merged));
+    state.events.append(into, reconciled);
+    state.push.forget(from);
+    state.watchers.forget(from);
+    state.traces.forget(from);
+    info!(%from, %into, "sessions merged");
+    Ok(events::project(&state.events.session_events(into)))
+}
+
+#[derive(Debug, Serialize)]
+pub struct RegeneratedReport {
+    pub session_id: String,
+    pub format: ReportFormat,
+    pub path: String,
+    /// Where the report it replaced was moved, if there was one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous: Option<String>,
+}
+
+/// Writes a confirmed inquiry's report again from the session as it is now
+/// and the current texts and format, keeping the previous one as a
+/// numbered version.
+pub async fn regenerate_report(
+    state: &SharedState,
+    session_id: &str,
+) -> Result<RegeneratedReport, ApiError> {
+    let session = state
+        .sessions
+        .with_session(session_id, |s| s.clone())
+        .await
+        .ok_or_else(|| ApiError::NotFound(format!("no session {session_id}")))?;
+    let projection = events::project(&state.events.session_events(session_id));
+    if projection.confirmed_at_ms.is_none() {
+        return Err(ApiError::BadRequest(format!(
+            "session {session_id} has no confirmed inquiry"
+        )));
+    }
+
+    let format = state.config_for(&session.tenant).report_format();
+    let dir = state.reports_dir.clone();
+    let written = tokio::task::spawn_blocking(move || {
+        let previous = archive_report(&dir, &session.id, format)?;
+        let path = generate_report(&session, &dir, format)?;
+        Ok::<_, ReportError>((path, previous))
+    })
+    .await
+    .map_err(|e| {
+        error!(%session_id, "report task failed: {e}");
+        ApiError::Internal("the report could not be written".into())
+    })?;
+    let (path, previous) = written.map_err(|e| {
+        error!(%session_id, "report regeneration failed: {e}");
+        ApiError::Busy("the report could not be written".into())
+    })?;
+
+    let path = path.display().to_string();
+    state.events.append(
+        session_id,
+        vec![ConversationEvent::ReportGenerated { path: path.clone() }],
+    );
+    info!(%session_id, %path, "report regenerated");
+    Ok(RegeneratedReport {
+        session_id: session_id.to_string(),
+        format,
+        path,
+        previous: previous.map(|p| p.display().to_string()),
+    })
+}
+
+/// Turns maintenance mode on or off. Sessions get the time it lasted
+/// back, so none expire because of it.
+pub async fn set_maintenance(state: &AppState, enabled: bool) -> MaintenanceStatus {
+    if enabled {
+        if state.maintenance.start() {
+            warn!("maintenance mode on");
+        }
+    } else if let Some(lasted) = state.maintenance.end() {
+        state.sessions.extend_idle(lasted).await;
+        info!(lasted_secs = lasted.as_secs(), "maintenance mode off");
+    }
+    state.maintenance.status()
+}
+
+/// Returns a live session id, creating a session when needed. A new
+/// session's language comes from the request or is guessed from `first_message`.
+///
+/// When a session cap is reached the visitor gets a polite "we're busy"
+/// reply in their language instead.
+async fn resolve_session(
+    state: &AppState,
+    session_id: Option<&str>,
+    requested_language: Option<Language>,
+    tenant: &str,
+    first_message: &str,
+    client_ip: Option<IpAddr>,
+) -> Result<String, ApiError> {
+    let language = requested_language.or_else(|| Language::detect(first_message));
+    let (session_id, created) = state
+        .sessions
+        .resolve(session_id, language, tenant, client_ip)
+        .await
+        .map_err(|e| {
+            warn!(client_ip = ?client_ip, %tenant, "session refused: {e}");
+            state.metrics.record_session_rejected(tenant, e.as_str());
+            ApiError::Busy(text(language.unwrap_or_default(), Text::Busy).to_string())
+        })?;
+    if created {
+        state.metrics.record_session_created(tenant);
+        if let Some(language) = state
+            .sessions
+            .with_session(&session_id, |session| session.language)
+            .await
+        {
+            state.events.append(
+                &session_id,
+                vec![ConversationEvent::SessionStarted { language }],
+            );
+        }
+    }
+    Ok(session_id)
+}
+
+/// How `message` reads on its own, before the flow has its say.
+pub fn read_intent(
+    message: &str,
+    custom: Option<&Reply>,
+    vocabulary: Vocabulary,
+) -> (IntentSource, Intent) {
+    match custom.map(|c| c.intent) {
+        Some(Intent::Custom(ai::FALLBACK_INTENT)) => {
+            // What the rules read the message as, though they couldn't
+            // answer it.
+            let (_, intent) = read_intent(message, None, vocabulary);
+            return (IntentSource::Fallback, intent);
+        }
+        Some(intent) => return (IntentSource::CustomHandler, intent),
+        None => {}
+    }
+    if let Some(intent) = vocabulary.learned.intent(message) {
+        return (IntentSource::Learned, intent);
+    }
+    match chatbot::detect_intent(&vocabulary.synonyms.expand(message)) {
+        Intent::Unknown => (IntentSource::None, Intent::Unknown),
+        intent => (IntentSource::Keywords, intent),
+    }
+}
+
+/// Runs one message through the bot while the session is held, unless a
+/// registered handler already answered it with `custom`, and keeps the
+/// turn's `trace`. Returns the reply and, when it calls for a report or an
+/// email, a snapshot of the session to build them from.
+fn run_turn(
+    state: &AppState,
+    session: &mut Session,
+    message: &str,
+    metadata: Option<&MessageMetadata>,
+    custom: Option<Reply>,
+    mut trace: TurnTrace,
+) -> (Reply, Option<Session>) {
+    let (before_state, before_data) = (session.state, session.data.clone());
+    let over = state.sessions.settings().limits.conversation_over(session);
+    let message_id = session.push_visitor_message(message, metadata.cloned());
+    trace.message_id = message_id.clone();
+    let mut checkpoint = TurnCheckpoint {
+        state: before_state,
+        data: before_data.clone(),
+        failed_answers: session.failed_answers,
+        message_ids: vec![message_id.clone()],
+    };
+    handoff::publish(state, &session.id, &message_id, Role::User, message);
+    session.operators_available = state.presence.anyone_online();
+    let (synonyms, learned) = (state.synonyms.table(), state.training.learned());
+    let vocabulary = Vocabulary {
+        synonyms: &synonyms,
+        learned: &learned,
+    };
+    let (source, detected) = read_intent(message, custom.as_ref(), vocabulary);
+    let mut reply = if over {
+        let overrides = state.tenant_replies.for_tenant(&session.tenant);
+        chatbot::wrap_up(session.language, &overrides)
+    } else {
+        custom.unwrap_or_else(|| rule_reply(state, session, message, metadata, vocabulary))
+    };
+    trace.lap("reply");
+    trace.postprocessors = state
+        .config_for(&session.tenant)
+        .postprocess(session, &mut reply);
+    trace.lap("postprocess");
+    // An answer from the AI fallback has an intent of its own, so only
+    // what it didn't answer is logged.
+    if reply.intent == Intent::Unknown {
+        state.unanswered.record(message, session.language);
+    }
+    // Answers inside the inquiry flow are names, addresses and the like.
+    if before_state == ConversationState::Idle {
+        state.keywords.record(message);
+    }
+    if before_state == ConversationState::Idle
+        && !matches!(
+            session.state,
+            ConversationState::Idle
+                | ConversationState::ConfirmingTranscriptEmail
+                | ConversationState::AskingCallbackEmail
+        )
+    {
+        state.metrics.record_inquiry_started(&session.tenant);
+    }
+    let fallback = source == IntentSource::Fallback;
+    state.metrics.record_language(
+        &session.tenant,
+        session.language.code(),
+        reply.intent == Intent::Unknown || fallback,
+    );
+    if fallback {
+        state
+            .metrics
+            .record_fallback(&session.tenant, session.language.code());
+    }
+
+    let mut turn_events = vec![ConversationEvent::MessageReceived {
+        text: message.to_string(),
+        message_id,
+        metadata: metadata.cloned(),
+    }];
+    turn_events.extend(events::changes(before_state, &before_data, session));
+    if reply.inquiry_confirmed {
+        turn_events.push(ConversationEvent::InquiryConfirmed);
+    }
+    if reply.escalation_requested {
+        turn_events.push(ConversationEvent::EscalationRequested);
+    }
+    if reply.callback_requested {
+        turn_events.push(ConversationEvent::CallbackRequested);
+    }
+    // While an operator has the session the bot's reply is empty.
+    if !reply.text.is_empty() {
+        let message_id = session.push_message(Role::Bot, &reply.text);
+        handoff::publish(state, &session.id, &message_id, Role::Bot, &reply.text);
+        turn_events.push(ConversationEvent::ReplySent {
+            text: reply.text.clone(),
+            intent: reply.intent.as_str().to_string(),
+            message_id: message_id.clone(),
+        });
+        checkpoint.message_ids.push(message_id.clone());
+        reply.message_id = Some(message_id);
+    }
+    if let Some(score) = reply.satisfaction {
+        turn_events.push(ConversationEvent::SatisfactionRated { score });
+    }
+    if reply.session_closed {
+        turn_events.push(ConversationEvent::SessionClosed);
+    }
+    // Appended while the session is held so a session's events stay in
+    // turn order.
+    state.events.append(&session.id, turn_events);
+
+    let snapshot = (reply.inquiry_confirmed
+        || reply.transcript_requested
+        || reply.escalation_requested
+        || reply.callback_requested)
+        .then(|| session.clone());
+    session.last_turn = snapshot.is_none().then_some(checkpoint);
+
+    trace.lap("record");
+    trace.intent_source = source;
+    trace.detected_intent = detected.as_str().to_string();
+    trace.intent = reply.intent.as_str().to_string();
+    trace.sub_intent = reply.sub_intent.clone();
+    trace.state_before = before_state;
+    trace.state_after = session.state;
+    trace.template = reply.template.clone();
+    trace.reply = reply.text.clone();
+    state.traces.record(&session.id, trace);
+    (reply, snapshot)
+}
+
+/// The built-in rules' reply to `message`, with the tenant's config.
+pub(crate) fn rule_reply(
+    state: &AppState,
+    session: &mut Session,
+    message: &str,
+    metadata: Option<&MessageMetadata>,
+    vocabulary: Vocabulary,
+) -> Reply {
+    let overrides = state.tenant_replies.for_tenant(&session.tenant);
+    let config = state.config_for(&session.tenant);
+    let (qualification, validation) = (config.qualification(), config.validation());
+    let page = metadata
+        .and_then(|m| m.page_url.as_deref())
+        .and_then(|url| config.page_hint(url));
+    chatbot::generate_reply(
+        session,
+        message,
+        &config.catalog(),
+        &overrides,
+        AnswerRules {
+            validation: &validation,
+            qualification: qualification.as_ref(),
+        },
+        page.as_ref(),
+        vocabulary,
+    )
+}
+
+/// Runs [`after_turn`] for each turn, in order, without holding up the
+/// replies: queuing a webhook rewrites the outbox file and events go to
+/// the event log, so it happens on a blocking thread while the response
+/// is sent.
+fn spawn_after_turns(
+    state: &SharedState,
+    session_id: &str,
+    tenant: String,
+    turns: Vec<(Reply, Option<Session>)>,
+) {
+    let (state, session_id) = (state.clone(), session_id.to_string());
+    tokio::task::spawn_blocking(move || {
+        for (reply, snapshot) in turns {
+            after_turn(&state, &session_id, &tenant, &reply, snapshot);
+        }
+    });
+}
+
+/// Side effects of a turn that don't need the session: metrics, report
+/// generation, the confirmation webhook, the transcript email and the
+/// transfer to a live-chat tool.
+fn after_turn(
+    state: &SharedState,
+    session_id: &str,
+    tenant: &str,
+    reply: &Reply,
+    snapshot: Option<Session>,
+) {
+    if reply.escalation_requested
+        && state.live_chat.is_some()
+        && let Some(session) = snapshot.clone()
+    {
+        let state = state.clone();
+        tokio::spawn(async move { livechat::transfer(&state, session).await });
+    }
+    if reply.transcript_requested
+        && let Some(session) = snapshot.clone()
+    {
+        queue_transcript_email(state, session);
+    }
+    if reply.callback_requested
+        && let Some(session) = &snapshot
+    {
+        request_callback(state, session);
+    }
+    if reply.inquiry_confirmed
+        && let Some(session) = snapshot
+    {
+        state.metrics.record_inquiry_confirmed(tenant);
+        let config = state.config_for(tenant);
+        if let Some(url) = config.webhook_url() {
+            let data = json!({
+                "session_id": session.id,
+                "language": session.language,
+                "inquiry": session.data,
+            });
+            state
+                .outbox
+                .enqueue(OutboxJob::new("inquiry.confirmed", &url, data));
+        }
+        let (state, format) = (state.clone(), config.report_format());
+        tokio::spawn(async move {
+            let session_id = session.id.clone();
+            let written = tokio::task::spawn_blocking({
+                let state = state.clone();
+                move || generate_report(&session, &state.reports_dir, format)
+            })
+            .await;
+            match written {
+                Ok(Ok(path)) => {
+                    info!(%session_id, path = %path.display(), "report generated");
+                    state.events.append(
+                        &session_id,
+                        vec![ConversationEvent::ReportGenerated {
+                            path: path.display().to_string(),
+                        }],
+                    );
+                    survey::ask(&state, &session_id).await;
+                }
+                Ok(Err(e)) => error!(%session_id, "report generation failed: {e}"),
+                Err(e) => error!(%session_id, "report task failed: {e}"),
+            }
+        });
+    }
+
+    info!(
+        %session_id,
+        tenant,
+        intent = reply.intent.as_str(),
+        "turn answered"
+    );
+    state.metrics.record_message(tenant);
+    state.metrics.record_intent(tenant, reply.intent.as_str());
+    if let Some(score) = reply.satisfaction {
+        state.metrics.record_satisfaction(tenant, score);
+    }
+    if let Some(sub_intent) = &reply.sub_intent {
+        state
+            .metrics
+            .record_sub_intent(tenant, reply.intent.as_str(), sub_intent);
+    }
+}
+
+/// Emails the transcript, attaching the inquiry report if one was written
+/// as a PDF; a Word report is a draft for sales, not for the visitor.
+fn queue_transcript_email(state: &SharedState, session: Session) {
+    let Some(url) = state.mail_relay_url.clone() else {
+        warn!(session_id = %session.id, "MAIL_RELAY_URL is not set; transcript email dropped");
+        return;
+    };
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let report = fs::read(report_path(
+            &state.reports_dir,
+            &session.id,
+            ReportFormat::Pdf,
+        ))
+        .ok();
+        let inbound = state.email.as_ref().map(|email| email.address());
+        let Some(email) = mail::transcript_email(&session, report, inbound) else {
+            return;
+        };
+        let to = email.to.clone();
+        let data = json!({
+            "session_id": session.id,
+            "consent": { "given_at_ms": events::now_ms() },
+            "email": email,
+        });
+        state
+            .outbox
+            .enqueue(OutboxJob::new("email.transcript", &url, data));
+        state.events.append(
+            &session.id,
+            vec![ConversationEvent::TranscriptEmailQueued { to }],
+        );
+    });
+}
+
+/// Asks the team to email the visitor back, through the webhook that gets
+/// confirmed inquiries; the transcript so far goes along.
+fn request_callback(state: &SharedState, session: &Session) {
+    let Some(url) = state.config_for(&session.tenant).webhook_url() else {
+        warn!(session_id = %session.id, "no webhook_url is configured; callback request dropped");
+        return;
+    };
+    let transcript: Vec<_> = session
+        .messages
+        .iter()
+        .map(|m| json!({ "role": m.role, "text": m.content, "at_ms": m.at_ms() }))
+        .collect();
+    let data = json!({
+        "session_id": session.id,
+        "language": session.language,
+        "inquiry": session.data,
+        "transcript": transcript,
+    });
+    state
+        .outbox
+        .enqueue(OutboxJob::new("callback.requested", &url, data));
+    info!(session_id = %session.id, "callback requested");
+}
@@ -0,0 +1,1121 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt, mem,
+    net::IpAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, OwnedMutexGuard, RwLock};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    events::now_ms,
+    flow::{self, Event, Restore},
+    i18n::Language,
+    tenant::DEFAULT_TENANT,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Bot,
+    /// A human operator who took over the conversation.
+    Agent,
+}
+
+/// How far a message sent to the visitor got, as their client reports it.
+/// Read implies delivered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReceiptStatus {
+    Delivered,
+    Read,
+}
+
+/// Longest `page_url` a message may carry, in bytes.
+const MAX_PAGE_URL_BYTES: usize = 2048;
+/// Longest of the other text fields of [`MessageMetadata`], in bytes.
+const MAX_METADATA_FIELD_BYTES: usize = 256;
+/// Largest `custom` payload, as JSON.
+const MAX_CUSTOM_PAYLOAD_BYTES: usize = 4096;
+
+/// What the widget knows about where and how a visitor's message was
+/// sent. Everything is optional; the bot only reads what it understands.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MessageMetadata {
+    /// The page the widget is embedded in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page_title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub widget_version: Option<String>,
+    /// The A/B bucket the visitor is in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub experiment: Option<String>,
+    /// Anything else the embedding site wants kept with the message.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom: Option<serde_json::Value>,
+}
+
+impl MessageMetadata {
+    pub fn validate(&self) -> Result<(), String> {
+        if self
+            .page_url
+            .as_ref()
+            .is_some_and(|url| url.len() > MAX_PAGE_URL_BYTES)
+        {
+            return Err(format!(
+                "metadata.page_url must be at most {MAX_PAGE_URL_BYTES} bytes"
+            ));
+        }
+        for (field, value) in [
+            ("page_title", &self.page_title),
+            ("widget_version", &self.widget_version),
+            ("experiment", &self.experiment),
+        ] {
+            if value
+                .as_ref()
+                .is_some_and(|v| v.len() > MAX_METADATA_FIELD_BYTES)
+            {
+                return Err(format!(
+                    "metadata.{field} must be at most {MAX_METADATA_FIELD_BYTES} bytes"
+                ));
+            }
+        }
+        if let Some(custom) = &self.custom
+            && custom.to_string().len() > MAX_CUSTOM_PAYLOAD_BYTES
+        {
+            return Err(format!(
+                "metadata.custom must be at most {MAX_CUSTOM_PAYLOAD_BYTES} bytes of JSON"
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub id: String,
+    pub role: Role,
+    pub content: String,
+    /// When it was sent, by the wall clock. Idle expiry goes by the
+    /// session's `last_activity` instead, which a clock change can't move.
+    pub timestamp: SystemTime,
+    /// Only for bot and operator messages.
+    pub receipt: Option<ReceiptStatus>,
+    /// Only for visitor messages, when their client sent some.
+    pub metadata: Option<MessageMetadata>,
+}
+
+impl Message {
+    /// When it was sent, in Unix milliseconds.
+    pub fn at_ms(&self) -> u64 {
+        self.timestamp
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default()
+    }
+}
+
+/// Where the visitor is in the inquiry flow.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConversationState {
+    #[default]
+    Idle,
+    AskingName,
+    AskingEmail,
+    AskingProjectDetails,
+    AskingBudget,
+    /// Everything is captured; waiting for the visitor to confirm or edit
+    /// the summary.
+    ConfirmingSummary,
+    /// Waiting for consent to email the conversation transcript.
+    ConfirmingTranscriptEmail,
+    /// Nobody from the team is online; waiting for an address to email
+    /// the visitor back at.
+    AskingCallbackEmail,
+}
+
+impl ConversationState {
+    /// Whether the visitor is partway through an inquiry.
+    pub fn in_inquiry(self) -> bool {
+        matches!(
+            self,
+            ConversationState::AskingName
+                | ConversationState::AskingEmail
+                | ConversationState::AskingProjectDetails
+                | ConversationState::AskingBudget
+                | ConversationState::ConfirmingSummary
+        )
+    }
+}
+
+/// A piece of data the inquiry flow collects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    Name,
+    Email,
+    ProjectDetails,
+    Budget,
+}
+
+/// The answer to an optional question.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldValue<T> {
+    Given(T),
+    /// The visitor chose not to answer ("skip", "not sure").
+    Unspecified,
+}
+
+impl<T> FieldValue<T> {
+    pub fn given(&self) -> Option<&T> {
+        match self {
+            FieldValue::Given(value) => Some(value),
+            FieldValue::Unspecified => None,
+        }
+    }
+}
+
+/// How an inquiry measured up to the minimum budget (see
+/// [`crate::chatbot::Qualification`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LeadTag {
+    /// Told what to expect; the inquiry went on.
+    BelowMinimumBudget,
+    /// Sent to the self-service offering instead of sending an inquiry.
+    SelfService,
+}
+
+impl LeadTag {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LeadTag::BelowMinimumBudget => "below_minimum_budget",
+            LeadTag::SelfService => "self_service",
+        }
+    }
+}
+
+/// Data collected from the visitor during the inquiry flow. `None` means
+/// the question has not been answered yet.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionData {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub project_details: Option<String>,
+    pub budget: Option<FieldValue<String>>,
+    /// Set when the budget fell short of the minimum.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<LeadTag>,
+}
+
+impl Field {
+    pub const ALL: [Field; 4] = [
+        Field::Name,
+        Field::Email,
+        Field::ProjectDetails,
+        Field::Budget,
+    ];
+}
+
+impl SessionData {
+    pub fn get(&self, field: Field) -> Option<FieldValue<String>> {
+        let given = |v: &Option<String>| v.clone().map(FieldValue::Given);
+        match field {
+            Field::Name => given(&self.name),
+            Field::Email => given(&self.email),
+            Field::ProjectDetails => given(&self.project_details),
+            Field::Budget => self.budget.clone(),
+        }
+    }
+
+    pub fn has(&self, field: Field) -> bool {
+        match field {
+            Field::Name => self.name.is_some(),
+            Field::Email => self.email.is_some(),
+            Field::ProjectDetails => self.project_details.is_some(),
+            Field::Budget => self.budget.is_some(),
+        }
+    }
+
+    /// Fills in what `other` knows and `self` doesn't. An answer beats a
+    /// skipped question.
+    pub fn merge(&mut self, other: SessionData) {
+        let SessionData {
+            name,
+            email,
+            project_details,
+            budget,
+            tag,
+        } = other;
+        self.name = self.name.take().or(name);
+        self.email = self.email.take().or(email);
+        self.project_details = self.project_details.take().or(project_details);
+        self.tag = self.tag.or(tag);
+        self.budget = match (self.budget.take(), budget) {
+            (Some(FieldValue::Unspecified) | None, Some(given @ FieldValue::Given(_))) => {
+                Some(given)
+            }
+            (mine, theirs) => mine.or(theirs),
+        };
+    }
+
+    pub fn set(&mut self, field: Field, value: String) {
+        match field {
+            Field::Name => self.name = Some(value),
+            Field::Email => self.email = Some(value),
+            Field::ProjectDetails => self.project_details = Some(value),
+            Field::Budget => self.budget = Some(FieldValue::Given(value)),
+        }
+    }
+}
+
+/// How the session stood before the last turn, so the visitor can correct
+/// or take back their message and the turn can be undone.
+#[derive(Debug, Clone)]
+pub struct TurnCheckpoint {
+    pub state: ConversationState,
+    pub data: SessionData,
+    pub failed_answers: u8,
+    /// The turn's messages: the visitor's, then the reply if there was one.
+    pub message_ids: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub id: String,
+    pub language: Language,
+    /// See [`crate::tenant`].
+    pub tenant: String,
+    pub state: ConversationState,
+    pub data: SessionData,
+    pub messages: Vec<Message>,
+    pub created_at: Instant,
+    pub last_activity: Instant,
+    /// Set once the visitor has been warned that the session is about to
+    /// expire; cleared by the next message.
+    pub inactivity_warned: bool,
+    /// An operator has taken over; the bot stays quiet until released.
+    pub handed_off: bool,
+    /// The visitor was asked to rate the conversation; their next message
+    /// may be the score. See [`crate::survey`].
+    pub survey_pending: bool,
+    /// Invalid answers in a row to the pending question; cleared when the
+    /// flow moves on.
+    pub failed_answers: u8,
+    /// Whether an operator could take the conversation this turn; see
+    /// [`crate::presence`].
+    pub operators_available: bool,
+    /// `None` when the last turn had effects outside the session (a
+    /// confirmed inquiry, an email, a transfer) and cannot be undone.
+    pub last_turn: Option<TurnCheckpoint>,
+}
+
+impl Session {
+    pub fn new(id: String, language: Language) -> Self {
+        let now = Instant::now();
+        Self {
+            id,
+            language,
+            tenant: DEFAULT_TENANT.to_string(),
+            state: ConversationState::Idle,
+            data: SessionData::default(),
+            messages: Vec::new(),
+            created_at: now,
+            last_activity: now,
+            inactivity_warned: false,
+            handed_off: false,
+            survey_pending: false,
+            failed_answers: 0,
+            operators_available: true,
+            last_turn: None,
+        }
+    }
+
+    /// Appends a message and returns its id.
+    pub fn push_message(&mut self, role: Role, content: impl Into<String>) -> String {
+        let now = Instant::now();
+        let id = Uuid::new_v4().to_string();
+        self.messages.push(Message {
+            id: id.clone(),
+            role,
+            content: content.into(),
+            timestamp: SystemTime::now(),
+            receipt: None,
+            metadata: None,
+        });
+        self.last_activity = now;
+        self.inactivity_warned = false;
+        id
+    }
+
+    /// Adds a visitor message with what their client sent alongside it.
+    pub fn push_visitor_message(
+        &mut self,
+        content: impl Into<String>,
+        metadata: Option<MessageMetadata>,
+    ) -> String {
+        let id = self.push_message(Role::User, content);
+        if let Some(message) = self.messages.last_mut() {
+            message.metadata = metadata;
+        }
+        id
+    }
+
+    /// Records that the visitor's client got, or showed, a bot or operator
+    /// message; a receipt never goes back from read to delivered. Returns
+    /// the message's previous receipt, or `None` when the session sent no
+    /// message `id`.
+    pub fn record_receipt(
+        &mut self,
+        id: &str,
+        status: ReceiptStatus,
+    ) -> Option<Option<ReceiptStatus>> {
+        let message = self
+            .messages
+            .iter_mut()
+            .find(|m| m.id == id && m.role != Role::User)?;
+        let before = message.receipt;
+        message.receipt = before.max(Some(status));
+        Some(before)
+    }
+
+    /// Whether the turn that `message_id` started can still be undone:
+    /// nothing was said since and no operator has the session.
+    pub fn can_undo(&self, message_id: &str) -> bool {
+        let Some(turn) = &self.last_turn else {
+            return false;
+        };
+        let tail = self.messages.len().checked_sub(turn.message_ids.len());
+        !self.handed_off
+            && turn.message_ids.first().is_some_and(|id| id == message_id)
+            && tail.is_some_and(|start| {
+                self.messages[start..]
+                    .iter()
+                    .map(|m| &m.id)
+                    .eq(turn.message_ids.iter())
+            })
+    }
+
+    /// Undoes the last turn: restores the state and data from before it
+    /// and drops its messages, whose ids are returned.
+    pub fn undo_turn(&mut self) -> Vec<String> {
+        let Some(turn) = self.last_turn.take() else {
+            return Vec::new();
+        };
+        self.data = turn.data;
+        flow::restore(self, turn.state, Restore::Undo);
+        self.failed_answers = turn.failed_answers;
+        let start = self.messages.len().saturating_sub(turn.message_ids.len());
+        self.messages.truncate(start);
+        turn.message_ids
+    }
+
+    /// Drops collected data and returns to `Idle`; history is kept.
+    pub fn reset(&mut self) {
+        if self.state != ConversationState::Idle {
+            flow::fire(self, Event::Reset);
+        }
+        self.data = SessionData::default();
+        self.failed_answers = 0;
+    }
+
+    pub fn is_expired(&self, ttl: Duration) -> bool {
+        self.last_activity.elapsed() > ttl
+    }
+
+    /// Bytes of message text in the history.
+    pub fn message_bytes(&self) -> usize {
+        self.messages.iter().map(|m| m.content.len()).sum()
+    }
+
+    /// Rough heap and inline footprint: the struct, its message buffer and
+    /// every owned string. Allocator overhead is not counted.
+    pub fn approx_bytes(&self) -> usize {
+        let strings = [
+            &self.data.name,
+            &self.data.email,
+            &self.data.project_details,
+        ]
+        .into_iter()
+        .flatten()
+        .chain(self.data.budget.as_ref().and_then(FieldValue::given))
+        .map(String::capacity)
+        .sum::<usize>();
+        mem::size_of::<Self>()
+            + self.id.capacity()
+            + self.tenant.capacity()
+            + self.messages.capacity() * mem::size_of::<Message>()
+            + self
+                .messages
+                .iter()
+                .map(|m| m.id.capacity() + m.content.capacity())
+                .sum::<usize>()
+            + strings
+    }
+}
+
+/// A session behind its own lock. Requests for the same session queue on
+/// it, so two concurrent messages can never both answer the same pending
+/// question; requests for different sessions don't contend.
+pub type SessionHandle = Arc<Mutex<Session>>;
+
+/// Why two sessions could not be merged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeError {
+    SameSession,
+    /// No live session with this id.
+    NotFound(String),
+}
+
+/// Caps that protect memory on small instances. `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionLimits {
+    pub max_sessions: Option<usize>,
+    /// Live sessions a single client IP may hold.
+    pub max_sessions_per_ip: Option<usize>,
+    /// Visitor messages one conversation may have. The next one is
+    /// answered with a goodbye and the session is closed.
+    pub max_conversation_turns: Option<usize>,
+    /// How long one conversation may go on, likewise.
+    pub max_conversation_secs: Option<u64>,
+}
+
+impl SessionLimits {
+    /// Whether `session` has gone on long enough to be wrapped up. A
+    /// conversation an operator has taken over is theirs to end.
+    pub fn conversation_over(&self, session: &Session) -> bool {
+        if session.handed_off {
+            return false;
+        }
+        let turns = session
+            .messages
+            .iter()
+            .filter(|m| m.role == Role::User)
+            .count();
+        self.max_conversation_turns.is_some_and(|max| turns >= max)
+            || self
+                .max_conversation_secs
+                .is_some_and(|max| session.created_at.elapsed().as_secs() >= max)
+    }
+}
+
+/// Why a new session was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionLimitError {
+    Capacity,
+    PerIp,
+}
+
+impl SessionLimitError {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SessionLimitError::Capacity => "capacity",
+            SessionLimitError::PerIp => "per_ip",
+        }
+    }
+}
+
+impl fmt::Display for SessionLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionLimitError::Capacity => write!(f, "session capacity reached"),
+            SessionLimitError::PerIp => write!(f, "too many sessions from this address"),
+        }
+    }
+}
+
+impl std::error::Error for SessionLimitError {}
+
+/// Approximate memory held by the session store.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct MemoryUsage {
+    pub sessions: usize,
+    pub messages: usize,
+    /// Text of all messages, without bookkeeping.
+    pub message_bytes: usize,
+    /// Everything the sessions own, see [`Session::approx_bytes`].
+    pub approx_bytes: usize,
+    /// Sessions busy with a request while measuring; not included above.
+    pub skipped: usize,
+}
+
+/// How long a session may sit idle, unless configured.
+pub const DEFAULT_SESSION_TTL: Duration = Duration::from_secs(60 * 60);
+/// How often expired sessions are dropped, unless configured.
+pub const DEFAULT_PURGE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Expiry, purge scheduling and caps; adjustable while the server runs
+/// through `/admin/sessions/settings`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionSettings {
+    pub ttl_secs: u64,
+    pub purge_interval_secs: u64,
+    #[serde(flatten)]
+    pub limits: SessionLimits,
+}
+
+impl SessionSettings {
+    pub fn ttl(&self) -> Duration {
+        Duration::from_secs(self.ttl_secs)
+    }
+
+    pub fn purge_interval(&self) -> Duration {
+        Duration::from_secs(self.purge_interval_secs)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.ttl_secs == 0 {
+            return Err("ttl_secs must be above zero".into());
+        }
+        if self.purge_interval_secs == 0 {
+            return Err("purge_interval_secs must be above zero".into());
+        }
+        if self.limits.max_conversation_turns == Some(0) {
+            return Err("max_conversation_turns must be above zero".into());
+        }
+        if self.limits.max_conversation_secs == Some(0) {
+            return Err("max_conversation_secs must be above zero".into());
+        }
+        Ok(())
+    }
+}
+
+/// How long a deleted session can be restored, unless configured.
+pub const DEFAULT_RESTORE_GRACE: Duration = Duration::from_secs(24 * 60 * 60);
+
+struct Entry {
+    handle: SessionHandle,
+    client_ip: Option<IpAddr>,
+}
+
+struct Deleted {
+    entry: Entry,
+    at: Instant,
+}
+
+/// Purge runs kept for `/admin/stats/purges`; older ones are dropped.
+const PURGE_HISTORY_CAPACITY: usize = 100;
+
+/// What one run of [`SessionManager::purge_expired`] did.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct PurgeStats {
+    pub at_ms: u64,
+    pub expired: usize,
+    pub deleted: usize,
+    /// Live sessions before and after the run.
+    pub sessions_before: usize,
+    pub sessions_after: usize,
+    /// Time spent scanning and removing, with the store locked.
+    #[serde(rename = "duration_ms", serialize_with = "as_millis")]
+    pub duration: Duration,
+}
+
+fn as_millis<S: serde::Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_f64(d.as_secs_f64() * 1000.0)
+}
+
+/// Sessions dropped by [`SessionManager::purge_expired`].
+#[derive(Debug, Default)]
+pub struct Purged {
+    /// Idle for longer than the TTL.
+    pub expired: Vec<Session>,
+    /// Deleted, and past the restore grace period.
+    pub deleted: Vec<Session>,
+    pub stats: PurgeStats,
+}
+
+impl Purged {
+    pub fn len(&self) -> usize {
+        self.expired.len() + self.deleted.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Session> {
+        self.expired.iter().chain(&self.deleted)
+    }
+}
+
+/// Why a deleted session could not be restored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestoreError {
+    /// Never deleted, or its grace period is over.
+    NotDeleted,
+    /// Restoring would exceed the session cap.
+    Capacity,
+}
+
+/// In-memory session store with idle expiry.
+pub struct SessionManager {
+    sessions: RwLock<HashMap<String, Entry>>,
+    /// Deleted sessions, kept for the restore grace period.
+    deleted: RwLock<HashMap<String, Deleted>>,
+    /// Ids of sessions merged into another, and the survivor's id; dropped
+    /// when the survivor is purged.
+    redirects: RwLock<HashMap<String, String>>,
+    restore_grace: Duration,
+    settings: std::sync::RwLock<SessionSettings>,
+    /// Logged when [`SessionManager::check_memory`] finds more than this.
+    memory_warning_bytes: Option<usize>,
+    over_memory_warning: AtomicBool,
+    /// The latest purge runs, oldest first.
+    purges: std::sync::Mutex<VecDeque<PurgeStats>>,
+}
+
+impl SessionManager {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            sessions: RwLock::new(HashMap::new()),
+            deleted: RwLock::new(HashMap::new()),
+            redirects: RwLock::new(HashMap::new()),
+            restore_grace: DEFAULT_RESTORE_GRACE,
+            settings: std::sync::RwLock::new(SessionSettings {
+                ttl_secs: ttl.as_secs(),
+                purge_interval_secs: DEFAULT_PURGE_INTERVAL.as_secs(),
+                limits: SessionLimits::default(),
+            }),
+            memory_warning_bytes: None,
+            over_memory_warning: AtomicBool::new(false),
+            purges: std::sync::Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn settings(&self) -> SessionSettings {
+        *self.settings.read().unwrap()
+    }
+
+    /// Applies new settings; they take effect on the next request or
+    /// purge. Sessions already over a lowered cap are kept.
+    pub fn set_settings(&self, settings: SessionSettings) {
+        *self.settings.write().unwrap() = settings;
+    }
+
+    pub fn set_limits(&self, limits: SessionLimits) {
+        self.settings.write().unwrap().limits = limits;
+    }
+
+    pub fn set_purge_interval(&self, interval: Duration) {
+        self.settings.write().unwrap().purge_interval_secs = interval.as_secs();
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.settings().ttl()
+    }
+
+    pub fn purge_interval(&self) -> Duration {
+        self.settings().purge_interval()
+    }
+
+    pub fn set_memory_warning(&mut self, bytes: Option<usize>) {
+        self.memory_warning_bytes = bytes;
+    }
+
+    pub fn set_restore_grace(&mut self, grace: Duration) {
+        self.restore_grace = grace;
+    }
+
+    pub fn restore_grace(&self) -> Duration {
+        self.restore_grace
+    }
+
+    /// A session that is currently locked is in use, hence not expired.
+    fn is_live(&self, handle: &SessionHandle, ttl: Duration) -> bool {
+        handle
+            .try_lock()
+            .map(|s| !s.is_expired(ttl))
+            .unwrap_or(true)
+    }
+
+    /// Returns the id of a live session, creating a new one when `id` is
+    /// missing, unknown or expired. The id of a merged session resolves to
+    /// the session it was merged into. New sessions belong to `tenant`; the
+    /// flag is `true` for them. Creating fails when a [`SessionLimits`] cap
+    /// is reached.
+    pub async fn resolve(
+        &self,
+        id: Option<&str>,
+        language: Option<Language>,
+        tenant: &str,
+        client_ip: Option<IpAddr>,
+    ) -> Result<(String, bool), SessionLimitError> {
+        let redirected = match id {
+            Some(id) => self.redirects.read().await.get(id).cloned(),
+            None => None,
+        };
+        let id = redirected.as_deref().or(id);
+        let settings = self.settings();
+        let mut sessions = self.sessions.write().await;
+        if let Some(id) = id
+            && sessions
+                .get(id)
+                .is_some_and(|e| self.is_live(&e.handle, settings.ttl()))
+        {
+            return Ok((id.to_string(), false));
+        }
+        if settings
+            .limits
+            .max_sessions
+            .is_some_and(|max| sessions.len() >= max)
+        {
+            return Err(SessionLimitError::Capacity);
+        }
+        if let (Some(max), Some(ip)) = (settings.limits.max_sessions_per_ip, client_ip)
+            && sessions
+                .values()
+                .filter(|e| e.client_ip == Some(ip))
+                .count()
+                >= max
+        {
+            return Err(SessionLimitError::PerIp);
+        }
+        let id = Uuid::new_v4().to_string();
+        let mut session = Session::new(id.clone(), language.unwrap_or_default());
+        session.tenant = tenant.to_string();
+        sessions.insert(
+            id.clone(),
+            Entry {
+                handle: Arc::new(Mutex::new(session)),
+                client_ip,
+            },
+        );
+        Ok((id, true))
+    }
+
+    /// Waits for exclusive access to the session. The guard may be held
+    /// across awaits; the store itself is not locked meanwhile.
+    pub async fn lock_session(&self, id: &str) -> Option<OwnedMutexGuard<Session>> {
+        let handle = self.sessions.read().await.get(id)?.handle.clone();
+        Some(handle.lock_owned().await)
+    }
+
+    /// Runs `f` on the session with exclusive access.
+    pub async fn with_session<R>(&self, id: &str, f: impl FnOnce(&mut Session) -> R) -> Option<R> {
+        let mut session = self.lock_session(id).await?;
+        Some(f(&mut session))
+    }
+
+    pub async fn get_history(&self, id: &str) -> Option<Vec<Message>> {
+        let session = self.lock_session(id).await?;
+        Some(session.messages.clone())
+    }
+
+    /// Takes the session out of service. It can be brought back with
+    /// [`SessionManager::restore_session`] until the restore grace period
+    /// is over, after which [`SessionManager::purge_expired`] drops it.
+    pub async fn remove_session(&self, id: &str) -> bool {
+        let Some(entry) = self.sessions.write().await.remove(id) else {
+            return false;
+        };
+        let deleted = Deleted {
+            entry,
+            at: Instant::now(),
+        };
+        self.deleted.write().await.insert(id.to_string(), deleted);
+        true
+    }
+
+    /// Merges session `from` into `into`, for a visitor who started twice:
+    /// the histories are interleaved by time, `into`'s data is completed
+    /// with `from`'s, and `from`'s id redirects to `into` from now on.
+    /// Returns the merged session.
+    pub async fn merge(&self, from: &str, into: &str) -> Result<Session, MergeError> {
+        if from == into {
+            return Err(MergeError::SameSession);
+        }
+        // Take `from` out of the store, and point its id at `into`, before
+        // waiting for either session: nothing may wait for a session while
+        // holding the store.
+        let (from_handle, into_handle) = {
+            let mut sessions = self.sessions.write().await;
+            let Some(into_handle) = sessions.get(into).map(|e| e.handle.clone()) else {
+                return Err(MergeError::NotFound(into.to_string()));
+            };
+            let Some(from_entry) = sessions.remove(from) else {
+                return Err(MergeError::NotFound(from.to_string()));
+            };
+            let mut redirects = self.redirects.write().await;
+            for survivor in redirects.values_mut().filter(|s| s.as_str() == from) {
+                *survivor = into.to_string();
+            }
+            redirects.insert(from.to_string(), into.to_string());
+            (from_entry.handle, into_handle)
+        };
+        // A turn that looked `from` up before it left the store may still
+        // be running; the merge waits for it.
+        let mut source = from_handle.lock_owned().await;
+        let mut target = into_handle.lock_owned().await;
+
+        target.messages.append(&mut source.messages);
+        target.messages.sort_by_key(|m| m.timestamp);
+        target.data.merge(mem::take(&mut source.data));
+        if target.state == ConversationState::Idle {
+            flow::restore(&mut target, source.state, Restore::Merge);
+        }
+        target.created_at = target.created_at.min(source.created_at);
+        target.last_activity = target.last_activity.max(source.last_activity);
+        target.handed_off |= source.handed_off;
+        target.last_turn = None;
+        Ok(target.clone())
+    }
+
+    /// Puts a deleted session back in service, counting as activity so it
+    /// does not expire straight away.
+    pub async fn restore_session(&self, id: &str) -> Result<(), RestoreError> {
+        // The entry moves back under the store, but a busy session is only
+        // waited for once the store is released.
+        let busy = {
+            let mut deleted = self.deleted.write().await;
+            if deleted
+                .get(id)
+                .is_none_or(|d| d.at.elapsed() > self.restore_grace)
+            {
+                return Err(RestoreError::NotDeleted);
+            }
+            let mut sessions = self.sessions.write().await;
+            if self
+                .settings()
+                .limits
+                .max_sessions
+                .is_some_and(|max| sessions.len() >= max)
+            {
+                return Err(RestoreError::Capacity);
+            }
+            let Some(Deleted { entry, .. }) = deleted.remove(id) else {
+                return Err(RestoreError::NotDeleted);
+            };
+            let busy = match entry.handle.try_lock() {
+                Ok(mut session) => {
+                    session.last_activity = Instant::now();
+                    None
+                }
+                Err(_) => Some(entry.handle.clone()),
+            };
+            sessions.insert(id.to_string(), entry);
+            busy
+        };
+        if let Some(handle) = busy {
+            handle.lock().await.last_activity = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Drops sessions idle for longer than the TTL, and deleted sessions
+    /// past the restore grace period, and returns them all, waiting for any
+    /// still busy after the store is released. The run is added to
+    /// [`SessionManager::purge_history`].
+    pub async fn purge_expired(&self) -> Purged {
+        let ttl = self.ttl();
+        let mut sessions = self.sessions.write().await;
+        let started = Instant::now();
+        let sessions_before = sessions.len();
+        let expired: Vec<String> = sessions
+            .iter()
+            .filter(|(_, e)| !self.is_live(&e.handle, ttl))
+            .map(|(id, _)| id.clone())
+            .collect();
+        let expired: Vec<Entry> = expired
+            .iter()
+            .filter_map(|id| sessions.remove(id))
+            .collect();
+        let sessions_after = sessions.len();
+        drop(sessions);
+
+        let mut deleted_sessions = self.deleted.write().await;
+        let past_grace: Vec<String> = deleted_sessions
+            .iter()
+            .filter(|(_, d)| d.at.elapsed() > self.restore_grace)
+            .map(|(id, _)| id.clone())
+            .collect();
+        let deleted: Vec<Entry> = past_grace
+            .iter()
+            .filter_map(|id| deleted_sessions.remove(id))
+            .map(|d| d.entry)
+            .collect();
+        drop(deleted_sessions);
+        let duration = started.elapsed();
+        // Out of the store now, but a turn that looked one up just before
+        // may still hold it; wait for it to finish.
+        let sessions = async |entries: Vec<Entry>| -> Vec<Session> {
+            let mut sessions = Vec::with_capacity(entries.len());
+            for entry in entries {
+                sessions.push(entry.handle.lock().await.clone());
+            }
+            sessions
+        };
+        let (expired, deleted) = (sessions(expired).await, sessions(deleted).await);
+        // Ids merged into a purged session have nowhere to go any more.
+        let purged: HashSet<&str> = expired
+            .iter()
+            .chain(&deleted)
+            .map(|s| s.id.as_str())
+            .collect();
+        if !purged.is_empty() {
+            self.redirects
+                .write()
+                .await
+                .retain(|_, survivor| !purged.contains(survivor.as_str()));
+        }
+        let stats = PurgeStats {
+            at_ms: now_ms(),
+            expired: expired.len(),
+            deleted: deleted.len(),
+            sessions_before,
+            sessions_after,
+            duration,
+        };
+        let mut purges = self.purges.lock().unwrap();
+        if purges.len() == PURGE_HISTORY_CAPACITY {
+            purges.pop_front();
+        }
+        purges.push_back(stats);
+        Purged {
+            expired,
+            deleted,
+            stats,
+        }
+    }
+
+    /// The latest purge runs, newest first.
+    pub fn purge_history(&self) -> Vec<PurgeStats> {
+        self.purges.lock().unwrap().iter().rev().copied().collect()
+    }
+
+    /// Moves every session's last activity forward by `by`, up to now, so
+    /// time the bot was unavailable doesn't count as idle.
+    /// Waits for sessions that are busy, without holding the store.
+    pub async fn extend_idle(&self, by: Duration) {
+        let now = Instant::now();
+        let handles: Vec<SessionHandle> = self
+            .sessions
+            .read()
+            .await
+            .values()
+            .map(|e| e.handle.clone())
+            .collect();
+        for handle in handles {
+            let mut session = handle.lock().await;
+            session.last_activity = (session.last_activity + by).min(now);
+        }
+    }
+
+    /// Sessions in the middle of an inquiry that have been idle for at
+    /// least `idle_for` and not warned yet. They are marked as warned.
+    pub async fn take_idle(&self, idle_for: Duration) -> Vec<(String, Language)> {
+        let sessions = self.sessions.read().await;
+        sessions
+            .values()
+            .filter_map(|e| {
+                let mut session = e.handle.try_lock().ok()?;
+                let due = !session.inactivity_warned
+                    && session.state != ConversationState::Idle
+                    && session.last_activity.elapsed() >= idle_for;
+                session.inactivity_warned |= due;
+                due.then(|| (session.id.clone(), session.language))
+            })
+            .collect()
+    }
+
+    pub async fn session_count(&self) -> usize {
+        self.sessions.read().await.len()
+    }
+
+    /// Copies of all sessions with their client address, for snapshots.
+    /// Waits for sessions that are busy.
+    pub async fn export(&self) -> Vec<(Session, Option<IpAddr>)> {
+        let entries: Vec<(SessionHandle, Option<IpAddr>)> = self
+            .sessions
+            .read()
+            .await
+            .values()
+            .map(|e| (e.handle.clone(), e.client_ip))
+            .collect();
+        let mut sessions = Vec::with_capacity(entries.len());
+        for (handle, client_ip) in entries {
+            sessions.push((handle.lock().await.clone(), client_ip));
+        }
+        sessions
+    }
+
+    /// Adds sessions from a snapshot, skipping expired ones and ids that
+    /// already exist. Returns the ids of those added.
+    pub async fn import(&self, restored: Vec<(Session, Option<IpAddr>)>) -> Vec<String> {
+        let ttl = self.ttl();
+        let mut sessions = self.sessions.write().await;
+        let mut added = Vec::new();
+        for (session, client_ip) in restored {
+            if session.is_expired(ttl) || sessions.contains_key(&session.id) {
+                continue;
+            }
+            added.push(session.id.clone());
+            sessions.insert(
+                session.id.clone(),
+                Entry {
+                    handle: Arc::new(Mutex::new(session)),
+                    client_ip,
+                },
+            );
+        }
+        added
+    }
+
+    /// Measures every session that is not busy right now.
+    pub async fn memory_usage(&self) -> MemoryUsage {
+        let sessions = self.sessions.read().await;
+        let mut usage = MemoryUsage {
+            approx_bytes: sessions.capacity() * mem::size_of::<(String, Entry)>(),
+            ..MemoryUsage::default()
+        };
+        for (id, entry) in sessions.iter() {
+            usage.sessions += 1;
+            usage.approx_bytes += id.capacity();
+            let Ok(session) = entry.handle.try_lock() else {
+                usage.skipped += 1;
+                continue;
+            };
+            usage.messages += session.messages.len();
+            usage.message_bytes += session.message_bytes();
+            usage.approx_bytes += session.approx_bytes();
+        }
+        usage
+    }
+
+    /// Measures memory and logs once when it crosses the warning threshold
+    /// in either direction.
+    pub async fn check_memory(&self) -> MemoryUsage {
+        let usage = self.memory_usage().await;
+        let Some(threshold) = self.memory_warning_bytes else {
+            return usage;
+        };
+        let over = usage.approx_bytes > threshold;
+        if self.over_memory_warning.swap(over, Ordering::Relaxed) != over {
+            if over {
+                warn!(
+                    bytes = usage.approx_bytes,
+                    threshold,
+                    sessions = usage.sessions,
+                    "session store above memory warning threshold; consider a shorter TTL or MAX_SESSIONS"
+                );
+            } else {
+                info!(
+                    bytes = usage.approx_bytes,
+                    threshold, "session store back below memory warning threshold"
+                );
+            }
+        }
+        usage
+    }
+}
@@ -0,0 +1,118 @@
+//! Carrying live sessions across a restart.
+//!
+//! On shutdown the server writes every session to `SESSION_SNAPSHOT`; the
+//! next instance reads it back on startup and keeps polling it, so when the
+//! old instance stops only after the new one is already serving (the
+//! `SO_REUSEPORT` handover) its sessions are still picked up.
+//!
+//! Sessions are written as [`SavedSession`]s, so a snapshot left by an
+//! older version is migrated on load and one from a newer version is
+//! refused. Their events go along, since the transcript, message edits and
+//! the operator queue are all read from the event log; a snapshot written
+//! before events were included restores sessions without them.
+//!
+//! The snapshot holds what visitors told the bot, so only the server's
+//! user can read it.
+
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    durable,
+    events::{StoredEvent, now_ms},
+    saved::{SavedSession, SchemaError},
+    state::SharedState,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Snapshot<S> {
+    saved_at_ms: u64,
+    sessions: Vec<S>,
+    /// The events of the sessions above.
+    #[serde(default)]
+    events: Vec<StoredEvent>,
+}
+
+/// Writes all sessions and their events to `path`, replacing it
+/// atomically.
+pub async fn save(state: &SharedState, path: &Path) -> io::Result<usize> {
+    let exported = state.sessions.export().await;
+    let events = exported
+        .iter()
+        .flat_map(|(session, _)| state.events.session_events(&session.id))
+        .collect();
+    let sessions: Vec<SavedSession> = exported
+        .iter()
+        .map(|(session, ip)| SavedSession::from_session(session, *ip))
+        .collect();
+    let count = sessions.len();
+    let snapshot = Snapshot {
+        saved_at_ms: now_ms(),
+        sessions,
+        events,
+    };
+    let json = serde_json::to_vec(&snapshot).map_err(io::Error::other)?;
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || durable::replace(&path, &json))
+        .await
+        .map_err(io::Error::other)??;
+    Ok(count)
+}
+
+/// Adds the sessions in `path` that this instance doesn't have yet and
+/// returns how many were added. A missing file restores nothing.
+pub async fn restore(state: &SharedState, path: &Path) -> io::Result<usize> {
+    let raw = match fs::read_to_string(path) {
+        Ok(raw) => raw,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let snapshot: Snapshot<serde_json::Value> =
+        serde_json::from_str(&raw).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let offline_ms = now_ms().saturating_sub(snapshot.saved_at_ms);
+    let sessions = snapshot
+        .sessions
+        .into_iter()
+        .map(|s| Ok(SavedSession::from_value(s, snapshot.saved_at_ms)?.into_session(offline_ms)))
+        .collect::<Result<_, SchemaError>>()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let added: HashSet<String> = state.sessions.import(sessions).await.into_iter().collect();
+    let events = snapshot
+        .events
+        .into_iter()
+        .filter(|e| added.contains(&e.session_id))
+        .collect();
+    state.events.restore(events);
+    Ok(added.len())
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Restores again whenever the snapshot file changes, for sessions saved
+/// by an instance that stopped after this one started.
+pub async fn watch(state: SharedState, path: PathBuf, every: Duration) {
+    let mut last = modified(&path);
+    let mut interval = tokio::time::interval(every);
+    loop {
+        interval.tick().await;
+        let current = modified(&path);
+        if current.is_none() || current == last {
+            continue;
+        }
+        last = current;
+        match restore(&state, &path).await {
+            Ok(0) => {}
+            Ok(n) => info!(sessions = n, "took over sessions from a stopped instance"),
+            Err(e) => warn!("{}: {e}", path.display()),
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use crate::{
+    ai::AiFallback,
+    analytics::{KeywordCounts, UnansweredLog},
+    assets::AssetSource,
+    blocklist::Blocklist,
+    bots::Bots,
+    catalog::ServicesCatalog,
+    channels::{
+        email::EmailChannel, messenger::Messenger, partner::PartnerApi, whatsapp::WhatsApp,
+    },
+    config::LiveConfig,
+    demo::DemoScript,
+    events::{EventStore, TranscriptEntry},
+    handoff::OperatorHours,
+    livechat::LiveChat,
+    maintenance::Maintenance,
+    metrics::MetricsManager,
+    outbox::Outbox,
+    plugins::IntentRegistry,
+    presence::Presence,
+    push::{PushHub, PushMessage},
+    review::ReviewQueue,
+    session::SessionManager,
+    synonyms::Synonyms,
+    tenant::{TenantReplies, TenantThemes},
+    trace::TurnTraces,
+    training::TrainingData,
+};
+
+/// Everything request handlers share.
+pub struct AppState {
+    /// The client for every outgoing HTTP call; see [`crate::http`].
+    pub http: reqwest::Client,
+    pub sessions: SessionManager,
+    pub metrics: MetricsManager,
+    pub unanswered: UnansweredLog,
+    pub keywords: KeywordCounts,
+    /// Catalog, webhook URL and reply overrides; reloadable at runtime.
+    pub config: LiveConfig,
+    /// Where confirmed inquiry reports are written.
+    pub reports_dir: PathBuf,
+    pub events: EventStore,
+    /// Proxies in front of the server that append to `X-Forwarded-For`;
+    /// with none, the header is ignored. See [`ClientIp`].
+    ///
+    /// [`ClientIp`]: crate::routes::client_ip::ClientIp
+    pub trusted_proxies: usize,
+    /// Bearer token for `/admin`; the admin API is disabled when unset.
+    pub admin_token: Option<String>,
+    /// Pending webhook deliveries.
+    pub outbox: Outbox,
+    /// Unprompted messages to visitors.
+    pub push: PushHub<PushMessage>,
+    /// Live transcripts for operators watching a session.
+    pub watchers: PushHub<TranscriptEntry>,
+    /// HTTP relay that sends visitor emails; none are sent when unset.
+    pub mail_relay_url: Option<String>,
+    /// Marketing automation told of sessions abandoned mid-inquiry; see
+    /// [`crate::abandoned`].
+    pub marketing_webhook_url: Option<String>,
+    /// The WhatsApp channel, when configured.
+    pub whatsapp: Option<WhatsApp>,
+    /// The Facebook Messenger channel, when configured.
+    pub messenger: Option<Messenger>,
+    /// The inbound mailbox, when configured.
+    pub email: Option<EmailChannel>,
+    /// The `/v1` partner API, when enabled.
+    pub partner: Option<PartnerApi>,
+    /// Where conversations go when a visitor asks for a person.
+    pub live_chat: Option<LiveChat>,
+    /// Where the widget's files are served from.
+    pub assets: AssetSource,
+    /// Answer paths that name no file with `index.html`.
+    pub spa_fallback: bool,
+    pub maintenance: Maintenance,
+    /// Visitors kept out of the chat.
+    pub blocklist: Blocklist,
+    /// Replies tenants have replaced with their own.
+    pub tenant_replies: TenantReplies,
+    /// Widget branding per tenant.
+    pub tenant_themes: TenantThemes,
+    /// Model answers to what the rules don't understand, per tenant.
+    pub ai: AiFallback,
+    pub synonyms: Synonyms,
+    /// Intents added by code using the crate.
+    pub intents: IntentRegistry,
+    /// Finished conversations sampled for grading.
+    pub review: ReviewQueue,
+    /// Utterances learned from intent corrections.
+    pub training: TrainingData,
+    /// Bots with flows of their own, by id.
+    pub bots: Bots,
+    /// When operators answer; always, unless `OPERATOR_HOURS` is set.
+    pub operator_hours: Option<OperatorHours>,
+    /// Which operators are online, from their heartbeats.
+    pub presence: Presence,
+    /// Played on `/chat/demo` instead of the built-in script.
+    pub demo_script: Option<DemoScript>,
+    /// What happened inside recent turns, per session.
+    pub traces: TurnTraces,
+}
+
+pub type SharedState = Arc<AppState>;
+
+impl AppState {
+    pub fn new(session_ttl: Duration, catalog: ServicesCatalog, reports_dir: PathBuf) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            sessions: SessionManager::new(session_ttl),
+            metrics: MetricsManager::new(),
+            unanswered: UnansweredLog::default(),
+            keywords: KeywordCounts::default(),
+            config: LiveConfig::new(catalog),
+            reports_dir,
+            events: EventStore::in_memory(),
+            trusted_proxies: 0,
+            admin_token: None,
+            outbox: Outbox::in_memory(),
+            push: PushHub::default(),
+            watchers: PushHub::default(),
+            mail_relay_url: None,
+            marketing_webhook_url: None,
+            whatsapp: None,
+            messenger: None,
+            email: None,
+            partner: None,
+            live_chat: None,
+            assets: AssetSource::default(),
+            spa_fallback: false,
+            maintenance: Maintenance::default(),
+            blocklist: Blocklist::default(),
+            tenant_replies: TenantReplies::default(),
+            tenant_themes: TenantThemes::default(),
+            ai: AiFallback::default(),
+            synonyms: Synonyms::default(),
+            intents: IntentRegistry::default(),
+            review: ReviewQueue::default(),
+            training: TrainingData::default(),
+            bots: Bots::default(),
+            operator_hours: None,
+            presence: Presence::default(),
+            demo_script: None,
+            traces: TurnTraces::default(),
+        }
+    }
+
+    /// The config for sessions of `tenant`: its bot's, if it is one.
+    pub fn config_for(&self, tenant: &str) -> &LiveConfig {
+        self.bots
+            .get(tenant)
+            .map_or(&self.config, |bot| &bot.config)
+    }
+}
@@ -0,0 +1,110 @@
+//! The satisfaction survey at the end of a conversation.
+//!
+//! Once a confirmed inquiry's report is written, or an operator hands the
+//! conversation back, the visitor is asked to rate it from 1 to 5. The
+//! question goes to clients listening on `/chat/events`; a score as the
+//! next message is stored with the session (`satisfaction_rated`) and
+//! counted per tenant. Anything else carries on as usual.
+//!
+//! `/admin/analytics/satisfaction` sums the scores up as CSAT, the share of
+//! 4s and 5s, and an NPS read onto the five-point scale: 5s are promoters,
+//! 1 to 3 detractors.
+
+use serde::Serialize;
+use tracing::info;
+
+use crate::{
+    events::ConversationEvent,
+    handoff,
+    i18n::{Text, text},
+    push::PushMessage,
+    session::Role,
+    state::AppState,
+};
+
+pub const SCORES: [u8; 5] = [1, 2, 3, 4, 5];
+
+/// The score in an answer such as "4", "4/5" or "5 stars"; `None` for
+/// anything with no or more than one digit.
+pub fn score(message: &str) -> Option<u8> {
+    let message = message.trim();
+    let message = message
+        .strip_suffix("/5")
+        .or_else(|| message.strip_suffix(" / 5"))
+        .unwrap_or(message);
+    let mut digits = message.chars().filter(char::is_ascii_digit);
+    let digit = digits.next()?.to_digit(10)? as u8;
+    let rest_is_words = message.chars().count() <= 12 && digits.next().is_none();
+    (rest_is_words && SCORES.contains(&digit)).then_some(digit)
+}
+
+/// Asks the visitor to rate the conversation, unless an operator has it.
+pub async fn ask(state: &AppState, session_id: &str) {
+    let language = state
+        .sessions
+        .with_session(session_id, |session| {
+            if session.handed_off {
+                return None;
+            }
+            session.survey_pending = true;
+            Some(session.language)
+        })
+        .await
+        .flatten();
+    let Some(language) = language else {
+        return;
+    };
+    let question = text(language, Text::SurveyQuestion).to_string();
+    let listeners = state.push.send(
+        session_id,
+        PushMessage::Survey {
+            text: question.clone(),
+            quick_replies: SCORES.iter().map(u8::to_string).collect(),
+        },
+    );
+    info!(%session_id, listeners, "satisfaction survey sent");
+    handoff::publish(state, session_id, "", Role::Bot, &question);
+    state.events.append(
+        session_id,
+        vec![ConversationEvent::SurveyAsked { text: question }],
+    );
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SatisfactionSummary {
+    pub responses: u64,
+    /// How many visitors gave each score, from 1 to 5.
+    pub scores: [u64; 5],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub average: Option<f64>,
+    /// Share of 4s and 5s, in percent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub csat: Option<f64>,
+    /// Promoters minus detractors, in percent of responses.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nps: Option<f64>,
+}
+
+impl SatisfactionSummary {
+    pub fn new(scores: [u64; 5]) -> Self {
+        let responses: u64 = scores.iter().sum();
+        if responses == 0 {
+            return Self::default();
+        }
+        let n = responses as f64;
+        let percent = |count: u64| (count as f64 * 1000.0 / n).round() / 10.0;
+        let total: u64 = SCORES
+            .iter()
+            .zip(scores)
+            .map(|(score, count)| u64::from(*score) * count)
+            .sum();
+        let (promoters, detractors) = (scores[4], scores[..3].iter().sum::<u64>());
+        Self {
+            responses,
+            scores,
+            average: Some((total as f64 * 100.0 / n).round() / 100.0),
+            csat: Some(percent(scores[3] + scores[4])),
+            nps: Some(((promoters as f64 - detractors as f64) * 1000.0 / n).round() / 10.0),
+        }
+    }
+}
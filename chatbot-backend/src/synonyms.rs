@@ -0,0 +1,224 @@
+//! Words visitors use for things the bot knows by another name, managed
+//! through `/admin/synonyms`.
+//!
+//! An alias such as "online shop" is read as its canonical phrase,
+//! "e-commerce", when the bot works out what a message asks for and which
+//! catalog item it names; answers to the inquiry questions are kept as
+//! written. With `SYNONYMS_PATH` set the table is loaded from that JSON
+//! file (`{"online shop": "e-commerce"}`), reloaded whenever it changes,
+//! and saved to it on every admin edit.
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use aho_corasick::{AhoCorasick, MatchKind};
+use tracing::{info, warn};
+
+use crate::{error::ApiError, state::SharedState, text::normalize};
+
+/// Most aliases the table may hold.
+pub const MAX_SYNONYMS: usize = 1_000;
+/// Longest alias or canonical phrase, in characters.
+const MAX_PHRASE_CHARS: usize = 100;
+
+/// Aliases and what they stand for, both normalized, ready to apply.
+#[derive(Debug, Default)]
+pub struct SynonymTable {
+    entries: BTreeMap<String, String>,
+    /// Each alias with a leading space, matched against the padded
+    /// message; `None` while the table is empty.
+    automaton: Option<AhoCorasick>,
+    /// The canonical phrase of each pattern, by pattern index.
+    canonical: Vec<String>,
+}
+
+impl SynonymTable {
+    /// Checks and normalizes `entries`.
+    fn new(entries: BTreeMap<String, String>) -> Result<Self, String> {
+        if entries.len() > MAX_SYNONYMS {
+            return Err(format!("at most {MAX_SYNONYMS} synonyms"));
+        }
+        let mut normalized = BTreeMap::new();
+        for (alias, canonical) in entries {
+            let (alias, canonical) = check(&alias, &canonical)?;
+            normalized.insert(alias, canonical);
+        }
+        let automaton = (!normalized.is_empty()).then(|| {
+            AhoCorasick::builder()
+                .match_kind(MatchKind::LeftmostLongest)
+                .build(normalized.keys().map(|alias| format!(" {alias}")))
+                .expect("aliases are plain text")
+        });
+        Ok(Self {
+            canonical: normalized.values().cloned().collect(),
+            entries: normalized,
+            automaton,
+        })
+    }
+
+    pub fn entries(&self) -> &BTreeMap<String, String> {
+        &self.entries
+    }
+
+    /// `message` normalized, with every alias in it, as whole words,
+    /// replaced by its canonical phrase. Longer aliases win over the
+    /// shorter ones they contain.
+    pub fn expand(&self, message: &str) -> String {
+        let normalized = normalize(message);
+        let Some(automaton) = &self.automaton else {
+            return normalized;
+        };
+        let padded = format!(" {normalized} ");
+        let mut out = String::with_capacity(padded.len());
+        let mut copied = 0;
+        for m in automaton.find_iter(&padded) {
+            // The alias has to end where a word does.
+            if padded.as_bytes()[m.end()] != b' ' {
+                continue;
+            }
+            out.push_str(&padded[copied..m.start()]);
+            out.push(' ');
+            out.push_str(&self.canonical[m.pattern().as_usize()]);
+            copied = m.end();
+        }
+        out.push_str(&padded[copied..]);
+        out.trim().to_string()
+    }
+}
+
+/// Normalizes an alias and its canonical phrase, refusing ones that
+/// cannot be applied.
+fn check(alias: &str, canonical: &str) -> Result<(String, String), String> {
+    let (alias, canonical) = (normalize(alias), normalize(canonical));
+    if alias.is_empty() || canonical.is_empty() {
+        return Err("alias and canonical phrase need a letter or digit each".into());
+    }
+    if alias.chars().count() > MAX_PHRASE_CHARS || canonical.chars().count() > MAX_PHRASE_CHARS {
+        return Err(format!(
+            "aliases and canonical phrases are at most {MAX_PHRASE_CHARS} characters"
+        ));
+    }
+    if alias == canonical {
+        return Err(format!("{alias:?} is an alias of itself"));
+    }
+    Ok((alias, canonical))
+}
+
+#[derive(Default)]
+pub struct Synonyms {
+    table: RwLock<Arc<SynonymTable>>,
+    /// Where the table is kept, when `SYNONYMS_PATH` is set.
+    path: Option<PathBuf>,
+}
+
+impl Synonyms {
+    /// Loads the table kept at `path`; a missing file is an empty table.
+    pub fn load(path: PathBuf) -> io::Result<Self> {
+        let synonyms = Self {
+            table: RwLock::default(),
+            path: Some(path),
+        };
+        synonyms.reload()?;
+        Ok(synonyms)
+    }
+
+    /// The table as it is now, to apply to one message.
+    pub fn table(&self) -> Arc<SynonymTable> {
+        self.table.read().unwrap().clone()
+    }
+
+    pub fn entries(&self) -> BTreeMap<String, String> {
+        self.table().entries.clone()
+    }
+
+    /// Makes `alias` stand for `canonical`, replacing what it stood for.
+    pub fn set(&self, alias: &str, canonical: &str) -> Result<BTreeMap<String, String>, ApiError> {
+        let (alias, canonical) = check(alias, canonical).map_err(ApiError::BadRequest)?;
+        self.update(|entries| {
+            entries.insert(alias, canonical);
+        })
+    }
+
+    /// Forgets `alias`.
+    pub fn remove(&self, alias: &str) -> Result<BTreeMap<String, String>, ApiError> {
+        let alias = normalize(alias);
+        if !self.table().entries.contains_key(&alias) {
+            return Err(ApiError::NotFound(format!("{alias:?} is not an alias")));
+        }
+        self.update(|entries| {
+            entries.remove(&alias);
+        })
+    }
+
+    fn update(
+        &self,
+        change: impl FnOnce(&mut BTreeMap<String, String>),
+    ) -> Result<BTreeMap<String, String>, ApiError> {
+        let mut table = self.table.write().unwrap();
+        let mut entries = table.entries.clone();
+        change(&mut entries);
+        let updated = SynonymTable::new(entries).map_err(ApiError::BadRequest)?;
+        if let Some(path) = &self.path
+            && let Err(e) = save(path, updated.entries())
+        {
+            warn!("cannot write synonyms {}: {e}", path.display());
+            return Err(ApiError::Busy("the synonyms could not be saved".into()));
+        }
+        let entries = updated.entries.clone();
+        *table = Arc::new(updated);
+        Ok(entries)
+    }
+
+    /// Reads the file again; on error the table in force is kept.
+    pub fn reload(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let entries = match fs::read(path) {
+            Ok(raw) => serde_json::from_slice(&raw).map_err(io::Error::other)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        let table = SynonymTable::new(entries).map_err(io::Error::other)?;
+        *self.table.write().unwrap() = Arc::new(table);
+        Ok(())
+    }
+}
+
+/// Writes through a temporary sibling, so a crash never leaves half a file.
+fn save(path: &Path, entries: &BTreeMap<String, String>) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let json = serde_json::to_vec_pretty(entries).map_err(io::Error::other)?;
+    fs::write(&tmp, json)?;
+    fs::rename(&tmp, path)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Polls `path` and reloads the synonyms whenever it changes.
+pub async fn watch(state: SharedState, path: PathBuf, interval: Duration) {
+    let mut last_modified = modified(&path);
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let now_modified = modified(&path);
+        if now_modified == last_modified {
+            continue;
+        }
+        last_modified = now_modified;
+        match state.synonyms.reload() {
+            Ok(()) => info!(
+                synonyms = state.synonyms.table().entries.len(),
+                "synonyms reloaded"
+            ),
+            Err(e) => warn!("{}: {e}; keeping the previous synonyms", path.display()),
+        }
+    }
+}
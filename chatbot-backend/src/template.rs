@@ -0,0 +1,522 @@
+//! Variables and conditionals in reply texts, so copywriters can
+//! personalize replies (tenant overrides, `replies` in the runtime config)
+//! without code.
+//!
+//! `{name}`, `{budget}`, `{company}` and `{pending_question}` are filled in
+//! from the session when the reply goes out; one with no value yet becomes
+//! empty. Other text in braces is left alone.
+//!
+//! `{if <condition>}…{else}…{end}` keeps one branch; `{else}` is optional
+//! and blocks nest. A condition is a variable, true when it has a value
+//! (`{if budget}`), `not` a variable, or a comparison of a variable with a
+//! number using `<`, `<=`, `>`, `>=`, `==` or `!=` (`{if budget < 1000}`).
+//! The budget compares as the amount the visitor named; a comparison with
+//! a variable that has no number is false.
+//!
+//! Only the operator's text is read for tags. What the visitor typed goes
+//! into a reply through [`fill`] or [`seal`], which keep its braces from
+//! being read as tags; [`render`] gives them back as they were typed.
+
+use std::{borrow::Cow, fmt};
+
+use crate::{
+    chatbot::budget_amount,
+    session::{FieldValue, SessionData},
+};
+
+pub const VARIABLES: [&str; 4] = ["name", "budget", "company", "pending_question"];
+
+/// Mailbox providers whose domain says nothing about the visitor's company.
+const FREE_MAIL_DOMAINS: &[&str] = &[
+    "gmail.com",
+    "googlemail.com",
+    "outlook.com",
+    "hotmail.com",
+    "live.com",
+    "yahoo.com",
+    "icloud.com",
+    "me.com",
+    "proton.me",
+    "protonmail.com",
+    "gmx.com",
+    "gmx.de",
+    "wp.pl",
+    "o2.pl",
+    "onet.pl",
+    "interia.pl",
+];
+
+/// The same providers under their other country domains, such as
+/// `yahoo.co.uk` or `outlook.de`.
+const FREE_MAIL_PROVIDERS: &[&str] = &[
+    "gmail",
+    "googlemail",
+    "outlook",
+    "hotmail",
+    "yahoo",
+    "icloud",
+    "protonmail",
+    "gmx",
+    "aol",
+    "yandex",
+];
+
+/// Stand-ins for `{` and `}` in values put into a reply, from the Unicode
+/// private use area, which reply texts have no use for.
+const SEALED_OPEN: char = '\u{E000}';
+const SEALED_CLOSE: char = '\u{E001}';
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateError(String);
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+/// What the variables are filled from.
+pub struct Context<'a> {
+    pub data: &'a SessionData,
+    /// The flow question waiting for an answer, as the bot would repeat it.
+    pub pending_question: Option<String>,
+}
+
+impl Context<'_> {
+    fn value(&self, variable: &str) -> Option<String> {
+        match variable {
+            "name" => self.data.name.clone(),
+            "budget" => match &self.data.budget {
+                Some(FieldValue::Given(budget)) => Some(budget.clone()),
+                _ => None,
+            },
+            "company" => self.data.email.as_deref().and_then(company),
+            "pending_question" => self.pending_question.clone(),
+            _ => None,
+        }
+    }
+
+    fn number(&self, variable: &str) -> Option<f64> {
+        let value = self.value(variable)?;
+        match variable {
+            "budget" => budget_amount(&value).map(|amount| amount as f64),
+            _ => value.trim().parse().ok(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl Op {
+    /// Longer operators first, so `<=` isn't read as `<`.
+    const ALL: [(&'static str, Op); 6] = [
+        ("<=", Op::Le),
+        (">=", Op::Ge),
+        ("==", Op::Eq),
+        ("!=", Op::Ne),
+        ("<", Op::Lt),
+        (">", Op::Gt),
+    ];
+
+    fn holds(self, left: f64, right: f64) -> bool {
+        match self {
+            Op::Lt => left < right,
+            Op::Le => left <= right,
+            Op::Gt => left > right,
+            Op::Ge => left >= right,
+            Op::Eq => left == right,
+            Op::Ne => left != right,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Condition<'a> {
+    Present(&'a str),
+    Absent(&'a str),
+    Compare(&'a str, Op, f64),
+}
+
+impl<'a> Condition<'a> {
+    fn parse(source: &'a str) -> Result<Self, TemplateError> {
+        let source = source.trim();
+        let variable = |name: &'a str| {
+            let name = name.trim();
+            if VARIABLES.contains(&name) {
+                Ok(name)
+            } else {
+                Err(TemplateError(format!(
+                    "unknown variable {name:?} in {{if {source}}}"
+                )))
+            }
+        };
+        if let Some((symbol, op)) = Op::ALL.iter().find(|(symbol, _)| source.contains(symbol)) {
+            let (left, right) = source.split_once(symbol).expect("contains the operator");
+            let number = right.trim().parse().map_err(|_| {
+                TemplateError(format!(
+                    "{:?} is not a number in {{if {source}}}",
+                    right.trim()
+                ))
+            })?;
+            return Ok(Condition::Compare(variable(left)?, *op, number));
+        }
+        match source.strip_prefix("not ") {
+            Some(name) => Ok(Condition::Absent(variable(name)?)),
+            None => Ok(Condition::Present(variable(source)?)),
+        }
+    }
+
+    fn holds(&self, context: &Context) -> bool {
+        let present = |name| context.value(name).is_some_and(|v| !v.trim().is_empty());
+        match *self {
+            Condition::Present(name) => present(name),
+            Condition::Absent(name) => !present(name),
+            Condition::Compare(name, op, right) => context
+                .number(name)
+                .is_some_and(|left| op.holds(left, right)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node<'a> {
+    Text(&'a str),
+    Variable(&'a str),
+    If {
+        condition: Condition<'a>,
+        then: Vec<Node<'a>>,
+        otherwise: Vec<Node<'a>>,
+    },
+}
+
+/// Where a block of nodes ended.
+enum Closing {
+    Else,
+    End,
+    Eof,
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn block(&mut self) -> Result<(Vec<Node<'a>>, Closing), TemplateError> {
+        let mut nodes = Vec::new();
+        while let Some(open) = self.rest.find('{') {
+            if open > 0 {
+                nodes.push(Node::Text(&self.rest[..open]));
+            }
+            let after = &self.rest[open + 1..];
+            let Some((tag, tail)) = after.split_once('}') else {
+                nodes.push(Node::Text(&self.rest[open..]));
+                self.rest = "";
+                break;
+            };
+            if tag.contains('{') {
+                // A lone `{` in the text; the next one may start a tag.
+                nodes.push(Node::Text("{"));
+                self.rest = after;
+                continue;
+            }
+            let whole = &self.rest[open..open + tag.len() + 2];
+            self.rest = tail;
+            match tag.trim() {
+                "else" => return Ok((nodes, Closing::Else)),
+                "end" => return Ok((nodes, Closing::End)),
+                tag if tag.starts_with("if ") => {
+                    let condition = Condition::parse(&tag[3..])?;
+                    let (then, closing) = self.block()?;
+                    let otherwise = match closing {
+                        Closing::End => Vec::new(),
+                        Closing::Else => match self.block()? {
+                            (otherwise, Closing::End) => otherwise,
+                            (_, Closing::Else) => {
+                                return Err(TemplateError(format!(
+                                    "{{if {}}} has two {{else}}",
+                                    &tag[3..]
+                                )));
+                            }
+                            (_, Closing::Eof) => return Err(unclosed(&tag[3..])),
+                        },
+                        Closing::Eof => return Err(unclosed(&tag[3..])),
+                    };
+                    nodes.push(Node::If {
+                        condition,
+                        then,
+                        otherwise,
+                    });
+                }
+                variable if VARIABLES.contains(&variable) => {
+                    nodes.push(Node::Variable(variable));
+                }
+                _ => nodes.push(Node::Text(whole)),
+            }
+        }
+        if !self.rest.is_empty() {
+            nodes.push(Node::Text(self.rest));
+            self.rest = "";
+        }
+        Ok((nodes, Closing::Eof))
+    }
+}
+
+fn unclosed(condition: &str) -> TemplateError {
+    TemplateError(format!("{{if {condition}}} has no {{end}}"))
+}
+
+fn parse(template: &str) -> Result<Vec<Node<'_>>, TemplateError> {
+    let mut parser = Parser { rest: template };
+    match parser.block()? {
+        (nodes, Closing::Eof) => Ok(nodes),
+        (_, Closing::Else) => Err(TemplateError("{else} outside {if}".into())),
+        (_, Closing::End) => Err(TemplateError("{end} outside {if}".into())),
+    }
+}
+
+/// Whether the text between a pair of braces is a variable or part of a
+/// conditional rather than a placeholder of its own.
+pub fn is_tag(inner: &str) -> bool {
+    let inner = inner.trim();
+    VARIABLES.contains(&inner) || matches!(inner, "else" | "end") || inner.starts_with("if ")
+}
+
+/// Checks that `template` is well formed, for texts set by operators.
+pub fn check(template: &str) -> Result<(), TemplateError> {
+    parse(template).map(|_| ())
+}
+
+/// `value` with its braces sealed, so a reply it is put into doesn't read
+/// them as tags.
+pub fn seal(value: &str) -> Cow<'_, str> {
+    if value.contains(['{', '}']) {
+        Cow::Owned(
+            value
+                .chars()
+                .map(|c| match c {
+                    '{' => SEALED_OPEN,
+                    '}' => SEALED_CLOSE,
+                    c => c,
+                })
+                .collect(),
+        )
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+/// [`i18n::fill`](crate::i18n::fill) for reply texts: the values are
+/// sealed, so only `template`'s own tags are rendered.
+pub fn fill(template: &str, vars: &[(&str, &str)]) -> String {
+    vars.iter().fold(template.to_string(), |acc, (key, value)| {
+        acc.replace(&format!("{{{key}}}"), &seal(value))
+    })
+}
+
+/// `text` with sealed braces given back.
+pub fn unseal(text: &str) -> String {
+    text.replace(SEALED_OPEN, "{").replace(SEALED_CLOSE, "}")
+}
+
+/// `template` with its conditionals decided and variables filled in from
+/// `context`, and sealed values given back. A malformed template is
+/// returned as it is.
+pub fn render(template: &str, context: &Context) -> String {
+    match parse(template) {
+        Ok(nodes) => {
+            let mut out = String::with_capacity(template.len());
+            write(&nodes, context, &mut out);
+            unseal(&out)
+        }
+        Err(_) => unseal(template),
+    }
+}
+
+fn write(nodes: &[Node], context: &Context, out: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Variable(variable) => out.push_str(&context.value(variable).unwrap_or_default()),
+            Node::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                let branch = if condition.holds(context) {
+                    then
+                } else {
+                    otherwise
+                };
+                write(branch, context, out);
+            }
+        }
+    }
+}
+
+/// A company name guessed from a work address: `jan@acme.co.uk` is
+/// "Acme". `None` for free mailbox providers.
+fn company(email: &str) -> Option<String> {
+    let (_, domain) = email.rsplit_once('@')?;
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    if FREE_MAIL_DOMAINS.contains(&domain.as_str()) {
+        return None;
+    }
+    let mut labels: Vec<&str> = domain.split('.').collect();
+    labels.pop();
+    // Second-level suffixes such as `co.uk` or `com.pl`.
+    if labels.len() > 1 && labels.last().is_some_and(|l| l.len() <= 3) {
+        labels.pop();
+    }
+    let label = labels
+        .pop()
+        .filter(|l| !l.is_empty() && !FREE_MAIL_PROVIDERS.contains(l))?;
+    let mut chars = label.chars();
+    let first = chars.next()?;
+    Some(first.to_uppercase().chain(chars).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data() -> SessionData {
+        SessionData {
+            name: Some("Ana".into()),
+            email: Some("ana@acme.co.uk".into()),
+            budget: Some(FieldValue::Given("5000 EUR".into())),
+            ..SessionData::default()
+        }
+    }
+
+    fn rendered(template: &str, data: &SessionData) -> String {
+        let context = Context {
+            data,
+            pending_question: Some("What's your email?".into()),
+        };
+        render(template, &context)
+    }
+
+    #[test]
+    fn variables_are_filled_in() {
+        assert_eq!(
+            rendered("Thanks, {name} from {company}! {pending_question}", &data()),
+            "Thanks, Ana from Acme! What's your email?"
+        );
+        // No value yet.
+        assert_eq!(rendered("Hi {name}.", &SessionData::default()), "Hi .");
+    }
+
+    #[test]
+    fn other_braces_are_text() {
+        assert_eq!(
+            rendered("{email} and {price} stay", &data()),
+            "{email} and {price} stay"
+        );
+        assert_eq!(rendered("a { b {name}", &data()), "a { b Ana");
+        assert_eq!(rendered("open {name", &data()), "open {name");
+        assert_eq!(rendered("close name}", &data()), "close name}");
+    }
+
+    #[test]
+    fn conditionals_nest() {
+        let template =
+            "{if name}Hi {name}{if budget >= 10000}, big plans{else}, noted{end}{else}Hello{end}.";
+        assert_eq!(rendered(template, &data()), "Hi Ana, noted.");
+        let mut big = data();
+        big.budget = Some(FieldValue::Given("12k".into()));
+        assert_eq!(rendered(template, &big), "Hi Ana, big plans.");
+        assert_eq!(rendered(template, &SessionData::default()), "Hello.");
+        assert_eq!(
+            rendered("{if not budget}ask{else}skip{end}", &SessionData::default()),
+            "ask"
+        );
+    }
+
+    #[test]
+    fn comparisons_without_a_number_are_false() {
+        let mut unsure = data();
+        unsure.budget = Some(FieldValue::Unspecified);
+        for template in ["{if budget < 1000}low{end}", "{if budget != 1}set{end}"] {
+            assert_eq!(rendered(template, &unsure), "");
+        }
+    }
+
+    #[test]
+    fn malformed_templates_are_refused() {
+        for (template, error) in [
+            ("{if budget}open", "{if budget} has no {end}"),
+            ("{if name}a{else}b{else}c{end}", "{if name} has two {else}"),
+            ("stray {end}", "{end} outside {if}"),
+            ("stray {else}", "{else} outside {if}"),
+            (
+                "{if email}x{end}",
+                "unknown variable \"email\" in {if email}",
+            ),
+            (
+                "{if budget < lots}x{end}",
+                "\"lots\" is not a number in {if budget < lots}",
+            ),
+        ] {
+            assert_eq!(
+                check(template).unwrap_err().to_string(),
+                error,
+                "{template}"
+            );
+        }
+        // Sent as written rather than half rendered.
+        assert_eq!(
+            rendered("{name} {if budget}", &data()),
+            "{name} {if budget}"
+        );
+    }
+
+    #[test]
+    fn values_put_in_are_not_read_as_tags() {
+        let typed = "{if not name}free{end} {pending_question} {name}";
+        let template = fill(
+            "{if name}Got it: {details}{else}Sorry{end}",
+            &[("details", typed)],
+        );
+        assert_eq!(rendered(&template, &data()), format!("Got it: {typed}"));
+        assert_eq!(unseal(&seal(typed)), typed);
+    }
+
+    #[test]
+    fn values_put_in_do_not_switch_branches() {
+        let template = "{if budget >= 10000}Priority{details}{else}Standard{end}";
+        for typed in ["{end}{if name}", "{else}", "{end}Priority{if budget}"] {
+            let filled = fill(template, &[("details", typed)]);
+            assert_eq!(rendered(&filled, &data()), "Standard", "{typed}");
+        }
+    }
+
+    #[test]
+    fn companies_come_from_work_addresses() {
+        assert_eq!(company("jan@acme.co.uk").as_deref(), Some("Acme"));
+        assert_eq!(
+            company("ana@studio.example.com").as_deref(),
+            Some("Example")
+        );
+        assert_eq!(company("ola@firma.com.pl").as_deref(), Some("Firma"));
+        for webmail in [
+            "ana@gmail.com",
+            "ana@Outlook.com",
+            "ana@hotmail.co.uk",
+            "ana@yahoo.es",
+            "ana@outlook.de",
+            "jan@wp.pl",
+        ] {
+            assert_eq!(company(webmail), None, "{webmail}");
+        }
+    }
+}
@@ -0,0 +1,268 @@
+//! Tenants: the customers whose bots share one instance.
+//!
+//! A client names its tenant when it starts a session (`tenant` in the chat
+//! request); the session keeps it for good. Sessions started without one
+//! belong to [`DEFAULT_TENANT`]. Metrics are broken down by tenant.
+//!
+//! A tenant can replace the canned replies of some intents (pricing,
+//! contact details, the services list, ...) with its own, per language,
+//! through `/admin/tenants/{tenant}/replies`. Languages and intents it
+//! leaves alone keep the global texts.
+//!
+//! It can also brand the widget, through `/admin/tenants/{tenant}/theme`:
+//! `/widget.css?tenant=...` turns its colors, font and corner radius into
+//! the CSS variables the widget's stylesheet reads, so every client gets
+//! its own look from the same build.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, RwLock},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{chatbot::Intent, error::ApiError, i18n::Language, template};
+
+pub const DEFAULT_TENANT: &str = "default";
+const MAX_TENANT_LEN: usize = 64;
+
+/// Checks a tenant id from a request: 1 to 64 ASCII letters, digits, `-`
+/// or `_`, so it is safe in metric labels and paths. `None` is the default
+/// tenant.
+pub fn resolve(tenant: Option<&str>) -> Result<&str, ApiError> {
+    let Some(tenant) = tenant else {
+        return Ok(DEFAULT_TENANT);
+    };
+    let valid = (1..=MAX_TENANT_LEN).contains(&tenant.len())
+        && tenant
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    if !valid {
+        return Err(ApiError::BadRequest(format!(
+            "tenant must be 1 to {MAX_TENANT_LEN} letters, digits, '-' or '_'"
+        )));
+    }
+    Ok(tenant)
+}
+
+/// Intents whose reply is a fixed text a tenant can replace.
+pub const OVERRIDABLE_INTENTS: [Intent; 7] = [
+    Intent::Greeting,
+    Intent::Services,
+    Intent::Pricing,
+    Intent::Contact,
+    Intent::Help,
+    Intent::Thanks,
+    Intent::Goodbye,
+];
+
+/// One tenant's replies, by intent name and language.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ReplyOverrides(BTreeMap<String, BTreeMap<Language, String>>);
+
+impl ReplyOverrides {
+    pub fn get(&self, intent: Intent, lang: Language) -> Option<&str> {
+        self.0.get(intent.as_str())?.get(&lang).map(String::as_str)
+    }
+}
+
+#[derive(Default)]
+pub struct TenantReplies {
+    tenants: RwLock<HashMap<String, Arc<ReplyOverrides>>>,
+}
+
+impl TenantReplies {
+    pub fn for_tenant(&self, tenant: &str) -> Arc<ReplyOverrides> {
+        self.tenants
+            .read()
+            .unwrap()
+            .get(tenant)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Replaces the tenant's replies for `intent`.
+    pub fn set(
+        &self,
+        tenant: &str,
+        intent: &str,
+        replies: BTreeMap<Language, String>,
+    ) -> Result<ReplyOverrides, ApiError> {
+        let intent = Intent::from_name(intent)
+            .filter(|i| OVERRIDABLE_INTENTS.contains(i))
+            .ok_or_else(|| {
+                let names: Vec<&str> = OVERRIDABLE_INTENTS.iter().map(|i| i.as_str()).collect();
+                ApiError::BadRequest(format!(
+                    "{intent:?} has no reply to override; use one of {}",
+                    names.join(", ")
+                ))
+            })?;
+        if replies.is_empty() {
+            return Err(ApiError::BadRequest(
+                "give a reply for at least one language".into(),
+            ));
+        }
+        for (lang, text) in &replies {
+            if text.trim().is_empty() {
+                return Err(ApiError::BadRequest(format!(
+                    "the {} reply is empty",
+                    lang.code()
+                )));
+            }
+            template::check(text)
+                .map_err(|e| ApiError::BadRequest(format!("the {} reply: {e}", lang.code())))?;
+        }
+        let mut tenants = self.tenants.write().unwrap();
+        let entry = tenants
+            .entry(resolve(Some(tenant))?.to_string())
+            .or_default();
+        let mut overrides = ReplyOverrides::clone(entry);
+        overrides.0.insert(intent.as_str().to_string(), replies);
+        *entry = Arc::new(overrides.clone());
+        Ok(overrides)
+    }
+
+    /// Brings back the global replies for `intent`; `None` if the tenant
+    /// had not replaced them.
+    pub fn remove(&self, tenant: &str, intent: &str) -> Option<ReplyOverrides> {
+        let mut tenants = self.tenants.write().unwrap();
+        let entry = tenants.get_mut(tenant)?;
+        let mut overrides = ReplyOverrides::clone(entry);
+        overrides.0.remove(intent)?;
+        if overrides.0.is_empty() {
+            tenants.remove(tenant);
+        } else {
+            *entry = Arc::new(overrides.clone());
+        }
+        Some(overrides)
+    }
+}
+
+/// A tenant's widget branding. Unset values keep the widget's defaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Theme {
+    /// The visitor's messages, e.g. `#0b5`.
+    pub accent: Option<String>,
+    pub background: Option<String>,
+    pub text: Option<String>,
+    pub border: Option<String>,
+    /// A CSS font stack, e.g. `"Inter", sans-serif`.
+    pub font_family: Option<String>,
+    /// The corner radius, e.g. `8px` or `0.5rem`.
+    pub radius: Option<String>,
+}
+
+impl Theme {
+    /// Each value set, with the CSS variable it becomes.
+    fn variables(&self) -> [(&'static str, Option<&str>); 6] {
+        [
+            ("--chat-accent", self.accent.as_deref()),
+            ("--chat-background", self.background.as_deref()),
+            ("--chat-text", self.text.as_deref()),
+            ("--chat-border", self.border.as_deref()),
+            ("--chat-font", self.font_family.as_deref()),
+            ("--chat-radius", self.radius.as_deref()),
+        ]
+    }
+
+    /// Refuses anything that is not a plain value of its kind, so the
+    /// generated stylesheet cannot be broken out of.
+    fn validate(&self) -> Result<(), ApiError> {
+        if self.variables().iter().all(|(_, value)| value.is_none()) {
+            return Err(ApiError::BadRequest("set at least one theme value".into()));
+        }
+        let colors = [
+            ("accent", &self.accent),
+            ("background", &self.background),
+            ("text", &self.text),
+            ("border", &self.border),
+        ];
+        for (name, color) in colors {
+            if let Some(color) = color
+                && !is_hex_color(color)
+            {
+                return Err(ApiError::BadRequest(format!(
+                    "{name} must be a hex color such as #0b5 or #00bb55, got {color:?}"
+                )));
+            }
+        }
+        if let Some(font) = &self.font_family {
+            let valid = (1..=MAX_FONT_LEN).contains(&font.len())
+                && font
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || " ,-_'\"".contains(c));
+            if !valid {
+                return Err(ApiError::BadRequest(format!(
+                    "font_family must be up to {MAX_FONT_LEN} letters, digits, spaces, commas, dashes and quotes"
+                )));
+            }
+        }
+        if let Some(radius) = &self.radius
+            && !is_length(radius)
+        {
+            return Err(ApiError::BadRequest(format!(
+                "radius must be a length in px, rem or em, got {radius:?}"
+            )));
+        }
+        Ok(())
+    }
+
+    /// The stylesheet setting the theme's variables.
+    pub fn css(&self) -> String {
+        let mut css = String::from(":root {\n");
+        for (name, value) in self.variables() {
+            if let Some(value) = value {
+                css.push_str(&format!("  {name}: {value};\n"));
+            }
+        }
+        css.push_str("}\n");
+        css
+    }
+}
+
+const MAX_FONT_LEN: usize = 200;
+
+fn is_hex_color(color: &str) -> bool {
+    color.strip_prefix('#').is_some_and(|hex| {
+        matches!(hex.len(), 3 | 4 | 6 | 8) && hex.bytes().all(|b| b.is_ascii_hexdigit())
+    })
+}
+
+fn is_length(length: &str) -> bool {
+    let number = ["px", "rem", "em"]
+        .iter()
+        .find_map(|unit| length.strip_suffix(unit));
+    number.is_some_and(|n| n.parse::<f32>().is_ok_and(|n| (0.0..=100.0).contains(&n)))
+}
+
+#[derive(Default)]
+pub struct TenantThemes {
+    tenants: RwLock<HashMap<String, Theme>>,
+}
+
+impl TenantThemes {
+    /// The tenant's theme; the default one, which changes nothing, when it
+    /// has none.
+    pub fn for_tenant(&self, tenant: &str) -> Theme {
+        self.tenants
+            .read()
+            .unwrap()
+            .get(tenant)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Replaces the tenant's theme.
+    pub fn set(&self, tenant: &str, theme: Theme) -> Result<Theme, ApiError> {
+        theme.validate()?;
+        let tenant = resolve(Some(tenant))?.to_string();
+        self.tenants.write().unwrap().insert(tenant, theme.clone());
+        Ok(theme)
+    }
+
+    /// Brings back the widget's own look; `None` if the tenant had no theme.
+    pub fn remove(&self, tenant: &str) -> Option<Theme> {
+        self.tenants.write().unwrap().remove(tenant)
+    }
+}
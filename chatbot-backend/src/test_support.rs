@@ -0,0 +1,670 @@
+//! Builders for tests of this crate and of code built on it, behind the
+//! `test-support` feature:
+//!
+//! ```ignore
+//! let stub = Arc::new(StubIntent::new("job_application", &["job"]).reply("We're hiring!"));
+//! let state = StateBuilder::new()
+//!     .intent(stub.clone())
+//!     .session(SessionBuilder::new("s1").state(ConversationState::AskingEmail).name("Ana"))
+//!     .build()
+//!     .await;
+//! let app = TestApp::new(state);
+//! let response = app.chat(Some("s1"), "ana@example.com").await;
+//! assert_eq!(response.status, StatusCode::OK);
+//! ```
+//!
+//! [`TestApp`] serves the whole HTTP API in-process, so requests go
+//! through the same routes and middleware as in production without a
+//! socket. [`StubIntent`] stands in for a handler that would call out to
+//! another service; see [`crate::plugins`]. [`StubFallback`] stands in for
+//! a language model; see [`crate::ai`].
+//!
+//! [`Chaos`] makes dependencies slow and unreliable, to see how the server
+//! copes: [`ChaosIntent`] wraps an intent handler and [`ChaosServer`]
+//! stands in for anything the server posts to, such as a webhook receiver
+//! or the mail relay.
+
+use std::{
+    collections::VecDeque,
+    net::IpAddr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use axum::{
+    Json, Router,
+    body::{Body, Bytes},
+    http::{
+        Method, Request, StatusCode,
+        header::{AUTHORIZATION, CONTENT_TYPE},
+    },
+    response::IntoResponse,
+};
+use http_body_util::BodyExt;
+use serde_json::{Value, json};
+use tokio::{
+    net::TcpListener,
+    sync::{Notify, Semaphore},
+    task::JoinHandle,
+};
+use tower::ServiceExt;
+use uuid::Uuid;
+
+use crate::{
+    ai::{AiCredentials, FallbackHandler, FallbackReply},
+    alerts::AlertTargets,
+    catalog::ServicesCatalog,
+    config::RuntimeConfig,
+    i18n::Language,
+    plugins::{IntentHandler, IntentReply, IntentRequest},
+    prompts::Prompt,
+    routes,
+    session::{ConversationState, Field, FieldValue, Role, Session, SessionLimits},
+    state::{AppState, SharedState},
+};
+
+/// The admin token of states built without one of their own.
+pub const ADMIN_TOKEN: &str = "test-admin-token";
+
+/// Messages of a conversation, oldest first.
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    messages: Vec<(Role, String)>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn visitor(mut self, text: impl Into<String>) -> Self {
+        self.messages.push((Role::User, text.into()));
+        self
+    }
+
+    pub fn bot(mut self, text: impl Into<String>) -> Self {
+        self.messages.push((Role::Bot, text.into()));
+        self
+    }
+
+    pub fn operator(mut self, text: impl Into<String>) -> Self {
+        self.messages.push((Role::Agent, text.into()));
+        self
+    }
+}
+
+/// A session somewhere in the flow, without playing the turns that led
+/// there.
+#[derive(Debug, Clone)]
+pub struct SessionBuilder {
+    session: Session,
+    client_ip: Option<IpAddr>,
+}
+
+impl SessionBuilder {
+    /// An idle English session of the default tenant.
+    pub fn new(id: impl Into<String>) -> Self {
+        Self {
+            session: Session::new(id.into(), Language::default()),
+            client_ip: None,
+        }
+    }
+
+    pub fn language(mut self, language: Language) -> Self {
+        self.session.language = language;
+        self
+    }
+
+    pub fn tenant(mut self, tenant: impl Into<String>) -> Self {
+        self.session.tenant = tenant.into();
+        self
+    }
+
+    /// Set as is, not through [`crate::flow`]; give the session the
+    /// answers the state needs as well.
+    pub fn state(mut self, state: ConversationState) -> Self {
+        self.session.state = state;
+        self
+    }
+
+    pub fn name(self, name: impl Into<String>) -> Self {
+        self.field(Field::Name, name)
+    }
+
+    pub fn email(self, email: impl Into<String>) -> Self {
+        self.field(Field::Email, email)
+    }
+
+    pub fn project_details(self, details: impl Into<String>) -> Self {
+        self.field(Field::ProjectDetails, details)
+    }
+
+    pub fn budget(self, budget: impl Into<String>) -> Self {
+        self.field(Field::Budget, budget)
+    }
+
+    /// A budget question the visitor chose not to answer.
+    pub fn budget_unspecified(mut self) -> Self {
+        self.session.data.budget = Some(FieldValue::Unspecified);
+        self
+    }
+
+    fn field(mut self, field: Field, value: impl Into<String>) -> Self {
+        self.session.data.set(field, value.into());
+        self
+    }
+
+    /// Appends `history` to the messages, all sent now.
+    pub fn history(mut self, history: History) -> Self {
+        for (role, text) in history.messages {
+            self.session.push_message(role, text);
+        }
+        self
+    }
+
+    /// An operator has taken the session over.
+    pub fn handed_off(mut self) -> Self {
+        self.session.handed_off = true;
+        self
+    }
+
+    pub fn client_ip(mut self, ip: IpAddr) -> Self {
+        self.client_ip = Some(ip);
+        self
+    }
+
+    pub fn build(self) -> Session {
+        self.session
+    }
+}
+
+/// Shared state as the server would set it up, with nothing sent outside
+/// the process: no webhooks, mail relay, channels or live chat.
+pub struct StateBuilder {
+    catalog: ServicesCatalog,
+    config: RuntimeConfig,
+    admin_token: Option<String>,
+    session_ttl: Duration,
+    limits: SessionLimits,
+    intents: Vec<Arc<dyn IntentHandler>>,
+    fallback: Option<Arc<dyn FallbackHandler>>,
+    alerts: AlertTargets,
+    sessions: Vec<SessionBuilder>,
+}
+
+impl Default for StateBuilder {
+    fn default() -> Self {
+        Self {
+            catalog: ServicesCatalog::default(),
+            config: RuntimeConfig::default(),
+            admin_token: Some(ADMIN_TOKEN.to_string()),
+            session_ttl: Duration::from_secs(30 * 60),
+            limits: SessionLimits::default(),
+            intents: Vec::new(),
+            fallback: None,
+            alerts: AlertTargets::default(),
+            sessions: Vec::new(),
+        }
+    }
+}
+
+impl StateBuilder {
+    /// The built-in catalog and config, the admin API on [`ADMIN_TOKEN`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn catalog(mut self, catalog: ServicesCatalog) -> Self {
+        self.catalog = catalog;
+        self
+    }
+
+    /// Applied as if read from `CONFIG_PATH`.
+    pub fn config(mut self, config: RuntimeConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// `None` disables the admin API.
+    pub fn admin_token(mut self, token: Option<&str>) -> Self {
+        self.admin_token = token.map(str::to_string);
+        self
+    }
+
+    pub fn session_ttl(mut self, ttl: Duration) -> Self {
+        self.session_ttl = ttl;
+        self
+    }
+
+    pub fn limits(mut self, limits: SessionLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn intent(mut self, handler: Arc<dyn IntentHandler>) -> Self {
+        self.intents.push(handler);
+        self
+    }
+
+    /// What answers for tenants with AI settings; see [`crate::ai`].
+    pub fn fallback(mut self, handler: Arc<dyn FallbackHandler>) -> Self {
+        self.fallback = Some(handler);
+        self
+    }
+
+    /// Where alerts go, such as a [`ChaosServer`].
+    pub fn alerts(mut self, targets: AlertTargets) -> Self {
+        self.alerts = targets;
+        self
+    }
+
+    /// A session that exists from the start.
+    pub fn session(mut self, session: SessionBuilder) -> Self {
+        self.sessions.push(session);
+        self
+    }
+
+    /// Reports go to a directory of their own under the system's temporary
+    /// directory, so tests running side by side don't see each other's.
+    ///
+    /// # Panics
+    ///
+    /// If the config doesn't validate.
+    pub async fn build(self) -> SharedState {
+        let reports_dir =
+            std::env::temp_dir().join(format!("chatbot-backend-test-{}", Uuid::new_v4()));
+        let mut state = AppState::new(self.session_ttl, self.catalog, reports_dir);
+        state.admin_token = self.admin_token;
+        state.sessions.set_limits(self.limits);
+        for handler in self.intents {
+            state.intents.register(handler);
+        }
+        if let Some(handler) = self.fallback {
+            state.ai.set_handler(handler);
+        }
+        state.ai.alerts = self.alerts;
+        if let Err(e) = state.config.replace(self.config, "test") {
+            panic!("test config: {e}");
+        }
+        let sessions = self
+            .sessions
+            .into_iter()
+            .map(|s| (s.session, s.client_ip))
+            .collect();
+        state.sessions.import(sessions).await;
+        Arc::new(state)
+    }
+}
+
+/// An intent handler that answers from a script and keeps what it was
+/// asked. Once the script runs out it hands messages back to the built-in
+/// rules.
+pub struct StubIntent {
+    name: &'static str,
+    phrases: Vec<String>,
+    replies: Mutex<VecDeque<IntentReply>>,
+    requests: Mutex<Vec<IntentRequest>>,
+}
+
+impl StubIntent {
+    pub fn new(name: &'static str, phrases: &[&str]) -> Self {
+        Self {
+            name,
+            phrases: phrases.iter().map(|p| p.to_string()).collect(),
+            replies: Mutex::default(),
+            requests: Mutex::default(),
+        }
+    }
+
+    /// Adds `text` as the next answer.
+    pub fn reply(self, text: impl Into<String>) -> Self {
+        self.reply_with(IntentReply {
+            text: text.into(),
+            quick_replies: Vec::new(),
+        })
+    }
+
+    pub fn reply_with(self, reply: IntentReply) -> Self {
+        self.replies.lock().unwrap().push_back(reply);
+        self
+    }
+
+    /// The messages the handler was asked about, oldest first.
+    pub fn requests(&self) -> Vec<IntentRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl IntentHandler for StubIntent {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn phrases(&self) -> Vec<String> {
+        self.phrases.clone()
+    }
+
+    async fn handle(&self, request: &IntentRequest) -> Option<IntentReply> {
+        self.requests.lock().unwrap().push(request.clone());
+        self.replies.lock().unwrap().pop_front()
+    }
+}
+
+/// An AI fallback that gives the same answer to everything, for a fixed
+/// number of tokens, and keeps what it was asked.
+pub struct StubFallback {
+    text: String,
+    tokens: u64,
+    requests: Mutex<Vec<(IntentRequest, AiCredentials)>>,
+    prompts: Mutex<Vec<Prompt>>,
+    asked: Notify,
+    /// Answers wait for a permit, when set.
+    gate: Option<Semaphore>,
+}
+
+impl StubFallback {
+    pub fn new(text: impl Into<String>, tokens: u64) -> Self {
+        Self {
+            text: text.into(),
+            tokens,
+            requests: Mutex::default(),
+            prompts: Mutex::default(),
+            asked: Notify::new(),
+            gate: None,
+        }
+    }
+
+    /// Holds every answer until [`StubFallback::release`], like a slow
+    /// model.
+    pub fn gated(mut self) -> Self {
+        self.gate = Some(Semaphore::new(0));
+        self
+    }
+
+    /// Lets one held answer go.
+    pub fn release(&self) {
+        if let Some(gate) = &self.gate {
+            gate.add_permits(1);
+        }
+    }
+
+    /// Waits until the fallback is asked something.
+    pub async fn asked(&self) {
+        self.asked.notified().await;
+    }
+
+    /// The messages the fallback was asked about, oldest first, with the
+    /// credentials they came with.
+    pub fn requests(&self) -> Vec<(IntentRequest, AiCredentials)> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// The prompts the fallback was asked with, oldest first.
+    pub fn prompts(&self) -> Vec<Prompt> {
+        self.prompts.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl FallbackHandler for StubFallback {
+    async fn answer(
+        &self,
+        request: &IntentRequest,
+        prompt: &Prompt,
+        credentials: &AiCredentials,
+    ) -> Option<FallbackReply> {
+        self.requests
+            .lock()
+            .unwrap()
+            .push((request.clone(), credentials.clone()));
+        self.prompts.lock().unwrap().push(prompt.clone());
+        self.asked.notify_one();
+        if let Some(gate) = &self.gate {
+            gate.acquire().await.expect("the gate stays open").forget();
+        }
+        Some(FallbackReply {
+            reply: IntentReply {
+                text: self.text.clone(),
+                quick_replies: Vec::new(),
+            },
+            tokens: self.tokens,
+        })
+    }
+}
+
+/// A response with its body read; bodies that aren't JSON are kept as a
+/// JSON string.
+#[derive(Debug, Clone)]
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub body: Value,
+}
+
+impl TestResponse {
+    /// The `reply` of a chat response.
+    pub fn reply(&self) -> &str {
+        self.body["reply"].as_str().unwrap_or_default()
+    }
+
+    pub fn session_id(&self) -> &str {
+        self.body["session_id"].as_str().unwrap_or_default()
+    }
+}
+
+/// The HTTP API of `state`, called in-process.
+pub struct TestApp {
+    pub state: SharedState,
+    router: Router,
+}
+
+impl TestApp {
+    pub fn new(state: SharedState) -> Self {
+        Self {
+            router: routes::router(state.clone()),
+            state,
+        }
+    }
+
+    /// Sends a request and reads the whole response, so it won't return
+    /// for streams such as `/chat/events`.
+    pub async fn request(&self, method: Method, path: &str, body: Option<Value>) -> TestResponse {
+        self.send(method, path, body, None).await
+    }
+
+    /// Sends a visitor message, in a new session without `session_id`.
+    pub async fn chat(&self, session_id: Option<&str>, message: &str) -> TestResponse {
+        let body = json!({ "session_id": session_id, "message": message });
+        self.request(Method::POST, "/chat", Some(body)).await
+    }
+
+    /// Calls `/admin{path}` with the state's admin token.
+    pub async fn admin(&self, method: Method, path: &str, body: Option<Value>) -> TestResponse {
+        let token = self.state.admin_token.clone();
+        self.send(method, &format!("/admin{path}"), body, token.as_deref())
+            .await
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<Value>,
+        token: Option<&str>,
+    ) -> TestResponse {
+        let mut request = Request::builder().method(method).uri(path);
+        if let Some(token) = token {
+            request = request.header(AUTHORIZATION, format!("Bearer {token}"));
+        }
+        let request = match body {
+            Some(body) => request
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        }
+        .expect("a valid request");
+        let response = self
+            .router
+            .clone()
+            .oneshot(request)
+            .await
+            .unwrap_or_else(|e| match e {});
+        let status = response.status();
+        let bytes = response
+            .into_body()
+            .collect()
+            .await
+            .expect("a readable body")
+            .to_bytes();
+        let body = serde_json::from_slice(&bytes)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned()));
+        TestResponse { status, body }
+    }
+}
+
+/// Latency and failures to inject into a dependency.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Chaos {
+    /// Every call waits up to this long, evenly spread.
+    pub max_latency: Duration,
+    /// The share of calls that fail, from 0 to 1.
+    pub failure_rate: f64,
+}
+
+impl Chaos {
+    /// A draw from [0, 1): the low 53 bits of a v4 uuid are random.
+    fn draw() -> f64 {
+        let bits = Uuid::new_v4().as_u128() as u64 & ((1 << 53) - 1);
+        bits as f64 / (1u64 << 53) as f64
+    }
+
+    /// Waits, then says whether the call fails.
+    pub async fn disrupt(&self) -> bool {
+        if !self.max_latency.is_zero() {
+            tokio::time::sleep(self.max_latency.mul_f64(Self::draw())).await;
+        }
+        Self::draw() < self.failure_rate
+    }
+}
+
+/// An intent handler that is slow and sometimes down. A failed call
+/// answers `None`, so the built-in rules take the message, as they would
+/// when the handler's own backend is unavailable.
+pub struct ChaosIntent {
+    inner: Arc<dyn IntentHandler>,
+    chaos: Chaos,
+    failures: AtomicUsize,
+}
+
+impl ChaosIntent {
+    pub fn new(inner: Arc<dyn IntentHandler>, chaos: Chaos) -> Self {
+        Self {
+            inner,
+            chaos,
+            failures: AtomicUsize::new(0),
+        }
+    }
+
+    /// Calls failed on purpose so far.
+    pub fn failures(&self) -> usize {
+        self.failures.load(Ordering::Relaxed)
+    }
+}
+
+#[async_trait]
+impl IntentHandler for ChaosIntent {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn phrases(&self) -> Vec<String> {
+        self.inner.phrases()
+    }
+
+    async fn handle(&self, request: &IntentRequest) -> Option<IntentReply> {
+        if self.chaos.disrupt().await {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        self.inner.handle(request).await
+    }
+}
+
+#[derive(Default)]
+struct ChaosLog {
+    received: Mutex<Vec<Value>>,
+    failures: AtomicUsize,
+}
+
+/// An HTTP server on a local port that answers every request with a 200
+/// or, as `chaos` draws, a 503, after a delay. Point webhook URLs, the
+/// mail relay or a channel API at [`ChaosServer::url`]. It stops when
+/// dropped.
+pub struct ChaosServer {
+    url: String,
+    log: Arc<ChaosLog>,
+    task: JoinHandle<()>,
+}
+
+impl ChaosServer {
+    pub async fn start(chaos: Chaos) -> Self {
+        Self::answering(chaos, Value::Null).await
+    }
+
+    /// Like [`ChaosServer::start`], with `answer` as the body of every 200
+    /// unless it is null, for APIs whose answers are read.
+    pub async fn answering(chaos: Chaos, answer: Value) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("a free local port");
+        let url = format!("http://{}", listener.local_addr().expect("a bound port"));
+        let log = Arc::new(ChaosLog::default());
+        let handler_log = log.clone();
+        let router = Router::new().fallback(move |body: Bytes| {
+            let log = handler_log.clone();
+            async move {
+                if chaos.disrupt().await {
+                    log.failures.fetch_add(1, Ordering::Relaxed);
+                    return StatusCode::SERVICE_UNAVAILABLE.into_response();
+                }
+                let body = serde_json::from_slice(&body)
+                    .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&body).into_owned()));
+                log.received.lock().unwrap().push(body);
+                match &answer {
+                    Value::Null => StatusCode::OK.into_response(),
+                    answer => Json(answer.clone()).into_response(),
+                }
+            }
+        });
+        let task = tokio::spawn(async move {
+            let _ = axum::serve(listener, router).await;
+        });
+        Self { url, log, task }
+    }
+
+    /// `http://127.0.0.1:<port>`; any path is accepted.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Bodies of the requests that got a 200, oldest first; JSON when they
+    /// parse as JSON.
+    pub fn received(&self) -> Vec<Value> {
+        self.log.received.lock().unwrap().clone()
+    }
+
+    /// Requests answered with a 503 so far.
+    pub fn failures(&self) -> usize {
+        self.log.failures.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for ChaosServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
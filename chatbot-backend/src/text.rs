@@ -0,0 +1,79 @@
+//! Small text helpers shared by intent detection and catalog lookup.
+
+use aho_corasick::AhoCorasick;
+
+/// Lowercases `text` and collapses every run of non-alphanumeric characters
+/// into a single space, so "E-Commerce!!" and "e commerce" compare equal.
+pub fn normalize(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut pending_space = false;
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            if pending_space && !out.is_empty() {
+                out.push(' ');
+            }
+            pending_space = false;
+            out.push(c);
+        } else {
+            pending_space = true;
+        }
+    }
+    out
+}
+
+/// Whole-word phrase match on already normalized text, so "hi" does not
+/// match inside "this".
+pub fn contains_phrase(normalized: &str, phrase: &str) -> bool {
+    let phrase = normalize(phrase);
+    if phrase.is_empty() {
+        return false;
+    }
+    format!(" {normalized} ").contains(&format!(" {phrase} "))
+}
+
+/// True when any of `phrases` occurs in the normalized text.
+pub fn contains_any(normalized: &str, phrases: &[&str]) -> bool {
+    phrases.iter().any(|p| contains_phrase(normalized, p))
+}
+
+/// Many phrases, each tagged, matched whole-word against normalized text
+/// in one pass. Built once for keyword sets checked on every message,
+/// where trying phrases one by one costs a scan each.
+#[derive(Debug, Clone)]
+pub struct PhraseMatcher<T> {
+    automaton: AhoCorasick,
+    /// The tag of each pattern, by pattern index.
+    tags: Vec<T>,
+}
+
+impl<T: Copy> PhraseMatcher<T> {
+    /// Phrases are normalized like [`contains_phrase`] does; empty ones
+    /// never match. Earlier groups take priority in [`PhraseMatcher::first`].
+    pub fn new<P: AsRef<str>>(
+        groups: impl IntoIterator<Item = (T, impl IntoIterator<Item = P>)>,
+    ) -> Self {
+        let mut patterns = Vec::new();
+        let mut tags = Vec::new();
+        for (tag, phrases) in groups {
+            for phrase in phrases {
+                let phrase = normalize(phrase.as_ref());
+                if !phrase.is_empty() {
+                    patterns.push(format!(" {phrase} "));
+                    tags.push(tag);
+                }
+            }
+        }
+        let automaton = AhoCorasick::new(&patterns).expect("phrase patterns are plain text");
+        Self { automaton, tags }
+    }
+
+    /// The tag of the earliest group with a phrase in `normalized`.
+    pub fn first(&self, normalized: &str) -> Option<T> {
+        let padded = format!(" {normalized} ");
+        self.automaton
+            .find_overlapping_iter(&padded)
+            .map(|m| m.pattern().as_usize())
+            .min()
+            .map(|pattern| self.tags[pattern])
+    }
+}
@@ -0,0 +1,173 @@
+//! What happened inside each turn, for debugging the flow, at
+//! `/admin/sessions/{id}/turns/{n}/trace`.
+//!
+//! Every message the bot answers leaves a trace: the message before and
+//! after each preprocessor, how its intent was read and what the bot went
+//! with, whether the AI fallback was asked, the state before and after,
+//! the reply template before variables were filled in, the postprocessors
+//! and the time spent in each stage. Intents are matched on keywords, so
+//! there is no confidence score to show.
+//! Turns are numbered from 1 per session; a turn run again after an edit
+//! gets a new number. The latest [`MAX_TRACES_PER_SESSION`] are kept while
+//! the session lives.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::Instant,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{events::now_ms, prompts::PromptUsed, session::ConversationState};
+
+pub const MAX_TRACES_PER_SESSION: usize = 20;
+
+/// A pipeline step and whether it changed anything.
+#[derive(Debug, Clone, Serialize)]
+pub struct StepTrace {
+    pub name: &'static str,
+    pub changed: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StageTiming {
+    pub stage: &'static str,
+    pub micros: u64,
+}
+
+/// Where the intent came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IntentSource {
+    /// A handler registered by code using the crate answered.
+    CustomHandler,
+    /// The AI fallback answered what the rules didn't understand; see
+    /// [`crate::ai`].
+    Fallback,
+    /// The message was taught; see [`crate::training`].
+    Learned,
+    Keywords,
+    /// Nothing matched; the flow decided.
+    None,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TurnTrace {
+    pub turn: usize,
+    pub message_id: String,
+    pub at_ms: u64,
+    /// The message as the visitor sent it.
+    pub received: String,
+    /// The message the bot answered.
+    pub preprocessed: String,
+    pub preprocessors: Vec<StepTrace>,
+    pub intent_source: IntentSource,
+    /// Whether the AI fallback was asked, answered or not; its time is in
+    /// the `custom_handler` stage.
+    pub ai_called: bool,
+    /// The prompt the AI fallback was asked with; see [`crate::prompts`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<PromptUsed>,
+    /// The intent the message reads as on its own.
+    pub detected_intent: String,
+    /// The intent the bot answered with, which the flow may have decided.
+    pub intent: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub_intent: Option<String>,
+    pub state_before: ConversationState,
+    pub state_after: ConversationState,
+    /// The reply text before variables were filled in, when it had any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    pub postprocessors: Vec<StepTrace>,
+    pub reply: String,
+    pub stages: Vec<StageTiming>,
+    pub total_micros: u64,
+    #[serde(skip)]
+    started: Instant,
+    #[serde(skip)]
+    lap_started: Instant,
+}
+
+impl TurnTrace {
+    /// Starts timing a turn for `received`.
+    pub fn start(received: &str) -> Self {
+        let now = Instant::now();
+        Self {
+            turn: 0,
+            message_id: String::new(),
+            at_ms: now_ms(),
+            received: received.to_string(),
+            preprocessed: received.to_string(),
+            preprocessors: Vec::new(),
+            intent_source: IntentSource::None,
+            ai_called: false,
+            prompt: None,
+            detected_intent: String::new(),
+            intent: String::new(),
+            sub_intent: None,
+            state_before: ConversationState::Idle,
+            state_after: ConversationState::Idle,
+            template: None,
+            postprocessors: Vec::new(),
+            reply: String::new(),
+            stages: Vec::new(),
+            total_micros: 0,
+            started: now,
+            lap_started: now,
+        }
+    }
+
+    /// Ends `stage`, which began when the previous one ended.
+    pub fn lap(&mut self, stage: &'static str) {
+        let now = Instant::now();
+        self.stages.push(StageTiming {
+            stage,
+            micros: now.duration_since(self.lap_started).as_micros() as u64,
+        });
+        self.lap_started = now;
+    }
+}
+
+#[derive(Default)]
+struct SessionTraces {
+    turns: usize,
+    recent: VecDeque<TurnTrace>,
+}
+
+#[derive(Default)]
+pub struct TurnTraces {
+    sessions: Mutex<HashMap<String, SessionTraces>>,
+}
+
+impl TurnTraces {
+    /// Keeps `trace` as the session's next turn.
+    pub fn record(&self, session_id: &str, mut trace: TurnTrace) {
+        trace.total_micros = trace.started.elapsed().as_micros() as u64;
+        let mut sessions = self.sessions.lock().unwrap();
+        let traces = sessions.entry(session_id.to_string()).or_default();
+        traces.turns += 1;
+        trace.turn = traces.turns;
+        if traces.recent.len() >= MAX_TRACES_PER_SESSION {
+            traces.recent.pop_front();
+        }
+        traces.recent.push_back(trace);
+    }
+
+    /// The trace of turn `turn`, if it is still kept.
+    pub fn get(&self, session_id: &str, turn: usize) -> Option<TurnTrace> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .get(session_id)?
+            .recent
+            .iter()
+            .find(|t| t.turn == turn)
+            .cloned()
+    }
+
+    pub fn forget(&self, session_id: &str) {
+        self.sessions.lock().unwrap().remove(session_id);
+    }
+}
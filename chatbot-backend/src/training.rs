@@ -0,0 +1,175 @@
+//! Utterances whose intent people have corrected, learned by the bot.
+//!
+//! When a reviewer grading a sampled conversation (see [`crate::review`])
+//! names a different intent for a visitor message, the pair is kept. The
+//! bot then reads that message, normalized, as the intent it was given
+//! instead of matching keywords. With `INTENT_TRAINING_PATH` set, pairs are
+//! appended to that file in the format of `eval/intents.jsonl` and read
+//! back on start and whenever the file changes, so they can also be edited
+//! or fed to another classifier; without it they last until restart.
+
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use tracing::{info, warn};
+
+use crate::{
+    chatbot::Intent,
+    eval::{self, EvalError, Sample},
+    state::SharedState,
+    text::normalize,
+};
+
+/// Whether messages can be learned as `intent`. Answers to flow questions,
+/// ratings and corrections are read from where the conversation stood,
+/// not from the words alone.
+pub fn learnable(intent: Intent) -> bool {
+    !matches!(
+        intent,
+        Intent::Answer
+            | Intent::Rating
+            | Intent::Correction
+            | Intent::HandedOff
+            | Intent::Custom(_)
+    )
+}
+
+/// Learned utterances, normalized, and their intents.
+#[derive(Debug, Default)]
+pub struct LearnedIntents {
+    by_text: HashMap<String, Intent>,
+}
+
+impl LearnedIntents {
+    fn learn(&mut self, text: &str, intent: Intent) {
+        let key = normalize(text);
+        if !key.is_empty() && learnable(intent) {
+            self.by_text.insert(key, intent);
+        }
+    }
+
+    /// The intent `message` was taught as, if it was.
+    pub fn intent(&self, message: &str) -> Option<Intent> {
+        if self.by_text.is_empty() {
+            return None;
+        }
+        self.by_text.get(&normalize(message)).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_text.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_text.is_empty()
+    }
+}
+
+#[derive(Default)]
+pub struct TrainingData {
+    learned: RwLock<Arc<LearnedIntents>>,
+    /// Where pairs are kept, when `INTENT_TRAINING_PATH` is set.
+    path: Option<PathBuf>,
+}
+
+impl TrainingData {
+    /// Loads the pairs kept at `path`; a missing file is none.
+    pub fn load(path: PathBuf) -> Result<Self, EvalError> {
+        let training = Self {
+            learned: RwLock::default(),
+            path: Some(path),
+        };
+        training.reload()?;
+        Ok(training)
+    }
+
+    /// The utterances learned so far, to apply to one message.
+    pub fn learned(&self) -> Arc<LearnedIntents> {
+        self.learned.read().unwrap().clone()
+    }
+
+    /// Teaches the bot that `text` means `intent`, later pairs for the same
+    /// text winning. Intents that can't be learned are ignored.
+    pub fn record(&self, text: &str, intent: Intent) {
+        if !learnable(intent) {
+            return;
+        }
+        let mut learned = self.learned.write().unwrap();
+        if let Some(path) = &self.path {
+            let sample = Sample {
+                text: text.to_string(),
+                intent: intent.as_str().to_string(),
+            };
+            if let Err(e) = append(path, &sample) {
+                warn!("cannot add to {}: {e}", path.display());
+            }
+        }
+        let mut updated = LearnedIntents {
+            by_text: learned.by_text.clone(),
+        };
+        updated.learn(text, intent);
+        *learned = Arc::new(updated);
+    }
+
+    /// Reads the file again; on error the pairs in force are kept.
+    pub fn reload(&self) -> Result<(), EvalError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let samples = match eval::load_dataset(path) {
+            Ok(samples) => samples,
+            Err(EvalError::Io(e)) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        let mut learned = LearnedIntents::default();
+        for (sample, intent) in &samples {
+            learned.learn(&sample.text, *intent);
+        }
+        *self.learned.write().unwrap() = Arc::new(learned);
+        Ok(())
+    }
+}
+
+fn append(path: &Path, sample: &Sample) -> io::Result<()> {
+    let mut line = serde_json::to_string(sample).map_err(io::Error::other)?;
+    line.push('\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(line.as_bytes())
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Polls `path` and reloads the learned utterances whenever it changes.
+pub async fn watch(state: SharedState, path: PathBuf, interval: Duration) {
+    let mut last_modified = modified(&path);
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let now_modified = modified(&path);
+        if now_modified == last_modified {
+            continue;
+        }
+        last_modified = now_modified;
+        match state.training.reload() {
+            Ok(()) => info!(
+                utterances = state.training.learned().len(),
+                "intent training data reloaded"
+            ),
+            Err(e) => warn!(
+                "{}: {e}; keeping the previous training data",
+                path.display()
+            ),
+        }
+    }
+}
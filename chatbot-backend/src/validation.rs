@@ -0,0 +1,266 @@
+//! What counts as an answer to each inquiry question, `validation` in the
+//! runtime config (see [`crate::config`]):
+//!
+//! ```json
+//! {
+//!   "name": { "min_chars": 2, "max_chars": 50, "punctuation": "-'’" },
+//!   "email": { "strict": true },
+//!   "project_details": { "min_chars": 10 },
+//!   "budget": { "min": 500, "max": 1000000 }
+//! }
+//! ```
+//!
+//! Every key is optional and the values above, but for `strict` and the
+//! budget bounds, are the defaults. An answer that breaks a rule is asked
+//! for again with a prompt naming the rule, in the visitor's language.
+
+use serde::{Deserialize, Serialize};
+use unicode_normalization::{UnicodeNormalization, char::is_combining_mark};
+
+use crate::{
+    chatbot::budget_amount,
+    i18n::{Language, Text, fill, format_money, text},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NameRules {
+    pub min_chars: usize,
+    pub max_chars: usize,
+    /// Allowed between letters besides spaces. Letters of any alphabet
+    /// always are.
+    pub punctuation: String,
+}
+
+impl Default for NameRules {
+    fn default() -> Self {
+        Self {
+            min_chars: 2,
+            max_chars: 50,
+            punctuation: "-'’".into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmailRules {
+    /// Only plain ASCII addresses with a proper domain, e.g. no
+    /// `ana@localhost` or `ana..b@example.com`.
+    pub strict: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DetailsRules {
+    pub min_chars: usize,
+}
+
+impl Default for DetailsRules {
+    fn default() -> Self {
+        Self { min_chars: 10 }
+    }
+}
+
+/// Bounds on the amount, in the catalog's currency. A budget outside them
+/// is asked for again; see [`crate::chatbot::Qualification`] for budgets
+/// that are taken but fall short.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BudgetRules {
+    pub min: Option<u64>,
+    pub max: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ValidationRules {
+    pub name: NameRules,
+    pub email: EmailRules,
+    pub project_details: DetailsRules,
+    pub budget: BudgetRules,
+}
+
+/// Why an answer was turned down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Invalid {
+    /// Not a name at all, such as a greeting.
+    Name,
+    NameLength,
+    NameCharacters,
+    Email,
+    DetailsTooShort,
+    /// No amount in it.
+    Budget,
+    BudgetBelowMin,
+    BudgetAboveMax,
+}
+
+/// Replies to "what's your name?" that are not names, by language.
+fn not_names(lang: Language) -> &'static [&'static str] {
+    match lang {
+        Language::En => &["hi", "hello", "hey", "yes", "no", "ok", "okay", "thanks"],
+        Language::Pl => &["cześć", "hej", "witam", "tak", "nie", "ok", "dzięki"],
+        Language::Es => &["hola", "sí", "si", "no", "vale", "ok", "gracias"],
+    }
+}
+
+/// Characters a strict address may have before the `@`, besides letters
+/// and digits.
+const STRICT_LOCAL_PUNCTUATION: &str = ".!#$%&'*+/=?^_`{|}~-";
+
+impl ValidationRules {
+    /// Checks the settings themselves.
+    pub fn validate(&self) -> Result<(), String> {
+        let name = &self.name;
+        if name.min_chars == 0 || name.max_chars < name.min_chars {
+            return Err(
+                "validation.name: min_chars must be above zero and at most max_chars".into(),
+            );
+        }
+        if name
+            .punctuation
+            .chars()
+            .any(|c| c.is_alphanumeric() || c.is_whitespace() || c.is_control())
+        {
+            return Err(
+                "validation.name.punctuation may only hold punctuation, not letters, digits or spaces"
+                    .into(),
+            );
+        }
+        if let (Some(min), Some(max)) = (self.budget.min, self.budget.max)
+            && min > max
+        {
+            return Err("validation.budget: min must be at most max".into());
+        }
+        Ok(())
+    }
+
+    /// Whether `name` reads as a person's name: letters in any alphabet
+    /// (Łukasz, Peña, Zoë, 王芳), with spaces and the allowed punctuation
+    /// between them (O'Brien, María-José). Names are compared in NFC, and
+    /// combining marks that NFC leaves apart are taken with the letter
+    /// before them. Emoji, digits and the punctuation of links and
+    /// addresses rule it out, as do the words that answer a question in
+    /// `lang` without giving a name.
+    pub fn check_name(&self, name: &str, lang: Language) -> Result<(), Invalid> {
+        let rules = &self.name;
+        let name: String = name.trim().nfc().collect();
+        if !(rules.min_chars..=rules.max_chars).contains(&name.chars().count()) {
+            return Err(Invalid::NameLength);
+        }
+        let mut after_letter = false;
+        for c in name.chars() {
+            let allowed = if is_combining_mark(c) {
+                after_letter
+            } else {
+                c.is_alphabetic() || c == ' ' || rules.punctuation.contains(c)
+            };
+            if !allowed {
+                return Err(Invalid::NameCharacters);
+            }
+            after_letter = c.is_alphabetic() || is_combining_mark(c);
+        }
+        if !name.starts_with(char::is_alphabetic)
+            || !after_letter
+            || not_names(lang).contains(&name.to_lowercase().as_str())
+        {
+            return Err(Invalid::Name);
+        }
+        Ok(())
+    }
+
+    pub fn check_email(&self, email: &str) -> Result<(), Invalid> {
+        let Some((local, domain)) = email.split_once('@') else {
+            return Err(Invalid::Email);
+        };
+        let lenient = !local.is_empty()
+            && !domain.contains('@')
+            && domain.contains('.')
+            && !domain.starts_with('.')
+            && !domain.ends_with('.')
+            && !email.chars().any(char::is_whitespace);
+        if !lenient || (self.email.strict && !is_strict_email(local, domain)) {
+            return Err(Invalid::Email);
+        }
+        Ok(())
+    }
+
+    pub fn check_project_details(&self, details: &str) -> Result<(), Invalid> {
+        if details.trim().chars().count() < self.project_details.min_chars {
+            return Err(Invalid::DetailsTooShort);
+        }
+        Ok(())
+    }
+
+    pub fn check_budget(&self, budget: &str) -> Result<(), Invalid> {
+        let Some(amount) = budget_amount(budget) else {
+            return Err(Invalid::Budget);
+        };
+        if self.budget.min.is_some_and(|min| amount < min) {
+            return Err(Invalid::BudgetBelowMin);
+        }
+        if self.budget.max.is_some_and(|max| amount > max) {
+            return Err(Invalid::BudgetAboveMax);
+        }
+        Ok(())
+    }
+
+    /// What to tell the visitor about an answer turned down for
+    /// `invalid`, in `lang`, amounts in `currency`.
+    pub fn prompt(&self, invalid: Invalid, lang: Language, currency: &str) -> String {
+        let name = &self.name;
+        let money = |amount: Option<u64>| format_money(lang, amount.unwrap_or_default(), currency);
+        match invalid {
+            Invalid::Name => text(lang, Text::InvalidName).to_string(),
+            Invalid::NameCharacters if name.punctuation.is_empty() => {
+                text(lang, Text::NameLettersOnly).to_string()
+            }
+            Invalid::NameCharacters => {
+                let allowed: Vec<String> = name.punctuation.chars().map(String::from).collect();
+                fill(
+                    text(lang, Text::NameCharacters),
+                    &[("allowed", &allowed.join(" "))],
+                )
+            }
+            Invalid::NameLength => fill(
+                text(lang, Text::NameLength),
+                &[
+                    ("min", &name.min_chars.to_string()),
+                    ("max", &name.max_chars.to_string()),
+                ],
+            ),
+            Invalid::Email => text(lang, Text::InvalidEmail).to_string(),
+            Invalid::DetailsTooShort => text(lang, Text::ProjectDetailsTooShort).to_string(),
+            Invalid::Budget => text(lang, Text::InvalidBudget).to_string(),
+            Invalid::BudgetBelowMin => fill(
+                text(lang, Text::BudgetBelowRange),
+                &[("min", &money(self.budget.min))],
+            ),
+            Invalid::BudgetAboveMax => fill(
+                text(lang, Text::BudgetAboveRange),
+                &[("max", &money(self.budget.max))],
+            ),
+        }
+    }
+}
+
+fn is_strict_email(local: &str, domain: &str) -> bool {
+    let local_ok = local
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || STRICT_LOCAL_PUNCTUATION.contains(c))
+        && !local.starts_with('.')
+        && !local.ends_with('.')
+        && !local.contains("..");
+    let labels: Vec<&str> = domain.split('.').collect();
+    let labels_ok = labels.iter().all(|label| {
+        (1..=63).contains(&label.len())
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+    });
+    let tld_ok = labels
+        .last()
+        .is_some_and(|tld| tld.len() >= 2 && tld.chars().all(|c| c.is_ascii_alphabetic()));
+    local_ok && labels.len() >= 2 && labels_ok && tld_ok
+}
@@ -0,0 +1,228 @@
+//! The nightly export of conversation events and metrics to a data
+//! warehouse, for BI.
+//!
+//! Shortly after midnight UTC, every event written since the previous
+//! export and the counters of every tenant go to each configured sink as
+//! JSON lines:
+//!
+//! - `EXPORT_DIR`: appended to `chatbot-events-v1-<date>.jsonl` and
+//!   `chatbot-metrics-v1-<date>.jsonl` there, for a job that ships the
+//!   directory to S3 or loads it into BigQuery.
+//! - `EXPORT_URL`: POSTed as `application/x-ndjson`, one request per kind,
+//!   with the kind, date and schema version in `X-Export-*` headers and
+//!   `EXPORT_TOKEN`, if set, as a bearer token.
+//!
+//! Every record carries `schema_version`; a change that renames or drops a
+//! field raises [`SCHEMA_VERSION`], which is also in the file names, so
+//! loaders never mix the two. Events are anonymized for each sink as
+//! `EXPORT_DIR_ANONYMIZATION` and `EXPORT_URL_ANONYMIZATION` say, `strip`
+//! unless set; see [`crate::anonymize`]. Metrics are the counters since
+//! the server started; consecutive exports give the day's activity.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+use serde::Serialize;
+use serde_json::Value;
+use tracing::{error, info, warn};
+
+use crate::{
+    anonymize::{Anonymization, Anonymizer},
+    events::{StoredEvent, now_ms},
+    i18n::iso_date,
+    metrics::MetricsSnapshot,
+    state::SharedState,
+};
+
+/// The layout of exported records.
+pub const SCHEMA_VERSION: u32 = 1;
+const DAY_MS: u64 = 24 * 60 * 60 * 1_000;
+/// Exports run this long after midnight UTC, once the day is over.
+const RUN_AFTER_MIDNIGHT_MS: u64 = 10 * 60 * 1_000;
+
+#[derive(Debug, Clone)]
+pub enum ExportTarget {
+    Directory(PathBuf),
+    Http { url: String, token: Option<String> },
+}
+
+#[derive(Debug, Clone)]
+pub struct ExportSink {
+    pub target: ExportTarget,
+    /// How much of each event this sink may see.
+    pub anonymization: Anonymization,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExportSettings {
+    pub sinks: Vec<ExportSink>,
+    /// Mixed into hashes, so they can't be matched to live ids.
+    pub salt: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Events,
+    Metrics,
+}
+
+impl Kind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::Events => "events",
+            Kind::Metrics => "metrics",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct EventRecord {
+    schema_version: u32,
+    session: String,
+    seq: u64,
+    at_ms: u64,
+    #[serde(flatten)]
+    event: Value,
+}
+
+#[derive(Debug, Serialize)]
+struct MetricsRecord<'a> {
+    schema_version: u32,
+    at_ms: u64,
+    tenant: &'a str,
+    #[serde(flatten)]
+    counters: &'a MetricsSnapshot,
+}
+
+fn event_lines(events: &[StoredEvent], anonymizer: &Anonymizer) -> String {
+    let mut lines = String::new();
+    for stored in events {
+        let record = EventRecord {
+            schema_version: SCHEMA_VERSION,
+            session: anonymizer.session(&stored.session_id),
+            seq: stored.seq,
+            at_ms: stored.at_ms,
+            event: anonymizer.event(&stored.event),
+        };
+        lines.push_str(&serde_json::to_string(&record).expect("records serialize"));
+        lines.push('\n');
+    }
+    lines
+}
+
+fn metrics_lines(state: &SharedState, at_ms: u64) -> String {
+    let mut lines = String::new();
+    let mut tenants = state.metrics.tenants();
+    tenants.sort();
+    for tenant in &tenants {
+        let Some(counters) = state.metrics.tenant_snapshot(tenant) else {
+            continue;
+        };
+        let record = MetricsRecord {
+            schema_version: SCHEMA_VERSION,
+            at_ms,
+            tenant,
+            counters: &counters,
+        };
+        lines.push_str(&serde_json::to_string(&record).expect("records serialize"));
+        lines.push('\n');
+    }
+    lines
+}
+
+async fn deliver(
+    state: &SharedState,
+    target: &ExportTarget,
+    kind: Kind,
+    date: &str,
+    lines: &str,
+) -> Result<(), String> {
+    match target {
+        ExportTarget::Directory(dir) => {
+            let path = dir.join(format!(
+                "chatbot-{}-v{SCHEMA_VERSION}-{date}.jsonl",
+                kind.as_str()
+            ));
+            let append = || -> io::Result<()> {
+                fs::create_dir_all(dir)?;
+                let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+                file.write_all(lines.as_bytes())
+            };
+            append().map_err(|e| format!("{}: {e}", path.display()))
+        }
+        ExportTarget::Http { url, token } => {
+            let mut request = state
+                .http
+                .post(url)
+                .header("Content-Type", "application/x-ndjson")
+                .header("X-Export-Kind", kind.as_str())
+                .header("X-Export-Date", date)
+                .header("X-Export-Schema-Version", SCHEMA_VERSION.to_string())
+                .body(lines.to_string());
+            if let Some(token) = token {
+                request = request.bearer_auth(token);
+            }
+            match request.send().await {
+                Ok(response) if response.status().is_success() => Ok(()),
+                Ok(response) => Err(format!("{url} answered {}", response.status())),
+                Err(e) => Err(format!("{url}: {e}")),
+            }
+        }
+    }
+}
+
+/// Ships what happened since the previous export to every sink. Events a
+/// sink failed to take are kept for the next night, when every sink gets
+/// them again.
+pub async fn export(state: &SharedState, settings: &ExportSettings) {
+    let at_ms = now_ms();
+    // Named after the day that just ended.
+    let date = iso_date(at_ms.saturating_sub(RUN_AFTER_MIDNIGHT_MS + 1));
+    let events = state.events.take_unexported();
+    let metrics_lines = metrics_lines(state, at_ms);
+    let mut failed = false;
+    for sink in &settings.sinks {
+        let anonymizer = Anonymizer {
+            mode: sink.anonymization,
+            salt: settings.salt.clone(),
+        };
+        let event_lines = event_lines(&events, &anonymizer);
+        for (kind, lines) in [
+            (Kind::Events, &event_lines),
+            (Kind::Metrics, &metrics_lines),
+        ] {
+            if lines.is_empty() {
+                continue;
+            }
+            if let Err(e) = deliver(state, &sink.target, kind, &date, lines).await {
+                error!("{} export failed: {e}", kind.as_str());
+                failed |= kind == Kind::Events;
+            }
+        }
+    }
+    if failed {
+        warn!(events = events.len(), "keeping events for the next export");
+        state.events.return_unexported(events);
+    } else {
+        info!(events = events.len(), %date, "exported to the warehouse");
+    }
+}
+
+/// How long from `now_ms` until the next run, shortly after midnight UTC.
+fn until_next_run(now_ms: u64) -> Duration {
+    let next = (now_ms.saturating_sub(RUN_AFTER_MIDNIGHT_MS) / DAY_MS + 1) * DAY_MS
+        + RUN_AFTER_MIDNIGHT_MS;
+    Duration::from_millis(next - now_ms)
+}
+
+/// Exports every night until the server stops.
+pub async fn run(state: SharedState, settings: ExportSettings) {
+    loop {
+        tokio::time::sleep(until_next_run(now_ms())).await;
+        export(&state, &settings).await;
+    }
+}
@@ -0,0 +1,191 @@
+//! The weekly analytics report for management, emailed to the
+//! `WEEKLY_REPORT_RECIPIENTS` every Monday at 07:00 UTC.
+//!
+//! It covers the time since the previous report, or since the server
+//! started: conversations, the inquiry funnel, the most common intents and
+//! the words visitors used most outside the inquiry flow. It is rendered
+//! as a PDF by [`crate::report`] and sent through the mail relay, one email
+//! per recipient.
+
+use std::time::Duration;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde_json::json;
+use tracing::{error, info, warn};
+
+use crate::{
+    events::now_ms,
+    i18n::{Language, format_datetime},
+    mail::{Attachment, Email},
+    metrics::MetricsSnapshot,
+    outbox::OutboxJob,
+    report::generate_weekly_pdf,
+    state::SharedState,
+};
+
+/// Intents and keywords listed.
+pub const TOP_ENTRIES: usize = 10;
+const WEEK_MS: u64 = 7 * 24 * 60 * 60 * 1_000;
+/// Monday 1970-01-05, 07:00 UTC: the first send time after the epoch.
+const FIRST_SEND_MS: u64 = (4 * 24 + 7) * 60 * 60 * 1_000;
+
+#[derive(Debug, Clone)]
+pub struct FunnelStage {
+    pub label: &'static str,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct WeeklyReport {
+    pub from_ms: u64,
+    pub to_ms: u64,
+    pub conversations: u64,
+    pub messages: u64,
+    /// From conversations to confirmed inquiries.
+    pub funnel: Vec<FunnelStage>,
+    pub top_intents: Vec<(String, u64)>,
+    pub top_keywords: Vec<(String, u64)>,
+}
+
+impl WeeklyReport {
+    /// The activity between two snapshots of the metrics.
+    pub fn between(
+        previous: &MetricsSnapshot,
+        current: &MetricsSnapshot,
+        top_keywords: Vec<(String, u64)>,
+        from_ms: u64,
+        to_ms: u64,
+    ) -> Self {
+        let conversations = current
+            .sessions_created
+            .saturating_sub(previous.sessions_created);
+        let funnel = vec![
+            FunnelStage {
+                label: "Conversations",
+                count: conversations,
+            },
+            FunnelStage {
+                label: "Started an inquiry",
+                count: current
+                    .inquiries_started
+                    .saturating_sub(previous.inquiries_started),
+            },
+            FunnelStage {
+                label: "Confirmed",
+                count: current
+                    .inquiries_confirmed
+                    .saturating_sub(previous.inquiries_confirmed),
+            },
+        ];
+        let mut top_intents: Vec<(String, u64)> = current
+            .intents
+            .iter()
+            .map(|(intent, n)| {
+                let before = previous.intents.get(intent).copied().unwrap_or_default();
+                (intent.clone(), n.saturating_sub(before))
+            })
+            .filter(|(_, n)| *n > 0)
+            .collect();
+        top_intents.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top_intents.truncate(TOP_ENTRIES);
+        Self {
+            from_ms,
+            to_ms,
+            conversations,
+            messages: current
+                .messages_total
+                .saturating_sub(previous.messages_total),
+            funnel,
+            top_intents,
+            top_keywords,
+        }
+    }
+
+    pub fn period(&self) -> String {
+        format!(
+            "{} – {}",
+            format_datetime(Language::En, self.from_ms),
+            format_datetime(Language::En, self.to_ms)
+        )
+    }
+
+    /// A plain-text version, for the email body.
+    pub fn summary(&self) -> String {
+        let mut text = format!("Chatbot activity, {}\n\n", self.period());
+        text.push_str(&format!(
+            "{} conversations, {} visitor messages.\n\n",
+            self.conversations, self.messages
+        ));
+        for stage in &self.funnel {
+            text.push_str(&format!("{}: {}\n", stage.label, stage.count));
+        }
+        text.push_str("\nThe full report is attached.\n");
+        text
+    }
+}
+
+/// How long from `now_ms` until the next Monday, 07:00 UTC.
+fn until_next_send(now_ms: u64) -> Duration {
+    let since_first = now_ms.saturating_sub(FIRST_SEND_MS);
+    let next = FIRST_SEND_MS + (since_first / WEEK_MS + 1) * WEEK_MS;
+    Duration::from_millis(next - now_ms)
+}
+
+/// Sends a report every week until the server stops.
+pub async fn run(state: SharedState, recipients: Vec<String>) {
+    let mut previous = state.metrics.snapshot();
+    let mut from_ms = now_ms();
+    loop {
+        tokio::time::sleep(until_next_send(now_ms())).await;
+        let current = state.metrics.snapshot();
+        let to_ms = now_ms();
+        let keywords = state.keywords.take_top(TOP_ENTRIES);
+        let report = WeeklyReport::between(&previous, &current, keywords, from_ms, to_ms);
+        (previous, from_ms) = (current, to_ms);
+        send(&state, &recipients, report).await;
+    }
+}
+
+async fn send(state: &SharedState, recipients: &[String], report: WeeklyReport) {
+    let Some(url) = state.mail_relay_url.clone() else {
+        warn!("MAIL_RELAY_URL is not set; weekly report dropped");
+        return;
+    };
+    let (subject, summary) = (
+        format!("Weekly chatbot report, {}", report.period()),
+        report.summary(),
+    );
+    let pdf = match tokio::task::spawn_blocking(move || generate_weekly_pdf(&report)).await {
+        Ok(Ok(pdf)) => pdf,
+        Ok(Err(e)) => {
+            error!("weekly report failed: {e}");
+            return;
+        }
+        Err(e) => {
+            error!("weekly report task failed: {e}");
+            return;
+        }
+    };
+    let attachment = Attachment {
+        filename: "weekly-report.pdf".to_string(),
+        content_type: "application/pdf".to_string(),
+        content_base64: STANDARD.encode(pdf),
+    };
+    for to in recipients {
+        let email = Email {
+            to: to.clone(),
+            subject: subject.clone(),
+            text: summary.clone(),
+            attachments: vec![attachment.clone()],
+            reply_to: None,
+            in_reply_to: None,
+            references: Vec::new(),
+        };
+        state.outbox.enqueue(OutboxJob::new(
+            "email.weekly_report",
+            &url,
+            json!({ "email": email }),
+        ));
+    }
+    info!(recipients = recipients.len(), "weekly report queued");
+}
@@ -0,0 +1,204 @@
+//! Reading an answer to a yes/no question, in any of the bot's languages.
+//!
+//! Visitors rarely answer with a bare "yes". They hedge ("not sure yet"),
+//! negate a negative ("nothing wrong", "no está mal") or turn a yes around
+//! ("that's not correct"), so phrases are tried from the most specific
+//! kind to the least: hedges, then negated negatives, then negated
+//! affirmatives, and only then the plain words. Between a plain yes and a
+//! plain no in one message, no wins; confirming something the visitor
+//! objected to is worse than asking again.
+//!
+//! Emoji and shorthand ("👍", "k", "nah") are first read as the plain yes
+//! or no of the conversation's language. Only the reading changes; the
+//! transcript keeps what the visitor wrote.
+
+use crate::{
+    i18n::Language,
+    text::{contains_any, normalize},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum YesNo {
+    Yes,
+    No,
+    /// The visitor answered, but hasn't made up their mind.
+    Unsure,
+}
+
+const HEDGES: &[&str] = &[
+    // English
+    "maybe",
+    "perhaps",
+    "not sure",
+    "i don't know",
+    "i dont know",
+    "dunno",
+    "not yet",
+    // Polish
+    "może",
+    "nie wiem",
+    "nie jestem pewien",
+    "nie jestem pewna",
+    "jeszcze nie wiem",
+    // Spanish
+    "quizás",
+    "quizá",
+    "tal vez",
+    "no sé",
+    "no se",
+    "no estoy seguro",
+    "no estoy segura",
+];
+
+/// A negative negated, which agrees.
+const DOUBLE_NEGATIVES: &[&str] = &[
+    "not wrong",
+    "nothing wrong",
+    "not incorrect",
+    "nothing to change",
+    "no changes",
+    "no problem",
+    "why not",
+    "nie źle",
+    "nic do zmiany",
+    "bez zmian",
+    "nie ma problemu",
+    "czemu nie",
+    "no está mal",
+    "no esta mal",
+    "nada que cambiar",
+    "sin cambios",
+    "sin problema",
+    "por qué no",
+    "por que no",
+];
+
+/// An affirmative negated, which disagrees.
+const NEGATED_AFFIRMATIVES: &[&str] = &[
+    "not correct",
+    "not right",
+    "not ok",
+    "not okay",
+    "don't send",
+    "dont send",
+    "nie zgadza się",
+    "nie wysyłaj",
+    "no es correcto",
+    "no está bien",
+    "no esta bien",
+    "no lo envíes",
+];
+
+const AFFIRMATIVES: &[&str] = &[
+    "yes",
+    "yep",
+    "yeah",
+    "yup",
+    "correct",
+    "confirm",
+    "looks good",
+    "all good",
+    "send it",
+    "ok",
+    "okay",
+    "sure",
+    "tak",
+    "zgadza się",
+    "potwierdzam",
+    "wyślij",
+    "sí",
+    "si",
+    "correcto",
+    "confirmo",
+    "envíalo",
+    "vale",
+    "de acuerdo",
+];
+
+const NEGATIVES: &[&str] = &["no", "nope", "wrong", "nie", "źle", "incorrecto", "mal"];
+
+/// Emoji that mean yes, without variation selectors or skin tones.
+const YES_EMOJI: &[&str] = &["👍", "👌", "✅", "✔", "☑", "🆗", "🙆", "💯"];
+/// Emoji that mean no.
+const NO_EMOJI: &[&str] = &["👎", "❌", "✖", "❎", "🙅", "🚫", "⛔"];
+/// Shorthand for yes, in any language.
+const YES_WORDS: &[&str] = &[
+    "k", "kk", "okk", "oki", "okey", "ya", "yah", "yea", "ye", "yass", "ofc", "spoko", "jasne",
+    "pewnie", "nom", "sip", "sep", "dale", "claro", "va",
+];
+/// Shorthand for no, in any language.
+const NO_WORDS: &[&str] = &["n", "nah", "nop", "nopes", "nee", "niee", "nel"];
+/// Single letters that are a whole answer in the language, but a word of
+/// their own elsewhere: "y" is "and" in Spanish.
+const YES_LETTERS: &[(Language, &str)] = &[(Language::En, "y"), (Language::Pl, "t")];
+
+/// `message` with emoji and shorthand answers replaced by the plain yes
+/// or no of `language`.
+fn plain(message: &str, language: Language) -> String {
+    let (yes, no) = match language {
+        Language::En => ("yes", "no"),
+        Language::Pl => ("tak", "nie"),
+        Language::Es => ("sí", "no"),
+    };
+    let stripped: String = message
+        .chars()
+        .filter(|c| !matches!(c, '\u{fe0f}' | '\u{1f3fb}'..='\u{1f3ff}'))
+        .collect();
+    let whole = stripped.trim().trim_end_matches(['.', '!']).to_lowercase();
+    if YES_LETTERS.contains(&(language, whole.as_str())) {
+        return yes.to_string();
+    }
+    let words: Vec<String> = stripped
+        .split_whitespace()
+        .map(|word| {
+            let bare = word
+                .trim_matches(|c: char| c.is_ascii_punctuation())
+                .to_lowercase();
+            if YES_WORDS.contains(&bare.as_str()) {
+                return yes.to_string();
+            }
+            if NO_WORDS.contains(&bare.as_str()) {
+                return no.to_string();
+            }
+            let mut word = word.to_string();
+            for emoji in YES_EMOJI {
+                word = word.replace(emoji, &format!(" {yes} "));
+            }
+            for emoji in NO_EMOJI {
+                word = word.replace(emoji, &format!(" {no} "));
+            }
+            word
+        })
+        .collect();
+    words
+        .join(" ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// What `message`, written in a conversation in `language`, answers, or
+/// `None` when it says neither yes nor no.
+pub fn interpret(message: &str, language: Language) -> Option<YesNo> {
+    let message = plain(message, language);
+    let normalized = normalize(&message);
+    if contains_any(&normalized, HEDGES) {
+        Some(YesNo::Unsure)
+    } else if is_double_negative(&message) {
+        Some(YesNo::Yes)
+    } else if contains_any(&normalized, NEGATED_AFFIRMATIVES)
+        || contains_any(&normalized, NEGATIVES)
+    {
+        Some(YesNo::No)
+    } else if contains_any(&normalized, AFFIRMATIVES) {
+        Some(YesNo::Yes)
+    } else {
+        None
+    }
+}
+
+/// Whether `message` agrees by negating a negative, as "nothing to change"
+/// does; it shouldn't then count as asking for a change.
+pub fn is_double_negative(message: &str) -> bool {
+    contains_any(&normalize(message), DOUBLE_NEGATIVES)
+}
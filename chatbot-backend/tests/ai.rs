@@ -0,0 +1,425 @@
+//! The AI fallback: which messages reach the model, on whose account, and
+//! what happens once a tenant's monthly budget is used up.
+
+use std::{sync::Arc, time::Duration};
+
+use axum::http::{Method, StatusCode};
+use chatbot_backend::{
+    ai::{AiFallback, AiSettings, LlamaServer},
+    alerts::AlertTargets,
+    events::now_ms,
+    outbox,
+    prompts::PromptStore,
+    test_support::{
+        Chaos, ChaosServer, SessionBuilder, StateBuilder, StubFallback, TestApp, TestResponse,
+    },
+};
+use serde_json::json;
+use tokio::task::JoinHandle;
+
+const ANSWER: &str = "We don't sell gift cards, sorry.";
+
+async fn chat(app: &TestApp, tenant: &str, message: &str) -> TestResponse {
+    let body = json!({ "message": message, "tenant": tenant });
+    let response = app.request(Method::POST, "/chat", Some(body)).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    response
+}
+
+#[tokio::test]
+async fn only_what_the_rules_miss_reaches_the_model() {
+    let model = Arc::new(StubFallback::new(ANSWER, 10));
+    let app = TestApp::new(StateBuilder::new().fallback(model.clone()).build().await);
+    let set = app
+        .admin(
+            Method::PUT,
+            "/tenants/acme/ai",
+            Some(json!({ "api_key": "sk-acme", "model": "small" })),
+        )
+        .await;
+    assert_eq!(set.status, StatusCode::OK, "{}", set.body);
+
+    let answered = chat(&app, "acme", "do you sell gift cards?").await;
+    assert_eq!(answered.reply(), ANSWER);
+    let greeted = chat(&app, "acme", "hello").await;
+    assert_ne!(greeted.reply(), ANSWER);
+    // Other tenants have no key, so the rules answer.
+    assert_ne!(
+        chat(&app, "globex", "do you sell gift cards?")
+            .await
+            .reply(),
+        ANSWER
+    );
+
+    let requests = model.requests();
+    assert_eq!(requests.len(), 1);
+    let (request, credentials) = &requests[0];
+    assert_eq!(request.tenant, "acme");
+    assert_eq!(request.message, "do you sell gift cards?");
+    assert_eq!(credentials.api_key, "sk-acme");
+    assert_eq!(credentials.model.as_deref(), Some("small"));
+
+    let path = format!("/sessions/{}/turns/1/trace", answered.session_id());
+    let trace = app.admin(Method::GET, &path, None).await;
+    assert_eq!(trace.body["ai_called"], true);
+    assert_eq!(trace.body["intent_source"], "fallback");
+    assert_eq!(trace.body["intent"], "ai_fallback");
+    let path = format!("/sessions/{}/turns/1/trace", greeted.session_id());
+    let trace = app.admin(Method::GET, &path, None).await;
+    assert_eq!(trace.body["ai_called"], false);
+    assert_eq!(trace.body["intent_source"], "keywords");
+
+    let english = app.state.metrics.snapshot().languages["en"];
+    assert_eq!(english.messages, 3);
+    // The model's answer and globex's unanswered question.
+    assert_eq!(english.unknown, 2);
+    assert_eq!(english.fallback, 1);
+
+    let unanswered = app.admin(Method::GET, "/analytics/unanswered", None).await;
+    let unanswered = unanswered.body.as_array().unwrap();
+    assert_eq!(unanswered.len(), 1);
+    assert_eq!(unanswered[0]["count"], 1);
+}
+
+async fn waiting_for_the_model(
+    model: &Arc<StubFallback>,
+) -> (Arc<TestApp>, JoinHandle<TestResponse>) {
+    let app = Arc::new(TestApp::new(
+        StateBuilder::new()
+            .fallback(model.clone())
+            .session(SessionBuilder::new("s1").tenant("acme"))
+            .build()
+            .await,
+    ));
+    app.admin(
+        Method::PUT,
+        "/tenants/acme/ai",
+        Some(json!({ "api_key": "sk-acme" })),
+    )
+    .await;
+    let asking = tokio::spawn({
+        let app = app.clone();
+        async move { app.chat(Some("s1"), "do you sell gift cards?").await }
+    });
+    model.asked().await;
+    (app, asking)
+}
+
+#[tokio::test]
+async fn the_session_is_not_held_while_the_model_answers() {
+    let model = Arc::new(StubFallback::new(ANSWER, 10).gated());
+    let (app, asking) = waiting_for_the_model(&model).await;
+
+    let read = app.state.sessions.with_session("s1", |s| s.messages.len());
+    let read = tokio::time::timeout(Duration::from_secs(1), read).await;
+    assert_eq!(read.expect("the session is free"), Some(0));
+
+    model.release();
+    assert_eq!(asking.await.unwrap().reply(), ANSWER);
+}
+
+#[tokio::test]
+async fn an_answer_to_a_session_that_moved_on_is_dropped() {
+    let model = Arc::new(StubFallback::new(ANSWER, 10).gated());
+    let (app, asking) = waiting_for_the_model(&model).await;
+
+    let started = app.chat(Some("s1"), "I want to start a project").await;
+    assert_eq!(started.status, StatusCode::OK, "{}", started.body);
+    model.release();
+    let late = asking.await.unwrap();
+    assert_eq!(late.status, StatusCode::OK, "{}", late.body);
+    assert_ne!(late.reply(), ANSWER);
+    let status = app.admin(Method::GET, "/tenants/acme/ai", None).await;
+    assert_eq!(status.body["tokens_used"], 10);
+}
+
+#[tokio::test]
+async fn the_inquiry_flow_stays_with_the_rules() {
+    let model = Arc::new(StubFallback::new(ANSWER, 10));
+    let app = TestApp::new(StateBuilder::new().fallback(model.clone()).build().await);
+    app.admin(
+        Method::PUT,
+        "/tenants/acme/ai",
+        Some(json!({ "api_key": "sk-acme" })),
+    )
+    .await;
+
+    let body = json!({ "message": "I want to start a project", "tenant": "acme" });
+    let started = app.request(Method::POST, "/chat", Some(body)).await;
+    let body = json!({ "session_id": started.session_id(), "message": "Ana Nowak" });
+    let answered = app.request(Method::POST, "/chat", Some(body)).await;
+    assert_ne!(answered.reply(), ANSWER);
+    assert!(model.requests().is_empty());
+}
+
+#[tokio::test]
+async fn a_tenant_over_budget_gets_rule_based_replies_and_an_alert() {
+    let receiver = ChaosServer::start(Chaos::default()).await;
+    let model = Arc::new(StubFallback::new(ANSWER, 60));
+    let app = TestApp::new(
+        StateBuilder::new()
+            .fallback(model.clone())
+            .alerts(AlertTargets {
+                webhook_url: Some(receiver.url().to_string()),
+                slack_url: None,
+            })
+            .build()
+            .await,
+    );
+    app.admin(
+        Method::PUT,
+        "/tenants/acme/ai",
+        Some(json!({ "api_key": "sk-acme", "monthly_token_budget": 100 })),
+    )
+    .await;
+
+    assert_eq!(
+        chat(&app, "acme", "do you sell gift cards?").await.reply(),
+        ANSWER
+    );
+    assert!(app.state.outbox.is_empty());
+    // This answer goes over the budget.
+    assert_eq!(
+        chat(&app, "acme", "what about vouchers?").await.reply(),
+        ANSWER
+    );
+    assert_ne!(chat(&app, "acme", "and coupons?").await.reply(), ANSWER);
+    assert_eq!(model.requests().len(), 2);
+
+    let status = app.admin(Method::GET, "/tenants/acme/ai", None).await;
+    assert_eq!(status.body["tokens_used"], 120);
+    assert_eq!(status.body["over_budget"], true);
+
+    outbox::deliver_due(&app.state, 10).await;
+    let received = receiver.received();
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0]["event"], "ai.budget_exceeded");
+    assert_eq!(received[0]["data"]["tenant"], "acme");
+    assert_eq!(received[0]["data"]["monthly_token_budget"], 100);
+
+    // A bigger budget lets the model answer again, without a second alert.
+    app.admin(
+        Method::PUT,
+        "/tenants/acme/ai",
+        Some(json!({ "monthly_token_budget": 1000 })),
+    )
+    .await;
+    assert_eq!(chat(&app, "acme", "and coupons?").await.reply(), ANSWER);
+    assert!(app.state.outbox.is_empty());
+}
+
+#[tokio::test]
+async fn operators_get_drafts_from_the_model_for_what_the_rules_miss() {
+    let model = Arc::new(StubFallback::new(ANSWER, 10));
+    let app = TestApp::new(StateBuilder::new().fallback(model.clone()).build().await);
+    let session_id = chat(&app, "acme", "do you sell gift cards?")
+        .await
+        .session_id()
+        .to_string();
+    let path = format!("/sessions/{session_id}/suggest-reply");
+
+    let rules = app.admin(Method::POST, &path, None).await;
+    assert_eq!(rules.body["intent"], "unknown");
+    assert_eq!(rules.body["source"], "none");
+
+    app.admin(
+        Method::PUT,
+        "/tenants/acme/ai",
+        Some(json!({ "api_key": "sk-acme" })),
+    )
+    .await;
+    let drafted = app.admin(Method::POST, &path, None).await;
+    assert_eq!(drafted.status, StatusCode::OK, "{}", drafted.body);
+    assert_eq!(drafted.body["text"], ANSWER);
+    assert_eq!(drafted.body["intent"], "ai_fallback");
+    assert_eq!(drafted.body["source"], "fallback");
+    let status = app.admin(Method::GET, "/tenants/acme/ai", None).await;
+    assert_eq!(status.body["tokens_used"], 10);
+}
+
+#[tokio::test]
+async fn calls_use_the_prompt_version_in_force() {
+    let model = Arc::new(StubFallback::new(ANSWER, 10));
+    let app = TestApp::new(StateBuilder::new().fallback(model.clone()).build().await);
+    app.admin(
+        Method::PUT,
+        "/tenants/acme/ai",
+        Some(json!({ "api_key": "sk-acme" })),
+    )
+    .await;
+    let unknown = app
+        .admin(
+            Method::PUT,
+            "/prompts/fallback",
+            Some(json!({ "text": "Answer as {persona}." })),
+        )
+        .await;
+    assert_eq!(unknown.status, StatusCode::BAD_REQUEST);
+    let set = app
+        .admin(
+            Method::PUT,
+            "/prompts/fallback",
+            Some(json!({ "text": "Answer {tenant}'s visitors in {language}." })),
+        )
+        .await;
+    assert_eq!(set.status, StatusCode::OK, "{}", set.body);
+    assert_eq!(set.body["active"], 1);
+
+    let answered = chat(&app, "acme", "do you sell gift cards?").await;
+    let path = format!("/sessions/{}/turns/1/trace", answered.session_id());
+    let trace = app.admin(Method::GET, &path, None).await;
+    assert_eq!(
+        trace.body["prompt"],
+        json!({ "name": "fallback", "version": 1 })
+    );
+
+    let rolled_back = app
+        .admin(
+            Method::POST,
+            "/prompts/fallback/rollback",
+            Some(json!({ "version": 0 })),
+        )
+        .await;
+    assert_eq!(rolled_back.status, StatusCode::OK, "{}", rolled_back.body);
+    assert_eq!(rolled_back.body["versions"].as_array().unwrap().len(), 2);
+    let missing = app
+        .admin(
+            Method::POST,
+            "/prompts/fallback/rollback",
+            Some(json!({ "version": 7 })),
+        )
+        .await;
+    assert_eq!(missing.status, StatusCode::NOT_FOUND);
+    chat(&app, "acme", "what about vouchers?").await;
+
+    let prompts = model.prompts();
+    assert_eq!(prompts.len(), 2);
+    assert_eq!(prompts[0].version, 1);
+    assert_eq!(prompts[0].text, "Answer acme's visitors in English.");
+    assert_eq!(prompts[1].version, 0);
+    assert!(prompts[1].text.contains("Reply briefly, in English."));
+}
+
+#[test]
+fn prompt_versions_survive_a_restart() {
+    let path = std::env::temp_dir().join(format!("prompts-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let prompts = PromptStore::load(path.clone()).unwrap();
+    prompts.set("fallback", "First, in {language}.").unwrap();
+    prompts.set("fallback", "Second, in {language}.").unwrap();
+    prompts.rollback("fallback", 1).unwrap();
+    drop(prompts);
+
+    let reloaded = PromptStore::load(path.clone()).unwrap();
+    let prompt = reloaded.render("fallback", &[("language", "Polski")]);
+    assert_eq!(
+        (prompt.version, prompt.text.as_str()),
+        (1, "First, in Polski.")
+    );
+    assert_eq!(reloaded.get("fallback").unwrap().versions.len(), 3);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn a_local_model_answers_without_a_key() {
+    let completion = json!({
+        "choices": [{ "message": { "content": " Gift cards are on the shop page. " } }],
+        "usage": { "total_tokens": 42 },
+    });
+    let server = ChaosServer::answering(Chaos::default(), completion).await;
+    let model = LlamaServer::new(reqwest::Client::new(), server.url());
+    let app = TestApp::new(StateBuilder::new().fallback(Arc::new(model)).build().await);
+    let set = app
+        .admin(Method::PUT, "/tenants/acme/ai", Some(json!({})))
+        .await;
+    assert_eq!(set.status, StatusCode::OK, "{}", set.body);
+
+    let answered = chat(&app, "acme", "do you sell gift cards?").await;
+    assert_eq!(answered.reply(), "Gift cards are on the shop page.");
+    let received = server.received();
+    assert_eq!(received.len(), 1);
+    assert!(received[0].get("model").is_none());
+    assert_eq!(
+        received[0]["messages"][1]["content"],
+        "do you sell gift cards?"
+    );
+    let status = app.admin(Method::GET, "/tenants/acme/ai", None).await;
+    assert_eq!(status.body["tokens_used"], 42);
+}
+
+#[tokio::test]
+async fn the_key_is_never_shown() {
+    let app = TestApp::new(StateBuilder::new().build().await);
+    let missing = app
+        .admin(
+            Method::PUT,
+            "/tenants/acme/ai",
+            Some(json!({ "monthly_token_budget": 100 })),
+        )
+        .await;
+    assert_eq!(missing.status, StatusCode::BAD_REQUEST);
+
+    let set = app
+        .admin(
+            Method::PUT,
+            "/tenants/acme/ai",
+            Some(json!({ "api_key": "sk-secret" })),
+        )
+        .await;
+    let shown = app.admin(Method::GET, "/tenants/acme/ai", None).await;
+    for response in [&set, &shown] {
+        assert_eq!(response.status, StatusCode::OK);
+        assert!(!response.body.to_string().contains("sk-secret"));
+    }
+
+    let removed = app.admin(Method::DELETE, "/tenants/acme/ai", None).await;
+    assert_eq!(removed.status, StatusCode::OK);
+    let gone = app.admin(Method::GET, "/tenants/acme/ai", None).await;
+    assert_eq!(gone.status, StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn settings_and_usage_survive_a_restart() {
+    let path = std::env::temp_dir().join(format!("tenant-ai-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let ai = AiFallback::load(path.clone()).unwrap();
+    ai.configure(
+        "acme",
+        AiSettings {
+            api_key: Some("sk-acme".into()),
+            monthly_token_budget: Some(1000),
+            ..AiSettings::default()
+        },
+    )
+    .unwrap();
+    assert!(ai.record("acme", 250, now_ms()).is_none());
+    ai.save();
+    drop(ai);
+
+    let reloaded = AiFallback::load(path.clone()).unwrap();
+    let status = reloaded.status("acme").unwrap();
+    assert_eq!(status.tokens_used, 250);
+    assert_eq!(status.monthly_token_budget, Some(1000));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn usage_starts_over_each_month() {
+    let ai = AiFallback::default();
+    ai.configure(
+        "acme",
+        AiSettings {
+            api_key: Some("sk-acme".into()),
+            monthly_token_budget: Some(100),
+            ..AiSettings::default()
+        },
+    )
+    .unwrap();
+    // 2026-01-31 and 2026-02-01, UTC.
+    let (january, february) = (1_769_817_600_000, 1_769_904_000_000);
+    let exceeded = ai.record("acme", 150, january).expect("over budget");
+    assert_eq!(exceeded.month, "2026-01");
+    assert_eq!(exceeded.tokens_used, 150);
+    assert!(ai.record("acme", 50, february).is_none());
+    assert_eq!(ai.record("acme", 60, february).unwrap().tokens_used, 110);
+}
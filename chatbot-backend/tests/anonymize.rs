@@ -0,0 +1,255 @@
+//! Anonymized events: nothing that identifies a visitor is kept, and what
+//! analytics need is.
+
+use chatbot_backend::{
+    anonymize::{Anonymization, Anonymizer},
+    events::{ConversationEvent, EventStore, StoredEvent},
+    i18n::Language,
+    session::{ConversationState, Field, MessageMetadata},
+};
+use serde_json::{Value, json};
+
+const SESSION: &str = "visitor-session-1";
+const NAME: &str = "Ana Nowak";
+const EMAIL: &str = "Ana.Nowak@example.com";
+const PHONE: &str = "+48 600 123 456";
+
+/// What a visitor who identifies themselves in every way leaves behind.
+fn conversation() -> Vec<StoredEvent> {
+    use ConversationEvent as E;
+    let store = EventStore::in_memory();
+    store.append(
+        SESSION,
+        vec![
+            E::SessionStarted {
+                language: Language::En,
+            },
+            E::MessageReceived {
+                text: format!("Hi, I'm {NAME}, what are your prices? Call me on {PHONE}"),
+                message_id: "m-1".into(),
+                metadata: Some(MessageMetadata {
+                    page_url: Some(format!("https://example.com/contact?email={EMAIL}")),
+                    page_title: Some(format!("Welcome back, {NAME}")),
+                    ..Default::default()
+                }),
+            },
+            E::ReplySent {
+                text: format!("Nice to meet you, {NAME}!"),
+                intent: "pricing".into(),
+                message_id: "m-2".into(),
+            },
+            E::StateChanged {
+                from: ConversationState::AskingName,
+                to: ConversationState::AskingEmail,
+            },
+            E::FieldCaptured {
+                field: Field::Name,
+                value: Some(NAME.into()),
+            },
+            E::FieldCaptured {
+                field: Field::Email,
+                value: Some(EMAIL.into()),
+            },
+            E::FieldCaptured {
+                field: Field::ProjectDetails,
+                value: Some(format!(
+                    "A shop, the full service; ring {PHONE} or write to {EMAIL}"
+                )),
+            },
+            E::FieldCaptured {
+                field: Field::Budget,
+                value: None,
+            },
+            E::MessageEdited {
+                message_id: "m-1".into(),
+                text: format!("It's {NAME}, {PHONE}"),
+            },
+            E::ReportGenerated {
+                path: format!("reports/{NAME}.pdf"),
+            },
+            E::TranscriptEmailQueued { to: EMAIL.into() },
+            E::AgentMessage {
+                text: format!("{NAME}, I'll call {PHONE} in a minute"),
+                message_id: "m-3".into(),
+            },
+            E::Transferred {
+                provider: "livechat".into(),
+                external_id: format!("chat-{EMAIL}"),
+            },
+            E::SessionMerged {
+                from: format!("{EMAIL}-earlier"),
+            },
+            E::SatisfactionRated { score: 5 },
+        ],
+    );
+    store.session_events(SESSION)
+}
+
+fn anonymizer(mode: Anonymization) -> Anonymizer {
+    Anonymizer {
+        mode,
+        salt: "pepper".into(),
+    }
+}
+
+/// Panics on any trace of the visitor in `kept`.
+fn assert_anonymous(kept: &str) {
+    let lower = kept.to_lowercase();
+    for personal in [
+        NAME,
+        "nowak",
+        EMAIL,
+        "example.com",
+        PHONE,
+        "123 456",
+        SESSION,
+    ] {
+        assert!(
+            !lower.contains(&personal.to_lowercase()),
+            "{personal:?} kept in {kept}"
+        );
+    }
+}
+
+#[test]
+fn nothing_personal_is_kept() {
+    let events = conversation();
+    for mode in [
+        Anonymization::Strip,
+        Anonymization::Hash,
+        Anonymization::Keywords,
+    ] {
+        let anonymizer = anonymizer(mode);
+        for stored in &events {
+            assert_anonymous(&anonymizer.record(stored).to_string());
+        }
+    }
+}
+
+#[test]
+fn what_analytics_need_is_kept() {
+    let events = conversation();
+    let anonymizer = anonymizer(Anonymization::Strip);
+    let records: Vec<Value> = events.iter().map(|e| anonymizer.record(e)).collect();
+    for (record, stored) in records.iter().zip(&events) {
+        assert_eq!(record["seq"], stored.seq);
+        assert_eq!(record["at_ms"], stored.at_ms);
+        assert_eq!(record["session_id"], anonymizer.hash(SESSION));
+    }
+    let types: Vec<&str> = records
+        .iter()
+        .map(|r| r["type"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        types[..4],
+        [
+            "session_started",
+            "message_received",
+            "reply_sent",
+            "state_changed"
+        ]
+    );
+
+    assert_eq!(records[0]["language"], "en");
+    assert_eq!(records[1]["length"], events_text_len(&events[1]));
+    assert_eq!(records[2]["intent"], "pricing");
+    assert_eq!(records[3]["from"], "asking_name");
+    assert_eq!(records[3]["to"], "asking_email");
+    assert_eq!(
+        records[4],
+        json!({
+            "seq": events[4].seq,
+            "session_id": anonymizer.hash(SESSION),
+            "at_ms": events[4].at_ms,
+            "type": "field_captured",
+            "field": "name",
+            "given": true,
+        })
+    );
+    assert_eq!(records[7]["field"], "budget");
+    assert_eq!(records[7]["given"], false);
+    assert_eq!(records[12]["provider"], "livechat");
+    assert_eq!(records[14]["score"], 5);
+    // Strip keeps no stand-in for the address at all.
+    assert_eq!(records[10].as_object().unwrap().len(), 4);
+}
+
+fn events_text_len(stored: &StoredEvent) -> usize {
+    match &stored.event {
+        ConversationEvent::MessageReceived { text, .. } => text.chars().count(),
+        other => panic!("not a message: {other:?}"),
+    }
+}
+
+#[test]
+fn hashes_count_returning_visitors_without_naming_them() {
+    let events = conversation();
+    let hash = anonymizer(Anonymization::Hash);
+    let record = |i: usize| hash.record(&events[i]);
+    // The captured email and the transcript address are the same visitor,
+    // whatever the case they were typed in.
+    let captured = record(5)["value"].clone();
+    assert_eq!(captured, hash.hash(&EMAIL.to_lowercase()));
+    assert_eq!(record(10)["to"], captured);
+    assert_eq!(record(4)["value"], hash.hash(&NAME.to_lowercase()));
+    // Project details are free text; no hash of them is kept.
+    assert!(record(6).get("value").is_none());
+
+    let other_salt = Anonymizer {
+        mode: Anonymization::Hash,
+        salt: "salt".into(),
+    };
+    assert_ne!(other_salt.record(&events[5])["value"], captured);
+}
+
+#[test]
+fn keywords_keep_what_was_asked_about() {
+    let events = conversation();
+    let keywords = anonymizer(Anonymization::Keywords);
+    let asked = keywords.record(&events[1]);
+    assert!(
+        asked["keywords"]
+            .as_array()
+            .unwrap()
+            .contains(&json!("prices")),
+        "{asked}"
+    );
+    let details = keywords.record(&events[6]);
+    assert!(
+        details["keywords"]
+            .as_array()
+            .unwrap()
+            .contains(&json!("service")),
+        "{details}"
+    );
+    // Strip keeps only the length.
+    assert!(anonymizer(Anonymization::Strip).record(&events[1])["keywords"].is_null());
+}
+
+#[test]
+fn none_keeps_events_as_they_are() {
+    let events = conversation();
+    let kept = anonymizer(Anonymization::None).record(&events[1]);
+    assert_eq!(kept, serde_json::to_value(&events[1]).unwrap());
+}
+
+#[test]
+fn the_event_log_is_written_anonymized() {
+    let path = std::env::temp_dir().join(format!("anonymized-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut store = EventStore::with_log_file(&path).unwrap();
+    store.set_log_anonymizer(anonymizer(Anonymization::Hash));
+    let events: Vec<ConversationEvent> = conversation().into_iter().map(|e| e.event).collect();
+    let count = events.len();
+    store.append(SESSION, events);
+    store.flush_log();
+
+    let written = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(written.lines().count(), count);
+    assert_anonymous(&written);
+    // The live store still has the conversation for the operators.
+    assert!(store.session_events(SESSION).iter().any(
+        |e| matches!(&e.event, ConversationEvent::TranscriptEmailQueued { to } if to == EMAIL)
+    ));
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,435 @@
+//! How the bot reads what visitors and operators write: card numbers to
+//! redact, keyword phrases, yes/no answers, budgets, names, emails and
+//! project details, operator hours and blocked addresses.
+
+use chatbot_backend::{
+    blocklist::{BlockKind, Blocklist},
+    chatbot::budget_amount,
+    handoff::OperatorHours,
+    i18n::Language,
+    pipeline::{PipelineConfig, Processors},
+    session::Session,
+    text::{PhraseMatcher, contains_phrase, normalize},
+    validation::{BudgetRules, DetailsRules, EmailRules, Invalid, NameRules, ValidationRules},
+    yesno::{YesNo, interpret},
+};
+use proptest::{prelude::*, sample::select};
+
+const REDACTED: &str = "[card number removed]";
+
+fn redact(message: &str) -> String {
+    let config = PipelineConfig {
+        pre: vec!["redact_card_numbers".into()],
+        post: Vec::new(),
+    };
+    let session = Session::new("s1".into(), Language::En);
+    Processors::default()
+        .preprocess(&config, &session, message)
+        .0
+}
+
+#[test]
+fn card_numbers_passing_luhn_are_redacted() {
+    assert_eq!(
+        redact("my card is 4111 1111 1111 1111, thanks"),
+        format!("my card is {REDACTED}, thanks")
+    );
+    assert_eq!(redact("5500-0000-0000-0004"), REDACTED);
+    assert_eq!(redact("378282246310005"), REDACTED);
+}
+
+#[test]
+fn other_numbers_are_kept() {
+    // One digit off, so the check digit doesn't match.
+    assert_eq!(redact("4111 1111 1111 1112"), "4111 1111 1111 1112");
+    // Too short to be a card, though it passes the check.
+    assert_eq!(redact("call 4242 4242 42"), "call 4242 4242 42");
+    assert_eq!(redact("budget 15000 EUR"), "budget 15000 EUR");
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Topic {
+    Reset,
+    Inquiry,
+    Pricing,
+}
+
+fn topics() -> PhraseMatcher<Topic> {
+    PhraseMatcher::new([
+        (Topic::Reset, vec!["start over"]),
+        (Topic::Inquiry, vec!["start a project", "E-Commerce!!", ""]),
+        (Topic::Pricing, vec!["price", "how much", "hi"]),
+    ])
+}
+
+#[test]
+fn phrases_match_whole_words() {
+    let topics = topics();
+    let first = |message: &str| topics.first(&normalize(message));
+    assert_eq!(first("What's the PRICE?"), Some(Topic::Pricing));
+    assert_eq!(first("I'd like to start a project"), Some(Topic::Inquiry));
+    // Phrases are normalized like messages are.
+    assert_eq!(first("we need e commerce"), Some(Topic::Inquiry));
+    // Not inside other words, and not across part of one.
+    assert_eq!(first("this is priceless"), None);
+    assert_eq!(first("start a projector"), None);
+    assert_eq!(first(""), None);
+}
+
+#[test]
+fn earlier_groups_win_wherever_they_are() {
+    let topics = topics();
+    let first = |message: &str| topics.first(&normalize(message));
+    assert_eq!(
+        first("how much would it be to start a project"),
+        Some(Topic::Inquiry)
+    );
+    assert_eq!(
+        first("how much... actually, let's start over"),
+        Some(Topic::Reset)
+    );
+}
+
+const WORDS: [&str; 10] = [
+    "start", "over", "a", "project", "price", "how", "much", "hi", "this", "e",
+];
+
+proptest! {
+    #[test]
+    fn one_pass_finds_what_checking_each_phrase_does(
+        words in prop::collection::vec(select(WORDS.as_slice()), 0..8),
+    ) {
+        let message = words.join(" ");
+        let one_by_one = [
+            (Topic::Reset, &["start over"][..]),
+            (Topic::Inquiry, &["start a project", "E-Commerce!!"][..]),
+            (Topic::Pricing, &["price", "how much", "hi"][..]),
+        ]
+        .into_iter()
+        .find(|(_, phrases)| phrases.iter().any(|p| contains_phrase(&message, p)))
+        .map(|(topic, _)| topic);
+        prop_assert_eq!(topics().first(&message), one_by_one);
+    }
+}
+
+#[test]
+fn plain_answers() {
+    for yes in ["yes", "Tak", "sí", "looks good", "de acuerdo"] {
+        assert_eq!(interpret(yes, Language::En), Some(YesNo::Yes), "{yes}");
+    }
+    for no in ["no", "nope", "nie", "wrong"] {
+        assert_eq!(interpret(no, Language::En), Some(YesNo::No), "{no}");
+    }
+    assert_eq!(interpret("the weather is nice", Language::En), None);
+}
+
+#[test]
+fn hedges_and_negations() {
+    assert_eq!(interpret("not sure yet", Language::En), Some(YesNo::Unsure));
+    assert_eq!(interpret("nie wiem", Language::En), Some(YesNo::Unsure));
+    assert_eq!(
+        interpret("nothing wrong there", Language::En),
+        Some(YesNo::Yes)
+    );
+    assert_eq!(interpret("no está mal", Language::En), Some(YesNo::Yes));
+    assert_eq!(
+        interpret("that's not correct", Language::En),
+        Some(YesNo::No)
+    );
+    // Between a yes and a no, no wins.
+    assert_eq!(interpret("yes, no, wait", Language::En), Some(YesNo::No));
+}
+
+#[test]
+fn emoji_and_shorthand_answers() {
+    assert_eq!(interpret("👍🏽", Language::En), Some(YesNo::Yes));
+    assert_eq!(interpret("k", Language::En), Some(YesNo::Yes));
+    assert_eq!(interpret("nah", Language::En), Some(YesNo::No));
+    assert_eq!(interpret("❌", Language::Pl), Some(YesNo::No));
+    assert_eq!(interpret("t", Language::Pl), Some(YesNo::Yes));
+    // A letter that is a word of its own in the language.
+    assert_eq!(interpret("y", Language::Es), None);
+}
+
+#[test]
+fn budget_amounts() {
+    assert_eq!(budget_amount("15000"), Some(15_000));
+    assert_eq!(budget_amount("5,000 EUR"), Some(5_000));
+    assert_eq!(budget_amount("5 000 zł"), Some(5_000));
+    assert_eq!(budget_amount("2.5k"), Some(2_500));
+    assert_eq!(budget_amount("1500,50"), Some(1_501));
+    assert_eq!(budget_amount("3-5k"), Some(3_000));
+    assert_eq!(budget_amount("about 1.2 million"), Some(1_200_000));
+    assert_eq!(budget_amount("20 tys. zł"), Some(20_000));
+    assert_eq!(budget_amount("not sure"), None);
+}
+
+fn check_name(name: &str, lang: Language) -> Result<(), Invalid> {
+    ValidationRules::default().check_name(name, lang)
+}
+
+#[test]
+fn names_in_any_alphabet() {
+    for name in [
+        "Łukasz Nowak",
+        "Zoë",
+        "Владимир",
+        "Ηλέκτρα",
+        "王芳",
+        "さくら",
+        "अनिल",
+        "Seán O'Brien",
+        "María-José Peña",
+        "Ngũgĩ wa Thiong’o",
+    ] {
+        assert_eq!(check_name(name, Language::En), Ok(()), "{name}");
+    }
+}
+
+#[test]
+fn combining_marks_belong_to_their_letter() {
+    // "Zoë" typed as "e" and a combining diaeresis.
+    assert_eq!(check_name("Zoe\u{0308}", Language::En), Ok(()));
+    // A mark NFC has no single letter for.
+    assert_eq!(check_name("Ana\u{0331}", Language::En), Ok(()));
+    assert_eq!(
+        check_name("\u{0308}Zoe", Language::En),
+        Err(Invalid::NameCharacters)
+    );
+    assert_eq!(
+        check_name("Zoe \u{0308}", Language::En),
+        Err(Invalid::NameCharacters)
+    );
+    // Three characters once composed.
+    let rules = ValidationRules {
+        name: NameRules {
+            max_chars: 3,
+            ..NameRules::default()
+        },
+        ..ValidationRules::default()
+    };
+    assert_eq!(rules.check_name("Zoe\u{0308}", Language::En), Ok(()));
+}
+
+#[test]
+fn what_is_not_a_name() {
+    for name in [
+        "Ana 🙂",
+        "ana@example.com",
+        "www.example.com",
+        "R2D2",
+        "Ana_Nowak",
+    ] {
+        assert_eq!(
+            check_name(name, Language::En),
+            Err(Invalid::NameCharacters),
+            "{name}"
+        );
+    }
+    for name in ["-Ana", "Ana-", "'Ana"] {
+        assert_eq!(check_name(name, Language::En), Err(Invalid::Name), "{name}");
+    }
+    assert_eq!(check_name("A", Language::En), Err(Invalid::NameLength));
+}
+
+#[test]
+fn answers_that_are_not_names_depend_on_the_language() {
+    assert_eq!(check_name("Hello", Language::En), Err(Invalid::Name));
+    assert_eq!(check_name("Cześć", Language::Pl), Err(Invalid::Name));
+    assert_eq!(check_name("Gracias", Language::Es), Err(Invalid::Name));
+    // Someone may well be called that elsewhere.
+    assert_eq!(check_name("Hola", Language::En), Ok(()));
+    assert_eq!(check_name("Hey", Language::Pl), Ok(()));
+}
+
+#[test]
+fn emails() {
+    let lenient = ValidationRules::default();
+    let strict = ValidationRules {
+        email: EmailRules { strict: true },
+        ..ValidationRules::default()
+    };
+    for email in ["ana@example.com", "ana.nowak+chat@mail.example.co.uk"] {
+        assert_eq!(lenient.check_email(email), Ok(()), "{email}");
+        assert_eq!(strict.check_email(email), Ok(()), "{email}");
+    }
+    // Fine unless strict.
+    for email in [
+        "ana..b@example.com",
+        ".ana@example.com",
+        "łukasz@example.pl",
+        "ana@exa_mple.com",
+        "ana@-example.com",
+        "ana@example.c0m",
+    ] {
+        assert_eq!(lenient.check_email(email), Ok(()), "{email}");
+        assert_eq!(strict.check_email(email), Err(Invalid::Email), "{email}");
+    }
+    for email in [
+        "ana",
+        "@example.com",
+        "ana@localhost",
+        "ana@@example.com",
+        "ana@.example.com",
+        "ana@example.com.",
+        "ana nowak@example.com",
+    ] {
+        assert_eq!(lenient.check_email(email), Err(Invalid::Email), "{email}");
+    }
+}
+
+#[test]
+fn project_details_long_enough() {
+    let rules = ValidationRules::default();
+    assert_eq!(rules.check_project_details("An online shop"), Ok(()));
+    // Spaces around the answer don't count.
+    assert_eq!(
+        rules.check_project_details("   a shop    "),
+        Err(Invalid::DetailsTooShort)
+    );
+    // Characters, not bytes.
+    assert_eq!(rules.check_project_details("żółć źdźbł"), Ok(()));
+    let brief = ValidationRules {
+        project_details: DetailsRules { min_chars: 3 },
+        ..ValidationRules::default()
+    };
+    assert_eq!(brief.check_project_details("app"), Ok(()));
+}
+
+#[test]
+fn budgets_within_bounds() {
+    let rules = ValidationRules {
+        budget: BudgetRules {
+            min: Some(500),
+            max: Some(1_000_000),
+        },
+        ..ValidationRules::default()
+    };
+    assert_eq!(rules.check_budget("5 000 zł"), Ok(()));
+    assert_eq!(rules.check_budget("500"), Ok(()));
+    assert_eq!(rules.check_budget("499"), Err(Invalid::BudgetBelowMin));
+    assert_eq!(
+        rules.check_budget("2 million"),
+        Err(Invalid::BudgetAboveMax)
+    );
+    assert_eq!(rules.check_budget("depends"), Err(Invalid::Budget));
+    // Without bounds any amount is taken.
+    assert_eq!(ValidationRules::default().check_budget("1"), Ok(()));
+
+    let prompt = rules.prompt(Invalid::BudgetBelowMin, Language::En, "EUR");
+    assert!(prompt.contains("500"), "{prompt}");
+}
+
+#[test]
+fn validation_settings_that_make_no_sense() {
+    assert_eq!(ValidationRules::default().validate(), Ok(()));
+    let with_name = |name: NameRules| ValidationRules {
+        name,
+        ..ValidationRules::default()
+    };
+    for name in [
+        NameRules {
+            min_chars: 0,
+            ..NameRules::default()
+        },
+        NameRules {
+            min_chars: 10,
+            max_chars: 5,
+            ..NameRules::default()
+        },
+        NameRules {
+            punctuation: "- ".into(),
+            ..NameRules::default()
+        },
+    ] {
+        assert!(with_name(name.clone()).validate().is_err(), "{name:?}");
+    }
+    let budget = ValidationRules {
+        budget: BudgetRules {
+            min: Some(10_000),
+            max: Some(5_000),
+        },
+        ..ValidationRules::default()
+    };
+    assert!(budget.validate().is_err());
+}
+
+/// Unix milliseconds of `day` (0 is Thursday 1970-01-01) at `hh:mm` UTC.
+fn at(day: u64, hh: u64, mm: u64) -> u64 {
+    ((day * 24 + hh) * 60 + mm) * 60_000
+}
+
+#[test]
+fn operator_hours_on_weekdays() {
+    let hours = OperatorHours::parse("mon-fri 09:00-17:00").unwrap();
+    // Thursday.
+    assert!(hours.contains(at(0, 9, 0)));
+    assert!(hours.contains(at(0, 16, 59)));
+    assert!(!hours.contains(at(0, 17, 0)));
+    assert!(!hours.contains(at(0, 8, 59)));
+    // Saturday.
+    assert!(!hours.contains(at(2, 12, 0)));
+}
+
+#[test]
+fn operator_hours_with_an_offset() {
+    let hours = OperatorHours::parse("09:00-17:00 +02:00").unwrap();
+    assert!(hours.contains(at(0, 7, 0)));
+    assert!(!hours.contains(at(0, 15, 0)));
+}
+
+#[test]
+fn operator_hours_past_midnight_belong_to_the_day_they_start() {
+    let hours = OperatorHours::parse("fri 22:00-02:00").unwrap();
+    // Friday 23:00 and Saturday 01:00.
+    assert!(hours.contains(at(1, 23, 0)));
+    assert!(hours.contains(at(2, 1, 0)));
+    // Friday 01:00 is Thursday's night.
+    assert!(!hours.contains(at(1, 1, 0)));
+}
+
+#[test]
+fn operator_hours_refuse_nonsense() {
+    assert!(OperatorHours::parse("someday 09:00-17:00").is_err());
+    assert!(OperatorHours::parse("09:00-09:00").is_err());
+    assert!(OperatorHours::parse("nine to five").is_err());
+}
+
+#[test]
+fn email_domains_block_their_subdomains() {
+    let blocklist = Blocklist::default();
+    blocklist
+        .add(BlockKind::EmailDomain, "spam.example")
+        .unwrap();
+    assert!(blocklist.blocks_email("a@spam.example"));
+    assert!(blocklist.blocks_email("a@mail.SPAM.example."));
+    assert!(!blocklist.blocks_email("a@notspam.example"));
+    assert!(!blocklist.blocks_email("a@example"));
+    assert!(!blocklist.blocks_email("not an address"));
+}
+
+#[test]
+fn visitor_entries_are_trimmed() {
+    let blocklist = Blocklist::default();
+    blocklist.add(BlockKind::Visitor, " s1 ").unwrap();
+    assert!(blocklist.blocks_visitor("s1"));
+    assert!(blocklist.remove(BlockKind::Visitor, "s1 ").unwrap());
+    assert!(!blocklist.has_visitor_entries());
+}
+
+#[test]
+fn the_blocklist_survives_a_restart() {
+    let path = std::env::temp_dir().join(format!("blocklist-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let blocklist = Blocklist::load(path.clone()).unwrap();
+    blocklist
+        .add(BlockKind::EmailDomain, "spam.example")
+        .unwrap();
+    blocklist.add(BlockKind::Ip, "192.0.2.1").unwrap();
+    drop(blocklist);
+
+    let reloaded = Blocklist::load(path.clone()).unwrap();
+    assert!(reloaded.blocks_email("a@spam.example"));
+    assert!(reloaded.blocks_ip("192.0.2.1".parse().unwrap()));
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,211 @@
+//! How the server copes with slow and failing dependencies: webhook
+//! receivers that are down, intent handlers that time out, and visitors
+//! arriving all at once.
+
+use std::{sync::Arc, time::Duration};
+
+use axum::http::{Method, StatusCode};
+use chatbot_backend::{
+    events::now_ms,
+    outbox::{self, DEAD_LETTER_TTL, JobStatus, MAX_ATTEMPTS, OutboxJob},
+    session::SessionLimits,
+    test_support::{Chaos, ChaosIntent, ChaosServer, StateBuilder, StubIntent, TestApp},
+};
+use serde_json::json;
+
+const DOWN: Chaos = Chaos {
+    max_latency: Duration::ZERO,
+    failure_rate: 1.0,
+};
+
+const SLOW: Chaos = Chaos {
+    max_latency: Duration::from_millis(50),
+    failure_rate: 0.0,
+};
+
+#[tokio::test]
+async fn a_receiver_that_is_down_gets_the_job_again_later() {
+    let state = StateBuilder::new().build().await;
+    let down = ChaosServer::start(DOWN).await;
+    let up = ChaosServer::start(SLOW).await;
+    let failing = OutboxJob::new("test", down.url(), json!({ "n": 1 }));
+    let failing_id = failing.id.clone();
+    state.outbox.enqueue(failing);
+    state
+        .outbox
+        .enqueue(OutboxJob::new("test", up.url(), json!({ "n": 2 })));
+
+    outbox::deliver_due(&state, 10).await;
+    assert_eq!(down.failures(), 1);
+    assert_eq!(up.received().len(), 1);
+    assert_eq!(up.received()[0]["data"]["n"], 2);
+
+    let job = state.outbox.get(&failing_id).expect("kept for a retry");
+    assert_eq!(job.status, JobStatus::Pending);
+    assert_eq!(job.attempts, 1);
+    assert!(job.next_attempt_at_ms > now_ms());
+    assert!(job.last_error.is_some_and(|e| e.contains("503")));
+    assert_eq!(state.outbox.len(), 1);
+
+    // Not due yet, so another round leaves it alone.
+    outbox::deliver_due(&state, 10).await;
+    assert_eq!(down.failures(), 1);
+
+    let redelivered = TestApp::new(state.clone())
+        .admin(
+            Method::POST,
+            &format!("/deliveries/{failing_id}/redeliver"),
+            None,
+        )
+        .await;
+    assert_eq!(redelivered.status, StatusCode::OK, "{}", redelivered.body);
+    outbox::deliver_due(&state, 10).await;
+    assert_eq!(down.failures(), 2);
+}
+
+#[tokio::test]
+async fn a_receiver_gets_its_jobs_in_order() {
+    let state = StateBuilder::new().build().await;
+    let down = ChaosServer::start(DOWN).await;
+    let first = OutboxJob::new("test", down.url(), json!({ "n": 1 }));
+    let second = OutboxJob::new("test", down.url(), json!({ "n": 2 }));
+    let (first_id, second_id) = (first.id.clone(), second.id.clone());
+    state.outbox.enqueue(first);
+    state.outbox.enqueue(second);
+
+    outbox::deliver_due(&state, 10).await;
+    assert_eq!(down.failures(), 1);
+    assert_eq!(state.outbox.get(&second_id).unwrap().attempts, 0);
+    // The second is due, but waits while the first backs off.
+    outbox::deliver_due(&state, 10).await;
+    assert_eq!(down.failures(), 1);
+
+    // Once the first is given up on, the second goes out.
+    for _ in 1..MAX_ATTEMPTS {
+        state.outbox.record_failure(&first_id, "down".into());
+    }
+    outbox::deliver_due(&state, 10).await;
+    assert_eq!(down.failures(), 2);
+    assert_eq!(state.outbox.get(&second_id).unwrap().attempts, 1);
+}
+
+#[tokio::test]
+async fn jobs_out_of_attempts_are_dead_letters_for_a_while() {
+    let state = StateBuilder::new().build().await;
+    let down = ChaosServer::start(DOWN).await;
+    let job = OutboxJob::new("test", down.url(), json!({}));
+    let id = job.id.clone();
+    state.outbox.enqueue(job);
+    outbox::deliver_due(&state, 10).await;
+    assert_eq!(down.failures(), 1);
+    // The retries after the first, without waiting out the backoff.
+    for _ in 1..MAX_ATTEMPTS {
+        state.outbox.record_failure(&id, "down".into());
+    }
+    let job = state.outbox.get(&id).unwrap();
+    assert_eq!(job.status, JobStatus::Failed);
+    let failed_at = job.failed_at_ms.expect("when it failed");
+
+    assert_eq!(state.outbox.prune_dead_letters(now_ms()), 0);
+    let expired = failed_at + DEAD_LETTER_TTL.as_millis() as u64 + 1;
+    assert_eq!(state.outbox.prune_dead_letters(expired), 1);
+    assert!(state.outbox.is_empty());
+}
+
+#[tokio::test]
+async fn the_session_cap_holds_when_everyone_arrives_at_once() {
+    let stub = Arc::new(StubIntent::new("weather", &["hello"]));
+    let state = StateBuilder::new()
+        .intent(Arc::new(ChaosIntent::new(stub, SLOW)))
+        .limits(SessionLimits {
+            max_sessions: Some(5),
+            ..SessionLimits::default()
+        })
+        .build()
+        .await;
+    let app = Arc::new(TestApp::new(state));
+
+    let visitors: Vec<_> = (0..20)
+        .map(|_| {
+            let app = app.clone();
+            tokio::spawn(async move { app.chat(None, "hello").await.status })
+        })
+        .collect();
+    let mut statuses = Vec::new();
+    for visitor in visitors {
+        statuses.push(visitor.await.unwrap());
+    }
+    let served = statuses.iter().filter(|s| **s == StatusCode::OK).count();
+    let turned_away = statuses
+        .iter()
+        .filter(|s| **s == StatusCode::SERVICE_UNAVAILABLE)
+        .count();
+    assert_eq!((served, turned_away), (5, 15));
+    assert_eq!(app.state.sessions.session_count().await, 5);
+}
+
+#[tokio::test]
+async fn messages_to_one_session_wait_their_turn() {
+    let stub = Arc::new(StubIntent::new("weather", &["hello"]));
+    let app = Arc::new(TestApp::new(
+        StateBuilder::new()
+            .intent(Arc::new(ChaosIntent::new(stub, SLOW)))
+            .build()
+            .await,
+    ));
+    let session_id = app.chat(None, "hello").await.session_id().to_string();
+
+    let sends: Vec<_> = (1..=10)
+        .map(|n| {
+            let app = app.clone();
+            let session_id = session_id.clone();
+            tokio::spawn(async move { app.chat(Some(&session_id), &format!("hello {n}")).await })
+        })
+        .collect();
+    for send in sends {
+        let response = send.await.unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.session_id(), session_id);
+    }
+
+    let history = app.state.sessions.get_history(&session_id).await.unwrap();
+    assert_eq!(history.len(), 22);
+    let page = app
+        .request(
+            Method::GET,
+            &format!("/sessions/{session_id}/messages?limit=200"),
+            None,
+        )
+        .await;
+    let transcript = page.body["messages"].as_array().unwrap();
+    assert_eq!(transcript.len(), history.len());
+    for (entry, message) in transcript.iter().zip(&history) {
+        assert_eq!(entry["id"], message.id.as_str());
+    }
+}
+
+#[tokio::test]
+async fn a_handler_that_is_down_leaves_the_message_to_the_rules() {
+    let stub = Arc::new(StubIntent::new("shipping_rates", &["shipping"]).reply("From 100 EUR."));
+    let flaky = Arc::new(ChaosIntent::new(stub.clone(), DOWN));
+    let app = TestApp::new(StateBuilder::new().intent(flaky.clone()).build().await);
+
+    let response = app.chat(None, "what are your shipping rates?").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(!response.reply().is_empty());
+    assert_ne!(response.reply(), "From 100 EUR.");
+    assert_eq!(flaky.failures(), 1);
+    assert!(stub.requests().is_empty());
+}
+
+#[tokio::test]
+async fn a_slow_handler_still_answers() {
+    let stub = Arc::new(StubIntent::new("shipping_rates", &["shipping"]).reply("From 100 EUR."));
+    let slow = Arc::new(ChaosIntent::new(stub.clone(), SLOW));
+    let app = TestApp::new(StateBuilder::new().intent(slow.clone()).build().await);
+
+    let response = app.chat(None, "what are your shipping rates?").await;
+    assert_eq!(response.reply(), "From 100 EUR.");
+    assert_eq!(slow.failures(), 0);
+    assert_eq!(stub.requests()[0].message, "what are your shipping rates?");
+}
@@ -0,0 +1,221 @@
+//! The event log: what is written to its file and when, and the
+//! conversation its events rebuild.
+
+use axum::http::{Method, StatusCode};
+use chatbot_backend::{
+    events::{self, ConversationEvent, EventStore},
+    i18n::Language,
+    session::{ConversationState, Field, FieldValue, ReceiptStatus, Role, SessionData},
+    test_support::{StateBuilder, TestApp},
+};
+use serde_json::{Value, json};
+
+#[test]
+fn the_log_file_is_written_behind() {
+    let path = std::env::temp_dir().join(format!("events-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let store = EventStore::with_log_file(&path).unwrap();
+    store.append(
+        "s1",
+        vec![ConversationEvent::SessionStarted {
+            language: Language::En,
+        }],
+    );
+    store.append("s2", vec![ConversationEvent::EscalationRequested]);
+    // Appending leaves the disk to the writer.
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+    assert_eq!(store.session_events("s1").len(), 1);
+
+    store.flush_log();
+    let written = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<Value> = written
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["seq"], 1);
+    assert_eq!(lines[0]["session_id"], "s1");
+    assert_eq!(lines[1]["type"], "escalation_requested");
+
+    store.flush_log();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), written);
+    std::fs::remove_file(path).unwrap();
+}
+
+/// Id of the visitor's last message in `session_id`.
+async fn last_visitor_message(app: &TestApp, session_id: &str) -> String {
+    app.state
+        .sessions
+        .with_session(session_id, |s| {
+            s.messages
+                .iter()
+                .rev()
+                .find(|m| m.role == Role::User)
+                .map(|m| m.id.clone())
+        })
+        .await
+        .flatten()
+        .expect("a visitor message")
+}
+
+#[tokio::test]
+async fn the_events_rebuild_the_live_session() {
+    let app = TestApp::new(StateBuilder::new().build().await);
+    let id = app
+        .chat(None, "I'd like to start a project")
+        .await
+        .session_id()
+        .to_string();
+    app.chat(Some(&id), "Ana").await;
+    // The visitor corrects their name after the bot took it.
+    let message_id = last_visitor_message(&app, &id).await;
+    let edited = app
+        .request(
+            Method::PATCH,
+            &format!("/sessions/{id}/messages/{message_id}"),
+            Some(json!({ "text": "Beata" })),
+        )
+        .await;
+    assert_eq!(edited.status, StatusCode::OK, "{}", edited.body);
+    app.chat(Some(&id), "beata@example.com").await;
+    app.chat(Some(&id), "An online shop for handmade ceramics")
+        .await;
+    app.chat(Some(&id), "Can I talk to a person?").await;
+
+    let events = app.state.events.session_events(&id);
+    let projection = events::project(&events);
+    let (language, state, data, messages) = app
+        .state
+        .sessions
+        .with_session(&id, |s| {
+            let messages: Vec<(String, Role, String)> = s
+                .messages
+                .iter()
+                .map(|m| (m.id.clone(), m.role, m.content.clone()))
+                .collect();
+            (s.language, s.state, s.data.clone(), messages)
+        })
+        .await
+        .expect("a live session");
+    assert_eq!(data.name.as_deref(), Some("Beata"));
+    assert_eq!(projection.language, language);
+    assert_eq!(projection.state, state);
+    assert_eq!(projection.data, data);
+    let transcript: Vec<(String, Role, String)> = projection
+        .transcript
+        .iter()
+        .map(|e| (e.id.clone(), e.role, e.text.clone()))
+        .collect();
+    assert_eq!(transcript, messages);
+    assert_eq!(projection.last_seq, events.last().unwrap().seq);
+
+    // Another instance given the same events puts the visitor in line for
+    // an operator just the same.
+    let waiting: Vec<String> = app
+        .state
+        .events
+        .waiting()
+        .into_iter()
+        .map(|w| w.0)
+        .collect();
+    assert_eq!(waiting, [id.as_str()]);
+    let replayed = EventStore::in_memory();
+    replayed.restore(events);
+    assert_eq!(replayed.waiting(), app.state.events.waiting());
+    let rebuilt = events::project(&replayed.session_events(&id));
+    assert_eq!(rebuilt.data, projection.data);
+    assert_eq!(rebuilt.transcript.len(), projection.transcript.len());
+}
+
+#[test]
+fn corrections_replace_what_they_correct() {
+    use ConversationEvent as E;
+    let store = EventStore::in_memory();
+    let message = |id: &str, text: &str| E::MessageReceived {
+        text: text.into(),
+        message_id: id.into(),
+        metadata: None,
+    };
+    store.append(
+        "s1",
+        vec![
+            E::SessionStarted {
+                language: Language::Es,
+            },
+            message("m-1", "Ana"),
+            E::FieldCaptured {
+                field: Field::Name,
+                value: Some("Ana".into()),
+            },
+            E::MessageEdited {
+                message_id: "m-1".into(),
+                text: "Beata".into(),
+            },
+            E::FieldCleared { field: Field::Name },
+            E::FieldCaptured {
+                field: Field::Name,
+                value: Some("Beata".into()),
+            },
+            message("m-2", "no sé"),
+            E::FieldCaptured {
+                field: Field::Budget,
+                value: None,
+            },
+            message("m-3", "oops"),
+            E::MessageDeleted {
+                message_id: "m-3".into(),
+            },
+            E::ReplySent {
+                text: "¡Gracias!".into(),
+                intent: "thanks".into(),
+                message_id: "m-4".into(),
+            },
+            E::ReceiptRecorded {
+                message_id: "m-4".into(),
+                status: ReceiptStatus::Read,
+            },
+            // A late delivery report doesn't undo the read.
+            E::ReceiptRecorded {
+                message_id: "m-4".into(),
+                status: ReceiptStatus::Delivered,
+            },
+            E::LeadLabelsChanged {
+                added: vec!["hot".into(), "ceramics".into()],
+                removed: vec![],
+            },
+            E::LeadLabelsChanged {
+                added: vec![],
+                removed: vec!["hot".into()],
+            },
+            E::StateChanged {
+                from: ConversationState::AskingBudget,
+                to: ConversationState::ConfirmingSummary,
+            },
+        ],
+    );
+    let projection = events::project(&store.session_events("s1"));
+    assert_eq!(projection.language, Language::Es);
+    assert_eq!(projection.state, ConversationState::ConfirmingSummary);
+    assert_eq!(
+        projection.data,
+        SessionData {
+            name: Some("Beata".into()),
+            budget: Some(FieldValue::Unspecified),
+            ..SessionData::default()
+        }
+    );
+    let transcript: Vec<(&str, &str)> = projection
+        .transcript
+        .iter()
+        .map(|e| (e.id.as_str(), e.text.as_str()))
+        .collect();
+    assert_eq!(
+        transcript,
+        [("m-1", "Beata"), ("m-2", "no sé"), ("m-4", "¡Gracias!")]
+    );
+    assert_eq!(projection.transcript[2].receipt, Some(ReceiptStatus::Read));
+    assert_eq!(
+        projection.labels.into_iter().collect::<Vec<_>>(),
+        ["ceramics"]
+    );
+}
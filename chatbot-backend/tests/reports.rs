@@ -0,0 +1,76 @@
+//! Inquiry reports: what sales get and what the visitor previews.
+
+use std::time::{Duration, SystemTime};
+
+use chatbot_backend::{
+    i18n::{Language, Text, text},
+    report::{InquiryReport, ReportFormat, generate_report},
+    session::Session,
+    test_support::{History, SessionBuilder},
+};
+
+/// A visitor who took 20 s, 40 s and 10 min to answer the bot.
+fn answered_inquiry() -> Session {
+    let history = History::new()
+        .bot("Hi! How can I help?")
+        .visitor("I want to start a project")
+        .bot("What's your name?")
+        .visitor("Ana Nowak")
+        .bot("Your email?")
+        .visitor("ana@example.com");
+    let mut session = SessionBuilder::new("report-1")
+        .name("Ana Nowak")
+        .email("ana@example.com")
+        .project_details("A shop for our bakery")
+        .history(history)
+        .build();
+    let start = SystemTime::now() - Duration::from_secs(3_600);
+    let sent_after = [0, 20, 30, 70, 80, 680];
+    for (message, secs) in session.messages.iter_mut().zip(sent_after) {
+        message.timestamp = start + Duration::from_secs(secs);
+    }
+    session
+}
+
+#[test]
+fn sales_see_the_lead_quality() {
+    let session = answered_inquiry();
+    let report = InquiryReport::new(&session);
+    let quality: Vec<(&str, &str)> = report
+        .lead_quality()
+        .iter()
+        .map(|(label, value)| (*label, value.as_str()))
+        .collect();
+    // Email, name and details, with no budget yet; the median of 20 s,
+    // 40 s and 10 min.
+    assert_eq!(quality, [("Score", "4 / 5"), ("Reply time", "40 s")]);
+
+    let dir = std::env::temp_dir().join(format!("reports-{}", std::process::id()));
+    for format in [ReportFormat::Pdf, ReportFormat::Docx] {
+        let path = generate_report(&session, &dir, format).unwrap();
+        assert!(std::fs::metadata(path).unwrap().len() > 0);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_visitor_who_never_answered_has_no_reply_time() {
+    let session = SessionBuilder::new("report-2")
+        .language(Language::Pl)
+        .history(History::new().visitor("Dzień dobry"))
+        .build();
+    let report = InquiryReport::new(&session);
+    assert_eq!(report.lead_quality()[0].1, "0 / 5");
+    assert_eq!(
+        report.lead_quality()[1].1,
+        text(Language::Pl, Text::NotSpecified)
+    );
+}
+
+#[test]
+fn the_visitor_preview_leaves_the_lead_quality_out() {
+    let preview = InquiryReport::new(&answered_inquiry()).markdown();
+    assert!(preview.contains("Ana Nowak"));
+    assert!(!preview.contains("Lead quality"));
+    assert!(!preview.contains("/ 5"));
+}
@@ -0,0 +1,131 @@
+//! Saved sessions: reading every older schema version and writing the
+//! current one back.
+
+use chatbot_backend::{
+    i18n::Language,
+    saved::{SCHEMA_VERSION, SavedData, SavedSession, SavedState, SchemaError},
+    session::{ConversationState, FieldValue, LeadTag, ReceiptStatus, Role},
+    tenant::DEFAULT_TENANT,
+};
+use serde_json::{Value, json};
+
+/// When the fixtures were written, in Unix milliseconds.
+const SAVED_AT_MS: u64 = 1_750_000_000_000;
+
+fn fixture(raw: &str) -> SavedSession {
+    let value: Value = serde_json::from_str(raw).unwrap();
+    SavedSession::from_value(value, SAVED_AT_MS).unwrap()
+}
+
+#[test]
+fn a_version_1_session_gets_the_fields_it_lacked() {
+    let saved = fixture(include_str!("fixtures/saved/v1.json"));
+    assert_eq!(saved.schema_version, SCHEMA_VERSION);
+    assert_eq!(saved.id, "saved-v1");
+    assert_eq!(saved.language, Language::Pl);
+    assert_eq!(saved.tenant, DEFAULT_TENANT);
+    assert!(!saved.survey_pending);
+    assert_eq!(saved.failed_answers, 0);
+    assert_eq!(saved.state, SavedState::AskingBudget);
+    assert_eq!(
+        saved.data,
+        SavedData {
+            name: Some("Ana Nowak".into()),
+            email: Some("ana@example.com".into()),
+            project_details: Some("A shop for our bakery".into()),
+            budget: None,
+            tag: None,
+        }
+    );
+    assert_eq!(saved.client_ip, Some("203.0.113.7".parse().unwrap()));
+
+    // Both messages get ids, including the one saved as null, and their
+    // ages become when they were sent.
+    let [bot, visitor] = &saved.messages[..] else {
+        panic!("two messages, got {:?}", saved.messages);
+    };
+    assert!(!bot.id.is_empty() && !visitor.id.is_empty());
+    assert_ne!(bot.id, visitor.id);
+    assert_eq!(bot.at_ms, SAVED_AT_MS - 90_000);
+    assert_eq!(visitor.at_ms, SAVED_AT_MS - 60_000);
+    assert_eq!(bot.receipt, Some(ReceiptStatus::Read));
+    assert_eq!(visitor.role, Role::User);
+}
+
+#[test]
+fn a_version_2_session_keeps_what_it_had() {
+    let saved = fixture(include_str!("fixtures/saved/v2.json"));
+    assert_eq!(saved.schema_version, SCHEMA_VERSION);
+    assert_eq!(saved.tenant, "acme");
+    assert!(saved.survey_pending);
+    assert_eq!(saved.failed_answers, 2);
+    assert!(saved.inactivity_warned);
+    assert_eq!(saved.state, SavedState::ConfirmingSummary);
+    assert_eq!(saved.data.budget, Some(FieldValue::Unspecified));
+    assert_eq!(saved.data.tag, Some(LeadTag::BelowMinimumBudget));
+
+    let ids: Vec<&str> = saved.messages.iter().map(|m| m.id.as_str()).collect();
+    assert_eq!(ids, ["m-1", "m-2"]);
+    let sent: Vec<u64> = saved.messages.iter().map(|m| m.at_ms).collect();
+    assert_eq!(sent, [SAVED_AT_MS - 30_000, SAVED_AT_MS - 5_000]);
+    assert!(
+        serde_json::to_value(&saved.messages[0]).unwrap()["age_ms"].is_null(),
+        "ages are not carried over"
+    );
+}
+
+#[test]
+fn a_migrated_session_is_saved_again_as_it_was_read() {
+    for raw in [
+        include_str!("fixtures/saved/v1.json"),
+        include_str!("fixtures/saved/v2.json"),
+    ] {
+        let migrated = fixture(raw);
+        let (session, client_ip) = migrated.clone().into_session(0);
+        assert_eq!(session.state, ConversationState::from(migrated.state));
+        assert!(!session.handed_off, "nobody is watching the new instance");
+
+        let saved = SavedSession::from_session(&session, client_ip);
+        let written = serde_json::to_value(&saved).unwrap();
+        let reread = SavedSession::from_value(written, SAVED_AT_MS).unwrap();
+        assert_eq!(reread.schema_version, SCHEMA_VERSION);
+        assert_eq!(reread.id, migrated.id);
+        assert_eq!(reread.tenant, migrated.tenant);
+        assert_eq!(reread.state, migrated.state);
+        assert_eq!(reread.data, migrated.data);
+        assert_eq!(reread.survey_pending, migrated.survey_pending);
+        assert_eq!(reread.failed_answers, migrated.failed_answers);
+        assert_eq!(reread.client_ip, migrated.client_ip);
+        let messages = |s: &SavedSession| -> Vec<(String, Role, String, u64)> {
+            s.messages
+                .iter()
+                .map(|m| (m.id.clone(), m.role, m.content.clone(), m.at_ms))
+                .collect()
+        };
+        assert_eq!(messages(&reread), messages(&migrated));
+    }
+}
+
+#[test]
+fn what_cannot_be_read() {
+    let newer = json!({ "schema_version": SCHEMA_VERSION + 1, "id": "later" });
+    assert!(matches!(
+        SavedSession::from_value(newer, SAVED_AT_MS),
+        Err(SchemaError::Newer(v)) if v == SCHEMA_VERSION + 1
+    ));
+    for malformed in [
+        json!([]),
+        json!({ "schema_version": 0 }),
+        json!({ "schema_version": "3" }),
+    ] {
+        assert!(matches!(
+            SavedSession::from_value(malformed, SAVED_AT_MS),
+            Err(SchemaError::Malformed)
+        ));
+    }
+    let incomplete = json!({ "schema_version": SCHEMA_VERSION, "id": "half" });
+    assert!(matches!(
+        SavedSession::from_value(incomplete, SAVED_AT_MS),
+        Err(SchemaError::Json(_))
+    ));
+}
@@ -0,0 +1,487 @@
+//! Sessions through the HTTP API: correcting messages, paging the
+//! transcript, merging, deleting and restoring, the operator queue and
+//! the blocklist, and carrying sessions over a restart.
+
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use axum::http::{Method, StatusCode};
+use chatbot_backend::{
+    service::EDIT_WINDOW,
+    session::{ConversationState, Role},
+    snapshot,
+    test_support::{History, SessionBuilder, StateBuilder, StubIntent, TestApp, TestResponse},
+};
+use serde_json::{Value, json};
+
+async fn app() -> TestApp {
+    TestApp::new(StateBuilder::new().build().await)
+}
+
+async fn state_of(app: &TestApp, session_id: &str) -> ConversationState {
+    app.state
+        .sessions
+        .with_session(session_id, |s| s.state)
+        .await
+        .expect("a live session")
+}
+
+async fn transcript(app: &TestApp, path: &str) -> TestResponse {
+    let response = app.request(Method::GET, path, None).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    response
+}
+
+/// Id of the visitor's last message in the transcript.
+async fn last_visitor_message(app: &TestApp, session_id: &str) -> String {
+    let page = transcript(app, &format!("/sessions/{session_id}/messages")).await;
+    page.body["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .rev()
+        .find(|m| m["role"] == "user")
+        .and_then(|m| m["id"].as_str())
+        .expect("a visitor message")
+        .to_string()
+}
+
+/// A session asking for the visitor's email, after they gave their name.
+async fn asking_email(app: &TestApp) -> String {
+    let session_id = app
+        .chat(None, "I'd like to start a project")
+        .await
+        .session_id()
+        .to_string();
+    assert_eq!(
+        state_of(app, &session_id).await,
+        ConversationState::AskingName
+    );
+    app.chat(Some(&session_id), "Ana").await;
+    assert_eq!(
+        state_of(app, &session_id).await,
+        ConversationState::AskingEmail
+    );
+    session_id
+}
+
+#[tokio::test]
+async fn an_edit_answers_the_corrected_message_afresh() {
+    let app = app().await;
+    let session_id = asking_email(&app).await;
+    let message_id = last_visitor_message(&app, &session_id).await;
+
+    let path = format!("/sessions/{session_id}/messages/{message_id}");
+    let edited = app
+        .request(Method::PATCH, &path, Some(json!({ "text": "Beata" })))
+        .await;
+    assert_eq!(edited.status, StatusCode::OK, "{}", edited.body);
+    assert_eq!(edited.body["reevaluated"], true);
+    assert!(!edited.body["retracted"].as_array().unwrap().is_empty());
+
+    let name = app
+        .state
+        .sessions
+        .with_session(&session_id, |s| s.data.name.clone())
+        .await
+        .flatten();
+    assert_eq!(name.as_deref(), Some("Beata"));
+    assert_eq!(
+        state_of(&app, &session_id).await,
+        ConversationState::AskingEmail
+    );
+}
+
+#[tokio::test]
+async fn an_edited_message_gets_the_answer_a_new_one_would() {
+    let weather = Arc::new(StubIntent::new("weather", &["forecast"]).reply("Sunny all week."));
+    let app = TestApp::new(StateBuilder::new().intent(weather.clone()).build().await);
+    let session_id = app.chat(None, "hello").await.session_id().to_string();
+    let message_id = last_visitor_message(&app, &session_id).await;
+
+    let path = format!("/sessions/{session_id}/messages/{message_id}");
+    let edited = app
+        .request(
+            Method::PATCH,
+            &path,
+            Some(json!({ "text": "what's the forecast?" })),
+        )
+        .await;
+    assert_eq!(edited.status, StatusCode::OK, "{}", edited.body);
+    assert_eq!(edited.body["reply"], "Sunny all week.");
+    assert_eq!(weather.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn a_delete_undoes_the_turn() {
+    let app = app().await;
+    let session_id = asking_email(&app).await;
+    let message_id = last_visitor_message(&app, &session_id).await;
+
+    let path = format!("/sessions/{session_id}/messages/{message_id}");
+    let deleted = app.request(Method::DELETE, &path, None).await;
+    assert_eq!(deleted.status, StatusCode::OK, "{}", deleted.body);
+    assert_eq!(
+        state_of(&app, &session_id).await,
+        ConversationState::AskingName
+    );
+    let page = transcript(&app, &format!("/sessions/{session_id}/messages")).await;
+    assert!(
+        page.body["messages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|m| m["text"] != "Ana")
+    );
+}
+
+#[tokio::test]
+async fn only_the_last_recent_visitor_message_can_be_changed() {
+    let app = app().await;
+    let session_id = asking_email(&app).await;
+    let page = transcript(&app, &format!("/sessions/{session_id}/messages")).await;
+    let messages = page.body["messages"].as_array().unwrap().clone();
+    let first = messages.iter().find(|m| m["role"] == "user").unwrap();
+    let bot = messages.iter().find(|m| m["role"] == "bot").unwrap();
+
+    let edit = async |id: &str| {
+        let path = format!("/sessions/{session_id}/messages/{id}");
+        app.request(Method::PATCH, &path, Some(json!({ "text": "x" })))
+            .await
+            .status
+    };
+    let id = |m: &Value| m["id"].as_str().unwrap().to_string();
+    assert_eq!(edit(&id(first)).await, StatusCode::BAD_REQUEST);
+    assert_eq!(edit(&id(bot)).await, StatusCode::FORBIDDEN);
+
+    let last = last_visitor_message(&app, &session_id).await;
+    let past_window = SystemTime::now() - EDIT_WINDOW - Duration::from_secs(1);
+    app.state
+        .sessions
+        .with_session(&session_id, |s| {
+            for message in s.messages.iter_mut().filter(|m| m.id == last) {
+                message.timestamp = past_window;
+            }
+        })
+        .await;
+    assert_eq!(edit(&last).await, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn a_thumbs_up_confirms_but_stays_a_thumbs_up() {
+    let state = StateBuilder::new()
+        .session(
+            SessionBuilder::new("s1")
+                .name("Ana")
+                .email("ana@example.com")
+                .project_details("An online shop for handmade ceramics")
+                .budget("5000 EUR")
+                .state(ConversationState::ConfirmingSummary),
+        )
+        .build()
+        .await;
+    let app = TestApp::new(state);
+
+    let response = app.chat(Some("s1"), "👍").await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert_ne!(
+        state_of(&app, "s1").await,
+        ConversationState::ConfirmingSummary
+    );
+
+    let page = transcript(&app, "/sessions/s1/messages").await;
+    let visitor: Vec<&Value> = page.body["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|m| m["role"] == "user")
+        .map(|m| &m["text"])
+        .collect();
+    assert_eq!(visitor, vec!["👍"]);
+}
+
+#[tokio::test]
+async fn what_visitors_type_is_not_read_as_a_template() {
+    let details = "{if not name}Free of charge{end} {pending_question}";
+    let state = StateBuilder::new()
+        .session(
+            SessionBuilder::new("s1")
+                .name("Ana")
+                .email("ana@example.com")
+                .project_details(details)
+                .state(ConversationState::AskingBudget),
+        )
+        .build()
+        .await;
+    let app = TestApp::new(state);
+
+    let summary = app.chat(Some("s1"), "5000 EUR").await;
+    assert_eq!(summary.status, StatusCode::OK, "{}", summary.body);
+    assert!(summary.reply().contains(details), "{}", summary.reply());
+}
+
+#[tokio::test]
+async fn transcript_pages_run_back_from_the_newest() {
+    let app = app().await;
+    let session_id = app.chat(None, "hello").await.session_id().to_string();
+    for n in 1..=4 {
+        app.chat(Some(&session_id), &format!("message {n}")).await;
+    }
+    // Five visitor messages and five replies.
+    let path = format!("/sessions/{session_id}/messages");
+    let all = transcript(&app, &path).await.body["messages"]
+        .as_array()
+        .unwrap()
+        .clone();
+    assert_eq!(all.len(), 10);
+
+    let mut pages = Vec::new();
+    let mut cursor: Option<String> = None;
+    loop {
+        let query = match &cursor {
+            Some(before) => format!("{path}?limit=4&before={before}"),
+            None => format!("{path}?limit=4"),
+        };
+        let page = transcript(&app, &query).await;
+        pages.push(page.body["messages"].as_array().unwrap().clone());
+        match page.body["next_cursor"].as_str() {
+            Some(next) => cursor = Some(next.to_string()),
+            None => break,
+        }
+    }
+    assert_eq!(
+        pages.iter().map(Vec::len).collect::<Vec<_>>(),
+        vec![4, 4, 2]
+    );
+    let stitched: Vec<Value> = pages.into_iter().rev().flatten().collect();
+    assert_eq!(stitched, all);
+
+    // A new message doesn't move the pages already handed out.
+    app.chat(Some(&session_id), "one more").await;
+    let again = transcript(&app, &format!("{path}?limit=4&before={}", cursor.unwrap())).await;
+    assert_eq!(again.body["messages"].as_array().unwrap().len(), 2);
+
+    let bad = app
+        .request(Method::GET, &format!("{path}?before=nope"), None)
+        .await;
+    assert_eq!(bad.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn a_merged_session_redirects_to_the_one_it_joined() {
+    let state = StateBuilder::new()
+        .session(
+            SessionBuilder::new("first")
+                .name("Ana")
+                .history(History::new().visitor("hi").bot("Hello!")),
+        )
+        .session(SessionBuilder::new("second").email("ana@example.com"))
+        .build()
+        .await;
+    let app = TestApp::new(state);
+
+    let merged = app
+        .admin(
+            Method::POST,
+            "/sessions/second/merge",
+            Some(json!({ "from": "first" })),
+        )
+        .await;
+    assert_eq!(merged.status, StatusCode::OK, "{}", merged.body);
+    let data = app
+        .state
+        .sessions
+        .with_session("second", |s| s.data.clone())
+        .await
+        .unwrap();
+    assert!(data.name.is_some() && data.email.is_some());
+    assert!(app.state.sessions.lock_session("first").await.is_none());
+
+    // The visitor's old id carries on in the merged session.
+    let response = app.chat(Some("first"), "hello again").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.session_id(), "second");
+
+    let again = app
+        .admin(
+            Method::POST,
+            "/sessions/second/merge",
+            Some(json!({ "from": "first" })),
+        )
+        .await;
+    assert_eq!(again.status, StatusCode::NOT_FOUND);
+    let itself = app
+        .admin(
+            Method::POST,
+            "/sessions/second/merge",
+            Some(json!({ "from": "second" })),
+        )
+        .await;
+    assert_eq!(itself.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn a_merge_waiting_for_a_busy_session_leaves_the_store_free() {
+    let state = StateBuilder::new()
+        .session(SessionBuilder::new("first"))
+        .session(SessionBuilder::new("second"))
+        .build()
+        .await;
+    let busy = state.sessions.lock_session("second").await.unwrap();
+    let merging = tokio::spawn({
+        let state = state.clone();
+        async move { state.sessions.merge("first", "second").await }
+    });
+
+    let other = tokio::time::timeout(
+        Duration::from_secs(5),
+        state.sessions.resolve(None, None, "default", None),
+    )
+    .await
+    .expect("the store is not held while the merge waits");
+    assert!(other.is_ok());
+
+    drop(busy);
+    let merged = merging.await.unwrap();
+    assert_eq!(merged.map(|s| s.id).ok().as_deref(), Some("second"));
+}
+
+/// A session waiting for an operator, with one online.
+async fn waiting(app: &TestApp) -> String {
+    let online = app
+        .admin(
+            Method::PUT,
+            "/operators/op1/presence",
+            Some(json!({ "status": "online" })),
+        )
+        .await;
+    assert_eq!(online.status, StatusCode::OK, "{}", online.body);
+    let response = app.chat(None, "I want to talk to a person").await;
+    response.session_id().to_string()
+}
+
+async fn queued(app: &TestApp) -> Vec<String> {
+    let queue = app.admin(Method::GET, "/handoff/queue", None).await;
+    assert_eq!(queue.status, StatusCode::OK);
+    queue
+        .body
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|q| q["session_id"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn a_restored_session_goes_back_in_line() {
+    let app = app().await;
+    let session_id = waiting(&app).await;
+    assert_eq!(queued(&app).await, vec![session_id.clone()]);
+
+    let path = format!("/sessions/{session_id}");
+    let deleted = app.admin(Method::DELETE, &path, None).await;
+    assert_eq!(deleted.status, StatusCode::OK, "{}", deleted.body);
+    assert!(queued(&app).await.is_empty());
+    let gone = transcript_status(&app, &session_id).await;
+    assert_eq!(gone, StatusCode::NOT_FOUND);
+
+    let restored = app
+        .admin(Method::POST, &format!("{path}/restore"), None)
+        .await;
+    assert_eq!(restored.status, StatusCode::OK, "{}", restored.body);
+    assert_eq!(queued(&app).await, vec![session_id.clone()]);
+    assert_eq!(transcript_status(&app, &session_id).await, StatusCode::OK);
+
+    let twice = app
+        .admin(Method::POST, &format!("{path}/restore"), None)
+        .await;
+    assert_eq!(twice.status, StatusCode::NOT_FOUND);
+}
+
+async fn transcript_status(app: &TestApp, session_id: &str) -> StatusCode {
+    app.request(
+        Method::GET,
+        &format!("/sessions/{session_id}/messages"),
+        None,
+    )
+    .await
+    .status
+}
+
+#[tokio::test]
+async fn blocked_visitors_are_refused() {
+    let app = app().await;
+    let session_id = app.chat(None, "hello").await.session_id().to_string();
+    let blocked = app
+        .admin(
+            Method::PUT,
+            &format!("/blocklist/visitor/{session_id}"),
+            None,
+        )
+        .await;
+    assert!(blocked.status.is_success(), "{}", blocked.body);
+
+    let refused = app.chat(Some(&session_id), "hello again").await;
+    assert_eq!(refused.status, StatusCode::FORBIDDEN);
+
+    let domain = app
+        .admin(Method::PUT, "/blocklist/email_domain/spam.example", None)
+        .await;
+    assert!(domain.status.is_success(), "{}", domain.body);
+    let by_address = app.chat(None, "write to me at bot@mail.spam.example").await;
+    assert_eq!(by_address.status, StatusCode::FORBIDDEN);
+    let other = app.chat(None, "write to me at ana@example.com").await;
+    assert_eq!(other.status, StatusCode::OK);
+
+    let quiet = app
+        .admin(
+            Method::PUT,
+            "/blocklist/response",
+            Some(json!({ "response": "drop" })),
+        )
+        .await;
+    assert_eq!(quiet.status, StatusCode::OK);
+    let dropped = app.chat(Some(&session_id), "anyone?").await;
+    assert_eq!(dropped.status, StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn a_snapshot_carries_sessions_and_their_transcripts() {
+    let old = app().await;
+    let session_id = asking_email(&old).await;
+    let path = std::env::temp_dir().join(format!("snapshot-{session_id}.json"));
+    assert_eq!(snapshot::save(&old.state, &path).await.unwrap(), 1);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    let new = app().await;
+    assert_eq!(snapshot::restore(&new.state, &path).await.unwrap(), 1);
+    // Sessions this instance has already are left alone.
+    assert_eq!(snapshot::restore(&new.state, &path).await.unwrap(), 0);
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(
+        state_of(&new, &session_id).await,
+        ConversationState::AskingEmail
+    );
+    let messages_path = format!("/sessions/{session_id}/messages");
+    let before = transcript(&old, &messages_path).await;
+    let after = transcript(&new, &messages_path).await;
+    assert_eq!(after.body["messages"], before.body["messages"]);
+    let roles = new
+        .state
+        .sessions
+        .get_history(&session_id)
+        .await
+        .unwrap()
+        .iter()
+        .filter(|m| m.role == Role::User)
+        .count();
+    assert_eq!(roles, 2);
+}
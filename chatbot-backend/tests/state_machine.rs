@@ -0,0 +1,339 @@
+//! Random conversations through the inquiry flow, checking after every
+//! turn what must hold whatever the visitor says:
+//!
+//! - the state only changes along a row of [`flow::TRANSITIONS`], and every
+//!   question state has the answers to the questions before it;
+//! - a field only gets a value while its question is pending, or from a
+//!   correction of a value already given;
+//! - a reset always ends in `Idle` with nothing captured.
+
+use chatbot_backend::{
+    catalog::ServicesCatalog,
+    chatbot::{self, AnswerRules, Intent, Qualification, Reply, Vocabulary},
+    flow::{self, Event, Restore, TransitionResult},
+    i18n::Language,
+    session::{ConversationState, Field, LeadTag, Role, Session, SessionData},
+    synonyms::SynonymTable,
+    tenant::ReplyOverrides,
+    training::LearnedIntents,
+    validation::ValidationRules,
+};
+use proptest::{prelude::*, sample::select};
+
+/// Answers to the inquiry questions, valid and not, in every language.
+const ANSWERS: &[&str] = &[
+    "Ana",
+    "Łukasz Nowak",
+    "María-José",
+    "O'Brien",
+    "hi",
+    "ana@example.com",
+    "jan.kowalski@example.pl",
+    "not an email",
+    "ana@localhost",
+    "An online shop for handmade ceramics",
+    "Sklep internetowy z ceramiką",
+    "short",
+    "15000",
+    "3-5k",
+    "500 EUR",
+    "around 2000 zł",
+];
+
+/// What visitors say besides answers: commands, small talk, questions and
+/// replies to the summary.
+const COMMANDS: &[&str] = &[
+    "I'd like to start a project",
+    "Chcę zacząć nowy projekt",
+    "Quiero empezar un proyecto",
+    "reset",
+    "start over",
+    "od nowa",
+    "cancelar",
+    "skip",
+    "not sure",
+    "nie wiem",
+    "yes",
+    "no",
+    "tak",
+    "sí",
+    "change the email",
+    "edit budget",
+    "I want to fix my name",
+    "What services do you offer?",
+    "How much does a website cost?",
+    "Can I talk to a person?",
+    "Please email me the transcript",
+    "thanks",
+    "bye",
+    "Hello!",
+];
+
+const CORRECTIONS: &[&str] = &[
+    "actually my email is bob@example.com",
+    "sorry, my name is Bob",
+    "actually my budget is 8000",
+    "właściwie mój email to jan@example.pl",
+    "perdón, mi nombre es Lucía",
+];
+
+fn message() -> impl Strategy<Value = String> {
+    prop_oneof![
+        4 => select(ANSWERS).prop_map(str::to_string),
+        4 => select(COMMANDS).prop_map(str::to_string),
+        1 => select(CORRECTIONS).prop_map(str::to_string),
+        1 => "\\PC{0,40}",
+    ]
+}
+
+fn language() -> impl Strategy<Value = Language> {
+    select(Language::ALL.to_vec())
+}
+
+fn question(field: Field) -> ConversationState {
+    match field {
+        Field::Name => ConversationState::AskingName,
+        Field::Email => ConversationState::AskingEmail,
+        Field::ProjectDetails => ConversationState::AskingProjectDetails,
+        Field::Budget => ConversationState::AskingBudget,
+    }
+}
+
+/// The fields every question state needs answered before it.
+fn required(state: ConversationState) -> &'static [Field] {
+    match state {
+        ConversationState::Idle
+        | ConversationState::AskingName
+        | ConversationState::AskingCallbackEmail => &[],
+        ConversationState::AskingEmail => &[Field::Name],
+        ConversationState::AskingProjectDetails => &[Field::Name, Field::Email],
+        ConversationState::AskingBudget => &[Field::Name, Field::Email, Field::ProjectDetails],
+        ConversationState::ConfirmingSummary => &Field::ALL,
+        ConversationState::ConfirmingTranscriptEmail => &[Field::Email],
+    }
+}
+
+struct Bot {
+    catalog: ServicesCatalog,
+    overrides: ReplyOverrides,
+    validation: ValidationRules,
+    qualification: Option<Qualification>,
+    synonyms: SynonymTable,
+    learned: LearnedIntents,
+}
+
+impl Bot {
+    fn new(qualified: bool) -> Self {
+        Self {
+            catalog: ServicesCatalog::default(),
+            overrides: ReplyOverrides::default(),
+            validation: ValidationRules::default(),
+            qualification: qualified.then(|| Qualification {
+                min_budget: 5000,
+                self_service_url: Some("https://example.com/packages".into()),
+            }),
+            synonyms: SynonymTable::default(),
+            learned: LearnedIntents::default(),
+        }
+    }
+
+    fn turn(&self, session: &mut Session, message: &str) -> Reply {
+        session.push_message(Role::User, message);
+        chatbot::generate_reply(
+            session,
+            message,
+            &self.catalog,
+            &self.overrides,
+            AnswerRules {
+                validation: &self.validation,
+                qualification: self.qualification.as_ref(),
+            },
+            None,
+            Vocabulary {
+                synonyms: &self.synonyms,
+                learned: &self.learned,
+            },
+        )
+    }
+}
+
+fn check_turn(
+    before: (ConversationState, &SessionData),
+    session: &Session,
+    message: &str,
+    reply: &Reply,
+) -> Result<(), TestCaseError> {
+    let (from, data) = before;
+    let to = session.state;
+    prop_assert!(
+        from == to
+            || flow::TRANSITIONS
+                .iter()
+                .any(|t| t.from == from && t.to == to),
+        "{from:?} -> {to:?} is not in the flow, after {message:?}"
+    );
+    for field in required(to) {
+        prop_assert!(
+            session.data.has(*field),
+            "{to:?} without a {field:?}, after {message:?}"
+        );
+    }
+
+    for field in Field::ALL {
+        let (old, new) = (data.get(field), session.data.get(field));
+        if old == new {
+            continue;
+        }
+        if new.is_none() {
+            prop_assert!(
+                matches!(reply.intent, Intent::Reset | Intent::StartInquiry),
+                "{field:?} cleared by {message:?} ({:?})",
+                reply.intent
+            );
+            continue;
+        }
+        let asked = from == question(field)
+            || (field == Field::Email && from == ConversationState::AskingCallbackEmail);
+        let corrected = reply.intent == Intent::Correction && old.is_some();
+        prop_assert!(
+            asked || corrected,
+            "{field:?} set to {new:?} in {from:?} by {message:?}"
+        );
+    }
+
+    if chatbot::detect_intent(message) == Intent::Reset {
+        prop_assert_eq!(to, ConversationState::Idle, "reset by {:?}", message);
+        prop_assert_eq!(&session.data, &SessionData::default());
+    }
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(512))]
+
+    #[test]
+    fn every_turn_keeps_the_flow_consistent(
+        language in language(),
+        qualified in any::<bool>(),
+        operators_available in any::<bool>(),
+        messages in prop::collection::vec(message(), 1..40),
+    ) {
+        let bot = Bot::new(qualified);
+        let mut session = Session::new("proptest".into(), language);
+        session.operators_available = operators_available;
+        for message in &messages {
+            let (state, data) = (session.state, session.data.clone());
+            let reply = bot.turn(&mut session, message);
+            check_turn((state, &data), &session, message, &reply)?;
+        }
+    }
+
+    #[test]
+    fn reset_returns_to_idle_from_anywhere(
+        language in language(),
+        messages in prop::collection::vec(message(), 0..30),
+        reset in select(&["reset", "start over", "restart", "cancel", "od nowa", "anuluj", "reiniciar", "cancelar"][..]),
+    ) {
+        let bot = Bot::new(false);
+        let mut session = Session::new("proptest".into(), language);
+        for message in &messages {
+            bot.turn(&mut session, message);
+        }
+        let reply = bot.turn(&mut session, reset);
+        prop_assert_eq!(reply.intent, Intent::Reset);
+        prop_assert_eq!(session.state, ConversationState::Idle);
+        prop_assert_eq!(session.data, SessionData::default());
+        prop_assert_eq!(session.failed_answers, 0);
+    }
+}
+
+#[test]
+fn the_guards_pick_the_next_question() {
+    let bot = Bot::new(false);
+    let mut session = Session::new("s1".into(), Language::En);
+    session.state = ConversationState::AskingName;
+    session.data.email = Some("ana@example.com".into());
+    session.data.project_details = Some("An online shop for handmade ceramics".into());
+    bot.turn(&mut session, "Ana");
+    assert_eq!(session.state, ConversationState::AskingBudget);
+}
+
+#[test]
+fn events_the_table_does_not_declare_are_refused() {
+    let mut session = Session::new("s1".into(), Language::En);
+    session.state = ConversationState::AskingEmail;
+    session.data.name = Some("Ana".into());
+    assert_eq!(
+        flow::fire(&mut session, Event::Confirmed),
+        TransitionResult::Refused {
+            from: ConversationState::AskingEmail,
+            event: Event::Confirmed,
+        }
+    );
+    assert_eq!(session.state, ConversationState::AskingEmail);
+
+    // Declared, but the guard doesn't hold: there is no address to send
+    // the transcript to.
+    let mut session = Session::new("s2".into(), Language::En);
+    assert!(matches!(
+        flow::fire(&mut session, Event::TranscriptRequested),
+        TransitionResult::Refused { .. }
+    ));
+    assert_eq!(session.state, ConversationState::Idle);
+}
+
+#[test]
+fn a_move_the_table_refuses_asks_the_pending_question_again() {
+    // A budget answer with no name captured has nowhere to go.
+    let bot = Bot::new(false);
+    let mut session = Session::new("s1".into(), Language::En);
+    session.state = ConversationState::AskingBudget;
+    let reply = bot.turn(&mut session, "15000");
+    assert_eq!(session.state, ConversationState::AskingBudget);
+    assert_eq!(reply.intent, Intent::Unknown);
+}
+
+#[test]
+fn restoring_a_state_the_data_cannot_be_in_is_refused() {
+    let mut session = Session::new("s1".into(), Language::En);
+    session.data.name = Some("Ana".into());
+    assert!(!flow::restore(
+        &mut session,
+        ConversationState::ConfirmingSummary,
+        Restore::Undo
+    ));
+    assert_eq!(session.state, ConversationState::Idle);
+    assert!(flow::restore(
+        &mut session,
+        ConversationState::AskingEmail,
+        Restore::Merge
+    ));
+    assert_eq!(session.state, ConversationState::AskingEmail);
+}
+
+#[test]
+fn a_corrected_budget_is_qualified_again() {
+    let mut bot = Bot::new(true);
+    bot.qualification = Some(Qualification {
+        min_budget: 5000,
+        self_service_url: None,
+    });
+    let mut session = Session::new("s1".into(), Language::En);
+    for message in [
+        "I'd like to start a project",
+        "Ana",
+        "ana@example.com",
+        "An online shop for handmade ceramics",
+        "3000",
+    ] {
+        bot.turn(&mut session, message);
+    }
+    assert_eq!(session.state, ConversationState::ConfirmingSummary);
+    assert_eq!(session.data.tag, Some(LeadTag::BelowMinimumBudget));
+
+    bot.turn(&mut session, "actually my budget is 8000");
+    assert_eq!(session.data.tag, None);
+    bot.turn(&mut session, "actually my budget is 2000");
+    assert_eq!(session.data.tag, Some(LeadTag::BelowMinimumBudget));
+    assert_eq!(session.state, ConversationState::ConfirmingSummary);
+}